@@ -0,0 +1,203 @@
+//! SOD: Crossbeam
+//!
+//! This crate provides services backed by [`crossbeam_queue`] queues.
+//!
+//! * [`SpillToDiskService`] pushes to a bounded in-memory [`ArrayQueue`], spilling overflow to a disk-backed log instead of rejecting it.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, ErrorKind, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use crossbeam_queue::ArrayQueue;
+use serde::{de::DeserializeOwned, Serialize};
+use sod::Service;
+
+/// A [`Service`] that pushes each input to a bounded in-memory [`ArrayQueue`], spilling overflow to a disk-backed log when the queue is full.
+///
+/// Spilled items are serialized as JSON, one item per line, and reloaded into memory by [`SpillToDiskService::pop`] as capacity frees up.
+/// This bounds memory usage during bursts without rejecting inputs.
+///
+/// ## Ordering
+/// Items are popped in the order they were pushed.
+/// Once any item has spilled to disk, every subsequent push also goes to disk until the log has been fully reloaded,
+/// so newer items never overtake older spilled items by landing in memory first.
+/// When pushes race from multiple threads, items pushed by the same thread are always popped in the order that thread pushed them.
+///
+/// ## Durability
+/// The log is truncated when the service is created and whenever it has been fully reloaded, so spilled items are not recovered across restarts.
+pub struct SpillToDiskService<T> {
+    queue: ArrayQueue<T>,
+    log: Mutex<SpillLog>,
+    spilling: AtomicBool,
+    _phantom: PhantomData<fn(T)>,
+}
+struct SpillLog {
+    file: File,
+    read_offset: u64,
+    spilled: usize,
+}
+impl<T: Serialize + DeserializeOwned> SpillToDiskService<T> {
+    /// Create a new [`SpillToDiskService`], holding up to `capacity` items in memory and spilling overflow to the log file at `path`
+    ///
+    /// The log file is created if it does not exist, and truncated if it does.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    pub fn new<P: AsRef<Path>>(capacity: usize, path: P) -> io::Result<Self> {
+        assert!(capacity > 0, "SpillToDiskService capacity must be non-zero");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Self {
+            queue: ArrayQueue::new(capacity),
+            log: Mutex::new(SpillLog {
+                file,
+                read_offset: 0,
+                spilled: 0,
+            }),
+            spilling: AtomicBool::new(false),
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Pop the oldest item, reloading spilled items from disk into the freed in-memory capacity
+    pub fn pop(&self) -> io::Result<Option<T>> {
+        let item = self.queue.pop();
+        if self.spilling.load(Ordering::Acquire) {
+            self.reload()?;
+        }
+        match item {
+            Some(item) => Ok(Some(item)),
+            None => Ok(self.queue.pop()),
+        }
+    }
+
+    /// The number of items currently spilled to disk
+    pub fn spilled(&self) -> usize {
+        self.log.lock().expect("poisoned lock").spilled
+    }
+
+    fn reload(&self) -> io::Result<()> {
+        let mut log = self.log.lock().expect("poisoned lock");
+        let SpillLog {
+            file,
+            read_offset,
+            spilled,
+        } = &mut *log;
+        let mut reader = BufReader::new(&*file);
+        reader.seek(SeekFrom::Start(*read_offset))?;
+        let mut line = String::new();
+        while *spilled > 0 && !self.queue.is_full() {
+            line.clear();
+            let len = reader.read_line(&mut line)?;
+            if len == 0 {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "spill log ended before all spilled items were reloaded",
+                ));
+            }
+            let item: T = serde_json::from_str(&line)?;
+            // only advance past the item once it is in memory, so it is retried if a racing push took the capacity
+            if self.queue.push(item).is_err() {
+                break;
+            }
+            *read_offset += len as u64;
+            *spilled -= 1;
+        }
+        if *spilled == 0 {
+            file.set_len(0)?;
+            *read_offset = 0;
+            self.spilling.store(false, Ordering::Release);
+        }
+        Ok(())
+    }
+}
+impl<T: Serialize + DeserializeOwned> Service for SpillToDiskService<T> {
+    type Input = T;
+    type Output = ();
+    type Error = io::Error;
+    fn process(&self, input: T) -> Result<(), io::Error> {
+        let input = match self.spilling.load(Ordering::Acquire) {
+            true => input,
+            false => match self.queue.push(input) {
+                Ok(()) => return Ok(()),
+                Err(input) => input,
+            },
+        };
+        let mut log = self.log.lock().expect("poisoned lock");
+        // the log may have been fully reloaded while waiting for the lock
+        let input = match log.spilled {
+            0 => match self.queue.push(input) {
+                Ok(()) => return Ok(()),
+                Err(input) => input,
+            },
+            _ => input,
+        };
+        let mut line = serde_json::to_vec(&input)?;
+        line.push(b'\n');
+        log.file.seek(SeekFrom::End(0))?;
+        log.file.write_all(&line)?;
+        log.spilled += 1;
+        self.spilling.store(true, Ordering::Release);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spill_overflow_and_replay_in_order() {
+        let path = std::env::temp_dir().join(format!("sod-spill-{}.log", std::process::id()));
+        let queue = SpillToDiskService::new(3, &path).unwrap();
+        let item = |n: u32| (n, format!("item-{n}"));
+
+        for n in 0..10 {
+            queue.process(item(n)).unwrap();
+        }
+        assert_eq!(7, queue.spilled());
+        assert!(std::fs::metadata(&path).unwrap().len() > 0);
+
+        // freed capacity is refilled from disk, and new items keep spilling behind the older spilled items
+        assert_eq!(Some(item(0)), queue.pop().unwrap());
+        assert_eq!(Some(item(1)), queue.pop().unwrap());
+        assert_eq!(5, queue.spilled());
+        queue.process(item(10)).unwrap();
+        queue.process(item(11)).unwrap();
+        assert_eq!(7, queue.spilled());
+
+        let mut popped = Vec::new();
+        while let Some(item) = queue.pop().unwrap() {
+            popped.push(item);
+        }
+        assert_eq!((2..12).map(item).collect::<Vec<_>>(), popped);
+        assert_eq!(0, queue.spilled());
+        assert_eq!(0, std::fs::metadata(&path).unwrap().len());
+
+        // once drained, pushes go back to memory
+        queue.process(item(12)).unwrap();
+        assert_eq!(0, queue.spilled());
+        assert_eq!(Some(item(12)), queue.pop().unwrap());
+
+        drop(queue);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be non-zero")]
+    fn zero_capacity_panics() {
+        let path = std::env::temp_dir().join(format!("sod-spill-zero-{}.log", std::process::id()));
+        SpillToDiskService::<u32>::new(0, path).ok();
+    }
+}
@@ -0,0 +1,132 @@
+//! SOD: JSON Schema
+//!
+//! This crate provides [`JsonSchemaValidateService`], which validates [`serde_json::Value`] payloads against a JSON Schema using [`jsonschema`].
+//!
+//! The service produces the validated [`Value`] as output, so it may be placed in a [`sod::ServiceChain`] in front of a deserialization stage.
+
+use std::{error::Error, fmt::Display};
+
+use jsonschema::{ValidationError, Validator};
+use serde_json::Value;
+use sod::Service;
+
+/// A [`Service`] that validates each [`Value`] input against a JSON Schema, producing the input as output when it is valid.
+///
+/// The schema is compiled once at construction.
+/// When the input is invalid, a [`SchemaValidationError`] is returned listing every violation, not only the first.
+pub struct JsonSchemaValidateService {
+    validator: Validator,
+}
+impl JsonSchemaValidateService {
+    /// Compile the given schema, returning an error when the schema itself is invalid
+    pub fn new(schema: &Value) -> Result<Self, Box<ValidationError<'static>>> {
+        Ok(Self {
+            validator: jsonschema::validator_for(schema).map_err(Box::new)?,
+        })
+    }
+}
+impl Service for JsonSchemaValidateService {
+    type Input = Value;
+    type Output = Value;
+    type Error = SchemaValidationError;
+    fn process(&self, input: Value) -> Result<Value, SchemaValidationError> {
+        let violations: Vec<_> = self
+            .validator
+            .iter_errors(&input)
+            .map(|err| SchemaViolation {
+                instance_path: err.instance_path.to_string(),
+                schema_path: err.schema_path.to_string(),
+                message: err.to_string(),
+            })
+            .collect();
+        match violations.is_empty() {
+            true => Ok(input),
+            false => Err(SchemaValidationError { violations }),
+        }
+    }
+}
+
+/// Returned by [`JsonSchemaValidateService`] when the input does not conform to the schema.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SchemaValidationError {
+    /// Every violation found in the input
+    pub violations: Vec<SchemaViolation>,
+}
+impl Display for SchemaValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} schema violations", self.violations.len())?;
+        for violation in &self.violations {
+            write!(f, "; {violation}")?;
+        }
+        Ok(())
+    }
+}
+impl Error for SchemaValidationError {}
+
+/// A single violation within a [`SchemaValidationError`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SchemaViolation {
+    /// The JSON Pointer to the invalid value within the input, such as `/items/0`
+    pub instance_path: String,
+    /// The JSON Pointer to the schema keyword that failed, such as `/properties/items/items/type`
+    pub schema_path: String,
+    /// A human-readable description of the violation
+    pub message: String,
+}
+impl Display for SchemaViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.instance_path, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn service() -> JsonSchemaValidateService {
+        JsonSchemaValidateService::new(&json!({
+            "type": "object",
+            "required": ["name"],
+            "properties": {
+                "name": { "type": "string" },
+                "tags": { "type": "array", "items": { "type": "string" } },
+                "age": { "type": "integer", "minimum": 0 }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn valid_document_passes_through() {
+        let document = json!({ "name": "sod", "tags": ["a", "b"], "age": 3 });
+        assert_eq!(Ok(document.clone()), service().process(document));
+    }
+
+    #[test]
+    fn invalid_document_reports_all_violations() {
+        let err = service()
+            .process(json!({ "tags": ["a", 7], "age": -1 }))
+            .unwrap_err();
+        let mut paths: Vec<_> = err
+            .violations
+            .iter()
+            .map(|v| (v.instance_path.as_str(), v.schema_path.as_str()))
+            .collect();
+        paths.sort();
+        assert_eq!(
+            vec![
+                ("", "/required"),
+                ("/age", "/properties/age/minimum"),
+                ("/tags/1", "/properties/tags/items/type"),
+            ],
+            paths
+        );
+    }
+
+    #[test]
+    fn invalid_schema_is_rejected() {
+        assert!(JsonSchemaValidateService::new(&json!({ "type": 7 })).is_err());
+    }
+}
@@ -0,0 +1,341 @@
+//! SOD: OpenTelemetry
+//!
+//! This crate provides services to trace [`sod`] services with [`opentelemetry`] spans and to propagate trace context across service boundaries.
+//!
+//! * [`TracedService`] starts a span around each call to an underlying [`sod::AsyncService`], records the outcome as the span's status,
+//!   and makes the span's context current while the underlying service is polled.
+//! * [`SpanService`] additionally derives [`Baggage`] from each input, records it on the span, and makes it current for downstream services.
+//! * [`InjectContextService`] injects the current trace context into an outbound request, such as into the headers of an HTTP request.
+//!
+//! Inbound trace context may be extracted by giving [`TracedService::with_parent`] a function that calls [`TextMapPropagator::extract`] for the input.
+
+use std::{borrow::Cow, convert::Infallible, fmt::Debug, marker::PhantomData};
+
+use opentelemetry::{
+    baggage::{Baggage, BaggageExt, KeyValueMetadata},
+    context::FutureExt,
+    propagation::{Injector, TextMapPropagator},
+    trace::{Status, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use sod::{async_trait, AsyncService, Service};
+
+/// An [`AsyncService`] that starts a span around each call to an underlying [`AsyncService`].
+///
+/// The span's parent is the [`Context`] produced by the `parent` function, which defaults to [`Context::current`].
+/// While the underlying service is polled, the span's context is current, so nested traced services and [`InjectContextService`]s observe it.
+/// When the underlying service returns `Ok`, the span status is set to [`Status::Ok`].
+/// When it returns `Err`, the span status is set to [`Status::Error`] with the `Debug` representation of the error as the description.
+pub struct TracedService<S, T, F> {
+    service: S,
+    tracer: T,
+    name: Cow<'static, str>,
+    parent: F,
+}
+impl<S: AsyncService, T: Tracer> TracedService<S, T, fn(&S::Input) -> Context> {
+    pub fn new<N: Into<Cow<'static, str>>>(service: S, tracer: T, name: N) -> Self {
+        Self::with_parent(service, tracer, name, current_context)
+    }
+}
+impl<S: AsyncService, T: Tracer, F: Fn(&S::Input) -> Context> TracedService<S, T, F> {
+    /// Create a new [`TracedService`] which uses the given function to produce the parent [`Context`] for each input.
+    ///
+    /// Use this to continue a trace from an inbound request, for example:
+    /// `|req: &Request| propagator.extract(&req.headers)`
+    pub fn with_parent<N: Into<Cow<'static, str>>>(
+        service: S,
+        tracer: T,
+        name: N,
+        parent: F,
+    ) -> Self {
+        Self {
+            service,
+            tracer,
+            name: name.into(),
+            parent,
+        }
+    }
+}
+#[async_trait]
+impl<S, T, F> AsyncService for TracedService<S, T, F>
+where
+    S: AsyncService,
+    S::Error: Debug,
+    T: Tracer + Send + Sync,
+    T::Span: Send + Sync + 'static,
+    F: Fn(&S::Input) -> Context + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let parent = (self.parent)(&input);
+        let span = self.tracer.start_with_context(self.name.clone(), &parent);
+        let cx = parent.with_span(span);
+        let result = self.service.process(input).with_context(cx.clone()).await;
+        let span = cx.span();
+        match &result {
+            Ok(_) => span.set_status(Status::Ok),
+            Err(err) => span.set_status(Status::error(format!("{err:?}"))),
+        }
+        span.end();
+        result
+    }
+}
+
+fn current_context<I>(_: &I) -> Context {
+    Context::current()
+}
+
+/// An [`AsyncService`] that starts a span around each call to an underlying [`AsyncService`], carrying [`Baggage`] derived from the input.
+///
+/// The `baggage` function produces key/value pairs for each input, which are added to the current context's [`Baggage`].
+/// Every entry of the resulting [`Baggage`] is recorded as an attribute of the span, including entries inherited from an enclosing [`SpanService`].
+/// While the underlying service is polled, the [`Baggage`] and span are current, so nested spans of downstream services inherit the [`Baggage`],
+/// and it may be propagated across service boundaries by an [`InjectContextService`] using a baggage propagator.
+///
+/// When the underlying service returns `Ok`, the span status is set to [`Status::Ok`].
+/// When it returns `Err`, the span status is set to [`Status::Error`], and an `exception` event is added,
+/// both using the `Debug` representation of the error.
+pub struct SpanService<S, T, B> {
+    service: S,
+    tracer: T,
+    name: Cow<'static, str>,
+    baggage: B,
+}
+impl<S: AsyncService, T: Tracer, B: Fn(&S::Input) -> Vec<KeyValue>> SpanService<S, T, B> {
+    pub fn new<N: Into<Cow<'static, str>>>(service: S, tracer: T, name: N, baggage: B) -> Self {
+        Self {
+            service,
+            tracer,
+            name: name.into(),
+            baggage,
+        }
+    }
+}
+#[async_trait]
+impl<S, T, B> AsyncService for SpanService<S, T, B>
+where
+    S: AsyncService,
+    S::Error: Debug,
+    T: Tracer + Send + Sync,
+    T::Span: Send + Sync + 'static,
+    B: Fn(&S::Input) -> Vec<KeyValue> + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let parent = Context::current();
+        let mut baggage = baggage_entries(parent.baggage()).collect::<Baggage>();
+        for KeyValue { key, value, .. } in (self.baggage)(&input) {
+            baggage.insert(key, value.as_str().into_owned());
+        }
+        let attributes = baggage
+            .iter()
+            .map(|(key, (value, _))| KeyValue::new(key.clone(), value.as_str().to_owned()))
+            .collect::<Vec<_>>();
+        let baggage = baggage_entries(&baggage).collect::<Vec<_>>();
+        let cx = parent.with_baggage(baggage);
+        let span = self
+            .tracer
+            .span_builder(self.name.clone())
+            .with_attributes(attributes)
+            .start_with_context(&self.tracer, &cx);
+        let cx = cx.with_span(span);
+        let result = self.service.process(input).with_context(cx.clone()).await;
+        let span = cx.span();
+        match &result {
+            Ok(_) => span.set_status(Status::Ok),
+            Err(err) => {
+                let message = format!("{err:?}");
+                span.add_event(
+                    "exception",
+                    vec![KeyValue::new("exception.message", message.clone())],
+                );
+                span.set_status(Status::error(message));
+            }
+        }
+        span.end();
+        result
+    }
+}
+
+fn baggage_entries(baggage: &Baggage) -> impl Iterator<Item = KeyValueMetadata> + '_ {
+    baggage.iter().map(|(key, (value, metadata))| {
+        KeyValueMetadata::new(key.clone(), value.clone(), metadata.clone())
+    })
+}
+
+/// A [`Service`] that injects the current trace [`Context`] into each input using a [`TextMapPropagator`], producing the input as output.
+///
+/// The given `set` function is called for each key and value produced by the propagator, such as to set a header on an outbound HTTP request.
+pub struct InjectContextService<I, P, F> {
+    propagator: P,
+    set: F,
+    _phantom: PhantomData<fn(I)>,
+}
+impl<I, P: TextMapPropagator, F: Fn(&mut I, &str, String)> InjectContextService<I, P, F> {
+    pub fn new(propagator: P, set: F) -> Self {
+        Self {
+            propagator,
+            set,
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<I, P: TextMapPropagator, F: Fn(&mut I, &str, String)> Service
+    for InjectContextService<I, P, F>
+{
+    type Input = I;
+    type Output = I;
+    type Error = Infallible;
+    fn process(&self, mut input: I) -> Result<I, Infallible> {
+        self.propagator.inject(&mut SetInjector {
+            input: &mut input,
+            set: &self.set,
+        });
+        Ok(input)
+    }
+}
+
+struct SetInjector<'a, I, F> {
+    input: &'a mut I,
+    set: &'a F,
+}
+impl<I, F: Fn(&mut I, &str, String)> Injector for SetInjector<'_, I, F> {
+    fn set(&mut self, key: &str, value: String) {
+        (self.set)(self.input, key, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use futures::executor::block_on;
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_sdk::{
+        propagation::TraceContextPropagator,
+        trace::{InMemorySpanExporter, SdkTracerProvider},
+    };
+    use sod::FnService;
+
+    use super::*;
+
+    fn provider() -> (SdkTracerProvider, InMemorySpanExporter) {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter.clone())
+            .build();
+        (provider, exporter)
+    }
+
+    #[test]
+    fn traced_service_records_status() {
+        let (provider, exporter) = provider();
+        let service = TracedService::new(
+            FnService::new(|input: i32| match input {
+                0.. => Ok(input),
+                _ => Err("negative"),
+            })
+            .into_async(),
+            provider.tracer("test"),
+            "check",
+        );
+
+        assert_eq!(Ok(1), block_on(service.process(1)));
+        assert_eq!(Err("negative"), block_on(service.process(-1)));
+
+        provider.force_flush().unwrap();
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(2, spans.len());
+        assert_eq!("check", spans[0].name);
+        assert_eq!(Status::Ok, spans[0].status);
+        assert_eq!("check", spans[1].name);
+        assert_eq!(Status::error("\"negative\""), spans[1].status);
+    }
+
+    #[test]
+    fn traced_service_propagates_context() {
+        let (provider, exporter) = provider();
+        let inject = InjectContextService::new(
+            TraceContextPropagator::new(),
+            |headers: &mut HashMap<String, String>, key: &str, value: String| {
+                headers.insert(key.to_owned(), value);
+            },
+        );
+        let outbound = TracedService::new(inject.into_async(), provider.tracer("test"), "outbound");
+        let headers = block_on(outbound.process(HashMap::new())).unwrap();
+        provider.force_flush().unwrap();
+        let spans = exporter.get_finished_spans().unwrap();
+        let trace_id = spans[0].span_context.trace_id().to_string();
+        assert!(headers["traceparent"].contains(&trace_id));
+
+        let propagator = TraceContextPropagator::new();
+        let inbound = TracedService::with_parent(
+            FnService::new(|_: HashMap<String, String>| Ok::<_, ()>(())).into_async(),
+            provider.tracer("test"),
+            "inbound",
+            move |headers: &HashMap<String, String>| propagator.extract(headers),
+        );
+        block_on(inbound.process(headers)).unwrap();
+        provider.force_flush().unwrap();
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!("inbound", spans[1].name);
+        assert_eq!(
+            spans[0].span_context.trace_id(),
+            spans[1].span_context.trace_id()
+        );
+        assert_eq!(spans[0].span_context.span_id(), spans[1].parent_span_id);
+    }
+
+    #[test]
+    fn span_service_propagates_baggage() {
+        let (provider, exporter) = provider();
+        let inner = SpanService::new(
+            FnService::new(|id: u32| match id {
+                0 => Err("unknown user"),
+                // baggage from the enclosing span is visible downstream
+                _ => Ok(Context::current()
+                    .baggage()
+                    .get("user.id")
+                    .map(|v| v.to_string())),
+            })
+            .into_async(),
+            provider.tracer("test"),
+            "lookup",
+            |_: &u32| vec![KeyValue::new("stage", "lookup")],
+        );
+        let outer = SpanService::new(inner, provider.tracer("test"), "request", |id: &u32| {
+            vec![KeyValue::new("user.id", id.to_string())]
+        });
+
+        assert_eq!(Ok(Some("7".to_owned())), block_on(outer.process(7)));
+        assert_eq!(Err("unknown user"), block_on(outer.process(0)));
+
+        provider.force_flush().unwrap();
+        let spans = exporter.get_finished_spans().unwrap();
+        let attribute = |span: usize, key: &str| {
+            spans[span]
+                .attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.to_string())
+        };
+        assert_eq!(4, spans.len());
+        assert_eq!("lookup", spans[0].name);
+        assert_eq!(Some("7".to_owned()), attribute(0, "user.id"));
+        assert_eq!(Some("lookup".to_owned()), attribute(0, "stage"));
+        assert_eq!(Status::Ok, spans[0].status);
+        assert_eq!("request", spans[1].name);
+        assert_eq!(Some("7".to_owned()), attribute(1, "user.id"));
+        assert_eq!(None, attribute(1, "stage"));
+        assert_eq!(spans[1].span_context.span_id(), spans[0].parent_span_id);
+
+        assert_eq!(Some("0".to_owned()), attribute(2, "user.id"));
+        assert_eq!(Status::error("\"unknown user\""), spans[2].status);
+        assert_eq!("exception", spans[2].events.events[0].name);
+        assert_eq!(Status::error("\"unknown user\""), spans[3].status);
+    }
+}
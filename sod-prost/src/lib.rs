@@ -0,0 +1,96 @@
+//! SOD: Protobuf
+//!
+//! This crate provides services to encode and decode protobuf messages using [`prost`].
+//!
+//! * [`ProtoEncodeService`] encodes a [`Message`] to bytes.
+//! * [`ProtoDecodeService`] decodes bytes to a [`Message`], returning a [`DecodeError`] for malformed bytes.
+//!
+//! These services may be chained with transport services that send or receive `Vec<u8>`.
+
+use std::{convert::Infallible, marker::PhantomData};
+
+use prost::{DecodeError, Message};
+use sod::Service;
+
+/// A [`Service`] that encodes a [`Message`] to bytes.
+pub struct ProtoEncodeService<T> {
+    _phantom: PhantomData<fn(T)>,
+}
+impl<T: Message> ProtoEncodeService<T> {
+    pub fn new() -> Self {
+        Self {
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<T: Message> Default for ProtoEncodeService<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T: Message> Service for ProtoEncodeService<T> {
+    type Input = T;
+    type Output = Vec<u8>;
+    type Error = Infallible;
+    fn process(&self, input: T) -> Result<Vec<u8>, Infallible> {
+        Ok(input.encode_to_vec())
+    }
+}
+
+/// A [`Service`] that decodes bytes to a [`Message`].
+pub struct ProtoDecodeService<T> {
+    _phantom: PhantomData<fn() -> T>,
+}
+impl<T: Message + Default> ProtoDecodeService<T> {
+    pub fn new() -> Self {
+        Self {
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<T: Message + Default> Default for ProtoDecodeService<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T: Message + Default> Service for ProtoDecodeService<T> {
+    type Input = Vec<u8>;
+    type Output = T;
+    type Error = DecodeError;
+    fn process(&self, input: Vec<u8>) -> Result<T, DecodeError> {
+        T::decode(input.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, PartialEq, Message)]
+    struct Order {
+        #[prost(uint64, tag = "1")]
+        id: u64,
+        #[prost(string, tag = "2")]
+        symbol: String,
+        #[prost(int64, repeated, tag = "3")]
+        fills: Vec<i64>,
+    }
+
+    #[test]
+    fn round_trip() {
+        let order = Order {
+            id: 7,
+            symbol: "SOD".to_owned(),
+            fills: vec![100, -50],
+        };
+        let bytes = ProtoEncodeService::new().process(order.clone()).unwrap();
+        assert_eq!(Ok(order), ProtoDecodeService::<Order>::new().process(bytes));
+    }
+
+    #[test]
+    fn malformed_bytes() {
+        // field 1 as a varint with a truncated continuation byte
+        let result = ProtoDecodeService::<Order>::new().process(vec![0x08, 0x80]);
+        assert!(result.is_err());
+    }
+}
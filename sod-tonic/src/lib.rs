@@ -0,0 +1,205 @@
+//! SOD: Tonic
+//!
+//! This crate provides adapters between [`sod`] services and gRPC unary methods using [`tonic`].
+//!
+//! * [`UnaryCallService`] exposes a tonic client method as a [`sod::AsyncService`], so gRPC calls may be used in a [`sod::ServiceChain`].
+//! * [`UnaryMethod`] exposes a [`sod::AsyncService`] as a [`tonic::server::UnaryService`], so it may be used to implement a gRPC server method.
+
+use std::{future::Future, marker::PhantomData, pin::Pin, sync::Arc};
+
+use sod::{async_trait, AsyncService};
+use tonic::{server::UnaryService, Request, Response, Status};
+
+/// A [`sod::AsyncService`] that performs a gRPC unary call using a tonic client.
+///
+/// Since tonic client methods accept `&mut self`, the client is cloned for each call and given to the `call` function along with the [`Request`],
+/// for example: `|mut client: EchoClient<Channel>, request| async move { client.echo(request).await }`.
+/// Tonic clients are cheap to clone, sharing the underlying channel.
+pub struct UnaryCallService<Req, Resp, C, F> {
+    client: C,
+    call: F,
+    _phantom: PhantomData<fn(Req) -> Resp>,
+}
+impl<Req, Resp, C, F, Fut> UnaryCallService<Req, Resp, C, F>
+where
+    C: Clone,
+    F: Fn(C, Request<Req>) -> Fut,
+    Fut: Future<Output = Result<Response<Resp>, Status>>,
+{
+    pub fn new(client: C, call: F) -> Self {
+        Self {
+            client,
+            call,
+            _phantom: PhantomData,
+        }
+    }
+}
+#[async_trait]
+impl<Req, Resp, C, F, Fut> AsyncService for UnaryCallService<Req, Resp, C, F>
+where
+    Req: Send + 'static,
+    Resp: Send + 'static,
+    C: Clone + Send + Sync,
+    F: Fn(C, Request<Req>) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Response<Resp>, Status>> + Send,
+{
+    type Input = Req;
+    type Output = Resp;
+    type Error = Status;
+    async fn process(&self, input: Req) -> Result<Resp, Status> {
+        let response = (self.call)(self.client.clone(), Request::new(input)).await?;
+        Ok(response.into_inner())
+    }
+}
+
+/// A [`tonic::server::UnaryService`] that encapsulates a [`sod::AsyncService`], to be used as the implementation of a gRPC server method.
+///
+/// The message of each [`Request`] is given to the underlying service, and its output is produced as the [`Response`] message.
+/// The underlying service's [`Status`] errors are returned to the caller.
+pub struct UnaryMethod<S> {
+    service: Arc<S>,
+}
+impl<S> UnaryMethod<S> {
+    pub fn new(service: S) -> Self {
+        Self {
+            service: Arc::new(service),
+        }
+    }
+}
+impl<S> Clone for UnaryMethod<S> {
+    fn clone(&self) -> Self {
+        Self {
+            service: Arc::clone(&self.service),
+        }
+    }
+}
+impl<S: AsyncService<Error = Status> + 'static> UnaryService<S::Input> for UnaryMethod<S> {
+    type Response = S::Output;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<S::Output>, Status>> + Send>>;
+    fn call(&mut self, request: Request<S::Input>) -> Self::Future {
+        let service = Arc::clone(&self.service);
+        Box::pin(async move {
+            service
+                .process(request.into_inner())
+                .await
+                .map(Response::new)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use sod::{FnService, Service};
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::{
+        client::Grpc,
+        codegen::{http, Body, BoxFuture, Context, Poll, StdError},
+        server::NamedService,
+        transport::{Channel, Server},
+    };
+    use tonic_prost::ProstCodec;
+
+    use super::*;
+
+    // equivalent to the code generated for:
+    // service Echo { rpc Echo(EchoMessage) returns (EchoMessage); }
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct EchoMessage {
+        #[prost(string, tag = "1")]
+        message: String,
+    }
+
+    const ECHO_PATH: &str = "/echo.Echo/Echo";
+
+    struct EchoServer<S> {
+        echo: UnaryMethod<S>,
+    }
+    impl<S> Clone for EchoServer<S> {
+        fn clone(&self) -> Self {
+            Self {
+                echo: self.echo.clone(),
+            }
+        }
+    }
+    impl<S> NamedService for EchoServer<S> {
+        const NAME: &'static str = "echo.Echo";
+    }
+    impl<S, B> tonic::codegen::Service<http::Request<B>> for EchoServer<S>
+    where
+        S: AsyncService<Input = EchoMessage, Output = EchoMessage, Error = Status> + 'static,
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::Body>;
+        type Error = Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, request: http::Request<B>) -> Self::Future {
+            let echo = self.echo.clone();
+            Box::pin(async move {
+                Ok(match request.uri().path() {
+                    ECHO_PATH => {
+                        tonic::server::Grpc::new(ProstCodec::default())
+                            .unary(echo, request)
+                            .await
+                    }
+                    _ => Status::unimplemented("unknown method").into_http(),
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn unary_round_trip() {
+        let echo = FnService::new(|request: EchoMessage| match request.message.is_empty() {
+            true => Err(Status::invalid_argument("empty message")),
+            false => Ok(request),
+        })
+        .into_async();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(EchoServer {
+                    echo: UnaryMethod::new(echo),
+                })
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let channel = Channel::from_shared(format!("http://{addr}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let client = UnaryCallService::new(
+            Grpc::new(channel),
+            |mut client: Grpc<Channel>, request| async move {
+                client
+                    .ready()
+                    .await
+                    .map_err(|err| Status::unavailable(err.to_string()))?;
+                client
+                    .unary(
+                        request,
+                        http::uri::PathAndQuery::from_static(ECHO_PATH),
+                        ProstCodec::default(),
+                    )
+                    .await
+            },
+        );
+
+        let request = EchoMessage {
+            message: "hello".to_owned(),
+        };
+        assert_eq!(request.clone(), client.process(request).await.unwrap());
+
+        let status = client.process(EchoMessage::default()).await.unwrap_err();
+        assert_eq!(tonic::Code::InvalidArgument, status.code());
+        assert_eq!("empty message", status.message());
+    }
+}
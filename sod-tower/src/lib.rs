@@ -0,0 +1,136 @@
+//! SOD: Tower
+//!
+//! This crate provides adapters between [`sod`] services and [`tower`] services.
+//!
+//! * [`TowerCompat`] exposes a [`sod::AsyncService`] as a [`tower::Service`], so it may be used in `tower` and `hyper` stacks.
+//! * [`SodCompat`] exposes a [`tower::Service`] as a [`sod::AsyncService`], so it may be used in a [`sod::ServiceChain`].
+//!
+//! ```
+//! use sod::Service;
+//! use sod_tower::TowerCompat;
+//! use tower::ServiceExt;
+//!
+//! let service = TowerCompat::new(sod::IntoService::<u64, u32>::new().into_async());
+//! let output = futures::executor::block_on(service.oneshot(7u32)).unwrap();
+//! assert_eq!(7u64, output);
+//! ```
+
+use std::{
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use sod::{async_trait, AsyncService};
+use tower::ServiceExt;
+
+/// A [`tower::Service`] which encapsulates a [`sod::AsyncService`].
+///
+/// The underlying service is held in an [`Arc`], so the returned futures are `'static` and this adapter is cheaply [`Clone`]able.
+/// [`tower::Service::poll_ready`] is always ready, since a [`sod::AsyncService`] has no notion of readiness.
+pub struct TowerCompat<S> {
+    service: Arc<S>,
+}
+impl<S> TowerCompat<S> {
+    pub fn new(service: S) -> Self {
+        Self {
+            service: Arc::new(service),
+        }
+    }
+}
+impl<S> Clone for TowerCompat<S> {
+    fn clone(&self) -> Self {
+        Self {
+            service: Arc::clone(&self.service),
+        }
+    }
+}
+impl<S: AsyncService + 'static> tower::Service<S::Input> for TowerCompat<S> {
+    type Response = S::Output;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Output, S::Error>> + Send>>;
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+    fn call(&mut self, input: S::Input) -> Self::Future {
+        let service = Arc::clone(&self.service);
+        Box::pin(async move { service.process(input).await })
+    }
+}
+
+/// A [`sod::AsyncService`] which encapsulates a [`tower::Service`].
+///
+/// Since [`sod::AsyncService::process`] accepts `&self`, the underlying [`tower::Service`] is cloned for each call,
+/// which is then driven to readiness and called using [`tower::ServiceExt::oneshot`].
+pub struct SodCompat<T, I> {
+    service: T,
+    _phantom: PhantomData<fn(I)>,
+}
+impl<T, I> SodCompat<T, I> {
+    pub fn new(service: T) -> Self {
+        Self {
+            service,
+            _phantom: PhantomData,
+        }
+    }
+}
+#[async_trait]
+impl<T, I> AsyncService for SodCompat<T, I>
+where
+    T: tower::Service<I> + Clone + Send + Sync,
+    T::Response: Send + 'static,
+    T::Error: Send + 'static,
+    T::Future: Send,
+    I: Send + 'static,
+{
+    type Input = I;
+    type Output = T::Response;
+    type Error = T::Error;
+    async fn process(&self, input: I) -> Result<T::Response, T::Error> {
+        self.service.clone().oneshot(input).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use futures::executor::block_on;
+    use sod::{Service, ServiceChain};
+
+    use super::*;
+
+    struct AddService {
+        n: usize,
+    }
+    impl Service for AddService {
+        type Input = usize;
+        type Output = usize;
+        type Error = Infallible;
+        fn process(&self, input: usize) -> Result<usize, Infallible> {
+            Ok(input + self.n)
+        }
+    }
+
+    #[test]
+    fn tower_compat_oneshot() {
+        let service = TowerCompat::new(AddService { n: 2 }.into_async());
+        assert_eq!(Ok(5), block_on(service.clone().oneshot(3)));
+        assert_eq!(Ok(12), block_on(service.oneshot(10)));
+    }
+
+    #[test]
+    fn sod_compat_process() {
+        let service = SodCompat::new(tower::service_fn(|input: usize| async move {
+            Ok::<_, Infallible>(input * 2)
+        }));
+        assert_eq!(Ok(8), block_on(service.process(4)));
+
+        let chain = ServiceChain::start_async(service)
+            .next(AddService { n: 1 }.into_async())
+            .end();
+        assert_eq!(9, block_on(chain.process(4)).unwrap());
+    }
+}
@@ -0,0 +1,253 @@
+//! Services that acknowledge processed messages back to a source which requires acknowledgement, such as Kafka or SQS.
+
+use std::{
+    error::Error,
+    fmt::{Debug, Display},
+    mem,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    Service,
+};
+
+/// A [`Service`] that processes each message with an underlying service, collecting the ack tokens of successfully processed messages and acknowledging them in batches.
+///
+/// The ack token is extracted from each message using the given function, and a clone of each batch of tokens is given as a `Vec` to the `ack` service.
+/// A batch is flushed when it reaches `batch_size` tokens, or on the first call after `interval` has elapsed since its oldest token was collected.
+/// Pending tokens may also be flushed explicitly using [`AckBatchingService::flush`], such as before shutdown.
+///
+/// # Failures
+/// A message that fails processing is never added to a batch, so the source may redeliver it, and its error is returned as [`AckBatchError::ServiceError`].
+/// A failure of one message does not affect the other messages in a batch.
+/// When the `ack` service fails, the tokens of the failed batch remain pending and are retried by the next flush,
+/// and the output of the message whose call triggered the flush is returned in [`AckBatchError::AckFailed`], along with the ack error.
+pub struct AckBatchingService<S, K, F, A, C = SystemClock> {
+    service: S,
+    token: F,
+    ack: A,
+    batch_size: usize,
+    interval: Duration,
+    pending: Mutex<PendingAcks<K>>,
+    clock: C,
+}
+struct PendingAcks<K> {
+    tokens: Vec<K>,
+    oldest: Option<Instant>,
+}
+impl<S, K, F, A> AckBatchingService<S, K, F, A> {
+    /// Create a new [`AckBatchingService`]
+    ///
+    /// # Arguments
+    /// * `service` - the underlying service, which processes each message
+    /// * `token` - extracts the ack token from each message
+    /// * `ack` - acknowledges a batch of tokens to the source
+    /// * `batch_size` - the number of tokens that triggers a flush
+    /// * `interval` - the longest a token may be pending before the next call triggers a flush
+    ///
+    /// # Panics
+    /// Panics if `batch_size` is zero.
+    pub fn new(service: S, token: F, ack: A, batch_size: usize, interval: Duration) -> Self {
+        Self::with_clock(service, token, ack, batch_size, interval, SystemClock)
+    }
+}
+impl<S, K, F, A, C> AckBatchingService<S, K, F, A, C> {
+    /// Create a new [`AckBatchingService`] which uses the given [`Clock`] to measure the flush interval
+    ///
+    /// # Panics
+    /// Panics if `batch_size` is zero.
+    pub fn with_clock(
+        service: S,
+        token: F,
+        ack: A,
+        batch_size: usize,
+        interval: Duration,
+        clock: C,
+    ) -> Self {
+        assert!(batch_size > 0, "batch_size must be non-zero");
+        Self {
+            service,
+            token,
+            ack,
+            batch_size,
+            interval,
+            pending: Mutex::new(PendingAcks {
+                tokens: Vec::new(),
+                oldest: None,
+            }),
+            clock,
+        }
+    }
+}
+impl<S, K: Clone, F, A: Service<Input = Vec<K>>, C: Clock> AckBatchingService<S, K, F, A, C> {
+    /// Acknowledge all pending tokens, returning the number of tokens acknowledged.
+    ///
+    /// When the `ack` service fails, the tokens remain pending.
+    pub fn flush(&self) -> Result<usize, A::Error> {
+        let mut pending = self.pending.lock().expect("poisoned mutex");
+        self.flush_pending(&mut pending)
+    }
+
+    fn flush_pending(&self, pending: &mut PendingAcks<K>) -> Result<usize, A::Error> {
+        if pending.tokens.is_empty() {
+            return Ok(0);
+        }
+        self.ack.process(pending.tokens.clone())?;
+        pending.oldest = None;
+        Ok(mem::take(&mut pending.tokens).len())
+    }
+
+    fn is_due(&self, pending: &PendingAcks<K>) -> bool {
+        pending.tokens.len() >= self.batch_size
+            || pending
+                .oldest
+                .is_some_and(|oldest| self.clock.now().duration_since(oldest) >= self.interval)
+    }
+}
+impl<S, K, F, A, C> Service for AckBatchingService<S, K, F, A, C>
+where
+    S: Service,
+    K: Clone,
+    F: Fn(&S::Input) -> K,
+    A: Service<Input = Vec<K>>,
+    C: Clock,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = AckBatchError<S::Output, S::Error, A::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let token = (self.token)(&input);
+        let result = self.service.process(input);
+        let mut pending = self.pending.lock().expect("poisoned mutex");
+        if result.is_ok() {
+            pending.tokens.push(token);
+            pending.oldest.get_or_insert_with(|| self.clock.now());
+        }
+        let flushed = match self.is_due(&pending) {
+            true => self.flush_pending(&mut pending).map(|_| ()),
+            false => Ok(()),
+        };
+        match (result, flushed) {
+            (Err(err), _) => Err(AckBatchError::ServiceError(err)),
+            (Ok(output), Err(error)) => Err(AckBatchError::AckFailed { output, error }),
+            (Ok(output), Ok(())) => Ok(output),
+        }
+    }
+}
+
+/// Returned by [`AckBatchingService`], either when a message fails processing or when acknowledging a batch fails.
+#[derive(Clone, PartialEq, Eq)]
+pub enum AckBatchError<O, E, AE> {
+    /// The message failed processing, and its token was not added to a batch
+    ServiceError(E),
+    /// The message was processed, producing the given output, but acknowledging the batch failed, leaving its tokens pending
+    AckFailed { output: O, error: AE },
+}
+impl<O, E: Debug, AE: Debug> Debug for AckBatchError<O, E, AE> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ServiceError(err) => f.debug_tuple("ServiceError").field(err).finish(),
+            Self::AckFailed { error, .. } => {
+                f.debug_struct("AckFailed").field("error", error).finish()
+            }
+        }
+    }
+}
+impl<O, E: Display, AE: Display> Display for AckBatchError<O, E, AE> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ServiceError(err) => write!(f, "{err}"),
+            Self::AckFailed { error, .. } => write!(f, "ack failed: {error}"),
+        }
+    }
+}
+impl<O, E: Error + 'static, AE: Error + 'static> Error for AckBatchError<O, E, AE> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::ServiceError(err) => Some(err),
+            Self::AckFailed { error, .. } => Some(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, FnService};
+
+    #[test]
+    fn ack_batches_only_successful_messages() {
+        let clock = ManualClock::new();
+        let acked = Mutex::new(Vec::new());
+        let ack_fails = Mutex::new(false);
+        let service = AckBatchingService::with_clock(
+            FnService::new(|(token, ok): (u64, bool)| match ok {
+                true => Ok(token * 10),
+                false => Err(token),
+            }),
+            |(token, _): &(u64, bool)| *token,
+            FnService::new(|batch: Vec<u64>| match *ack_fails.lock().unwrap() {
+                true => Err("ack failed"),
+                false => {
+                    acked.lock().unwrap().push(batch);
+                    Ok(())
+                }
+            }),
+            3,
+            Duration::from_secs(1),
+            clock.clone(),
+        );
+
+        // flushed on size, excluding the failed message
+        assert_eq!(Ok(10), service.process((1, true)));
+        assert_eq!(
+            Err(AckBatchError::ServiceError(2)),
+            service.process((2, false))
+        );
+        assert_eq!(Ok(30), service.process((3, true)));
+        assert_eq!(Ok(40), service.process((4, true)));
+        assert_eq!(vec![vec![1, 3, 4]], *acked.lock().unwrap());
+
+        // flushed on interval, even by a call whose message fails
+        assert_eq!(Ok(50), service.process((5, true)));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            Err(AckBatchError::ServiceError(6)),
+            service.process((6, false))
+        );
+        assert_eq!(vec![vec![1, 3, 4], vec![5]], *acked.lock().unwrap());
+
+        // a failed ack leaves the batch pending to be retried
+        *ack_fails.lock().unwrap() = true;
+        assert_eq!(Ok(70), service.process((7, true)));
+        assert_eq!(Ok(80), service.process((8, true)));
+        assert_eq!(
+            Err(AckBatchError::AckFailed {
+                output: 90,
+                error: "ack failed"
+            }),
+            service.process((9, true))
+        );
+        *ack_fails.lock().unwrap() = false;
+        assert_eq!(Ok(3), service.flush());
+        assert_eq!(Ok(0), service.flush());
+        assert_eq!(
+            vec![vec![1, 3, 4], vec![5], vec![7, 8, 9]],
+            *acked.lock().unwrap()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "batch_size must be non-zero")]
+    fn ack_rejects_zero_batch_size() {
+        AckBatchingService::<_, u64, _, _>::new(
+            FnService::new(|token: u64| Ok::<_, ()>(token)),
+            |token: &u64| *token,
+            FnService::new(|_: Vec<u64>| Ok::<_, ()>(())),
+            0,
+            Duration::from_secs(1),
+        );
+    }
+}
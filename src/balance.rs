@@ -0,0 +1,316 @@
+//! Services that distribute inputs across multiple backend services.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash},
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{MutService, Service};
+
+/// A [`Service`] that dispatches each input to one of the underlying backend services, using smooth weighted round-robin.
+///
+/// Each backend receives a share of the inputs proportional to its weight, interleaved as evenly as possible rather than in bursts.
+/// This is the same algorithm used by nginx: every call, each backend's current weight is increased by its configured weight,
+/// the backend with the greatest current weight is selected, and the selected backend's current weight is decreased by the total weight.
+///
+/// Backend selection is guarded by a [`Mutex`], which is released before the selected backend is called,
+/// so this service is `Send` + `Sync` when the backends are, and may be shared using an [`crate::ArcService`].
+pub struct WeightedRoundRobinService<S> {
+    backends: Vec<(S, u32)>,
+    current: Mutex<Vec<i64>>,
+    total: i64,
+}
+impl<S> WeightedRoundRobinService<S> {
+    /// Create a new [`WeightedRoundRobinService`] from the given `(backend, weight)` pairs.
+    ///
+    /// # Panics
+    /// Panics if the sum of all weights is zero.
+    pub fn new(backends: Vec<(S, u32)>) -> Self {
+        let total = backends.iter().map(|(_, w)| *w as i64).sum();
+        assert!(
+            total > 0,
+            "WeightedRoundRobinService requires a positive total weight"
+        );
+        Self {
+            current: Mutex::new(vec![0; backends.len()]),
+            backends,
+            total,
+        }
+    }
+
+    fn select(&self) -> usize {
+        let mut current = self.current.lock().expect("poisoned mutex");
+        let mut selected = 0;
+        for (i, (_, weight)) in self.backends.iter().enumerate() {
+            current[i] += *weight as i64;
+            if current[i] > current[selected] {
+                selected = i;
+            }
+        }
+        current[selected] -= self.total;
+        selected
+    }
+}
+impl<S: Service> Service for WeightedRoundRobinService<S> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.backends[self.select()].0.process(input)
+    }
+}
+
+/// A [`Service`] that dispatches each input to a backend chosen at random, with probability proportional to its weight.
+///
+/// Unlike [`WeightedRoundRobinService`], each selection is independent of previous selections, which suits probabilistic routing such as canaries.
+/// Backends are selected using a small seedable PRNG ([SplitMix64](https://prng.di.unimi.it/splitmix64.c)), which is not cryptographically secure.
+/// The seed is exposed by [`WeightedRandomService::seed`], so a run may be reproduced using [`WeightedRandomService::with_seed`].
+///
+/// The PRNG state is advanced atomically, so this service is `Send` + `Sync` when the backends are.
+pub struct WeightedRandomService<S> {
+    backends: Vec<(S, f64)>,
+    total: f64,
+    seed: u64,
+    state: AtomicU64,
+}
+impl<S> WeightedRandomService<S> {
+    /// Create a new [`WeightedRandomService`] from the given `(backend, weight)` pairs, using a random seed.
+    ///
+    /// # Panics
+    /// Panics if any weight is negative or not finite, or if the sum of all weights is zero.
+    pub fn new(backends: Vec<(S, f64)>) -> Self {
+        Self::with_seed(backends, RandomState::new().hash_one(0u64))
+    }
+
+    /// Create a new [`WeightedRandomService`] from the given `(backend, weight)` pairs, using the given seed.
+    ///
+    /// # Panics
+    /// Panics if any weight is negative or not finite, or if the sum of all weights is zero.
+    pub fn with_seed(backends: Vec<(S, f64)>, seed: u64) -> Self {
+        assert!(
+            backends.iter().all(|(_, w)| w.is_finite() && *w >= 0.0),
+            "WeightedRandomService requires finite, non-negative weights"
+        );
+        let total = backends.iter().map(|(_, w)| *w).sum();
+        assert!(
+            total > 0.0,
+            "WeightedRandomService requires a positive total weight"
+        );
+        Self {
+            backends,
+            total,
+            seed,
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    /// The seed of the PRNG used to select backends
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn select(&self) -> usize {
+        let mut point = self.next_f64() * self.total;
+        for (i, (_, weight)) in self.backends.iter().enumerate() {
+            if point < *weight {
+                return i;
+            }
+            point -= weight;
+        }
+        // rounding may leave the point just past the end, so fall back to the last backend with a positive weight
+        self.backends
+            .iter()
+            .rposition(|(_, weight)| *weight > 0.0)
+            .expect("positive total weight")
+    }
+
+    fn next_f64(&self) -> f64 {
+        splitmix64_f64(&self.state)
+    }
+}
+
+/// Advance the given SplitMix64 state, producing a uniformly distributed value in `[0, 1)`
+pub(crate) fn splitmix64_f64(state: &AtomicU64) -> f64 {
+    let mut z = state
+        .fetch_add(SPLITMIX_GAMMA, Ordering::Relaxed)
+        .wrapping_add(SPLITMIX_GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+const SPLITMIX_GAMMA: u64 = 0x9E3779B97F4A7C15;
+impl<S: Service> Service for WeightedRandomService<S> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.backends[self.select()].0.process(input)
+    }
+}
+
+/// A [`Service`] that shards state across multiple independent [`MutService`] instances, each behind its own [`Mutex`], routing each input by the hash of its key.
+///
+/// The key is extracted from each input using the given function, and the input is dispatched to shard `hash(key) % shards`.
+/// Inputs with equal keys are always dispatched to the same shard, while inputs with unrelated keys are spread across shards,
+/// so concurrent callers only contend when their keys share a shard, unlike a single [`crate::MutexService`].
+pub struct ShardedService<S, K, F> {
+    shards: Vec<Mutex<S>>,
+    key: F,
+    hasher: RandomState,
+    _phantom: PhantomData<fn(K)>,
+}
+impl<S: MutService, K: Hash, F: Fn(&S::Input) -> K> ShardedService<S, K, F> {
+    /// Create a new [`ShardedService`] from the given shard instances.
+    ///
+    /// # Panics
+    /// Panics if no shards are given.
+    pub fn new(shards: Vec<S>, key: F) -> Self {
+        assert!(
+            !shards.is_empty(),
+            "ShardedService requires at least one shard"
+        );
+        Self {
+            shards: shards.into_iter().map(Mutex::new).collect(),
+            key,
+            hasher: RandomState::new(),
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<S: MutService, K: Hash, F: Fn(&S::Input) -> K> Service for ShardedService<S, K, F> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let hash = self.hasher.hash_one((self.key)(&input));
+        let shard = (hash % self.shards.len() as u64) as usize;
+        self.shards[shard]
+            .lock()
+            .expect("poisoned mutex")
+            .process(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        convert::Infallible,
+        sync::{atomic::AtomicUsize, Arc},
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{ArcService, FnService, MutexService};
+
+    fn backend(
+        id: usize,
+    ) -> FnService<(), usize, Infallible, impl Fn(()) -> Result<usize, Infallible>> {
+        FnService::new(move |_| Ok(id))
+    }
+
+    #[test]
+    fn weighted_round_robin_distribution() {
+        let service = ArcService::new(WeightedRoundRobinService::new(vec![
+            (backend(0), 3),
+            (backend(1), 1),
+        ]));
+
+        let first: Vec<usize> = (0..4).map(|_| service.process(()).unwrap()).collect();
+        assert_eq!(vec![0, 0, 1, 0], first);
+
+        let mut counts = [0; 2];
+        for _ in 0..400 {
+            counts[service.process(()).unwrap()] += 1;
+        }
+        assert_eq!([300, 100], counts);
+    }
+
+    #[test]
+    fn weighted_random_approximates_weights() {
+        let service =
+            WeightedRandomService::with_seed(vec![(backend(0), 0.9), (backend(1), 0.1)], 42);
+        assert_eq!(42, service.seed());
+
+        let mut counts = [0; 2];
+        for _ in 0..10_000 {
+            counts[service.process(()).unwrap()] += 1;
+        }
+        assert!((8_800..=9_200).contains(&counts[0]), "{counts:?}");
+
+        // the same seed reproduces the same selections
+        let replay =
+            WeightedRandomService::with_seed(vec![(backend(0), 0.9), (backend(1), 0.1)], 42);
+        let mut replayed = [0; 2];
+        for _ in 0..10_000 {
+            replayed[replay.process(()).unwrap()] += 1;
+        }
+        assert_eq!(counts, replayed);
+    }
+
+    struct CounterStore {
+        counts: HashMap<u64, u64>,
+        active: Arc<AtomicUsize>,
+        max_active: Arc<AtomicUsize>,
+    }
+    impl MutService for CounterStore {
+        type Input = u64;
+        type Output = u64;
+        type Error = Infallible;
+        fn process(&mut self, key: u64) -> Result<u64, Infallible> {
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_active.fetch_max(active, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(1));
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            let count = self.counts.entry(key).or_default();
+            *count += 1;
+            Ok(*count)
+        }
+    }
+
+    /// increment 8 distinct keys per thread from 8 threads, returning the max number of stores active at once
+    fn max_concurrency<S: Service<Input = u64, Output = u64, Error = Infallible> + Sync>(
+        service: S,
+        max_active: &AtomicUsize,
+    ) -> usize {
+        thread::scope(|scope| {
+            for t in 0..8 {
+                let service = &service;
+                scope.spawn(move || {
+                    for round in 1..=5 {
+                        for key in t * 100..t * 100 + 8 {
+                            assert_eq!(Ok(round), service.process(key));
+                        }
+                    }
+                });
+            }
+        });
+        max_active.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn sharded_reduces_contention() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let store = |max_active: &Arc<AtomicUsize>| CounterStore {
+            counts: HashMap::new(),
+            active: Arc::clone(&active),
+            max_active: Arc::clone(max_active),
+        };
+
+        let single_max = Arc::new(AtomicUsize::new(0));
+        let single = MutexService::new(store(&single_max));
+        assert_eq!(1, max_concurrency(single, &single_max));
+
+        let sharded_max = Arc::new(AtomicUsize::new(0));
+        let sharded =
+            ShardedService::new((0..8).map(|_| store(&sharded_max)).collect(), |key| *key);
+        assert!(max_concurrency(sharded, &sharded_max) > 1);
+    }
+}
@@ -0,0 +1,406 @@
+//! Services that stop calling a failing downstream for a while, giving it time to recover, using circuit breakers.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{Debug, Display},
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    async_trait,
+    clock::{Clock, SystemClock},
+    AsyncService, RetryError, Retryable, Service,
+};
+
+/// The state of a circuit breaker
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BreakerState {
+    /// Calls are passed through to the downstream
+    Closed,
+    /// Calls are rejected without calling the downstream
+    Open,
+    /// A single trial call is passed through to decide whether to close or re-open the breaker
+    HalfOpen,
+}
+
+/// A [`Service`] or [`AsyncService`] that maintains an independent circuit breaker for each key, so one failing downstream does not trip the breaker for healthy ones.
+///
+/// The key, such as a host or tenant, is extracted from each input using the given function.
+/// Each key's breaker opens after `failure_threshold` consecutive failures, rejecting calls for that key with [`CircuitBreakerError::Open`] without calling the underlying service.
+/// Once `open_duration` has elapsed, the breaker is half-open, and a single trial call is passed through: a success closes the breaker, while a failure re-opens it.
+/// A trial which does not complete within `open_duration`, such as a cancelled async call, no longer blocks another trial.
+///
+/// A key's breaker that has not been called for at least `idle_ttl` is evicted, unless it is open or has a trial in progress,
+/// so the number of tracked keys stays bounded by the keys called recently.
+/// An evicted breaker is recreated closed, so a key that was half-open when evicted is allowed `failure_threshold` failures again, rather than a single trial.
+///
+/// The state of each key's breaker may be observed using [`KeyedCircuitBreakerService::state`].
+/// This service implements [`Retryable`] for rejected inputs, so it may be encapsulated by a [`crate::RetryService`] to wait for a breaker to close.
+pub struct KeyedCircuitBreakerService<S, K, F, C = SystemClock> {
+    service: S,
+    key: F,
+    failure_threshold: usize,
+    open_duration: Duration,
+    idle_ttl: Duration,
+    breakers: Mutex<KeyedBreakers<K>>,
+    clock: C,
+}
+struct KeyedBreakers<K> {
+    breakers: HashMap<K, KeyedBreaker>,
+    last_purge: Option<Instant>,
+}
+struct KeyedBreaker {
+    breaker: Breaker,
+    last_called: Instant,
+}
+/// The state of a single circuit breaker, which is timed by the caller's [`Clock`]
+#[derive(Default)]
+pub(crate) struct Breaker {
+    failures: usize,
+    opened_at: Option<Instant>,
+    trial_started: Option<Instant>,
+}
+impl Breaker {
+    pub(crate) fn state(&self, now: Instant, open_duration: Duration) -> BreakerState {
+        match self.opened_at {
+            None => BreakerState::Closed,
+            Some(opened_at) if now.duration_since(opened_at) < open_duration => BreakerState::Open,
+            Some(_) => BreakerState::HalfOpen,
+        }
+    }
+
+    /// Returns `true` if the breaker is open or has a trial in progress, so it may not be replaced by a closed breaker
+    fn is_busy(&self, now: Instant, open_duration: Duration) -> bool {
+        let trial = matches!(self.trial_started, Some(started) if now.duration_since(started) < open_duration);
+        trial || self.state(now, open_duration) == BreakerState::Open
+    }
+
+    /// Returns `true` if a call may be passed through, starting a trial when half-open
+    pub(crate) fn admit(&mut self, now: Instant, open_duration: Duration) -> bool {
+        match self.state(now, open_duration) {
+            BreakerState::Closed => true,
+            BreakerState::Open => false,
+            BreakerState::HalfOpen => match self.trial_started {
+                Some(started) if now.duration_since(started) < open_duration => false,
+                _ => {
+                    self.trial_started = Some(now);
+                    true
+                }
+            },
+        }
+    }
+
+    /// Record the outcome of a call which was admitted
+    pub(crate) fn record(&mut self, now: Instant, success: bool, failure_threshold: usize) {
+        let trial = self.trial_started.take().is_some();
+        match success {
+            true => {
+                self.failures = 0;
+                self.opened_at = None;
+            }
+            false => {
+                self.failures += 1;
+                if trial || self.failures >= failure_threshold {
+                    self.opened_at = Some(now);
+                }
+            }
+        }
+    }
+}
+impl<S, K: Clone + Eq + Hash, F> KeyedCircuitBreakerService<S, K, F> {
+    /// Create a new [`KeyedCircuitBreakerService`]
+    ///
+    /// # Arguments
+    /// * `service` - the underlying service
+    /// * `key` - extracts the breaker key from each input
+    /// * `failure_threshold` - the number of consecutive failures that opens a key's breaker
+    /// * `open_duration` - how long a key's breaker stays open before allowing a trial call
+    /// * `idle_ttl` - the duration after which an uncalled key's breaker is evicted, unless it is open or has a trial in progress
+    ///
+    /// # Panics
+    /// Panics if `failure_threshold` is zero.
+    pub fn new(
+        service: S,
+        key: F,
+        failure_threshold: usize,
+        open_duration: Duration,
+        idle_ttl: Duration,
+    ) -> Self {
+        Self::with_clock(
+            service,
+            key,
+            failure_threshold,
+            open_duration,
+            idle_ttl,
+            SystemClock,
+        )
+    }
+}
+impl<S, K: Clone + Eq + Hash, F, C: Clock> KeyedCircuitBreakerService<S, K, F, C> {
+    /// Create a new [`KeyedCircuitBreakerService`] which uses the given [`Clock`] to time open breakers and evict idle ones
+    ///
+    /// # Panics
+    /// Panics if `failure_threshold` is zero.
+    pub fn with_clock(
+        service: S,
+        key: F,
+        failure_threshold: usize,
+        open_duration: Duration,
+        idle_ttl: Duration,
+        clock: C,
+    ) -> Self {
+        assert!(failure_threshold > 0, "failure_threshold must be non-zero");
+        Self {
+            service,
+            key,
+            failure_threshold,
+            open_duration,
+            idle_ttl,
+            breakers: Mutex::new(KeyedBreakers {
+                breakers: HashMap::new(),
+                last_purge: None,
+            }),
+            clock,
+        }
+    }
+
+    /// The current state of the given key's breaker, which is [`BreakerState::Closed`] for a key that has not been called
+    pub fn state(&self, key: &K) -> BreakerState {
+        let now = self.clock.now();
+        match self
+            .breakers
+            .lock()
+            .expect("poisoned mutex")
+            .breakers
+            .get(key)
+        {
+            Some(entry) => entry.breaker.state(now, self.open_duration),
+            None => BreakerState::Closed,
+        }
+    }
+
+    /// The number of keys with a breaker that has not been evicted
+    pub fn tracked_keys(&self) -> usize {
+        self.breakers.lock().expect("poisoned mutex").breakers.len()
+    }
+
+    /// Returns `true` if a call for the key may be passed through
+    fn admit(&self, key: &K) -> bool {
+        let now = self.clock.now();
+        let mut state = self.breakers.lock().expect("poisoned mutex");
+        let last_purge = *state.last_purge.get_or_insert(now);
+        if now.duration_since(last_purge) >= self.idle_ttl {
+            state.breakers.retain(|_, entry| {
+                now.duration_since(entry.last_called) < self.idle_ttl
+                    || entry.breaker.is_busy(now, self.open_duration)
+            });
+            state.last_purge = Some(now);
+        }
+        let entry = state
+            .breakers
+            .entry(key.clone())
+            .or_insert_with(|| KeyedBreaker {
+                breaker: Breaker::default(),
+                last_called: now,
+            });
+        entry.last_called = now;
+        entry.breaker.admit(now, self.open_duration)
+    }
+
+    fn record(&self, key: K, success: bool) {
+        let now = self.clock.now();
+        let mut state = self.breakers.lock().expect("poisoned mutex");
+        let entry = state.breakers.entry(key).or_insert_with(|| KeyedBreaker {
+            breaker: Breaker::default(),
+            last_called: now,
+        });
+        entry.last_called = now;
+        entry.breaker.record(now, success, self.failure_threshold);
+    }
+}
+impl<S, K, F, C> Service for KeyedCircuitBreakerService<S, K, F, C>
+where
+    S: Service,
+    K: Clone + Eq + Hash,
+    F: Fn(&S::Input) -> K,
+    C: Clock,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = CircuitBreakerError<S::Input, S::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let key = (self.key)(&input);
+        if !self.admit(&key) {
+            return Err(CircuitBreakerError::Open(input));
+        }
+        let result = self.service.process(input);
+        self.record(key, result.is_ok());
+        result.map_err(CircuitBreakerError::ServiceError)
+    }
+}
+#[async_trait]
+impl<S, K, F, C> AsyncService for KeyedCircuitBreakerService<S, K, F, C>
+where
+    S: AsyncService,
+    K: Clone + Eq + Hash + Send + Sync,
+    F: Fn(&S::Input) -> K + Send + Sync,
+    C: Clock + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = CircuitBreakerError<S::Input, S::Error>;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let key = (self.key)(&input);
+        if !self.admit(&key) {
+            return Err(CircuitBreakerError::Open(input));
+        }
+        let result = self.service.process(input).await;
+        self.record(key, result.is_ok());
+        result.map_err(CircuitBreakerError::ServiceError)
+    }
+}
+impl<I, E, S, K, F, C> Retryable<I, CircuitBreakerError<I, E>>
+    for KeyedCircuitBreakerService<S, K, F, C>
+{
+    fn parse_retry(
+        &self,
+        err: CircuitBreakerError<I, E>,
+    ) -> Result<I, RetryError<CircuitBreakerError<I, E>>> {
+        match err {
+            CircuitBreakerError::Open(input) => Ok(input),
+            err => Err(RetryError::ServiceError(err)),
+        }
+    }
+}
+
+/// Returned by circuit-breaking services, either when the breaker is open or when the underlying service fails.
+#[derive(Clone, PartialEq, Eq)]
+pub enum CircuitBreakerError<I, E> {
+    Open(I),
+    ServiceError(E),
+}
+impl<I, E: Debug> Debug for CircuitBreakerError<I, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Open(_) => f.write_str("Open"),
+            Self::ServiceError(err) => f.debug_tuple("ServiceError").field(err).finish(),
+        }
+    }
+}
+impl<I, E: Display> Display for CircuitBreakerError<I, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Open(_) => f.write_str("circuit breaker open"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<I, E: Error + 'static> Error for CircuitBreakerError<I, E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Open(_) => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::{clock::ManualClock, FnService};
+
+    #[test]
+    fn keyed_breaker_isolates_failing_key() {
+        let clock = ManualClock::new();
+        let a_down = AtomicBool::new(true);
+        let service = KeyedCircuitBreakerService::with_clock(
+            FnService::new(|host: &'static str| {
+                match host == "a" && a_down.load(Ordering::Relaxed) {
+                    true => Err("down"),
+                    false => Ok(host),
+                }
+            }),
+            |host: &&'static str| *host,
+            2,
+            Duration::from_secs(5),
+            Duration::from_secs(60),
+            clock.clone(),
+        );
+
+        for _ in 0..2 {
+            assert_eq!(
+                Err(CircuitBreakerError::ServiceError("down")),
+                service.process("a")
+            );
+        }
+        assert_eq!(BreakerState::Open, service.state(&"a"));
+        assert_eq!(Err(CircuitBreakerError::Open("a")), service.process("a"));
+
+        // the healthy key is unaffected
+        assert_eq!(Ok("b"), service.process("b"));
+        assert_eq!(BreakerState::Closed, service.state(&"b"));
+
+        // a failed trial re-opens the breaker
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(BreakerState::HalfOpen, service.state(&"a"));
+        assert_eq!(
+            Err(CircuitBreakerError::ServiceError("down")),
+            service.process("a")
+        );
+        assert_eq!(BreakerState::Open, service.state(&"a"));
+
+        // a successful trial closes the breaker
+        a_down.store(false, Ordering::Relaxed);
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(Ok("a"), service.process("a"));
+        assert_eq!(BreakerState::Closed, service.state(&"a"));
+    }
+
+    #[test]
+    fn keyed_breaker_evicts_idle_breakers() {
+        let clock = ManualClock::new();
+        let service = KeyedCircuitBreakerService::with_clock(
+            FnService::new(|host: &'static str| match host {
+                "down" => Err("down"),
+                host => Ok(host),
+            }),
+            |host: &&'static str| *host,
+            1,
+            Duration::from_secs(30),
+            Duration::from_secs(10),
+            clock.clone(),
+        );
+        assert_eq!(Ok("a"), service.process("a"));
+        assert_eq!(Ok("b"), service.process("b"));
+        assert!(service.process("down").is_err());
+        assert_eq!(3, service.tracked_keys());
+
+        // idle closed breakers are evicted, while the open breaker is kept
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(Ok("b"), service.process("b"));
+        assert_eq!(2, service.tracked_keys());
+        assert_eq!(BreakerState::Open, service.state(&"down"));
+
+        // once the open breaker is half-open and idle, it is evicted too
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(Ok("b"), service.process("b"));
+        assert_eq!(1, service.tracked_keys());
+    }
+
+    #[test]
+    #[should_panic(expected = "failure_threshold must be non-zero")]
+    fn keyed_breaker_rejects_zero_failure_threshold() {
+        KeyedCircuitBreakerService::<_, &'static str, _>::new(
+            FnService::new(|host: &'static str| Ok::<_, ()>(host)),
+            |host: &&'static str| *host,
+            0,
+            Duration::from_secs(5),
+            Duration::from_secs(60),
+        );
+    }
+}
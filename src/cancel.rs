@@ -0,0 +1,220 @@
+//! Services that abandon in-flight calls when a shared [`CancellationToken`] is cancelled.
+//!
+//! Unlike a [`crate::deadline::Deadline`], cancellation is triggered explicitly, such as when the client of a request disconnects.
+//! The same token may be given to downstream services, which may poll [`CancellationToken::is_cancelled`] to stop their own work early.
+
+use std::{
+    error::Error,
+    fmt::Display,
+    future::{poll_fn, Future},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use crate::{async_trait, AsyncService};
+
+/// A token which may be cancelled once, shared by cloning.
+///
+/// Cancelling the token wakes every task awaiting [`CancellationToken::cancelled`], including every in-flight [`CancellableService`] call.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<TokenState>,
+}
+#[derive(Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel this token and every clone of it, waking all tasks awaiting cancellation
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut *self.inner.wakers.lock().expect("poisoned mutex"));
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// Returns `true` once this token has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Wait until this token has been cancelled
+    pub fn cancelled(&self) -> impl Future<Output = ()> + Send + '_ {
+        poll_fn(|cx| match self.poll_cancelled(cx) {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        })
+    }
+
+    /// Returns `true` if cancelled, otherwise registers the task to be woken on cancellation
+    fn poll_cancelled(&self, cx: &mut Context<'_>) -> bool {
+        if self.is_cancelled() {
+            return true;
+        }
+        let mut wakers = self.inner.wakers.lock().expect("poisoned mutex");
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        // cancellation may have raced with registration, in which case the waker may have been missed
+        self.is_cancelled()
+    }
+}
+
+/// An [`AsyncService`] that races each call to an underlying [`AsyncService`] against a [`CancellationToken`],
+/// returning [`CancellableError::Cancelled`] as soon as the token is cancelled.
+///
+/// When the token is cancelled first, the underlying call's future is dropped, abandoning its work at its current `.await` point.
+/// When the token is already cancelled, the underlying service is not called.
+pub struct CancellableService<S> {
+    service: S,
+    token: CancellationToken,
+}
+impl<S> CancellableService<S> {
+    pub fn new(service: S, token: CancellationToken) -> Self {
+        Self { service, token }
+    }
+
+    /// The token which cancels calls to this service, which may be cloned and given to downstream services
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+#[async_trait]
+impl<S: AsyncService> AsyncService for CancellableService<S> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = CancellableError<S::Error>;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        if self.token.is_cancelled() {
+            return Err(CancellableError::Cancelled);
+        }
+        let mut call = self.service.process(input);
+        poll_fn(|cx| {
+            // cancellation wins over a call which would complete in the same poll
+            if self.token.is_cancelled() {
+                return Poll::Ready(Err(CancellableError::Cancelled));
+            }
+            if let Poll::Ready(result) = Pin::as_mut(&mut call).poll(cx) {
+                return Poll::Ready(result.map_err(CancellableError::ServiceError));
+            }
+            match self.token.poll_cancelled(cx) {
+                true => Poll::Ready(Err(CancellableError::Cancelled)),
+                false => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+/// Returned by [`CancellableService`], either when the call was cancelled or when the underlying service fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CancellableError<E> {
+    Cancelled,
+    ServiceError(E),
+}
+impl<E: Display> Display for CancellableError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cancelled => f.write_str("cancelled"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<E: Error + 'static> Error for CancellableError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Cancelled => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, sync::atomic::AtomicUsize, thread, time::Duration};
+
+    use futures::executor::block_on;
+
+    use super::*;
+
+    /// Waits for the token to be cancelled, counting how far it got and whether its work was abandoned
+    struct SlowService {
+        token: CancellationToken,
+        started: AtomicUsize,
+        finished: AtomicUsize,
+        abandoned: Arc<AtomicUsize>,
+    }
+    struct Abandoned(Arc<AtomicUsize>);
+    impl Drop for Abandoned {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    #[async_trait]
+    impl AsyncService for SlowService {
+        type Input = u32;
+        type Output = u32;
+        type Error = Infallible;
+        async fn process(&self, input: u32) -> Result<u32, Infallible> {
+            if input == 0 {
+                return Ok(0);
+            }
+            self.started.fetch_add(1, Ordering::SeqCst);
+            let guard = Abandoned(Arc::clone(&self.abandoned));
+            // downstream work observes the same token, but never completes on its own
+            poll_fn(|_| match self.token.is_cancelled() {
+                true => Poll::Ready(()),
+                false => Poll::<()>::Pending,
+            })
+            .await;
+            std::mem::forget(guard);
+            self.finished.fetch_add(1, Ordering::SeqCst);
+            Ok(input)
+        }
+    }
+
+    #[test]
+    fn cancellation_abandons_inner_call() {
+        let token = CancellationToken::new();
+        let service = CancellableService::new(
+            SlowService {
+                token: token.clone(),
+                started: AtomicUsize::new(0),
+                finished: AtomicUsize::new(0),
+                abandoned: Arc::new(AtomicUsize::new(0)),
+            },
+            token.clone(),
+        );
+        assert_eq!(Ok(0), block_on(service.process(0)));
+
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            token.cancel();
+        });
+        assert_eq!(
+            Err(CancellableError::Cancelled),
+            block_on(service.process(7))
+        );
+        canceller.join().unwrap();
+
+        assert!(service.token().is_cancelled());
+        assert_eq!(1, service.service.started.load(Ordering::SeqCst));
+        assert_eq!(0, service.service.finished.load(Ordering::SeqCst));
+        assert_eq!(1, service.service.abandoned.load(Ordering::SeqCst));
+
+        // an already cancelled token rejects calls without calling the underlying service
+        assert_eq!(
+            Err(CancellableError::Cancelled),
+            block_on(service.process(8))
+        );
+        assert_eq!(1, service.service.started.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,87 @@
+//! Clocks that may optionally be used by time-aware services.
+//!
+//! Services that measure, pace, or window by time accept a [`Clock`], defaulting to [`SystemClock`].
+//! A [`ManualClock`] may be used in place of the [`SystemClock`] to make time deterministic, which is useful for tests and simulations.
+
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A source of time, able to report the current [`Instant`] and to sleep the calling thread.
+pub trait Clock {
+    /// The current [`Instant`], according to this clock
+    fn now(&self) -> Instant;
+
+    /// Block the calling thread for the given duration, according to this clock
+    fn sleep(&self, duration: Duration);
+}
+impl<C: Clock> Clock for &C {
+    fn now(&self) -> Instant {
+        (*self).now()
+    }
+    fn sleep(&self, duration: Duration) {
+        (*self).sleep(duration)
+    }
+}
+impl<C: Clock> Clock for Arc<C> {
+    fn now(&self) -> Instant {
+        self.as_ref().now()
+    }
+    fn sleep(&self, duration: Duration) {
+        self.as_ref().sleep(duration)
+    }
+}
+
+/// A [`Clock`] that calls [`Instant::now`] and [`std::thread::sleep`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// A [`Clock`] that only moves forward when advanced.
+///
+/// Calling [`Clock::sleep`] advances the clock by the given duration and returns immediately.
+/// Clones of a [`ManualClock`] share the same time, so a clone may be given to a service while the original is advanced by a test.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+impl ManualClock {
+    /// Create a new [`ManualClock`], starting at [`Instant::now`]
+    pub fn new() -> Self {
+        Self::starting_at(Instant::now())
+    }
+
+    /// Create a new [`ManualClock`], starting at the given [`Instant`]
+    pub fn starting_at(now: Instant) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Move the clock forward by the given duration
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().expect("poisoned mutex") += duration;
+    }
+}
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().expect("poisoned mutex")
+    }
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration)
+    }
+}
@@ -0,0 +1,195 @@
+//! Services that coalesce concurrent duplicate requests into a single call to an underlying service.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Condvar, Mutex},
+};
+
+use crate::Service;
+
+/// A [`Service`] that coalesces concurrent calls with equal inputs into a single call to the underlying [`Service`], like Go's `singleflight`.
+///
+/// The first caller for an input calls the underlying service, while callers that arrive with an equal input before it completes block until it does.
+/// Every caller then receives a clone of the same result, including when it is an `Err`.
+/// Once a call completes, the input is forgotten, so a later call with an equal input calls the underlying service again.
+///
+/// When the underlying service panics, the panic propagates to the first caller only,
+/// and the blocked callers start over, so one of them calls the underlying service again.
+pub struct SingleFlightService<S: Service> {
+    service: S,
+    in_flight: Mutex<HashMap<S::Input, SharedFlight<S>>>,
+}
+type SharedFlight<S> = Arc<Flight<<S as Service>::Output, <S as Service>::Error>>;
+struct Flight<O, E> {
+    state: Mutex<FlightState<O, E>>,
+    done: Condvar,
+}
+enum FlightState<O, E> {
+    Pending,
+    Completed(Result<O, E>),
+    Abandoned,
+}
+impl<S: Service> SingleFlightService<S> {
+    pub fn new(service: S) -> Self {
+        Self {
+            service,
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+}
+impl<S: Service> Service for SingleFlightService<S>
+where
+    S::Input: Clone + Eq + Hash,
+    S::Output: Clone,
+    S::Error: Clone,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        loop {
+            let (flight, leader) = {
+                let mut in_flight = self.in_flight.lock().expect("poisoned mutex");
+                match in_flight.get(&input) {
+                    Some(flight) => (Arc::clone(flight), false),
+                    None => {
+                        let flight = Arc::new(Flight {
+                            state: Mutex::new(FlightState::Pending),
+                            done: Condvar::new(),
+                        });
+                        in_flight.insert(input.clone(), Arc::clone(&flight));
+                        (flight, true)
+                    }
+                }
+            };
+            if leader {
+                let mut lead = Lead {
+                    service: self,
+                    input: &input,
+                    flight: &flight,
+                    result: None,
+                };
+                let result = self.service.process(input.clone());
+                lead.result = Some(result.clone());
+                return result;
+            }
+            let state = flight.state.lock().expect("poisoned mutex");
+            let state = flight
+                .done
+                .wait_while(state, |state| matches!(state, FlightState::Pending))
+                .expect("poisoned mutex");
+            if let FlightState::Completed(result) = &*state {
+                return result.clone();
+            }
+        }
+    }
+}
+
+/// The leader's call for a flight, which publishes its result when dropped, or abandons the flight when the call panicked
+struct Lead<'a, S: Service>
+where
+    S::Input: Eq + Hash,
+{
+    service: &'a SingleFlightService<S>,
+    input: &'a S::Input,
+    flight: &'a SharedFlight<S>,
+    result: Option<Result<S::Output, S::Error>>,
+}
+impl<'a, S: Service> Drop for Lead<'a, S>
+where
+    S::Input: Eq + Hash,
+{
+    fn drop(&mut self) {
+        self.service
+            .in_flight
+            .lock()
+            .expect("poisoned mutex")
+            .remove(self.input);
+        *self.flight.state.lock().expect("poisoned mutex") = match self.result.take() {
+            Some(result) => FlightState::Completed(result),
+            None => FlightState::Abandoned,
+        };
+        self.flight.done.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Barrier,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::FnService;
+
+    #[test]
+    fn single_flight_executes_once() {
+        let calls = AtomicUsize::new(0);
+        let barrier = Barrier::new(8);
+        let service = SingleFlightService::new(FnService::new(|key: &'static str| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(200));
+            Ok::<_, Infallible>(key.len())
+        }));
+
+        let outputs: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        service.process("key").unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(vec![3; 8], outputs);
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+
+        service.process("key").unwrap();
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn single_flight_recovers_from_panicking_leader() {
+        let calls = AtomicUsize::new(0);
+        let barrier = Barrier::new(4);
+        let service = SingleFlightService::new(FnService::new(|key: &'static str| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(200));
+            match call {
+                0 => panic!("leader panicked"),
+                _ => Ok::<_, Infallible>(key.len()),
+            }
+        }));
+
+        let results: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        service.process("key").unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().ok()).collect()
+        });
+
+        // the panicking leader's followers start over, and share a single new call
+        assert_eq!(1, results.iter().filter(|r| r.is_none()).count());
+        assert_eq!(
+            vec![3; 3],
+            results.into_iter().flatten().collect::<Vec<_>>()
+        );
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+        assert!(service.in_flight.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,286 @@
+//! Services whose configuration may be reloaded while they run, without a restart, optionally merged from multiple layers.
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::{async_trait, AsyncService, Service};
+
+/// A shared, atomically swappable configuration.
+///
+/// Clones of a [`ConfigHandle`] share the same configuration, so a clone may be kept to [`ConfigHandle::reload`] the configuration
+/// after its [`ConfigurableService`] has been moved into a chain or another thread.
+/// Readers hold an [`Arc`] to the configuration that was current when they read it, so a reload never changes a configuration that is in use.
+#[derive(Debug)]
+pub struct ConfigHandle<C> {
+    current: Arc<RwLock<Arc<C>>>,
+}
+impl<C> ConfigHandle<C> {
+    pub fn new(config: C) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(config))),
+        }
+    }
+
+    /// Get the current configuration
+    pub fn current(&self) -> Arc<C> {
+        Arc::clone(&self.current.read().expect("poisoned lock"))
+    }
+
+    /// Atomically replace the current configuration, returning the previous configuration
+    pub fn reload(&self, config: C) -> Arc<C> {
+        std::mem::replace(
+            &mut *self.current.write().expect("poisoned lock"),
+            Arc::new(config),
+        )
+    }
+}
+impl<C> Clone for ConfigHandle<C> {
+    fn clone(&self) -> Self {
+        Self {
+            current: Arc::clone(&self.current),
+        }
+    }
+}
+
+/// An input paired with the configuration that was current when it was received, produced by [`ConfigurableService`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Configured<C, T> {
+    pub config: Arc<C>,
+    pub value: T,
+}
+
+/// A [`Service`] or [`AsyncService`] that passes the current configuration to the underlying service along with each input, as a [`Configured`] input.
+///
+/// The configuration is read once at the start of each call, so a call observes a single configuration from start to finish,
+/// and calls that start after a [`ConfigurableService::reload`] observe the new configuration.
+/// This allows a chain to pick up new settings, such as a changed rate limit, without being rebuilt.
+pub struct ConfigurableService<S, C> {
+    service: S,
+    config: ConfigHandle<C>,
+}
+impl<S, C> ConfigurableService<S, C> {
+    /// Create a new [`ConfigurableService`] with the given initial configuration
+    pub fn new(service: S, config: C) -> Self {
+        Self::with_handle(service, ConfigHandle::new(config))
+    }
+
+    /// Create a new [`ConfigurableService`] sharing the configuration of the given [`ConfigHandle`]
+    pub fn with_handle(service: S, config: ConfigHandle<C>) -> Self {
+        Self { service, config }
+    }
+
+    /// Get a [`ConfigHandle`] that shares this service's configuration
+    pub fn handle(&self) -> ConfigHandle<C> {
+        self.config.clone()
+    }
+
+    /// Atomically replace the current configuration, returning the previous configuration
+    pub fn reload(&self, config: C) -> Arc<C> {
+        self.config.reload(config)
+    }
+}
+impl<I, C, S: Service<Input = Configured<C, I>>> Service for ConfigurableService<S, C> {
+    type Input = I;
+    type Output = S::Output;
+    type Error = S::Error;
+    fn process(&self, input: I) -> Result<Self::Output, Self::Error> {
+        self.service.process(Configured {
+            config: self.config.current(),
+            value: input,
+        })
+    }
+}
+#[async_trait]
+impl<I, C, S> AsyncService for ConfigurableService<S, C>
+where
+    I: Send + 'static,
+    C: Send + Sync + 'static,
+    S: AsyncService<Input = Configured<C, I>>,
+{
+    type Input = I;
+    type Output = S::Output;
+    type Error = S::Error;
+    async fn process(&self, input: I) -> Result<Self::Output, Self::Error> {
+        let configured = Configured {
+            config: self.config.current(),
+            value: input,
+        };
+        self.service.process(configured).await
+    }
+}
+
+/// A configuration which may be composed of layers, such as defaults, a file, environment variables, and overrides, used by [`LayeredConfigService`].
+///
+/// A typical implementation holds each setting as an [`Option`], where a setting that is `Some` in a higher-precedence layer replaces the setting of a lower layer.
+pub trait Merge {
+    /// Override the settings of this configuration with the settings given by a higher-precedence layer
+    fn merge(&mut self, higher: &Self);
+}
+
+/// A [`Service`] or [`AsyncService`] that merges multiple configuration layers into one effective configuration, passing it to the underlying service along with each input like a [`ConfigurableService`].
+///
+/// Layers are given in order of increasing precedence, so the last layer wins, and are merged using their [`Merge`] impl.
+/// Reloading a single layer using [`LayeredConfigService::reload_layer`] re-merges all layers and atomically replaces the effective configuration,
+/// which is shared with every [`ConfigHandle`] obtained from [`LayeredConfigService::handle`].
+pub struct LayeredConfigService<S, C> {
+    service: ConfigurableService<S, C>,
+    layers: Mutex<Vec<C>>,
+}
+impl<S, C: Clone + Merge> LayeredConfigService<S, C> {
+    /// Create a new [`LayeredConfigService`] from the given layers, ordered from lowest to highest precedence.
+    ///
+    /// # Panics
+    /// Panics if no layers are given.
+    pub fn new(service: S, layers: Vec<C>) -> Self {
+        let effective = Self::merge(&layers);
+        Self {
+            service: ConfigurableService::new(service, effective),
+            layers: Mutex::new(layers),
+        }
+    }
+
+    fn merge(layers: &[C]) -> C {
+        let (lowest, higher) = layers
+            .split_first()
+            .expect("LayeredConfigService requires at least one layer");
+        let mut effective = lowest.clone();
+        for layer in higher {
+            effective.merge(layer);
+        }
+        effective
+    }
+
+    /// Get a [`ConfigHandle`] that shares the effective configuration
+    pub fn handle(&self) -> ConfigHandle<C> {
+        self.service.handle()
+    }
+
+    /// Replace the layer at the given index, re-merging the effective configuration, and returning the previous layer.
+    ///
+    /// # Panics
+    /// Panics if there is no layer at the given index.
+    pub fn reload_layer(&self, index: usize, layer: C) -> C {
+        let mut layers = self.layers.lock().expect("poisoned mutex");
+        let previous = std::mem::replace(&mut layers[index], layer);
+        // reload while holding the lock, so concurrent reloads of different layers are never lost
+        self.service.reload(Self::merge(&layers));
+        previous
+    }
+}
+impl<I, C, S: Service<Input = Configured<C, I>>> Service for LayeredConfigService<S, C> {
+    type Input = I;
+    type Output = S::Output;
+    type Error = S::Error;
+    fn process(&self, input: I) -> Result<Self::Output, Self::Error> {
+        self.service.process(input)
+    }
+}
+#[async_trait]
+impl<I, C, S> AsyncService for LayeredConfigService<S, C>
+where
+    I: Send + 'static,
+    C: Send + Sync + 'static,
+    S: AsyncService<Input = Configured<C, I>>,
+{
+    type Input = I;
+    type Output = S::Output;
+    type Error = S::Error;
+    async fn process(&self, input: I) -> Result<Self::Output, Self::Error> {
+        self.service.process(input).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+    use crate::FnService;
+
+    struct RateConfig {
+        limit: u32,
+    }
+
+    #[test]
+    fn configurable_observes_reload() {
+        let service = ConfigurableService::new(
+            FnService::new(|input: Configured<RateConfig, u32>| {
+                Ok::<_, Infallible>(input.value.min(input.config.limit))
+            }),
+            RateConfig { limit: 10 },
+        );
+        let handle = service.handle();
+
+        assert_eq!(Ok(5), service.process(5));
+        assert_eq!(Ok(10), service.process(50));
+
+        let previous = handle.reload(RateConfig { limit: 100 });
+        assert_eq!(10, previous.limit);
+        assert_eq!(100, service.handle().current().limit);
+
+        assert_eq!(Ok(50), service.process(50));
+        assert_eq!(Ok(100), service.process(500));
+    }
+
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    struct AppConfig {
+        host: Option<&'static str>,
+        port: Option<u16>,
+        debug: Option<bool>,
+    }
+    impl Merge for AppConfig {
+        fn merge(&mut self, higher: &Self) {
+            self.host = higher.host.or(self.host);
+            self.port = higher.port.or(self.port);
+            self.debug = higher.debug.or(self.debug);
+        }
+    }
+
+    #[test]
+    fn layered_config_precedence_and_reload() {
+        let defaults = AppConfig {
+            host: Some("localhost"),
+            port: Some(80),
+            debug: Some(false),
+        };
+        let file = AppConfig {
+            port: Some(8080),
+            ..Default::default()
+        };
+        let env = AppConfig {
+            debug: Some(true),
+            ..Default::default()
+        };
+        let service = LayeredConfigService::new(
+            FnService::new(|input: Configured<AppConfig, ()>| {
+                Ok::<_, Infallible>((*input.config).clone())
+            }),
+            vec![defaults, file, env],
+        );
+        assert_eq!(
+            Ok(AppConfig {
+                host: Some("localhost"),
+                port: Some(8080),
+                debug: Some(true),
+            }),
+            service.process(())
+        );
+
+        let previous = service.reload_layer(
+            1,
+            AppConfig {
+                host: Some("example.com"),
+                port: Some(9090),
+                debug: Some(false),
+            },
+        );
+        assert_eq!(Some(8080), previous.port);
+        // the env layer still overrides the reloaded file layer
+        let expected = AppConfig {
+            host: Some("example.com"),
+            port: Some(9090),
+            debug: Some(true),
+        };
+        assert_eq!(Ok(expected.clone()), service.process(()));
+        assert_eq!(expected, *service.handle().current());
+    }
+}
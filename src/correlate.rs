@@ -0,0 +1,286 @@
+//! Services that correlate requests and responses over a duplex, fire-and-forget transport.
+//!
+//! A [`CorrelatingService`] attaches a unique id to each outbound request, sends it using an underlying transport service, and awaits the response with the matching id.
+//! Inbound responses are delivered by a [`CorrelationResponder`], obtained from [`CorrelatingService::responder`], which is typically called by a loop receiving from the inbound side of the transport.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    error::Error,
+    fmt::Display,
+    future::poll_fn,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    async_trait,
+    clock::{Clock, SystemClock},
+    AsyncService, Service,
+};
+
+/// A message tagged with the correlation id that pairs a request with its response.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Correlated<T> {
+    pub id: u64,
+    pub message: T,
+}
+
+/// An [`AsyncService`] that turns a fire-and-forget transport into request/reply.
+///
+/// Each request is wrapped in a [`Correlated`] with a unique id and passed to the underlying transport [`AsyncService`].
+/// The call then waits until a response with the same id is delivered to the [`CorrelationResponder`], producing the response message.
+/// Responses may arrive in any order.
+///
+/// A request whose response has not arrived within the timeout fails with [`CorrelationError::TimedOut`].
+/// Since this service does not depend on a timer, timeouts are detected whenever the responder processes a response or [`CorrelationResponder::expire`] is called,
+/// so a receive loop that may idle should call [`CorrelationResponder::expire`] periodically.
+pub struct CorrelatingService<Req, Resp, S, C = SystemClock> {
+    transport: S,
+    pending: Arc<Pending<Resp, C>>,
+    next_id: AtomicU64,
+    timeout: Duration,
+    _phantom: PhantomData<fn(Req)>,
+}
+struct Pending<Resp, C> {
+    slots: Mutex<HashMap<u64, Slot<Resp>>>,
+    clock: C,
+}
+struct Slot<Resp> {
+    deadline: Instant,
+    result: Option<Option<Resp>>,
+    waker: Option<Waker>,
+}
+impl<Req, Resp, S> CorrelatingService<Req, Resp, S> {
+    pub fn new(transport: S, timeout: Duration) -> Self {
+        Self::with_clock(transport, timeout, SystemClock)
+    }
+}
+impl<Req, Resp, S, C> CorrelatingService<Req, Resp, S, C> {
+    /// Create a new [`CorrelatingService`] which uses the given [`Clock`] for timeouts
+    pub fn with_clock(transport: S, timeout: Duration, clock: C) -> Self {
+        Self {
+            transport,
+            pending: Arc::new(Pending {
+                slots: Mutex::new(HashMap::new()),
+                clock,
+            }),
+            next_id: AtomicU64::new(0),
+            timeout,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Obtain a [`CorrelationResponder`], which delivers inbound responses to the awaiting requests
+    pub fn responder(&self) -> CorrelationResponder<Resp, C> {
+        CorrelationResponder {
+            pending: Arc::clone(&self.pending),
+        }
+    }
+}
+#[async_trait]
+impl<Req, Resp, S, C> AsyncService for CorrelatingService<Req, Resp, S, C>
+where
+    Req: Send + 'static,
+    Resp: Send + 'static,
+    S: AsyncService<Input = Correlated<Req>>,
+    C: Clock + Send + Sync,
+{
+    type Input = Req;
+    type Output = Resp;
+    type Error = CorrelationError<S::Error>;
+    async fn process(&self, input: Req) -> Result<Resp, Self::Error> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let deadline = self.pending.clock.now() + self.timeout;
+        self.pending.slots.lock().expect("poisoned mutex").insert(
+            id,
+            Slot {
+                deadline,
+                result: None,
+                waker: None,
+            },
+        );
+        let awaiting = Awaiting {
+            pending: &self.pending,
+            id,
+        };
+        self.transport
+            .process(Correlated { id, message: input })
+            .await
+            .map_err(CorrelationError::ServiceError)?;
+        poll_fn(|cx| {
+            let mut slots = awaiting.pending.slots.lock().expect("poisoned mutex");
+            let slot = slots.get_mut(&id).expect("pending slot");
+            match slot.result.take() {
+                Some(Some(response)) => Poll::Ready(Ok(response)),
+                Some(None) => Poll::Ready(Err(CorrelationError::TimedOut)),
+                None => {
+                    slot.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+
+/// Removes the pending slot when dropped, including when a call is cancelled
+struct Awaiting<'a, Resp, C> {
+    pending: &'a Pending<Resp, C>,
+    id: u64,
+}
+impl<Resp, C> Drop for Awaiting<'_, Resp, C> {
+    fn drop(&mut self) {
+        if let Ok(mut slots) = self.pending.slots.lock() {
+            slots.remove(&self.id);
+        }
+    }
+}
+
+/// A [`Service`] that delivers each inbound response to the [`CorrelatingService`] request with the matching id.
+///
+/// Produces `true` when the response was delivered, or `false` when no request is awaiting the id, such as when the request already timed out.
+/// Each call also expires any requests that have timed out.
+pub struct CorrelationResponder<Resp, C = SystemClock> {
+    pending: Arc<Pending<Resp, C>>,
+}
+impl<Resp, C> Clone for CorrelationResponder<Resp, C> {
+    fn clone(&self) -> Self {
+        Self {
+            pending: Arc::clone(&self.pending),
+        }
+    }
+}
+impl<Resp, C: Clock> CorrelationResponder<Resp, C> {
+    /// Fail every awaiting request whose timeout has elapsed with [`CorrelationError::TimedOut`]
+    pub fn expire(&self) {
+        let mut slots = self.pending.slots.lock().expect("poisoned mutex");
+        Self::expire_slots(&mut slots, self.pending.clock.now());
+    }
+
+    fn expire_slots(slots: &mut HashMap<u64, Slot<Resp>>, now: Instant) {
+        for slot in slots.values_mut() {
+            if slot.result.is_none() && now >= slot.deadline {
+                slot.result = Some(None);
+                if let Some(waker) = slot.waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+}
+impl<Resp, C: Clock> Service for CorrelationResponder<Resp, C> {
+    type Input = Correlated<Resp>;
+    type Output = bool;
+    type Error = Infallible;
+    fn process(&self, input: Correlated<Resp>) -> Result<bool, Infallible> {
+        let mut slots = self.pending.slots.lock().expect("poisoned mutex");
+        Self::expire_slots(&mut slots, self.pending.clock.now());
+        match slots.get_mut(&input.id) {
+            Some(slot) if slot.result.is_none() => {
+                slot.result = Some(Some(input.message));
+                if let Some(waker) = slot.waker.take() {
+                    waker.wake();
+                }
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// Returned by [`CorrelatingService`], either when the response did not arrive in time or when the transport fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CorrelationError<E> {
+    TimedOut,
+    ServiceError(E),
+}
+impl<E: Display> Display for CorrelationError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TimedOut => f.write_str("timed out awaiting response"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<E: Error + 'static> Error for CorrelationError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::TimedOut => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{executor::block_on, join};
+
+    use super::*;
+    use crate::{clock::ManualClock, FnService};
+
+    fn transport(
+        sent: &Arc<Mutex<Vec<Correlated<u32>>>>,
+    ) -> impl AsyncService<Input = Correlated<u32>, Output = (), Error = Infallible> {
+        let sent = Arc::clone(sent);
+        FnService::new(move |request| {
+            sent.lock().unwrap().push(request);
+            Ok(())
+        })
+        .into_async()
+    }
+
+    #[test]
+    fn correlates_out_of_order_responses() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let service = CorrelatingService::new(transport(&sent), Duration::from_secs(10));
+        let responder = service.responder();
+
+        let (first, second, _) = block_on(async {
+            join!(service.process(1), service.process(2), async {
+                let requests = sent.lock().unwrap().clone();
+                for request in requests.into_iter().rev() {
+                    let response = Correlated {
+                        id: request.id,
+                        message: format!("reply {}", request.message),
+                    };
+                    assert!(responder.process(response).unwrap());
+                }
+            })
+        });
+        assert_eq!(Ok("reply 1".to_owned()), first);
+        assert_eq!(Ok("reply 2".to_owned()), second);
+    }
+
+    #[test]
+    fn times_out_missing_response() {
+        let clock = ManualClock::new();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let service = CorrelatingService::<_, String, _, _>::with_clock(
+            transport(&sent),
+            Duration::from_secs(10),
+            clock.clone(),
+        );
+        let responder = service.responder();
+
+        let (result, _) = block_on(async {
+            join!(service.process(1), async {
+                responder.expire();
+                clock.advance(Duration::from_secs(10));
+                responder.expire();
+            })
+        });
+        assert_eq!(Err(CorrelationError::TimedOut), result);
+
+        let late = Correlated {
+            id: sent.lock().unwrap()[0].id,
+            message: "late".to_owned(),
+        };
+        assert!(!responder.process(late).unwrap());
+    }
+}
@@ -0,0 +1,195 @@
+//! Services that attach a [`Deadline`] to each input and honor it at every stage of a chain.
+//!
+//! An [`AttachDeadlineService`] wraps each input in a [`WithDeadline`], setting its deadline once.
+//! Each subsequent stage is wrapped in a [`WithDeadlineService`], which short-circuits with [`DeadlineError::DeadlineExceeded`] when the deadline has passed before the stage runs,
+//! and otherwise carries the deadline forward to the stage's output.
+//! Timeout-aware stages may accept a [`WithDeadline`] directly and consult [`Deadline::remaining`] to bound their own work.
+
+use std::{
+    convert::Infallible,
+    error::Error,
+    fmt::Display,
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    Service,
+};
+
+/// An [`Instant`] by which processing must complete.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline {
+    at: Instant,
+}
+impl Deadline {
+    /// Create a new [`Deadline`] at the given [`Instant`]
+    pub fn at(at: Instant) -> Self {
+        Self { at }
+    }
+
+    /// Create a new [`Deadline`] the given duration after the current time of the given [`Clock`]
+    pub fn after<C: Clock>(timeout: Duration, clock: &C) -> Self {
+        Self::at(clock.now() + timeout)
+    }
+
+    /// The [`Instant`] of this deadline
+    pub fn instant(&self) -> Instant {
+        self.at
+    }
+
+    /// The remaining budget according to the given [`Clock`], which is zero once the deadline has passed
+    pub fn remaining<C: Clock>(&self, clock: &C) -> Duration {
+        self.at.saturating_duration_since(clock.now())
+    }
+
+    /// Returns `true` if the deadline has passed according to the given [`Clock`]
+    pub fn is_exceeded<C: Clock>(&self, clock: &C) -> bool {
+        clock.now() >= self.at
+    }
+}
+
+/// A value accompanied by the [`Deadline`] by which it must be processed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WithDeadline<T> {
+    pub value: T,
+    pub deadline: Deadline,
+}
+impl<T> WithDeadline<T> {
+    pub fn new(value: T, deadline: Deadline) -> Self {
+        Self { value, deadline }
+    }
+}
+
+/// A [`Service`] that attaches a [`Deadline`] the given duration in the future to each input, producing a [`WithDeadline`].
+pub struct AttachDeadlineService<T, C = SystemClock> {
+    timeout: Duration,
+    clock: C,
+    _phantom: PhantomData<fn(T)>,
+}
+impl<T> AttachDeadlineService<T> {
+    pub fn new(timeout: Duration) -> Self {
+        Self::with_clock(timeout, SystemClock)
+    }
+}
+impl<T, C: Clock> AttachDeadlineService<T, C> {
+    /// Create a new [`AttachDeadlineService`] which uses the given [`Clock`] to set deadlines
+    pub fn with_clock(timeout: Duration, clock: C) -> Self {
+        Self {
+            timeout,
+            clock,
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<T, C: Clock> Service for AttachDeadlineService<T, C> {
+    type Input = T;
+    type Output = WithDeadline<T>;
+    type Error = Infallible;
+    fn process(&self, input: T) -> Result<Self::Output, Self::Error> {
+        Ok(WithDeadline::new(
+            input,
+            Deadline::after(self.timeout, &self.clock),
+        ))
+    }
+}
+
+/// A [`Service`] that runs an underlying [`Service`] only if the input's [`Deadline`] has not passed, carrying the deadline forward to the output.
+///
+/// When the deadline has passed before the underlying service runs, [`DeadlineError::DeadlineExceeded`] is returned and the underlying service is not called.
+/// A deadline that passes while the underlying service runs does not interrupt it, and is detected by the next stage.
+pub struct WithDeadlineService<S, C = SystemClock> {
+    service: S,
+    clock: C,
+}
+impl<S: Service> WithDeadlineService<S> {
+    pub fn new(service: S) -> Self {
+        Self::with_clock(service, SystemClock)
+    }
+}
+impl<S: Service, C: Clock> WithDeadlineService<S, C> {
+    /// Create a new [`WithDeadlineService`] which uses the given [`Clock`] to check deadlines
+    pub fn with_clock(service: S, clock: C) -> Self {
+        Self { service, clock }
+    }
+}
+impl<S: Service, C: Clock> Service for WithDeadlineService<S, C> {
+    type Input = WithDeadline<S::Input>;
+    type Output = WithDeadline<S::Output>;
+    type Error = DeadlineError<S::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let WithDeadline { value, deadline } = input;
+        if deadline.is_exceeded(&self.clock) {
+            return Err(DeadlineError::DeadlineExceeded);
+        }
+        match self.service.process(value) {
+            Ok(output) => Ok(WithDeadline::new(output, deadline)),
+            Err(err) => Err(DeadlineError::ServiceError(err)),
+        }
+    }
+}
+
+/// Returned by [`WithDeadlineService`], either when the deadline passed before the underlying service ran or when the underlying service fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeadlineError<E> {
+    DeadlineExceeded,
+    ServiceError(E),
+}
+impl<E: Display> Display for DeadlineError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DeadlineExceeded => f.write_str("deadline exceeded"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<E: Error + 'static> Error for DeadlineError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::DeadlineExceeded => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::{clock::ManualClock, FnService};
+
+    #[test]
+    fn later_stage_short_circuits() {
+        let clock = ManualClock::new();
+        let attach = AttachDeadlineService::with_clock(Duration::from_millis(100), clock.clone());
+        let stage = |cost: u64| {
+            let stage_clock = clock.clone();
+            WithDeadlineService::with_clock(
+                FnService::new(move |input: u32| {
+                    stage_clock.sleep(Duration::from_millis(cost));
+                    Ok::<_, ()>(input + 1)
+                }),
+                clock.clone(),
+            )
+        };
+        let third_ran = AtomicBool::new(false);
+        let third = WithDeadlineService::with_clock(
+            FnService::new(|input: u32| {
+                third_ran.store(true, Ordering::Relaxed);
+                Ok::<_, ()>(input)
+            }),
+            clock.clone(),
+        );
+
+        let input = attach.process(1).unwrap();
+        let output = stage(80).process(input).unwrap();
+        assert_eq!(2, output.value);
+        assert_eq!(Duration::from_millis(20), output.deadline.remaining(&clock));
+        let output = stage(30).process(output).unwrap();
+        assert_eq!(Duration::ZERO, output.deadline.remaining(&clock));
+        assert_eq!(Err(DeadlineError::DeadlineExceeded), third.process(output));
+        assert!(!third_ran.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,503 @@
+//! Services that skip inputs which have already been processed, identified by key or by content.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    hash::Hash,
+    marker::PhantomData,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    Service,
+};
+
+/// A pluggable store of idempotency keys, used by [`IdempotencyService`].
+///
+/// Implementations backed by an external store allow idempotency to be enforced across multiple processes.
+pub trait IdempotencyStore<K> {
+    /// Atomically record the key for the given ttl, returning `true` if the key was absent or expired, or `false` if the key is already recorded.
+    fn put_if_absent(&self, key: &K, ttl: Duration) -> bool;
+
+    /// Remove the key, allowing it to be recorded again
+    fn remove(&self, key: &K);
+}
+
+/// An in-memory [`IdempotencyStore`], which expires keys using the given [`Clock`].
+///
+/// Expired keys are purged lazily as new keys are recorded.
+pub struct InMemoryIdempotencyStore<K, C = SystemClock> {
+    state: Mutex<InMemoryState<K>>,
+    clock: C,
+}
+struct InMemoryState<K> {
+    expiries: HashMap<K, Instant>,
+    purge_at: usize,
+}
+const MIN_PURGE_AT: usize = 64;
+impl<K> InMemoryIdempotencyStore<K> {
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+}
+impl<K> Default for InMemoryIdempotencyStore<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K, C> InMemoryIdempotencyStore<K, C> {
+    pub fn with_clock(clock: C) -> Self {
+        Self {
+            state: Mutex::new(InMemoryState {
+                expiries: HashMap::new(),
+                purge_at: MIN_PURGE_AT,
+            }),
+            clock,
+        }
+    }
+}
+impl<K: Clone + Eq + Hash, C: Clock> IdempotencyStore<K> for InMemoryIdempotencyStore<K, C> {
+    fn put_if_absent(&self, key: &K, ttl: Duration) -> bool {
+        let now = self.clock.now();
+        let mut state = self.state.lock().expect("poisoned mutex");
+        if let Some(expiry) = state.expiries.get(key) {
+            if *expiry > now {
+                return false;
+            }
+        }
+        if state.expiries.len() >= state.purge_at {
+            state.expiries.retain(|_, expiry| *expiry > now);
+            state.purge_at = MIN_PURGE_AT.max(state.expiries.len() * 2);
+        }
+        state.expiries.insert(key.clone(), now + ttl);
+        true
+    }
+    fn remove(&self, key: &K) {
+        self.state
+            .lock()
+            .expect("poisoned mutex")
+            .expiries
+            .remove(key);
+    }
+}
+
+/// A [`Service`] that uses an [`IdempotencyStore`] to process each idempotency key at most once within a ttl.
+///
+/// The key is extracted from each input using the given function.
+/// When the key is newly recorded in the store, the input is passed to the underlying [`Service`] and `Some(Output)` is produced.
+/// When the key was already recorded, the underlying [`Service`] is not called and `None` is produced.
+/// When the underlying [`Service`] returns an `Err`, the key is removed from the store so the input may be retried.
+///
+/// Concurrent first-time calls with the same key are resolved by the store's atomic `put_if_absent`:
+/// exactly one caller delegates to the underlying service, and all others immediately produce `None` without waiting for it to finish.
+pub struct IdempotencyService<S, K, F, St>
+where
+    F: Fn(&S::Input) -> K,
+    S: Service,
+{
+    service: S,
+    key: F,
+    store: St,
+    ttl: Duration,
+    _phantom: PhantomData<fn(K)>,
+}
+impl<S, K, F, St> IdempotencyService<S, K, F, St>
+where
+    F: Fn(&S::Input) -> K,
+    S: Service,
+{
+    pub fn new(service: S, key: F, store: St, ttl: Duration) -> Self {
+        Self {
+            service,
+            key,
+            store,
+            ttl,
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<S, K, F, St> Service for IdempotencyService<S, K, F, St>
+where
+    S: Service,
+    F: Fn(&S::Input) -> K,
+    St: IdempotencyStore<K>,
+{
+    type Input = S::Input;
+    type Output = Option<S::Output>;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let key = (self.key)(&input);
+        if !self.store.put_if_absent(&key, self.ttl) {
+            return Ok(None);
+        }
+        match self.service.process(input) {
+            Ok(output) => Ok(Some(output)),
+            Err(err) => {
+                self.store.remove(&key);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A pluggable store of processed message ids, used by [`ExactlyOnceService`].
+///
+/// Implementations backed by durable storage allow processed ids to survive restarts.
+pub trait ProcessedStore<K> {
+    /// Returns `true` if the id was already committed as processed
+    fn is_processed(&self, id: &K) -> bool;
+
+    /// Commit the id as processed
+    fn commit(&self, id: K);
+}
+
+/// An in-memory [`ProcessedStore`], which retains all committed ids for the life of the store.
+pub struct InMemoryProcessedStore<K> {
+    processed: Mutex<HashSet<K>>,
+}
+impl<K> InMemoryProcessedStore<K> {
+    pub fn new() -> Self {
+        Self {
+            processed: Mutex::new(HashSet::new()),
+        }
+    }
+}
+impl<K> Default for InMemoryProcessedStore<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K: Eq + Hash> ProcessedStore<K> for InMemoryProcessedStore<K> {
+    fn is_processed(&self, id: &K) -> bool {
+        self.processed.lock().expect("poisoned mutex").contains(id)
+    }
+    fn commit(&self, id: K) {
+        self.processed.lock().expect("poisoned mutex").insert(id);
+    }
+}
+
+/// A [`Service`] that uses a [`ProcessedStore`] to skip redelivered messages from an at-least-once source.
+///
+/// The message id is extracted from each input using the given function.
+/// When the id was already committed, the underlying [`Service`] is not called and `None` is produced.
+/// Otherwise, the input is passed to the underlying [`Service`], and `Some(Output)` is produced.
+///
+/// # Ordering Guarantees
+/// The id is committed only after the underlying [`Service`] returns `Ok`, and an `Err` never commits the id.
+/// This ordering guarantees that a crash can never drop a message: any message that was not committed will be processed when it is redelivered.
+/// A crash between processing and committing will cause the message to be processed again when it is redelivered,
+/// so for strict exactly-once effects the underlying service's effect and the store's commit must be made atomic, such as by writing both in one transaction.
+///
+/// Messages with the same id are expected to be delivered by a single consumer, as with a partitioned source.
+/// Concurrent deliveries of the same id are not coordinated, and may both be processed.
+pub struct ExactlyOnceService<S, K, F, St>
+where
+    F: Fn(&S::Input) -> K,
+    S: Service,
+{
+    service: S,
+    id: F,
+    store: St,
+    _phantom: PhantomData<fn(K)>,
+}
+impl<S, K, F, St> ExactlyOnceService<S, K, F, St>
+where
+    F: Fn(&S::Input) -> K,
+    S: Service,
+{
+    pub fn new(service: S, id: F, store: St) -> Self {
+        Self {
+            service,
+            id,
+            store,
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<S, K, F, St> Service for ExactlyOnceService<S, K, F, St>
+where
+    S: Service,
+    F: Fn(&S::Input) -> K,
+    St: ProcessedStore<K>,
+{
+    type Input = S::Input;
+    type Output = Option<S::Output>;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let id = (self.id)(&input);
+        if self.store.is_processed(&id) {
+            return Ok(None);
+        }
+        let output = self.service.process(input)?;
+        self.store.commit(id);
+        Ok(Some(output))
+    }
+}
+
+/// A [`Service`] that skips payloads whose content has already been processed, deduplicating by content rather than by key.
+///
+/// Each payload is hashed using the given digest function, such as `|bytes: &[u8]| *blake3::hash(bytes).as_bytes()`.
+/// The digest should be a cryptographic hash, since a collision causes a distinct payload to be skipped.
+/// When the digest is newly recorded, the payload is passed to the underlying [`Service`] and `Some(Output)` is produced.
+/// When the digest was already recorded, the underlying [`Service`] is not called and `None` is produced.
+/// When the underlying [`Service`] returns an `Err`, the digest is forgotten so the payload may be retried.
+///
+/// By default every digest is retained for the life of the service.
+/// Use [`ContentDedupService::with_capacity`] to bound the number of retained digests, evicting the least recently seen digest when full.
+pub struct ContentDedupService<S, D, F> {
+    service: S,
+    digest: F,
+    capacity: Option<usize>,
+    seen: Mutex<SeenDigests<D>>,
+}
+struct SeenDigests<D> {
+    last_seen: HashMap<D, u64>,
+    by_age: BTreeMap<u64, D>,
+    tick: u64,
+}
+impl<S, D, F> ContentDedupService<S, D, F>
+where
+    S: Service,
+    S::Input: AsRef<[u8]>,
+    F: Fn(&[u8]) -> D,
+{
+    /// Create a new [`ContentDedupService`], which retains every digest
+    pub fn new(service: S, digest: F) -> Self {
+        Self::create(service, digest, None)
+    }
+
+    /// Create a new [`ContentDedupService`], which retains up to `capacity` of the most recently seen digests
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    pub fn with_capacity(service: S, digest: F, capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be non-zero");
+        Self::create(service, digest, Some(capacity))
+    }
+
+    fn create(service: S, digest: F, capacity: Option<usize>) -> Self {
+        Self {
+            service,
+            digest,
+            capacity,
+            seen: Mutex::new(SeenDigests {
+                last_seen: HashMap::new(),
+                by_age: BTreeMap::new(),
+                tick: 0,
+            }),
+        }
+    }
+}
+impl<D: Clone + Eq + Hash> SeenDigests<D> {
+    /// Record the digest as most recently seen, returning `true` if it was not already recorded
+    fn record(&mut self, digest: &D, capacity: Option<usize>) -> bool {
+        self.tick += 1;
+        let previous = self.last_seen.insert(digest.clone(), self.tick);
+        if let Some(previous) = previous {
+            self.by_age.remove(&previous);
+        }
+        self.by_age.insert(self.tick, digest.clone());
+        if let Some(capacity) = capacity {
+            while self.last_seen.len() > capacity {
+                if let Some((_, oldest)) = self.by_age.pop_first() {
+                    self.last_seen.remove(&oldest);
+                }
+            }
+        }
+        previous.is_none()
+    }
+
+    fn forget(&mut self, digest: &D) {
+        if let Some(tick) = self.last_seen.remove(digest) {
+            self.by_age.remove(&tick);
+        }
+    }
+}
+impl<S, D, F> Service for ContentDedupService<S, D, F>
+where
+    S: Service,
+    S::Input: AsRef<[u8]>,
+    D: Clone + Eq + Hash,
+    F: Fn(&[u8]) -> D,
+{
+    type Input = S::Input;
+    type Output = Option<S::Output>;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let digest = (self.digest)(input.as_ref());
+        if !self
+            .seen
+            .lock()
+            .expect("poisoned mutex")
+            .record(&digest, self.capacity)
+        {
+            return Ok(None);
+        }
+        match self.service.process(input) {
+            Ok(output) => Ok(Some(output)),
+            Err(err) => {
+                self.seen.lock().expect("poisoned mutex").forget(&digest);
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    use super::*;
+    use crate::{clock::ManualClock, FnService};
+
+    #[derive(Debug, PartialEq)]
+    struct Request {
+        id: u64,
+        value: &'static str,
+    }
+
+    fn request(id: u64, value: &'static str) -> Request {
+        Request { id, value }
+    }
+
+    #[test]
+    fn idempotency_first_seen_and_duplicate() {
+        let clock = ManualClock::new();
+        let service = IdempotencyService::new(
+            FnService::new(|req: Request| match req.value {
+                "bad" => Err(()),
+                value => Ok(value),
+            }),
+            |req: &Request| req.id,
+            InMemoryIdempotencyStore::with_clock(clock.clone()),
+            Duration::from_secs(60),
+        );
+
+        assert_eq!(Ok(Some("a")), service.process(request(1, "a")));
+        assert_eq!(Ok(None), service.process(request(1, "a")));
+        assert_eq!(Ok(Some("b")), service.process(request(2, "b")));
+
+        // failures release the key so it may be retried
+        assert_eq!(Err(()), service.process(request(3, "bad")));
+        assert_eq!(Ok(Some("c")), service.process(request(3, "c")));
+
+        // keys expire after the ttl
+        clock.advance(Duration::from_secs(61));
+        assert_eq!(Ok(Some("a")), service.process(request(1, "a")));
+    }
+
+    #[test]
+    fn idempotency_concurrent_first_calls() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = Arc::new(IdempotencyService::new(
+            {
+                let calls = Arc::clone(&calls);
+                FnService::new(move |_: u64| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    Ok::<_, ()>(())
+                })
+            },
+            |id: &u64| *id,
+            InMemoryIdempotencyStore::new(),
+            Duration::from_secs(60),
+        ));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let service = Arc::clone(&service);
+                thread::spawn(move || service.process(7).unwrap())
+            })
+            .collect();
+        let processed = handles
+            .into_iter()
+            .filter_map(|h| h.join().unwrap())
+            .count();
+        assert_eq!(1, processed);
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn exactly_once_skips_redelivery() {
+        let calls = Mutex::new(HashMap::new());
+        let service = ExactlyOnceService::new(
+            FnService::new(|(id, fail): (u64, bool)| {
+                *calls.lock().unwrap().entry(id).or_insert(0) += 1;
+                match fail {
+                    true => Err(id),
+                    false => Ok(id),
+                }
+            }),
+            |(id, _): &(u64, bool)| *id,
+            InMemoryProcessedStore::new(),
+        );
+
+        assert_eq!(Ok(Some(1)), service.process((1, false)));
+        assert_eq!(Ok(Some(2)), service.process((2, false)));
+        assert_eq!(Ok(None), service.process((1, false)));
+        assert_eq!(Err(3), service.process((3, true)));
+        assert_eq!(Ok(Some(3)), service.process((3, false)));
+        assert_eq!(Ok(None), service.process((2, false)));
+        assert_eq!(Ok(None), service.process((3, false)));
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(1, calls[&1]);
+        assert_eq!(1, calls[&2]);
+        // the failed attempt is not committed, so the redelivery is processed
+        assert_eq!(2, calls[&3]);
+    }
+
+    /// A stand-in for a cryptographic digest, which is not a dependency of this crate
+    fn digest(bytes: &[u8]) -> Vec<u8> {
+        bytes.to_vec()
+    }
+
+    #[test]
+    fn content_dedup_skips_identical_payloads() {
+        let calls = AtomicUsize::new(0);
+        let service = ContentDedupService::new(
+            FnService::new(|blob: Vec<u8>| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                Ok::<_, ()>(blob.len())
+            }),
+            digest,
+        );
+
+        assert_eq!(Ok(Some(5)), service.process(b"hello".to_vec()));
+        assert_eq!(Ok(None), service.process(b"hello".to_vec()));
+        assert_eq!(Ok(Some(5)), service.process(b"world".to_vec()));
+        assert_eq!(2, calls.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn content_dedup_evicts_least_recently_seen() {
+        let service = ContentDedupService::with_capacity(
+            FnService::new(|blob: &'static [u8]| Ok::<_, ()>(blob)),
+            digest,
+            2,
+        );
+
+        assert_eq!(Ok(Some(&b"a"[..])), service.process(b"a"));
+        assert_eq!(Ok(Some(&b"b"[..])), service.process(b"b"));
+        // seeing "a" again makes "b" the least recently seen, so it is evicted by "c"
+        assert_eq!(Ok(None), service.process(b"a"));
+        assert_eq!(Ok(Some(&b"c"[..])), service.process(b"c"));
+        assert_eq!(Ok(None), service.process(b"a"));
+        assert_eq!(Ok(Some(&b"b"[..])), service.process(b"b"));
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be non-zero")]
+    fn content_dedup_rejects_zero_capacity() {
+        ContentDedupService::with_capacity(
+            FnService::new(|blob: &'static [u8]| Ok::<_, ()>(blob)),
+            digest,
+            0,
+        );
+    }
+}
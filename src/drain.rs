@@ -0,0 +1,359 @@
+//! Services that gracefully drain a queue consumer, or the in-flight calls to a service, on shutdown.
+
+use std::{
+    error::Error,
+    fmt::Display,
+    future::{poll_fn, Future},
+    marker::PhantomData,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use crate::{async_trait, AsyncService, MutService, Service};
+
+/// A shared flag used to signal a [`DrainOnStopService`] or [`InFlightDrainService`] to stop.
+///
+/// Clones of a [`StopToken`] share the same flag, so a clone may be given to the service while the original is stopped by a shutdown hook.
+#[derive(Clone, Debug, Default)]
+pub struct StopToken {
+    stopped: Arc<AtomicBool>,
+}
+impl StopToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Signal all holders of this token to stop
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Release)
+    }
+
+    /// Returns `true` once [`StopToken::stop`] has been called
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+    }
+}
+
+/// A [`MutService`] that consumes a queue, draining the items remaining in the queue once a [`StopToken`] is stopped.
+///
+/// Each call polls one item from the `source`, `MutService<Input = (), Output = Option<T>>`, and passes it to the underlying [`MutService`], producing `Some(Output)`,
+/// or produces `None` when the source is empty.
+///
+/// Once stopped, each call continues to process one remaining item from the source, until the source produces `None`,
+/// at which point [`DrainError::Stopped`] is returned, which should cause the consuming loop to exit, such as by returning `Err` from the error handler of [`crate::thread::spawn_loop_mut`].
+/// When created using [`DrainOnStopService::with_limit`], at most `limit` items are processed after stopping,
+/// and every item remaining after that is handed to the `undrained` function, such as to re-enqueue or persist it, before [`DrainError::Stopped`] is returned.
+pub struct DrainOnStopService<T, P, S, F> {
+    source: P,
+    service: S,
+    stop: StopToken,
+    limit: Option<usize>,
+    drained: usize,
+    undrained: F,
+    _phantom: PhantomData<fn(T)>,
+}
+impl<T, P, S> DrainOnStopService<T, P, S, fn(T)>
+where
+    P: MutService<Input = (), Output = Option<T>>,
+    S: MutService<Input = T, Error = P::Error>,
+{
+    /// Create a new [`DrainOnStopService`] which processes every remaining item once stopped
+    pub fn new(source: P, service: S, stop: StopToken) -> Self {
+        Self {
+            source,
+            service,
+            stop,
+            limit: None,
+            drained: 0,
+            undrained: |_| {},
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<T, P, S, F> DrainOnStopService<T, P, S, F>
+where
+    P: MutService<Input = (), Output = Option<T>>,
+    S: MutService<Input = T, Error = P::Error>,
+    F: FnMut(T),
+{
+    /// Create a new [`DrainOnStopService`] which processes at most `limit` remaining items once stopped, handing the rest to the `undrained` function
+    pub fn with_limit(source: P, service: S, stop: StopToken, limit: usize, undrained: F) -> Self {
+        Self {
+            source,
+            service,
+            stop,
+            limit: Some(limit),
+            drained: 0,
+            undrained,
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<T, P, S, F> MutService for DrainOnStopService<T, P, S, F>
+where
+    P: MutService<Input = (), Output = Option<T>>,
+    S: MutService<Input = T, Error = P::Error>,
+    F: FnMut(T),
+{
+    type Input = ();
+    type Output = Option<S::Output>;
+    type Error = DrainError<P::Error>;
+    fn process(&mut self, _: ()) -> Result<Self::Output, Self::Error> {
+        let stopped = self.stop.is_stopped();
+        if stopped && self.limit.is_some_and(|limit| self.drained >= limit) {
+            while let Some(item) = self.source.process(()).map_err(DrainError::ServiceError)? {
+                (self.undrained)(item);
+            }
+            return Err(DrainError::Stopped);
+        }
+        let item = match self.source.process(()).map_err(DrainError::ServiceError)? {
+            Some(item) => item,
+            None if stopped => return Err(DrainError::Stopped),
+            None => return Ok(None),
+        };
+        if stopped {
+            self.drained += 1;
+        }
+        match self.service.process(item) {
+            Ok(output) => Ok(Some(output)),
+            Err(err) => Err(DrainError::ServiceError(err)),
+        }
+    }
+}
+
+/// An [`AsyncService`] or [`Service`] that counts the calls in flight to an underlying service, rejecting new calls once a [`StopToken`] is stopped
+/// while letting in-flight calls finish.
+///
+/// Once stopped, each new call returns [`DrainError::Stopped`] without calling the underlying service,
+/// which a server may translate into a "service unavailable" response.
+/// Use [`InFlightDrainService::await_drained`] after stopping to wait for every in-flight call to finish, such as before exiting.
+/// A call is no longer counted once it returns, panics, or, for an [`AsyncService`], once its future is dropped.
+pub struct InFlightDrainService<S> {
+    service: S,
+    stop: StopToken,
+    in_flight: AtomicUsize,
+    wakers: Mutex<Vec<Waker>>,
+}
+impl<S> InFlightDrainService<S> {
+    pub fn new(service: S, stop: StopToken) -> Self {
+        Self {
+            service,
+            stop,
+            in_flight: AtomicUsize::new(0),
+            wakers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The number of calls currently in flight
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Wait until no calls are in flight, which is final once the [`StopToken`] has been stopped
+    pub fn await_drained(&self) -> impl Future<Output = ()> + Send + '_
+    where
+        S: Sync,
+    {
+        poll_fn(|cx| match self.poll_drained(cx) {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        })
+    }
+
+    /// Returns `true` if no calls are in flight, otherwise registers the task to be woken when the last call finishes
+    fn poll_drained(&self, cx: &mut Context<'_>) -> bool {
+        // pairs with the fence in `enter`, so a new call either sees the stop or is counted here
+        fence(Ordering::SeqCst);
+        if self.in_flight() == 0 {
+            return true;
+        }
+        let mut wakers = self.wakers.lock().expect("poisoned mutex");
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        // the last call may have finished before registration, in which case the wake may have been missed
+        self.in_flight() == 0
+    }
+
+    /// Count a new call as in flight, or return `None` once stopped
+    fn enter(&self) -> Option<InFlight<'_, S>> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let call = InFlight { service: self };
+        fence(Ordering::SeqCst);
+        match self.stop.is_stopped() {
+            true => None,
+            false => Some(call),
+        }
+    }
+}
+/// A call counted as in flight until dropped, waking tasks awaiting the drain when it is the last call
+struct InFlight<'a, S> {
+    service: &'a InFlightDrainService<S>,
+}
+impl<'a, S> Drop for InFlight<'a, S> {
+    fn drop(&mut self) {
+        if self.service.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            let wakers = std::mem::take(&mut *self.service.wakers.lock().expect("poisoned mutex"));
+            wakers.into_iter().for_each(Waker::wake);
+        }
+    }
+}
+impl<S: Service> Service for InFlightDrainService<S> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = DrainError<S::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let _call = self.enter().ok_or(DrainError::Stopped)?;
+        self.service
+            .process(input)
+            .map_err(DrainError::ServiceError)
+    }
+}
+#[async_trait]
+impl<S: AsyncService> AsyncService for InFlightDrainService<S> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = DrainError<S::Error>;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let _call = self.enter().ok_or(DrainError::Stopped)?;
+        self.service
+            .process(input)
+            .await
+            .map_err(DrainError::ServiceError)
+    }
+}
+
+/// Returned by [`DrainOnStopService`] or [`InFlightDrainService`], either once stopped, or when the source or underlying service fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DrainError<E> {
+    Stopped,
+    ServiceError(E),
+}
+impl<E: Display> Display for DrainError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Stopped => f.write_str("stopped"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<E: Error + 'static> Error for DrainError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Stopped => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, convert::Infallible, sync::mpsc, time::Duration};
+
+    use futures::{executor::block_on, future::join};
+
+    use super::*;
+    use crate::{testing::Delay, thread::spawn_loop_mut};
+
+    struct QueueService(Arc<Mutex<VecDeque<u32>>>);
+    impl MutService for QueueService {
+        type Input = ();
+        type Output = Option<u32>;
+        type Error = Infallible;
+        fn process(&mut self, _: ()) -> Result<Option<u32>, Infallible> {
+            Ok(self.0.lock().unwrap().pop_front())
+        }
+    }
+
+    struct Record(mpsc::Sender<u32>);
+    impl MutService for Record {
+        type Input = u32;
+        type Output = ();
+        type Error = Infallible;
+        fn process(&mut self, input: u32) -> Result<(), Infallible> {
+            self.0.send(input).unwrap();
+            Ok(())
+        }
+    }
+
+    fn stop_on_drained(err: DrainError<Infallible>) -> Result<(), DrainError<Infallible>> {
+        match err {
+            DrainError::Stopped => Err(err),
+            DrainError::ServiceError(_) => Ok(()),
+        }
+    }
+
+    #[test]
+    fn drains_buffered_items_before_exit() {
+        let queue = Arc::new(Mutex::new((0..10).collect::<VecDeque<_>>()));
+        let (sender, processed) = mpsc::channel();
+        let stop = StopToken::new();
+        stop.stop();
+
+        let service = DrainOnStopService::new(QueueService(queue), Record(sender), stop);
+        spawn_loop_mut(service, stop_on_drained).join().unwrap();
+        assert_eq!(
+            (0..10).collect::<Vec<_>>(),
+            processed.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn hands_undrained_items_to_callback() {
+        let queue = Arc::new(Mutex::new((0..10).collect::<VecDeque<_>>()));
+        let (sender, processed) = mpsc::channel();
+        let (requeue, undrained) = mpsc::channel();
+        let stop = StopToken::new();
+        stop.stop();
+
+        let service = DrainOnStopService::with_limit(
+            QueueService(queue),
+            Record(sender),
+            stop,
+            3,
+            move |item| requeue.send(item).unwrap(),
+        );
+        spawn_loop_mut(service, stop_on_drained).join().unwrap();
+        assert_eq!(vec![0, 1, 2], processed.iter().collect::<Vec<_>>());
+        assert_eq!(
+            (3..10).collect::<Vec<_>>(),
+            undrained.iter().collect::<Vec<_>>()
+        );
+    }
+
+    struct SlowService;
+    #[async_trait]
+    impl AsyncService for SlowService {
+        type Input = u32;
+        type Output = u32;
+        type Error = Infallible;
+        async fn process(&self, input: u32) -> Result<u32, Infallible> {
+            Delay::new(Duration::from_millis(50)).await;
+            Ok(input)
+        }
+    }
+
+    #[test]
+    fn in_flight_calls_finish_while_new_calls_are_rejected() {
+        let stop = StopToken::new();
+        let service = InFlightDrainService::new(SlowService, stop.clone());
+        block_on(async {
+            let in_flight = service.process(1);
+            let stop_mid_call = async {
+                Delay::new(Duration::from_millis(10)).await;
+                assert_eq!(1, service.in_flight());
+                stop.stop();
+                assert_eq!(Err(DrainError::Stopped), service.process(2).await);
+                service.await_drained().await;
+                assert_eq!(0, service.in_flight());
+            };
+            let (result, ()) = join(in_flight, stop_mid_call).await;
+            assert_eq!(Ok(1), result);
+        });
+        assert_eq!(Err(DrainError::Stopped), block_on(service.process(3)));
+        assert_eq!(0, service.in_flight());
+    }
+}
@@ -0,0 +1,177 @@
+//! Services that detect the format of byte inputs from their leading bytes, dispatching each input to a decoder for its format.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{Debug, Display},
+};
+
+use crate::Service;
+
+/// A format that may be detected from the leading bytes of an input by [`Format::detect`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// A JSON object or array, which starts with `{` or `[` after any leading whitespace
+    Json,
+    /// A MessagePack map or array, which starts with a map or array marker
+    MessagePack,
+    /// A gzip stream, which starts with the magic bytes `1f 8b`
+    Gzip,
+    /// A zstd frame, which starts with the magic bytes `28 b5 2f fd`
+    Zstd,
+}
+impl Format {
+    /// Detect the format of the given bytes, returning `None` if no format is recognized.
+    ///
+    /// Compressed formats are detected by their magic bytes before structured formats are considered.
+    /// Only JSON and MessagePack objects and arrays are detected, since scalar documents have no reliable marker.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            return Some(Self::Gzip);
+        }
+        if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            return Some(Self::Zstd);
+        }
+        if let Some(b'{' | b'[') = bytes.iter().find(|b| !b.is_ascii_whitespace()) {
+            return Some(Self::Json);
+        }
+        match bytes.first() {
+            // fixmap, fixarray, array 16/32, and map 16/32
+            Some(0x80..=0x9f | 0xdc..=0xdf) => Some(Self::MessagePack),
+            _ => None,
+        }
+    }
+}
+
+/// A [`Service`] that detects the [`Format`] of each byte input using [`Format::detect`], and dispatches the input to the decoder [`Service`] registered for that format.
+///
+/// All decoders produce the same output type, so callers receive a uniform parsed value regardless of the format it arrived in.
+/// A decoder for a compressed format may itself decompress its input and delegate to another [`FormatDetectService`].
+///
+/// An input whose format is not recognized is rejected with [`FormatDetectError::UnknownFormat`],
+/// and an input whose format has no registered decoder is rejected with [`FormatDetectError::NoDecoder`], without calling any decoder.
+///
+/// Use [`FormatDetectService::decoder`] to register decoders.
+pub struct FormatDetectService<'a, O, E> {
+    decoders: HashMap<Format, Decoder<'a, O, E>>,
+}
+type Decoder<'a, O, E> = Box<dyn Service<Input = Vec<u8>, Output = O, Error = E> + 'a>;
+impl<'a, O, E> FormatDetectService<'a, O, E> {
+    pub fn new() -> Self {
+        Self {
+            decoders: HashMap::new(),
+        }
+    }
+
+    /// Register the decoder for the given format, replacing any existing decoder for that format
+    pub fn decoder<S>(mut self, format: Format, decoder: S) -> Self
+    where
+        S: Service<Input = Vec<u8>, Output = O, Error = E> + 'a,
+    {
+        self.decoders.insert(format, Box::new(decoder));
+        self
+    }
+}
+impl<'a, O, E> Default for FormatDetectService<'a, O, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<'a, O, E> Service for FormatDetectService<'a, O, E> {
+    type Input = Vec<u8>;
+    type Output = O;
+    type Error = FormatDetectError<E>;
+    fn process(&self, input: Vec<u8>) -> Result<O, Self::Error> {
+        let format = Format::detect(&input).ok_or_else(|| FormatDetectError::UnknownFormat {
+            leading: input.iter().take(4).copied().collect(),
+        })?;
+        let decoder = self
+            .decoders
+            .get(&format)
+            .ok_or(FormatDetectError::NoDecoder(format))?;
+        decoder
+            .process(input)
+            .map_err(FormatDetectError::ServiceError)
+    }
+}
+
+/// Returned by [`FormatDetectService`], either when an input cannot be dispatched to a decoder or when the decoder fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FormatDetectError<E> {
+    /// The format of the input was not recognized, where `leading` holds up to the first 4 bytes of the input
+    UnknownFormat {
+        leading: Vec<u8>,
+    },
+    /// The format of the input was recognized, but no decoder is registered for it
+    NoDecoder(Format),
+    ServiceError(E),
+}
+impl<E: Display> Display for FormatDetectError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownFormat { leading } => {
+                f.write_str("unknown format with leading bytes [")?;
+                for (i, b) in leading.iter().enumerate() {
+                    match i {
+                        0 => write!(f, "{b:02x}")?,
+                        _ => write!(f, " {b:02x}")?,
+                    }
+                }
+                f.write_str("]")
+            }
+            Self::NoDecoder(format) => write!(f, "no decoder registered for {format:?}"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<E: Error + 'static> Error for FormatDetectError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::UnknownFormat { .. } | Self::NoDecoder(_) => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FnService;
+
+    #[test]
+    fn format_detect_routes_to_decoder() {
+        let decoder = |name: &'static str| {
+            FnService::new(move |input: Vec<u8>| Ok::<_, ()>(format!("{name}:{}", input.len())))
+        };
+        let service = FormatDetectService::new()
+            .decoder(Format::Json, decoder("json"))
+            .decoder(Format::MessagePack, decoder("msgpack"))
+            .decoder(Format::Gzip, decoder("gzip"));
+
+        assert_eq!(
+            Ok("json:10".to_owned()),
+            service.process(b" \n{\"a\": 1}".to_vec())
+        );
+        // {"a": 1}
+        assert_eq!(
+            Ok("msgpack:4".to_owned()),
+            service.process(vec![0x81, 0xa1, b'a', 0x01])
+        );
+        assert_eq!(
+            Ok("gzip:5".to_owned()),
+            service.process(vec![0x1f, 0x8b, 0x08, 0x00, 0x00])
+        );
+
+        assert_eq!(
+            Err(FormatDetectError::NoDecoder(Format::Zstd)),
+            service.process(vec![0x28, 0xb5, 0x2f, 0xfd, 0x00])
+        );
+        let unknown = service.process(b"hello world".to_vec()).unwrap_err();
+        assert_eq!(
+            FormatDetectError::UnknownFormat {
+                leading: b"hell".to_vec()
+            },
+            unknown
+        );
+    }
+}
@@ -0,0 +1,618 @@
+//! Services that report the health of an underlying service, independent of processing, or that actively probe the health of a backend.
+
+use std::{
+    collections::hash_map::RandomState,
+    convert::Infallible,
+    hash::BuildHasher,
+    sync::{atomic::AtomicU64, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    balance::splitmix64_f64,
+    clock::{Clock, SystemClock},
+    Service,
+};
+
+/// The health of a service, as reported by health-reporting services
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Health {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+/// A [`Service`] that delegates to an underlying [`Service`] while tracking the outcome of each call over a sliding window, exposing the result as a [`Health`].
+///
+/// The error rate is the number of `Err` results divided by the total number of results within the window.
+/// When the error rate meets or exceeds the `unhealthy` threshold, [`HealthReportingService::health`] reports [`Health::Unhealthy`].
+/// Otherwise, when it meets or exceeds the `degraded` threshold, it reports [`Health::Degraded`].
+/// A service with no results within the window is [`Health::Healthy`].
+///
+/// The window is divided into a fixed number of time buckets held in a ring, as by [`WindowStatsService`], so memory is bounded regardless of throughput.
+pub struct HealthReportingService<S, C = SystemClock> {
+    service: S,
+    degraded: f64,
+    unhealthy: f64,
+    counts: WindowCounts,
+    clock: C,
+}
+impl<S: Service> HealthReportingService<S> {
+    /// Create a new [`HealthReportingService`]
+    ///
+    /// # Arguments
+    /// * `service` - the underlying service
+    /// * `window` - the duration of the sliding window
+    /// * `buckets` - the number of time buckets the window is divided into
+    /// * `degraded` - the error rate, from `0.0` to `1.0`, at which the service is degraded
+    /// * `unhealthy` - the error rate, from `0.0` to `1.0`, at which the service is unhealthy
+    ///
+    /// # Panics
+    /// Panics if `buckets` is zero or does not fit in a `u32`, or unless `0.0 <= degraded <= unhealthy <= 1.0`.
+    pub fn new(
+        service: S,
+        window: Duration,
+        buckets: usize,
+        degraded: f64,
+        unhealthy: f64,
+    ) -> Self {
+        Self::with_clock(service, window, buckets, degraded, unhealthy, SystemClock)
+    }
+}
+impl<S: Service, C: Clock> HealthReportingService<S, C> {
+    /// Create a new [`HealthReportingService`] which uses the given [`Clock`] for windowing
+    ///
+    /// # Panics
+    /// Panics if `buckets` is zero or does not fit in a `u32`, or unless `0.0 <= degraded <= unhealthy <= 1.0`.
+    pub fn with_clock(
+        service: S,
+        window: Duration,
+        buckets: usize,
+        degraded: f64,
+        unhealthy: f64,
+        clock: C,
+    ) -> Self {
+        assert!(
+            (0.0..=unhealthy).contains(&degraded) && unhealthy <= 1.0,
+            "thresholds must satisfy 0.0 <= degraded <= unhealthy <= 1.0, got degraded {degraded} and unhealthy {unhealthy}"
+        );
+        Self {
+            service,
+            degraded,
+            unhealthy,
+            counts: WindowCounts::new(window, buckets, clock.now()),
+            clock,
+        }
+    }
+
+    /// The current [`Health`] of the underlying service
+    pub fn health(&self) -> Health {
+        let (requests, errors) = self.counts.totals(self.clock.now());
+        if requests == 0 {
+            return Health::Healthy;
+        }
+        let error_rate = errors as f64 / requests as f64;
+        if error_rate >= self.unhealthy {
+            Health::Unhealthy
+        } else if error_rate >= self.degraded {
+            Health::Degraded
+        } else {
+            Health::Healthy
+        }
+    }
+}
+impl<S: Service, C: Clock> Service for HealthReportingService<S, C> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let result = self.service.process(input);
+        self.counts.record(self.clock.now(), result.is_err());
+        result
+    }
+}
+
+/// A [`Service`] that delegates to an underlying [`Service`] while recording the most recent `Err` and when it occurred, exposed by [`LastErrorService::last_error`].
+///
+/// Results are passed through unchanged. A later `Ok` does not clear the recorded error.
+pub struct LastErrorService<S: Service, C = SystemClock> {
+    service: S,
+    last_error: Mutex<Option<(Instant, S::Error)>>,
+    clock: C,
+}
+impl<S: Service> LastErrorService<S> {
+    pub fn new(service: S) -> Self {
+        Self::with_clock(service, SystemClock)
+    }
+}
+impl<S: Service, C: Clock> LastErrorService<S, C> {
+    /// Create a new [`LastErrorService`] which uses the given [`Clock`] to timestamp errors
+    pub fn with_clock(service: S, clock: C) -> Self {
+        Self {
+            service,
+            last_error: Mutex::new(None),
+            clock,
+        }
+    }
+}
+impl<S: Service, C: Clock> LastErrorService<S, C>
+where
+    S::Error: Clone,
+{
+    /// The most recent error returned by the underlying service and when it was returned, or `None` if no error has been returned
+    pub fn last_error(&self) -> Option<(Instant, S::Error)> {
+        self.last_error.lock().expect("poisoned mutex").clone()
+    }
+}
+impl<S: Service, C: Clock> Service for LastErrorService<S, C>
+where
+    S::Error: Clone,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let result = self.service.process(input);
+        if let Err(err) = &result {
+            *self.last_error.lock().expect("poisoned mutex") =
+                Some((self.clock.now(), err.clone()));
+        }
+        result
+    }
+}
+
+/// A [`Service`] that delegates to an underlying [`Service`] while counting calls and errors over a sliding window, exposed as [`WindowStatsService::requests_per_second`] and [`WindowStatsService::error_rate`].
+///
+/// The window is divided into a fixed number of time buckets held in a ring, so memory is bounded regardless of throughput.
+/// The window slides one bucket at a time, so more buckets make the window more precise, at the cost of summing more buckets per query.
+pub struct WindowStatsService<S, C = SystemClock> {
+    service: S,
+    counts: WindowCounts,
+    clock: C,
+}
+impl<S: Service> WindowStatsService<S> {
+    /// Create a new [`WindowStatsService`], dividing the `window` into `buckets` time buckets
+    ///
+    /// # Panics
+    /// Panics if `buckets` is zero or does not fit in a `u32`.
+    pub fn new(service: S, window: Duration, buckets: usize) -> Self {
+        Self::with_clock(service, window, buckets, SystemClock)
+    }
+}
+impl<S: Service, C: Clock> WindowStatsService<S, C> {
+    /// Create a new [`WindowStatsService`] which uses the given [`Clock`] for windowing
+    ///
+    /// # Panics
+    /// Panics if `buckets` is zero or does not fit in a `u32`.
+    pub fn with_clock(service: S, window: Duration, buckets: usize, clock: C) -> Self {
+        Self {
+            service,
+            counts: WindowCounts::new(window, buckets, clock.now()),
+            clock,
+        }
+    }
+
+    /// The number of calls per second within the window
+    pub fn requests_per_second(&self) -> f64 {
+        let (requests, _) = self.counts.totals(self.clock.now());
+        requests as f64 / self.counts.window.as_secs_f64()
+    }
+
+    /// The number of `Err` results divided by the number of calls within the window, or `0.0` when there were no calls
+    pub fn error_rate(&self) -> f64 {
+        match self.counts.totals(self.clock.now()) {
+            (0, _) => 0.0,
+            (requests, errors) => errors as f64 / requests as f64,
+        }
+    }
+}
+impl<S: Service, C: Clock> Service for WindowStatsService<S, C> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let result = self.service.process(input);
+        self.counts.record(self.clock.now(), result.is_err());
+        result
+    }
+}
+
+/// Counts of calls and errors over a sliding window, held in a ring of fixed time buckets
+struct WindowCounts {
+    window: Duration,
+    bucket_width: Duration,
+    origin: Instant,
+    buckets: Mutex<Vec<StatsBucket>>,
+}
+#[derive(Clone, Copy, Default)]
+struct StatsBucket {
+    epoch: u64,
+    requests: u64,
+    errors: u64,
+}
+impl WindowCounts {
+    fn new(window: Duration, buckets: usize, origin: Instant) -> Self {
+        assert!(buckets > 0, "buckets must be non-zero");
+        let divisor = u32::try_from(buckets).expect("buckets must fit in a u32");
+        Self {
+            window,
+            bucket_width: (window / divisor).max(Duration::from_nanos(1)),
+            origin,
+            buckets: Mutex::new(vec![StatsBucket::default(); buckets]),
+        }
+    }
+
+    fn epoch(&self, now: Instant) -> u64 {
+        let elapsed = now.duration_since(self.origin);
+        (elapsed.as_nanos() / self.bucket_width.as_nanos()) as u64
+    }
+
+    fn record(&self, now: Instant, error: bool) {
+        let epoch = self.epoch(now);
+        let mut buckets = self.buckets.lock().expect("poisoned mutex");
+        let len = buckets.len() as u64;
+        let bucket = &mut buckets[(epoch % len) as usize];
+        if bucket.epoch != epoch {
+            *bucket = StatsBucket {
+                epoch,
+                requests: 0,
+                errors: 0,
+            };
+        }
+        bucket.requests += 1;
+        if error {
+            bucket.errors += 1;
+        }
+    }
+
+    /// The number of calls and errors within the window
+    fn totals(&self, now: Instant) -> (u64, u64) {
+        let epoch = self.epoch(now);
+        let buckets = self.buckets.lock().expect("poisoned mutex");
+        let oldest = (epoch + 1).saturating_sub(buckets.len() as u64);
+        buckets
+            .iter()
+            .filter(|bucket| bucket.epoch >= oldest && bucket.epoch <= epoch)
+            .fold((0, 0), |(requests, errors), bucket| {
+                (requests + bucket.requests, errors + bucket.errors)
+            })
+    }
+}
+
+/// A shared [`Health`] status, updated by a [`HealthCheckService`] and read by any number of consumers, such as routing or breaker services.
+///
+/// Clones of a [`SharedHealth`] share the same status.
+#[derive(Clone, Debug)]
+pub struct SharedHealth {
+    health: Arc<Mutex<Health>>,
+}
+impl SharedHealth {
+    pub fn new(health: Health) -> Self {
+        Self {
+            health: Arc::new(Mutex::new(health)),
+        }
+    }
+
+    /// The current [`Health`]
+    pub fn get(&self) -> Health {
+        *self.health.lock().expect("poisoned mutex")
+    }
+
+    /// Replace the current [`Health`]
+    pub fn set(&self, health: Health) {
+        *self.health.lock().expect("poisoned mutex") = health;
+    }
+}
+
+/// A [`Service`] that probes a backend with a lightweight input on a jittered interval, updating a [`SharedHealth`] independently of request traffic.
+///
+/// Each call to `process` waits until the next probe is due, sends a clone of the `probe` input to the backend, then updates and produces the [`Health`].
+/// It is intended to be driven by a dedicated loop, such as on its own thread, while request paths consult the [`SharedHealth`] from [`HealthCheckService::health`].
+/// Probes are spaced by the `interval` plus a random jitter of up to `jitter`, so many checkers started together do not probe in lockstep.
+///
+/// A successful probe reports [`Health::Healthy`].
+/// A failed probe reports [`Health::Degraded`], until `failure_threshold` consecutive probes have failed, which reports [`Health::Unhealthy`].
+pub struct HealthCheckService<S: Service, C = SystemClock> {
+    service: S,
+    probe: S::Input,
+    interval: Duration,
+    jitter: Duration,
+    failure_threshold: usize,
+    health: SharedHealth,
+    schedule: Mutex<(Instant, usize)>,
+    rng: AtomicU64,
+    clock: C,
+}
+impl<S: Service> HealthCheckService<S> {
+    /// Create a new [`HealthCheckService`], which probes immediately on the first call, and starts out [`Health::Healthy`]
+    ///
+    /// # Panics
+    /// Panics if `failure_threshold` is zero.
+    pub fn new(
+        service: S,
+        probe: S::Input,
+        interval: Duration,
+        jitter: Duration,
+        failure_threshold: usize,
+    ) -> Self {
+        Self::with_clock(
+            service,
+            probe,
+            interval,
+            jitter,
+            failure_threshold,
+            SystemClock,
+        )
+    }
+}
+impl<S: Service, C: Clock> HealthCheckService<S, C> {
+    /// Create a new [`HealthCheckService`] which uses the given [`Clock`] to wait between probes
+    ///
+    /// # Panics
+    /// Panics if `failure_threshold` is zero.
+    pub fn with_clock(
+        service: S,
+        probe: S::Input,
+        interval: Duration,
+        jitter: Duration,
+        failure_threshold: usize,
+        clock: C,
+    ) -> Self {
+        assert!(failure_threshold > 0, "failure_threshold must be non-zero");
+        Self {
+            service,
+            probe,
+            interval,
+            jitter,
+            failure_threshold,
+            health: SharedHealth::new(Health::Healthy),
+            schedule: Mutex::new((clock.now(), 0)),
+            rng: AtomicU64::new(RandomState::new().hash_one(0u64)),
+            clock,
+        }
+    }
+
+    /// Get a [`SharedHealth`] that is updated by this service's probes
+    pub fn health(&self) -> SharedHealth {
+        self.health.clone()
+    }
+}
+impl<S: Service, C: Clock> Service for HealthCheckService<S, C>
+where
+    S::Input: Clone,
+{
+    type Input = ();
+    type Output = Health;
+    type Error = Infallible;
+    fn process(&self, _: ()) -> Result<Health, Infallible> {
+        let mut schedule = self.schedule.lock().expect("poisoned mutex");
+        let (next_probe, failures) = &mut *schedule;
+        let now = self.clock.now();
+        if *next_probe > now {
+            self.clock.sleep(*next_probe - now);
+        }
+        let health = match self.service.process(self.probe.clone()) {
+            Ok(_) => {
+                *failures = 0;
+                Health::Healthy
+            }
+            Err(_) => {
+                *failures += 1;
+                match *failures >= self.failure_threshold {
+                    true => Health::Unhealthy,
+                    false => Health::Degraded,
+                }
+            }
+        };
+        self.health.set(health);
+        let jitter = self.jitter.mul_f64(splitmix64_f64(&self.rng));
+        *next_probe = self.clock.now().max(*next_probe) + self.interval + jitter;
+        Ok(health)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::{clock::ManualClock, FnService};
+
+    #[test]
+    fn health_degrades_with_error_rate() {
+        let clock = ManualClock::new();
+        let service = HealthReportingService::with_clock(
+            FnService::new(|ok: bool| match ok {
+                true => Ok(()),
+                false => Err(()),
+            }),
+            Duration::from_secs(10),
+            10,
+            0.25,
+            0.5,
+            clock.clone(),
+        );
+        assert_eq!(Health::Healthy, service.health());
+
+        for _ in 0..4 {
+            service.process(true).unwrap();
+        }
+        service.process(false).unwrap_err();
+        assert_eq!(Health::Healthy, service.health());
+
+        service.process(false).unwrap_err();
+        assert_eq!(Health::Degraded, service.health());
+
+        clock.advance(Duration::from_secs(1));
+        for _ in 0..4 {
+            service.process(false).unwrap_err();
+        }
+        assert_eq!(Health::Unhealthy, service.health());
+
+        // the bucket holding the first six outcomes slides out of the window, leaving only failures
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(Health::Unhealthy, service.health());
+
+        // all outcomes fall out of the window
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(Health::Healthy, service.health());
+    }
+
+    #[test]
+    fn health_rejects_invalid_thresholds() {
+        let panics = |degraded, unhealthy| {
+            std::panic::catch_unwind(|| {
+                HealthReportingService::new(
+                    FnService::new(|_: ()| Ok::<_, ()>(())),
+                    Duration::from_secs(10),
+                    10,
+                    degraded,
+                    unhealthy,
+                )
+            })
+            .is_err()
+        };
+        assert!(!panics(0.0, 0.0));
+        assert!(!panics(0.25, 1.0));
+        assert!(panics(-0.1, 0.5));
+        assert!(panics(0.5, 0.25));
+        assert!(panics(0.5, 1.5));
+        assert!(panics(f64::NAN, 0.5));
+        assert!(panics(0.25, f64::NAN));
+    }
+
+    #[test]
+    fn last_error_records_timestamp() {
+        let clock = ManualClock::new();
+        let service = LastErrorService::with_clock(
+            FnService::new(|input: i32| match input {
+                0.. => Ok(input),
+                _ => Err(format!("negative {input}")),
+            }),
+            clock.clone(),
+        );
+        service.process(1).unwrap();
+        assert_eq!(None, service.last_error());
+
+        clock.advance(Duration::from_secs(1));
+        let failed_at = clock.now();
+        service.process(-1).unwrap_err();
+        clock.advance(Duration::from_secs(1));
+        service.process(2).unwrap();
+        assert_eq!(
+            Some((failed_at, "negative -1".to_owned())),
+            service.last_error()
+        );
+    }
+
+    #[test]
+    fn window_stats_rates() {
+        let clock = ManualClock::new();
+        let service = WindowStatsService::with_clock(
+            FnService::new(|ok: bool| match ok {
+                true => Ok(()),
+                false => Err(()),
+            }),
+            Duration::from_secs(10),
+            10,
+            clock.clone(),
+        );
+        assert_eq!(0.0, service.requests_per_second());
+        assert_eq!(0.0, service.error_rate());
+
+        for n in 0..15 {
+            let _ = service.process(n % 3 != 0);
+        }
+        assert_eq!(1.5, service.requests_per_second());
+        assert_eq!(1.0 / 3.0, service.error_rate());
+
+        clock.advance(Duration::from_secs(5));
+        for _ in 0..5 {
+            service.process(true).unwrap();
+        }
+        assert_eq!(2.0, service.requests_per_second());
+        assert_eq!(0.25, service.error_rate());
+
+        // the first bucket slides out of the window
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(0.5, service.requests_per_second());
+        assert_eq!(0.0, service.error_rate());
+    }
+
+    #[test]
+    fn window_stats_rejects_invalid_buckets() {
+        let panics = |buckets| {
+            std::panic::catch_unwind(|| {
+                WindowStatsService::new(
+                    FnService::new(|_: ()| Ok::<_, ()>(())),
+                    Duration::from_secs(10),
+                    buckets,
+                )
+            })
+            .is_err()
+        };
+        assert!(!panics(1));
+        assert!(panics(0));
+        #[cfg(target_pointer_width = "64")]
+        assert!(panics(u32::MAX as usize + 1));
+    }
+
+    #[test]
+    fn health_check_transitions_to_unhealthy() {
+        let clock = ManualClock::new();
+        let up = Arc::new(AtomicBool::new(true));
+        let backend = {
+            let up = Arc::clone(&up);
+            FnService::new(move |_: ()| match up.load(Ordering::SeqCst) {
+                true => Ok(()),
+                false => Err(()),
+            })
+        };
+        let checker = HealthCheckService::with_clock(
+            backend,
+            (),
+            Duration::from_secs(10),
+            Duration::from_secs(2),
+            3,
+            clock.clone(),
+        );
+        let health = checker.health();
+        let start = clock.now();
+
+        assert_eq!(Ok(Health::Healthy), checker.process(()));
+        assert_eq!(start, clock.now());
+
+        up.store(false, Ordering::SeqCst);
+        let mut observed = Vec::new();
+        for probe in 1..=3 {
+            let before = clock.now();
+            checker.process(()).unwrap();
+            let waited = clock.now() - before;
+            assert!(
+                waited >= Duration::from_secs(10) && waited <= Duration::from_secs(12),
+                "probe {probe} waited {waited:?}"
+            );
+            observed.push(health.get());
+        }
+        assert_eq!(
+            vec![Health::Degraded, Health::Degraded, Health::Unhealthy],
+            observed
+        );
+
+        up.store(true, Ordering::SeqCst);
+        assert_eq!(Ok(Health::Healthy), checker.process(()));
+        assert_eq!(Health::Healthy, health.get());
+    }
+
+    #[test]
+    #[should_panic(expected = "failure_threshold must be non-zero")]
+    fn health_check_rejects_zero_failure_threshold() {
+        HealthCheckService::new(
+            FnService::new(|_: ()| Ok::<_, ()>(())),
+            (),
+            Duration::from_secs(10),
+            Duration::ZERO,
+            0,
+        );
+    }
+}
@@ -0,0 +1,310 @@
+//! Services that hedge requests across multiple replicas to reduce tail latency.
+
+use std::{
+    future::{poll_fn, Future},
+    task::Poll,
+    time::Duration,
+};
+
+use crate::{
+    async_trait,
+    clock::{Clock, SystemClock},
+    timing::LatencyHistogramService,
+    AsyncService,
+};
+
+/// An [`AsyncService`] that dispatches a cloned input to all underlying replica [`AsyncService`]s concurrently, producing the first `Ok` output.
+///
+/// As soon as one replica produces `Ok`, the remaining in-flight calls are cancelled by dropping their futures.
+/// When every replica returns an `Err`, the error from the last replica to fail is returned.
+pub struct HedgeService<S> {
+    services: Vec<S>,
+}
+impl<S> HedgeService<S> {
+    /// Create a new [`HedgeService`] over the given replicas.
+    ///
+    /// # Panics
+    /// Panics if `services` is empty.
+    pub fn new(services: Vec<S>) -> Self {
+        assert!(
+            !services.is_empty(),
+            "HedgeService requires at least one service"
+        );
+        Self { services }
+    }
+}
+#[async_trait]
+impl<S: AsyncService> AsyncService for HedgeService<S>
+where
+    S::Input: Clone,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let mut pending: Vec<_> = self
+            .services
+            .iter()
+            .map(|service| service.process(input.clone()))
+            .collect();
+        let mut last_err = None;
+        poll_fn(|cx| {
+            let mut i = 0;
+            while i < pending.len() {
+                match pending[i].as_mut().poll(cx) {
+                    Poll::Ready(Ok(output)) => return Poll::Ready(Ok(output)),
+                    Poll::Ready(Err(err)) => {
+                        last_err = Some(err);
+                        drop(pending.swap_remove(i));
+                    }
+                    Poll::Pending => i += 1,
+                }
+            }
+            match pending.is_empty() {
+                true => Poll::Ready(Err(last_err.take().expect("no services"))),
+                false => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+/// An [`AsyncService`] that calls a primary [`AsyncService`], and hedges the call to a secondary [`AsyncService`] only once the primary
+/// has taken longer than its p95 latency, producing the first `Ok` output.
+///
+/// The latency of the primary is recorded in a [`LatencyHistogramService`], so the hedge delay adapts to the backend:
+/// a healthy backend is hedged for about 5% of calls, rather than doubling the load as a [`HedgeService`] would.
+/// Until the primary has completed a call, the given initial delay is used.
+/// When the hedge wins, the primary call is cancelled by dropping its future, and its elapsed time is recorded as its latency,
+/// so a slow backend raises the hedge delay no further than the time it was given.
+///
+/// The delay is awaited using the given `sleep` function, such as `tokio::time::sleep`, so this service is not tied to any runtime.
+/// When the primary fails before the delay has elapsed, its error is returned without hedging.
+/// Once hedged, the first `Ok` is produced, and when both calls fail, the error from the last call to fail is returned.
+pub struct AdaptiveHedgeService<S, D, C = SystemClock> {
+    primary: LatencyHistogramService<S, C>,
+    secondary: S,
+    initial_delay: Duration,
+    percentile: f64,
+    sleep: D,
+}
+impl<S, D> AdaptiveHedgeService<S, D> {
+    /// Create a new [`AdaptiveHedgeService`], hedging calls to `primary` with `secondary`
+    pub fn new(primary: S, secondary: S, initial_delay: Duration, sleep: D) -> Self {
+        Self::with_clock(primary, secondary, initial_delay, sleep, SystemClock)
+    }
+}
+impl<S, D, C: Clock> AdaptiveHedgeService<S, D, C> {
+    /// Create a new [`AdaptiveHedgeService`] which uses the given [`Clock`] to measure the latency of the primary
+    pub fn with_clock(
+        primary: S,
+        secondary: S,
+        initial_delay: Duration,
+        sleep: D,
+        clock: C,
+    ) -> Self {
+        Self {
+            primary: LatencyHistogramService::with_clock(primary, clock),
+            secondary,
+            initial_delay,
+            percentile: 95.0,
+            sleep,
+        }
+    }
+
+    /// Hedge once the primary has taken longer than the given latency percentile, rather than the p95
+    pub fn with_percentile(mut self, percentile: f64) -> Self {
+        self.percentile = percentile;
+        self
+    }
+
+    /// The delay after which the next call will be hedged
+    pub fn delay(&self) -> Duration {
+        self.primary
+            .percentile(self.percentile)
+            .unwrap_or(self.initial_delay)
+    }
+
+    /// The latency histogram of the primary
+    pub fn latency(&self) -> &LatencyHistogramService<S, C> {
+        &self.primary
+    }
+}
+#[async_trait]
+impl<S, D, F, C> AsyncService for AdaptiveHedgeService<S, D, C>
+where
+    S: AsyncService,
+    S::Input: Clone + Sync,
+    D: Fn(Duration) -> F + Send + Sync,
+    F: Future<Output = ()> + Send,
+    C: Clock + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let start = self.primary.clock.now();
+        let mut timer = Box::pin((self.sleep)(self.delay()));
+        let mut primary = Some(self.primary.process(input.clone()));
+        let mut secondary = None;
+        let mut hedged = false;
+        let mut last_err = None;
+        let result = poll_fn(|cx| {
+            if let Some(call) = primary.as_mut() {
+                if let Poll::Ready(result) = call.as_mut().poll(cx) {
+                    primary = None;
+                    match result {
+                        Ok(output) => return Poll::Ready(Ok(output)),
+                        Err(err) => last_err = Some(err),
+                    }
+                }
+            }
+            if !hedged {
+                if primary.is_none() || timer.as_mut().poll(cx).is_pending() {
+                    return match last_err.take() {
+                        Some(err) => Poll::Ready(Err(err)),
+                        None => Poll::Pending,
+                    };
+                }
+                hedged = true;
+                secondary = Some(self.secondary.process(input.clone()));
+            }
+            if let Some(call) = secondary.as_mut() {
+                if let Poll::Ready(result) = call.as_mut().poll(cx) {
+                    secondary = None;
+                    match result {
+                        Ok(output) => return Poll::Ready(Ok(output)),
+                        Err(err) => last_err = Some(err),
+                    }
+                }
+            }
+            match primary.is_none() && secondary.is_none() {
+                true => Poll::Ready(Err(last_err.take().expect("no calls"))),
+                false => Poll::Pending,
+            }
+        })
+        .await;
+        if primary.take().is_some() {
+            self.primary.record(start);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicU32, Ordering},
+        time::Instant,
+    };
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::testing::Delay;
+
+    struct Replica {
+        name: &'static str,
+        delay: Duration,
+        fail: bool,
+    }
+    #[async_trait]
+    impl AsyncService for Replica {
+        type Input = u32;
+        type Output = (&'static str, u32);
+        type Error = &'static str;
+        async fn process(&self, input: u32) -> Result<Self::Output, Self::Error> {
+            Delay::new(self.delay).await;
+            match self.fail {
+                true => Err(self.name),
+                false => Ok((self.name, input)),
+            }
+        }
+    }
+
+    #[test]
+    fn hedge_returns_fastest_replica() {
+        let service = HedgeService::new(vec![
+            Replica {
+                name: "slow",
+                delay: Duration::from_secs(5),
+                fail: false,
+            },
+            Replica {
+                name: "fast",
+                delay: Duration::from_millis(10),
+                fail: false,
+            },
+        ]);
+        let start = Instant::now();
+        assert_eq!(Ok(("fast", 1)), block_on(service.process(1)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn hedge_returns_last_error_when_all_fail() {
+        let service = HedgeService::new(vec![
+            Replica {
+                name: "second",
+                delay: Duration::from_millis(50),
+                fail: true,
+            },
+            Replica {
+                name: "first",
+                delay: Duration::from_millis(1),
+                fail: true,
+            },
+        ]);
+        assert_eq!(Err("second"), block_on(service.process(1)));
+    }
+
+    /// Responds immediately, except for an input of zero, which stalls when `stalls` is set
+    struct Backend {
+        name: &'static str,
+        stalls: bool,
+        calls: AtomicU32,
+    }
+    #[async_trait]
+    impl AsyncService for Backend {
+        type Input = u32;
+        type Output = (&'static str, u32);
+        type Error = &'static str;
+        async fn process(&self, input: u32) -> Result<Self::Output, Self::Error> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.stalls && input == 0 {
+                Delay::new(Duration::from_secs(5)).await;
+            }
+            Ok((self.name, input))
+        }
+    }
+
+    #[test]
+    fn adaptive_hedge_fires_only_for_slow_primary() {
+        let backend = |name, stalls| Backend {
+            name,
+            stalls,
+            calls: AtomicU32::new(0),
+        };
+        let service = AdaptiveHedgeService::new(
+            backend("primary", true),
+            backend("secondary", false),
+            Duration::from_secs(10),
+            Delay::new,
+        );
+        assert_eq!(Duration::from_secs(10), service.delay());
+
+        for n in 1..=20 {
+            assert_eq!(Ok(("primary", n)), block_on(service.process(n)));
+        }
+        assert_eq!(0, service.secondary.calls.load(Ordering::SeqCst));
+        // the delay adapts to the p95 of the primary, which responds immediately
+        assert!(service.delay() < Duration::from_millis(100));
+
+        let start = Instant::now();
+        assert_eq!(Ok(("secondary", 0)), block_on(service.process(0)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(1, service.secondary.calls.load(Ordering::SeqCst));
+        // the cancelled primary call is still recorded
+        assert_eq!(21, service.latency().count());
+    }
+}
@@ -25,18 +25,117 @@ use std::{
 pub static KEEP_RUNNING: AtomicBool = AtomicBool::new(true);
 
 /// First busy spin for 10 cycles, then yield for 10 cycles, then park for 1us, increasing by powers of two each attempt, maxing out at 1024us (1.024ms).
+///
+/// See [`ExponentialBackoff`] to configure each phase.
 pub fn backoff<E>(attempts: usize) -> Result<(), RetryError<E>> {
-    check_keep_running()?;
-    if attempts < 10 {
-    } else if attempts < 20 {
-        thread::yield_now();
-    } else if attempts < 30 {
-        let micros = 1 << (attempts - 20);
-        thread::park_timeout(Duration::from_micros(micros));
-    } else {
-        thread::park_timeout(Duration::from_micros(1024));
+    ExponentialBackoff::new().idle(attempts)
+}
+
+/// A configurable exponential backoff idle strategy, which defaults to the behavior of [`backoff`].
+///
+/// The strategy first busy spins for `spin_iters` attempts, then yields for `yield_iters` attempts,
+/// then parks for `min_park`, multiplying the park duration by `multiplier` each attempt, up to `max_park`.
+/// A `min_park` greater than `max_park` is clamped to `max_park`.
+/// Use [`ExponentialBackoff::build`] to produce an idle function that may be used anywhere [`backoff`] is.
+///
+/// ```
+/// use std::time::Duration;
+/// use sod::idle::ExponentialBackoff;
+///
+/// let idle = ExponentialBackoff::new()
+///     .spin_iters(0)
+///     .yield_iters(5)
+///     .max_park(Duration::from_micros(100))
+///     .build::<()>();
+/// assert!(idle(0).is_ok());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExponentialBackoff {
+    spin_iters: usize,
+    yield_iters: usize,
+    min_park: Duration,
+    max_park: Duration,
+    multiplier: f64,
+}
+impl ExponentialBackoff {
+    pub fn new() -> Self {
+        Self {
+            spin_iters: 10,
+            yield_iters: 10,
+            min_park: Duration::from_micros(1),
+            max_park: Duration::from_micros(1024),
+            multiplier: 2.0,
+        }
+    }
+
+    /// The number of attempts to busy spin, defaulting to 10
+    pub fn spin_iters(mut self, spin_iters: usize) -> Self {
+        self.spin_iters = spin_iters;
+        self
+    }
+
+    /// The number of attempts to yield after spinning, defaulting to 10
+    pub fn yield_iters(mut self, yield_iters: usize) -> Self {
+        self.yield_iters = yield_iters;
+        self
+    }
+
+    /// The duration of the first park after yielding, defaulting to 1us
+    pub fn min_park(mut self, min_park: Duration) -> Self {
+        self.min_park = min_park;
+        self
+    }
+
+    /// The greatest duration to park, defaulting to 1024us
+    pub fn max_park(mut self, max_park: Duration) -> Self {
+        self.max_park = max_park;
+        self
+    }
+
+    /// The factor the park duration is multiplied by each attempt, defaulting to 2
+    ///
+    /// # Panics
+    /// Panics if `multiplier` is not finite or is less than 1.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        assert!(
+            multiplier.is_finite() && multiplier >= 1.0,
+            "multiplier must be finite and at least 1, got {multiplier}"
+        );
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Produce an idle function for this strategy
+    pub fn build<E>(self) -> impl Fn(usize) -> Result<(), RetryError<E>> + Clone + Send + Sync {
+        move |attempts| self.idle(attempts)
+    }
+
+    /// Idle for the given attempt number according to this strategy
+    pub fn idle<E>(&self, attempts: usize) -> Result<(), RetryError<E>> {
+        check_keep_running()?;
+        match self.park_duration(attempts) {
+            Some(duration) => thread::park_timeout(duration),
+            None if attempts >= self.spin_iters => thread::yield_now(),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// The duration to park for the given attempt number, or `None` while spinning or yielding
+    fn park_duration(&self, attempts: usize) -> Option<Duration> {
+        let parks = attempts.checked_sub(self.spin_iters.saturating_add(self.yield_iters))?;
+        let exponent = i32::try_from(parks).unwrap_or(i32::MAX);
+        let secs = self.min_park.min(self.max_park).as_secs_f64() * self.multiplier.powi(exponent);
+        Some(match secs < self.max_park.as_secs_f64() {
+            true => Duration::from_secs_f64(secs),
+            false => self.max_park,
+        })
+    }
+}
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        Self::new()
     }
-    Ok(())
 }
 
 /// No-Op
@@ -112,3 +211,48 @@ fn check_keep_running<E>() -> Result<(), RetryError<E>> {
         Err(RetryError::Interrupted)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_backoff_park_durations() {
+        let micros = |micros| Some(Duration::from_micros(micros));
+        let default = ExponentialBackoff::new();
+        assert_eq!(None, default.park_duration(0));
+        assert_eq!(None, default.park_duration(19));
+        assert_eq!(micros(1), default.park_duration(20));
+        assert_eq!(micros(512), default.park_duration(29));
+        assert_eq!(micros(1024), default.park_duration(30));
+        assert_eq!(micros(1024), default.park_duration(usize::MAX));
+
+        let tight = ExponentialBackoff::new()
+            .spin_iters(0)
+            .yield_iters(2)
+            .min_park(Duration::from_micros(10))
+            .max_park(Duration::from_micros(50))
+            .multiplier(3.0);
+        assert_eq!(None, tight.park_duration(1));
+        assert_eq!(micros(10), tight.park_duration(2));
+        assert_eq!(micros(30), tight.park_duration(3));
+        assert_eq!(micros(50), tight.park_duration(4));
+        assert_eq!(Ok(()), tight.build::<()>()(5));
+
+        // a min_park above max_park is clamped, and a multiplier of 1 parks for a constant duration
+        let constant = ExponentialBackoff::new()
+            .spin_iters(0)
+            .yield_iters(0)
+            .min_park(Duration::from_micros(100))
+            .max_park(Duration::from_micros(40))
+            .multiplier(1.0);
+        assert_eq!(micros(40), constant.park_duration(0));
+        assert_eq!(micros(40), constant.park_duration(usize::MAX));
+
+        for multiplier in [0.5, 0.0, -2.0, f64::NAN, f64::INFINITY] {
+            let built =
+                std::panic::catch_unwind(|| ExponentialBackoff::new().multiplier(multiplier));
+            assert!(built.is_err(), "{multiplier}");
+        }
+    }
+}
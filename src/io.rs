@@ -0,0 +1,632 @@
+//! Services that perform non-blocking IO on any [`Read`] or [`Write`] stream.
+//!
+//! These services implement [`Retryable`], treating [`ErrorKind::WouldBlock`] and [`ErrorKind::Interrupted`] as retryable,
+//! so they may be encapsulated by a [`crate::RetryService`] to block with an idle strategy, or by a [`crate::RetryToOptionService`] to produce `None` when no progress can be made.
+//!
+//! Each service implements [`MutService`] for any stream, and [`Service`] for streams that implement [`Read`] or [`Write`] by reference, such as [`std::net::TcpStream`].
+//!
+//! In the other direction, a [`ServiceWriter`] or [`ServiceReader`] exposes a byte-oriented [`Service`] as a [`Write`] or [`Read`], for code that expects a stream.
+//!
+//! A [`ProtocolNegotiateService`] peeks the first bytes of a new connection to detect its protocol, dispatching the connection to the session service for that protocol.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{Debug, Display},
+    hash::Hash,
+    io::{self, ErrorKind, Read, Write},
+    mem,
+};
+
+use crate::{MutService, RetryError, Retryable, Service};
+
+fn is_retryable(err: &io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted)
+}
+
+/// A [`Service`] or [`MutService`] that performs a single read from a non-blocking stream, producing the bytes that were read.
+///
+/// An empty output indicates the end of the stream.
+pub struct NonBlockingReadService<R> {
+    reader: R,
+    buffer_size: usize,
+}
+impl<R> NonBlockingReadService<R> {
+    /// Create a new [`NonBlockingReadService`], reading up to `buffer_size` bytes per call
+    pub fn new(reader: R, buffer_size: usize) -> Self {
+        Self {
+            reader,
+            buffer_size,
+        }
+    }
+}
+fn read(mut reader: impl Read, buffer_size: usize) -> Result<Vec<u8>, io::Error> {
+    let mut buffer = vec![0; buffer_size];
+    let len = reader.read(&mut buffer)?;
+    buffer.truncate(len);
+    Ok(buffer)
+}
+impl<R: Read> MutService for NonBlockingReadService<R> {
+    type Input = ();
+    type Output = Vec<u8>;
+    type Error = io::Error;
+    fn process(&mut self, _: ()) -> Result<Self::Output, Self::Error> {
+        read(&mut self.reader, self.buffer_size)
+    }
+}
+impl<R> Service for NonBlockingReadService<R>
+where
+    for<'a> &'a R: Read,
+{
+    type Input = ();
+    type Output = Vec<u8>;
+    type Error = io::Error;
+    fn process(&self, _: ()) -> Result<Self::Output, Self::Error> {
+        read(&self.reader, self.buffer_size)
+    }
+}
+impl<R> Retryable<(), io::Error> for NonBlockingReadService<R> {
+    fn parse_retry(&self, err: io::Error) -> Result<(), RetryError<io::Error>> {
+        match is_retryable(&err) {
+            true => Ok(()),
+            false => Err(RetryError::ServiceError(err)),
+        }
+    }
+}
+
+/// A [`Service`] or [`MutService`] that writes all input bytes to a non-blocking stream.
+///
+/// When the stream cannot accept all bytes, a [`NonBlockingWriteError`] is returned containing the bytes that were not yet written,
+/// which are passed to the next attempt when retried.
+pub struct NonBlockingWriteService<W> {
+    writer: W,
+}
+impl<W> NonBlockingWriteService<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+fn write(mut writer: impl Write, mut input: Vec<u8>) -> Result<(), NonBlockingWriteError> {
+    let mut written = 0;
+    while written < input.len() {
+        match writer.write(&input[written..]) {
+            Ok(0) => {
+                input.drain(..written);
+                return Err(NonBlockingWriteError {
+                    error: ErrorKind::WriteZero.into(),
+                    remaining: input,
+                });
+            }
+            Ok(len) => written += len,
+            Err(error) => {
+                input.drain(..written);
+                return Err(NonBlockingWriteError {
+                    error,
+                    remaining: input,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+impl<W: Write> MutService for NonBlockingWriteService<W> {
+    type Input = Vec<u8>;
+    type Output = ();
+    type Error = NonBlockingWriteError;
+    fn process(&mut self, input: Vec<u8>) -> Result<(), NonBlockingWriteError> {
+        write(&mut self.writer, input)
+    }
+}
+impl<W> Service for NonBlockingWriteService<W>
+where
+    for<'a> &'a W: Write,
+{
+    type Input = Vec<u8>;
+    type Output = ();
+    type Error = NonBlockingWriteError;
+    fn process(&self, input: Vec<u8>) -> Result<(), NonBlockingWriteError> {
+        write(&self.writer, input)
+    }
+}
+impl<W> Retryable<Vec<u8>, NonBlockingWriteError> for NonBlockingWriteService<W> {
+    fn parse_retry(
+        &self,
+        err: NonBlockingWriteError,
+    ) -> Result<Vec<u8>, RetryError<NonBlockingWriteError>> {
+        match is_retryable(&err.error) {
+            true => Ok(err.remaining),
+            false => Err(RetryError::ServiceError(err)),
+        }
+    }
+}
+
+/// Returned by [`NonBlockingWriteService`] when not all bytes could be written.
+#[derive(Debug)]
+pub struct NonBlockingWriteError {
+    /// The error returned by the underlying stream
+    pub error: io::Error,
+    /// The bytes that were not written
+    pub remaining: Vec<u8>,
+}
+impl Display for NonBlockingWriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} with {} bytes remaining",
+            self.error,
+            self.remaining.len()
+        )
+    }
+}
+impl Error for NonBlockingWriteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// A [`Write`] adapter over a [`Service`], `Service<Input = Vec<u8>>`, allowing a pipeline to be given to code that expects a [`Write`].
+///
+/// Written bytes are buffered, and the whole buffer is passed to the service as a single input when the writer is flushed or dropped.
+/// Flushing with an empty buffer does not call the service.
+/// An error returned by the service is converted to an [`io::Error`] with [`ErrorKind::Other`], and the bytes given to the failed call are not retained.
+/// As with [`std::io::BufWriter`], errors while flushing on drop are ignored, so [`Write::flush`] should be called before dropping.
+pub struct ServiceWriter<S>
+where
+    S: Service<Input = Vec<u8>>,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    service: S,
+    buffer: Vec<u8>,
+}
+impl<S> ServiceWriter<S>
+where
+    S: Service<Input = Vec<u8>>,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    pub fn new(service: S) -> Self {
+        Self {
+            service,
+            buffer: Vec::new(),
+        }
+    }
+}
+impl<S> Write for ServiceWriter<S>
+where
+    S: Service<Input = Vec<u8>>,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.service
+            .process(mem::take(&mut self.buffer))
+            .map(|_| ())
+            .map_err(io::Error::other)
+    }
+}
+impl<S> Drop for ServiceWriter<S>
+where
+    S: Service<Input = Vec<u8>>,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// A [`Read`] adapter over a polling [`Service`], `Service<Input = (), Output = Option<Vec<u8>>>`, allowing a pipeline to be given to code that expects a [`Read`].
+///
+/// The service is called whenever all previously produced bytes have been read, and `None` indicates the end of the stream.
+/// An empty `Some` is skipped, and the service is called again.
+/// An error returned by the service is converted to an [`io::Error`] with [`ErrorKind::Other`].
+pub struct ServiceReader<S> {
+    service: S,
+    pending: Vec<u8>,
+    offset: usize,
+}
+impl<S> ServiceReader<S>
+where
+    S: Service<Input = (), Output = Option<Vec<u8>>>,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    pub fn new(service: S) -> Self {
+        Self {
+            service,
+            pending: Vec::new(),
+            offset: 0,
+        }
+    }
+}
+impl<S> Read for ServiceReader<S>
+where
+    S: Service<Input = (), Output = Option<Vec<u8>>>,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.offset == self.pending.len() {
+            match self.service.process(()).map_err(io::Error::other)? {
+                Some(bytes) => {
+                    self.pending = bytes;
+                    self.offset = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.pending.len() - self.offset);
+        buf[..len].copy_from_slice(&self.pending[self.offset..self.offset + len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
+
+/// A stream whose first bytes have already been read by a [`ProtocolNegotiateService`], which are replayed by [`Read`] before reading from the underlying stream.
+///
+/// Writes and flushes are passed directly to the underlying stream.
+pub struct PeekedStream<R> {
+    peeked: Vec<u8>,
+    offset: usize,
+    stream: R,
+}
+impl<R> PeekedStream<R> {
+    pub fn new(peeked: Vec<u8>, stream: R) -> Self {
+        Self {
+            peeked,
+            offset: 0,
+            stream,
+        }
+    }
+
+    /// The bytes peeked from the underlying stream which have not yet been read
+    pub fn peeked(&self) -> &[u8] {
+        &self.peeked[self.offset..]
+    }
+
+    /// Unwrap the unread peeked bytes and the underlying stream
+    pub fn into_parts(mut self) -> (Vec<u8>, R) {
+        self.peeked.drain(..self.offset);
+        (self.peeked, self.stream)
+    }
+}
+impl<R: Read> Read for PeekedStream<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset == self.peeked.len() {
+            return self.stream.read(buf);
+        }
+        let len = buf.len().min(self.peeked.len() - self.offset);
+        buf[..len].copy_from_slice(&self.peeked[self.offset..self.offset + len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
+impl<R: Write> Write for PeekedStream<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// A [`Service`] that accepts a new blocking connection, reads up to `peek_len` leading bytes to detect its protocol,
+/// and dispatches the connection as a [`PeekedStream`] to the session [`Service`] registered for that protocol.
+///
+/// This allows a single listener to serve several protocols, such as a WebSocket handshake alongside a raw framed protocol, on one port.
+/// The detector is called after each read with every byte read so far, and the connection is dispatched as soon as it recognizes a protocol,
+/// so a client whose first message is shorter than `peek_len` is not left waiting for a reply.
+/// The detector should return `None` while the leading bytes could still match a protocol,
+/// and the connection is only rejected once `peek_len` bytes have been read or the connection reaches the end of its stream.
+/// The session service reads the peeked bytes again from the [`PeekedStream`], so it sees the connection from its first byte.
+///
+/// A connection whose protocol is not recognized is rejected with [`ProtocolNegotiateError::UnknownProtocol`],
+/// and a connection whose protocol has no registered session service is rejected with [`ProtocolNegotiateError::NoHandler`].
+///
+/// Use [`ProtocolNegotiateService::protocol`] to register session services.
+pub struct ProtocolNegotiateService<'a, R, K, O, E, F> {
+    peek_len: usize,
+    detect: F,
+    handlers: HashMap<K, Handler<'a, R, O, E>>,
+}
+type Handler<'a, R, O, E> = Box<dyn Service<Input = PeekedStream<R>, Output = O, Error = E> + 'a>;
+impl<'a, R, K, O, E, F> ProtocolNegotiateService<'a, R, K, O, E, F>
+where
+    K: Hash + Eq,
+    F: Fn(&[u8]) -> Option<K>,
+{
+    pub fn new(peek_len: usize, detect: F) -> Self {
+        Self {
+            peek_len,
+            detect,
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Register the session service for the given protocol, replacing any existing session service for that protocol
+    pub fn protocol<S>(mut self, protocol: K, handler: S) -> Self
+    where
+        S: Service<Input = PeekedStream<R>, Output = O, Error = E> + 'a,
+    {
+        self.handlers.insert(protocol, Box::new(handler));
+        self
+    }
+}
+impl<'a, R, K, O, E, F> Service for ProtocolNegotiateService<'a, R, K, O, E, F>
+where
+    R: Read,
+    K: Hash + Eq + Debug,
+    F: Fn(&[u8]) -> Option<K>,
+{
+    type Input = R;
+    type Output = O;
+    type Error = ProtocolNegotiateError<K, E>;
+    fn process(&self, mut input: R) -> Result<O, Self::Error> {
+        let mut peeked = vec![0; self.peek_len];
+        let mut len = 0;
+        let mut protocol = None;
+        while protocol.is_none() && len < peeked.len() {
+            match input.read(&mut peeked[len..]) {
+                Ok(0) => break,
+                Ok(n) => {
+                    len += n;
+                    protocol = (self.detect)(&peeked[..len]);
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(ProtocolNegotiateError::Io(err)),
+            }
+        }
+        peeked.truncate(len);
+        let protocol = protocol.ok_or_else(|| ProtocolNegotiateError::UnknownProtocol {
+            leading: peeked.clone(),
+        })?;
+        let handler = self
+            .handlers
+            .get(&protocol)
+            .ok_or(ProtocolNegotiateError::NoHandler(protocol))?;
+        handler
+            .process(PeekedStream::new(peeked, input))
+            .map_err(ProtocolNegotiateError::ServiceError)
+    }
+}
+
+/// Returned by [`ProtocolNegotiateService`], either when a connection cannot be dispatched to a session service or when the session service fails.
+#[derive(Debug)]
+pub enum ProtocolNegotiateError<K, E> {
+    /// Reading the leading bytes of the connection failed
+    Io(io::Error),
+    /// The protocol of the connection was not recognized, where `leading` holds the peeked bytes
+    UnknownProtocol {
+        leading: Vec<u8>,
+    },
+    /// The protocol of the connection was recognized, but no session service is registered for it
+    NoHandler(K),
+    ServiceError(E),
+}
+impl<K: Debug, E: Display> Display for ProtocolNegotiateError<K, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::UnknownProtocol { leading } => {
+                write!(f, "unknown protocol after {} leading bytes", leading.len())
+            }
+            Self::NoHandler(protocol) => write!(f, "no handler registered for {protocol:?}"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<K: Debug, E: Error + 'static> Error for ProtocolNegotiateError<K, E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::UnknownProtocol { .. } | Self::NoHandler(_) => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{TcpListener, TcpStream},
+        sync::Mutex,
+        thread,
+    };
+
+    use super::*;
+    use crate::{idle, FnService, RetryService, RetryToOptionService};
+
+    fn connected_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        client.set_nonblocking(true).unwrap();
+        server.set_nonblocking(true).unwrap();
+        (client, server)
+    }
+
+    #[test]
+    fn non_blocking_read_retries_on_would_block() {
+        let (client, server) = connected_pair();
+        let writer = NonBlockingWriteService::new(client);
+
+        let reader = RetryToOptionService::new(NonBlockingReadService::new(
+            server.try_clone().unwrap(),
+            1024,
+        ));
+        assert_eq!(None, reader.process(()).unwrap());
+
+        writer.process(b"hello".to_vec()).unwrap();
+        let reader = RetryService::new(NonBlockingReadService::new(server, 1024), idle::backoff);
+        assert_eq!(b"hello".to_vec(), reader.process(()).unwrap());
+    }
+
+    #[test]
+    fn non_blocking_write_retries_remaining_bytes() {
+        let (client, server) = connected_pair();
+        let payload: Vec<u8> = (0..16 * 1024 * 1024).map(|i| i as u8).collect();
+
+        let writer = NonBlockingWriteService::new(client);
+        let err = writer.process(payload.clone()).unwrap_err();
+        assert_eq!(ErrorKind::WouldBlock, err.error.kind());
+        assert!(!err.remaining.is_empty() && err.remaining.len() < payload.len());
+
+        let expected_len = payload.len();
+        let drain = thread::spawn(move || {
+            let reader =
+                RetryService::new(NonBlockingReadService::new(server, 65536), idle::backoff);
+            let mut received = Vec::new();
+            while received.len() < expected_len {
+                received.extend(reader.process(()).unwrap());
+            }
+            received
+        });
+
+        let writer = RetryService::new(writer, idle::backoff);
+        writer.process(err.remaining).unwrap();
+
+        let received = drain.join().unwrap();
+        assert_eq!(payload, received);
+    }
+
+    #[test]
+    fn service_writer_calls_service_on_flush() {
+        let received = Mutex::new(Vec::new());
+        let mut writer = ServiceWriter::new(FnService::new(|bytes: Vec<u8>| {
+            received.lock().unwrap().push(bytes);
+            Ok::<_, io::Error>(())
+        }));
+
+        write!(writer, "hello, ").unwrap();
+        writer.write_all(b"world").unwrap();
+        assert!(received.lock().unwrap().is_empty());
+
+        writer.flush().unwrap();
+        writer.flush().unwrap();
+        assert_eq!(vec![b"hello, world".to_vec()], *received.lock().unwrap());
+
+        writer.write_all(b"!").unwrap();
+        drop(writer);
+        assert_eq!(
+            vec![b"hello, world".to_vec(), b"!".to_vec()],
+            *received.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn service_reader_reads_until_none() {
+        let chunks = Mutex::new(vec![&b"abc"[..], b"", b"defgh"].into_iter());
+        let mut reader = ServiceReader::new(FnService::new(|_: ()| {
+            Ok::<_, io::Error>(chunks.lock().unwrap().next().map(<[u8]>::to_vec))
+        }));
+
+        let mut buf = [0; 2];
+        assert_eq!(2, reader.read(&mut buf).unwrap());
+        assert_eq!(b"ab", &buf);
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!("cdefgh", rest);
+        assert_eq!(0, reader.read(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn protocol_negotiate_routes_by_leading_bytes() {
+        #[derive(Debug, PartialEq, Eq, Hash)]
+        enum Protocol {
+            WebSocket,
+            Framed,
+        }
+        let detect = |leading: &[u8]| match leading {
+            [b'G', b'E', b'T', b' ', ..] => Some(Protocol::WebSocket),
+            [0xf0, ..] => Some(Protocol::Framed),
+            _ => None,
+        };
+        let service = ProtocolNegotiateService::new(4, detect)
+            .protocol(
+                Protocol::WebSocket,
+                FnService::new(|mut stream: PeekedStream<TcpStream>| {
+                    let mut request = String::new();
+                    stream.read_to_string(&mut request)?;
+                    stream.write_all(b"HTTP/1.1 101 Switching Protocols\r\n\r\n")?;
+                    Ok::<_, io::Error>(format!("ws:{}", request.lines().next().unwrap()))
+                }),
+            )
+            .protocol(
+                Protocol::Framed,
+                FnService::new(|mut stream: PeekedStream<TcpStream>| {
+                    // a marker byte and a length prefix, followed by the payload
+                    let mut header = [0; 2];
+                    stream.read_exact(&mut header)?;
+                    let mut payload = vec![0; header[1] as usize];
+                    stream.read_exact(&mut payload)?;
+                    stream.write_all(&[header[1]])?;
+                    Ok(format!("framed:{}", String::from_utf8_lossy(&payload)))
+                }),
+            );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = |request: &'static [u8]| {
+            thread::spawn(move || {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                stream.write_all(request).unwrap();
+                stream.shutdown(std::net::Shutdown::Write).unwrap();
+                let mut response = Vec::new();
+                stream.read_to_end(&mut response).unwrap();
+                response
+            })
+        };
+
+        let websocket =
+            client(b"GET /chat HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n");
+        let (stream, _) = listener.accept().unwrap();
+        assert_eq!("ws:GET /chat HTTP/1.1", service.process(stream).unwrap());
+        assert!(websocket.join().unwrap().starts_with(b"HTTP/1.1 101"));
+
+        let framed = client(&[0xf0, 5, b'h', b'e', b'l', b'l', b'o']);
+        let (stream, _) = listener.accept().unwrap();
+        assert_eq!("framed:hello", service.process(stream).unwrap());
+        assert_eq!(vec![5], framed.join().unwrap());
+
+        let unknown = client(b"\x00\x01");
+        let (stream, _) = listener.accept().unwrap();
+        assert!(matches!(
+            service.process(stream),
+            Err(ProtocolNegotiateError::UnknownProtocol { leading }) if leading == [0, 1]
+        ));
+        unknown.join().unwrap();
+    }
+
+    #[test]
+    fn protocol_negotiate_dispatches_short_first_message() {
+        let detect = |leading: &[u8]| match leading {
+            [b'P', b'I', b'N', b'G', ..] => Some("ping"),
+            _ => None,
+        };
+        let service = ProtocolNegotiateService::new(16, detect).protocol(
+            "ping",
+            FnService::new(|mut stream: PeekedStream<TcpStream>| {
+                let mut request = [0; 6];
+                stream.read_exact(&mut request)?;
+                stream.write_all(b"PONG\r\n")?;
+                Ok::<_, io::Error>(request)
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // the client keeps its side open, waiting for a reply to a message shorter than the peek length
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"PING\r\n").unwrap();
+            let mut response = [0; 6];
+            stream.read_exact(&mut response).unwrap();
+            response
+        });
+        let (stream, _) = listener.accept().unwrap();
+        assert_eq!(*b"PING\r\n", service.process(stream).unwrap());
+        assert_eq!(*b"PONG\r\n", client.join().unwrap());
+    }
+}
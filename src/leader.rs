@@ -0,0 +1,120 @@
+//! Services that only act while this instance holds leadership, allowing the same binary to run on many instances while only one acts.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{async_trait, AsyncService, Service};
+
+/// A pluggable source of leadership, used by [`LeaderGatedService`].
+///
+/// Implementations backed by an external lock, such as an etcd lease or a Redis lock, allow a single leader to be elected across a cluster.
+pub trait Elector {
+    /// Returns `true` if this instance is currently the leader
+    fn is_leader(&self) -> bool;
+}
+impl<E: Elector> Elector for &E {
+    fn is_leader(&self) -> bool {
+        (*self).is_leader()
+    }
+}
+impl<E: Elector> Elector for Arc<E> {
+    fn is_leader(&self) -> bool {
+        self.as_ref().is_leader()
+    }
+}
+
+/// An in-memory [`Elector`], where leadership is set explicitly.
+///
+/// Clones of an [`InMemoryElector`] share the same leadership, so a clone may be given to a service while the original is toggled by a local election or a test.
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryElector {
+    leader: Arc<AtomicBool>,
+}
+impl InMemoryElector {
+    /// Create a new [`InMemoryElector`] with the given initial leadership
+    pub fn new(leader: bool) -> Self {
+        Self {
+            leader: Arc::new(AtomicBool::new(leader)),
+        }
+    }
+
+    /// Set whether this instance is the leader
+    pub fn set_leader(&self, leader: bool) {
+        self.leader.store(leader, Ordering::Release)
+    }
+}
+impl Elector for InMemoryElector {
+    fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::Acquire)
+    }
+}
+
+/// A [`Service`] or [`AsyncService`] that only delegates to the underlying service while the given [`Elector`] reports leadership.
+///
+/// While leader, the input is passed to the underlying service and `Some(Output)` is produced.
+/// While not leader, the input is dropped and `None` is produced.
+///
+/// Leadership is checked before each call, so leadership lost during a call does not interrupt that call.
+pub struct LeaderGatedService<S, E> {
+    service: S,
+    elector: E,
+}
+impl<S, E: Elector> LeaderGatedService<S, E> {
+    pub fn new(service: S, elector: E) -> Self {
+        Self { service, elector }
+    }
+}
+impl<S: Service, E: Elector> Service for LeaderGatedService<S, E> {
+    type Input = S::Input;
+    type Output = Option<S::Output>;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        match self.elector.is_leader() {
+            true => Ok(Some(self.service.process(input)?)),
+            false => Ok(None),
+        }
+    }
+}
+#[async_trait]
+impl<S: AsyncService, E: Elector + Send + Sync> AsyncService for LeaderGatedService<S, E> {
+    type Input = S::Input;
+    type Output = Option<S::Output>;
+    type Error = S::Error;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        match self.elector.is_leader() {
+            true => Ok(Some(self.service.process(input).await?)),
+            false => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, sync::atomic::AtomicUsize};
+
+    use super::*;
+    use crate::FnService;
+
+    #[test]
+    fn leader_gated_only_runs_while_leader() {
+        let calls = AtomicUsize::new(0);
+        let elector = InMemoryElector::new(false);
+        let service = LeaderGatedService::new(
+            FnService::new(|input: u32| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                Ok::<_, Infallible>(input)
+            }),
+            elector.clone(),
+        );
+
+        assert_eq!(Ok(None), service.process(1));
+        elector.set_leader(true);
+        assert_eq!(Ok(Some(2)), service.process(2));
+        assert_eq!(Ok(Some(3)), service.process(3));
+        elector.set_leader(false);
+        assert_eq!(Ok(None), service.process(4));
+        assert_eq!(2, calls.load(Ordering::Relaxed));
+    }
+}
@@ -16,7 +16,9 @@ use std::{
     convert::Infallible,
     error::Error,
     fmt::{Debug, Display},
+    future::Future,
     marker::PhantomData,
+    pin::Pin,
     rc::Rc,
     sync::{Arc, Mutex},
     thread::{spawn, JoinHandle},
@@ -27,8 +29,43 @@ use std::{
 #[doc(inline)]
 pub use async_trait::async_trait;
 
+pub mod ack;
+pub mod balance;
+pub mod breaker;
+pub mod cancel;
+pub mod clock;
+pub mod coalesce;
+pub mod config;
+pub mod correlate;
+pub mod deadline;
+pub mod dedup;
+pub mod drain;
+pub mod format;
+pub mod health;
+pub mod hedge;
 pub mod idle;
+pub mod io;
+pub mod leader;
+pub mod limit;
+pub mod lock;
+pub mod migrate;
+pub mod multiplex;
+pub mod optimistic;
+pub mod pool;
+pub mod queue;
+pub mod replay;
+pub mod resilience;
+pub mod saga;
+pub mod script;
+pub mod sequence;
+pub mod snapshot;
+pub mod stream;
+pub mod tap;
 pub mod thread;
+pub mod timing;
+
+#[cfg(test)]
+mod testing;
 
 /// A sync service trait
 ///
@@ -99,17 +136,39 @@ pub trait AsyncService: Send + Sync {
     async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error>;
 }
 
+/// The boxed [`Future`] of a single [`AsyncService`] call, returned by [`ServiceFutureExt::call`]
+pub type ServiceFuture<'a, O, E> = Pin<Box<dyn Future<Output = Result<O, E>> + Send + 'a>>;
+
+/// Produce the [`Future`] of a single call to the given [`AsyncService`], which may be awaited or combined with other futures, such as by `join!` or `select!`.
+pub fn service_future<S: AsyncService + ?Sized>(
+    service: &S,
+    input: S::Input,
+) -> impl Future<Output = Result<S::Output, S::Error>> + Send + '_ {
+    service.process(input)
+}
+
+/// Extends every [`AsyncService`] with [`ServiceFutureExt::call`], which produces the [`Future`] of a single call as a [`ServiceFuture`].
+///
+/// Unlike the `impl Future` returned by [`service_future`], a [`ServiceFuture`] may be named, such as to store pending calls in a `Vec`.
+pub trait ServiceFutureExt: AsyncService {
+    /// Produce the [`Future`] of a single call to this service with the given input
+    fn call(&self, input: Self::Input) -> ServiceFuture<'_, Self::Output, Self::Error> {
+        self.process(input)
+    }
+}
+impl<S: AsyncService + ?Sized> ServiceFutureExt for S {}
+
 /// A [`MutService`] that encapsulates an underlying [`Service`], exposing it as `mut`.
 pub struct ServiceMut<S: Service> {
     service: S,
 }
-impl<'a, S: Service> ServiceMut<S> {
+impl<S: Service> ServiceMut<S> {
     pub fn new(service: S) -> Self {
         Self { service }
     }
 }
 #[async_trait]
-impl<'a, S: Service> MutService for ServiceMut<S> {
+impl<S: Service> MutService for ServiceMut<S> {
     type Input = S::Input;
     type Output = S::Output;
     type Error = S::Error;
@@ -117,7 +176,7 @@ impl<'a, S: Service> MutService for ServiceMut<S> {
         self.service.process(input)
     }
 }
-impl<'a, S: Service> From<S> for ServiceMut<S> {
+impl<S: Service> From<S> for ServiceMut<S> {
     fn from(service: S) -> Self {
         Self { service }
     }
@@ -127,13 +186,13 @@ impl<'a, S: Service> From<S> for ServiceMut<S> {
 pub struct ServiceAsync<S: Service> {
     service: S,
 }
-impl<'a, S: Service + Send + Sync> ServiceAsync<S> {
+impl<S: Service + Send + Sync> ServiceAsync<S> {
     pub fn new(service: S) -> Self {
         Self { service }
     }
 }
 #[async_trait]
-impl<'a, S: Service + Send + Sync> AsyncService for ServiceAsync<S>
+impl<S: Service + Send + Sync> AsyncService for ServiceAsync<S>
 where
     S::Input: Send + 'static,
     S::Output: Send + 'static,
@@ -146,7 +205,7 @@ where
         self.service.process(input)
     }
 }
-impl<'a, S: Service + Send + Sync> From<S> for ServiceAsync<S> {
+impl<S: Service + Send + Sync> From<S> for ServiceAsync<S> {
     fn from(service: S) -> Self {
         Self { service }
     }
@@ -247,6 +306,11 @@ impl<O, I: Into<O>> IntoService<O, I> {
         }
     }
 }
+impl<O, I: Into<O>> Default for IntoService<O, I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl<O, I: Into<O>> Service for IntoService<O, I> {
     type Input = I;
     type Output = O;
@@ -267,6 +331,11 @@ impl<O, I: TryInto<O>> TryIntoService<O, I> {
         }
     }
 }
+impl<O, I: TryInto<O>> Default for TryIntoService<O, I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl<O, E, I: TryInto<O, Error = E>> Service for TryIntoService<O, I> {
     type Input = I;
     type Output = O;
@@ -276,6 +345,107 @@ impl<O, E, I: TryInto<O, Error = E>> Service for TryIntoService<O, I> {
     }
 }
 
+/// A [`Service`], [`MutService`], or [`AsyncService`] which passes the input as `Ok(output)` when it satisfies the given predicate,
+/// or rejects it with the error produced by the given function.
+///
+/// This may be used in place of a [`NoOpService`] between the links of a chain, to validate inputs before they reach downstream services.
+pub struct FilterService<I, F, G> {
+    predicate: F,
+    reject: G,
+    _phantom: PhantomData<fn(I)>,
+}
+impl<I, E, F: Fn(&I) -> bool, G: Fn() -> E> FilterService<I, F, G> {
+    pub fn new(predicate: F, reject: G) -> Self {
+        Self {
+            predicate,
+            reject,
+            _phantom: PhantomData,
+        }
+    }
+
+    fn filter(&self, input: I) -> Result<I, E> {
+        match (self.predicate)(&input) {
+            true => Ok(input),
+            false => Err((self.reject)()),
+        }
+    }
+}
+impl<I, E, F: Fn(&I) -> bool, G: Fn() -> E> Service for FilterService<I, F, G> {
+    type Input = I;
+    type Output = I;
+    type Error = E;
+    fn process(&self, input: I) -> Result<I, E> {
+        self.filter(input)
+    }
+}
+impl<I, E, F: Fn(&I) -> bool, G: Fn() -> E> MutService for FilterService<I, F, G> {
+    type Input = I;
+    type Output = I;
+    type Error = E;
+    fn process(&mut self, input: I) -> Result<I, E> {
+        self.filter(input)
+    }
+}
+#[async_trait]
+impl<I, E, F, G> AsyncService for FilterService<I, F, G>
+where
+    I: Send + 'static,
+    E: Send + 'static,
+    F: Fn(&I) -> bool + Send + Sync,
+    G: Fn() -> E + Send + Sync,
+{
+    type Input = I;
+    type Output = I;
+    type Error = E;
+    async fn process(&self, input: I) -> Result<I, E> {
+        self.filter(input)
+    }
+}
+
+/// A [`Service`], [`MutService`], or [`AsyncService`] which passes a reference of the input to the given function, then passes the input as `Ok(output)`.
+///
+/// This may be inserted between the links of a chain to observe the values flowing through it, such as to increment a metric or print a value while debugging.
+pub struct InspectService<I, F> {
+    f: F,
+    _phantom: PhantomData<fn(I)>,
+}
+impl<I, F: Fn(&I)> InspectService<I, F> {
+    pub fn new(f: F) -> Self {
+        Self {
+            f,
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<I, F: Fn(&I)> Service for InspectService<I, F> {
+    type Input = I;
+    type Output = I;
+    type Error = Infallible;
+    fn process(&self, input: I) -> Result<I, Infallible> {
+        (self.f)(&input);
+        Ok(input)
+    }
+}
+impl<I, F: Fn(&I)> MutService for InspectService<I, F> {
+    type Input = I;
+    type Output = I;
+    type Error = Infallible;
+    fn process(&mut self, input: I) -> Result<I, Infallible> {
+        (self.f)(&input);
+        Ok(input)
+    }
+}
+#[async_trait]
+impl<I: Send + 'static, F: Fn(&I) + Send + Sync> AsyncService for InspectService<I, F> {
+    type Input = I;
+    type Output = I;
+    type Error = Infallible;
+    async fn process(&self, input: I) -> Result<I, Infallible> {
+        (self.f)(&input);
+        Ok(input)
+    }
+}
+
 /// A [`Service`] which no-ops, passing the input as `Ok(output)`.
 pub struct NoOpService<'a, T> {
     _phantom: PhantomData<fn(&'a T)>,
@@ -287,6 +457,11 @@ impl<'a, T> NoOpService<'a, T> {
         }
     }
 }
+impl<'a, T> Default for NoOpService<'a, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl<'a, T> Service for NoOpService<'a, T> {
     type Input = T;
     type Output = T;
@@ -468,6 +643,158 @@ impl<I, O, E, F: Fn(I) -> Result<O, E>> MutService for FnMutService<I, O, E, F>
     }
 }
 
+/// A [`Service`], [`MutService`], or [`AsyncService`] that encapsulates another service, converting any error it returns using the given function.
+///
+/// This may be used to give two services the same error type, such as before passing them to `fork_clone` in a service chain.
+pub struct MapErrService<S, F> {
+    service: S,
+    f: F,
+}
+impl<S, F> MapErrService<S, F> {
+    pub fn new(service: S, f: F) -> Self {
+        Self { service, f }
+    }
+}
+impl<S: Service, E, F: Fn(S::Error) -> E> Service for MapErrService<S, F> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = E;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.service.process(input).map_err(&self.f)
+    }
+}
+impl<S: MutService, E, F: Fn(S::Error) -> E> MutService for MapErrService<S, F> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = E;
+    fn process(&mut self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.service.process(input).map_err(&self.f)
+    }
+}
+#[async_trait]
+impl<S, E, F> AsyncService for MapErrService<S, F>
+where
+    S: AsyncService,
+    E: Send + 'static,
+    F: Fn(S::Error) -> E + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = E;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.service.process(input).await.map_err(&self.f)
+    }
+}
+
+/// A [`Service`], [`MutService`], or [`AsyncService`] that encapsulates another service, passing its output to the given fallible function.
+///
+/// When the underlying service fails, its error is returned without calling the function.
+/// Otherwise, the result of the function is returned, which shares the error type of the underlying service.
+pub struct AndThenService<S, F> {
+    service: S,
+    f: F,
+}
+impl<S, F> AndThenService<S, F> {
+    pub fn new(service: S, f: F) -> Self {
+        Self { service, f }
+    }
+}
+impl<S: Service, O, F: Fn(S::Output) -> Result<O, S::Error>> Service for AndThenService<S, F> {
+    type Input = S::Input;
+    type Output = O;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.service.process(input).and_then(&self.f)
+    }
+}
+impl<S: MutService, O, F: Fn(S::Output) -> Result<O, S::Error>> MutService
+    for AndThenService<S, F>
+{
+    type Input = S::Input;
+    type Output = O;
+    type Error = S::Error;
+    fn process(&mut self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.service.process(input).and_then(&self.f)
+    }
+}
+#[async_trait]
+impl<S, O, F> AsyncService for AndThenService<S, F>
+where
+    S: AsyncService,
+    O: Send + 'static,
+    F: Fn(S::Output) -> Result<O, S::Error> + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = O;
+    type Error = S::Error;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.service.process(input).await.and_then(&self.f)
+    }
+}
+
+/// A [`Service`], [`MutService`], or [`AsyncService`] that calls a primary service, falling back to a secondary service with a clone of the same input when the primary fails.
+///
+/// Unlike a [`RetryService`], the fallback is a different service, such as a backup replica or a cache.
+/// When the primary succeeds, the secondary is not called. When the primary fails, its error is discarded, and the result of the secondary is returned.
+pub struct OrElseService<S1, S2> {
+    primary: S1,
+    secondary: S2,
+}
+impl<S1, S2> OrElseService<S1, S2> {
+    pub fn new(primary: S1, secondary: S2) -> Self {
+        Self { primary, secondary }
+    }
+}
+impl<S1, S2> Service for OrElseService<S1, S2>
+where
+    S1: Service,
+    S1::Input: Clone,
+    S2: Service<Input = S1::Input, Output = S1::Output>,
+{
+    type Input = S1::Input;
+    type Output = S1::Output;
+    type Error = S2::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        match self.primary.process(input.clone()) {
+            Ok(output) => Ok(output),
+            Err(_) => self.secondary.process(input),
+        }
+    }
+}
+impl<S1, S2> MutService for OrElseService<S1, S2>
+where
+    S1: MutService,
+    S1::Input: Clone,
+    S2: MutService<Input = S1::Input, Output = S1::Output>,
+{
+    type Input = S1::Input;
+    type Output = S1::Output;
+    type Error = S2::Error;
+    fn process(&mut self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        match self.primary.process(input.clone()) {
+            Ok(output) => Ok(output),
+            Err(_) => self.secondary.process(input),
+        }
+    }
+}
+#[async_trait]
+impl<S1, S2> AsyncService for OrElseService<S1, S2>
+where
+    S1: AsyncService,
+    S1::Input: Clone,
+    S2: AsyncService<Input = S1::Input, Output = S1::Output>,
+{
+    type Input = S1::Input;
+    type Output = S1::Output;
+    type Error = S2::Error;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        match self.primary.process(input.clone()).await {
+            Ok(output) => Ok(output),
+            Err(_) => self.secondary.process(input).await,
+        }
+    }
+}
+
 /// A [`Service`], [`MutService`], or [`AsyncService`] that encapsulates two service and accepts a [`Clone`]able input, which is passed to both underlying services, returning their outputs as a tuple.
 pub struct CloningForkService<S1, S2> {
     first: S1,
@@ -525,6 +852,174 @@ where
     }
 }
 
+/// A [`Service`], [`MutService`], or [`AsyncService`] that encapsulates two service and accepts a [`Clone`]able input, which is passed to both underlying services, combining their outputs into a single output using the given function.
+pub struct CombiningForkService<S1, S2, F> {
+    fork: CloningForkService<S1, S2>,
+    combine: F,
+}
+impl<S1, S2, F> CombiningForkService<S1, S2, F> {
+    pub fn new(first: S1, second: S2, combine: F) -> Self {
+        Self {
+            fork: CloningForkService::new(first, second),
+            combine,
+        }
+    }
+}
+impl<O, S1: Service, S2: Service<Input = S1::Input, Error = S1::Error>, F> Service
+    for CombiningForkService<S1, S2, F>
+where
+    S1::Input: Clone,
+    F: Fn(S1::Output, S2::Output) -> O,
+{
+    type Input = S1::Input;
+    type Output = O;
+    type Error = S1::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let (first, second) = self.fork.process(input)?;
+        Ok((self.combine)(first, second))
+    }
+}
+impl<O, S1: MutService, S2: MutService<Input = S1::Input, Error = S1::Error>, F> MutService
+    for CombiningForkService<S1, S2, F>
+where
+    S1::Input: Clone,
+    F: Fn(S1::Output, S2::Output) -> O,
+{
+    type Input = S1::Input;
+    type Output = O;
+    type Error = S1::Error;
+    fn process(&mut self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let (first, second) = self.fork.process(input)?;
+        Ok((self.combine)(first, second))
+    }
+}
+#[async_trait]
+impl<O, S1: AsyncService, S2: AsyncService<Input = S1::Input, Error = S1::Error>, F> AsyncService
+    for CombiningForkService<S1, S2, F>
+where
+    S1::Input: Clone + Sync,
+    F: Fn(S1::Output, S2::Output) -> O + Send + Sync,
+    O: Send + 'static,
+{
+    type Input = S1::Input;
+    type Output = O;
+    type Error = S1::Error;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let (first, second) = self.fork.process(input).await?;
+        Ok((self.combine)(first, second))
+    }
+}
+
+/// A [`Service`], [`MutService`], or [`AsyncService`] that encapsulates two services and accepts a [`Clone`]able input, which is passed to both underlying services,
+/// producing their output only when both services agree.
+///
+/// This supports fault detection by redundant execution, such as comparing a new implementation against an old one, or double-computing a critical value.
+/// When the outputs are equal, the first output is produced and the second is dropped.
+/// When the outputs differ, [`VerifyForkError::ForkMismatch`] is returned with both outputs.
+/// When the first service fails, the second service is not called.
+pub struct VerifyForkService<S1, S2> {
+    fork: CloningForkService<S1, S2>,
+}
+impl<S1, S2> VerifyForkService<S1, S2> {
+    pub fn new(first: S1, second: S2) -> Self {
+        Self {
+            fork: CloningForkService::new(first, second),
+        }
+    }
+}
+impl<S1, S2> Service for VerifyForkService<S1, S2>
+where
+    S1: Service,
+    S1::Input: Clone,
+    S1::Output: Eq,
+    S2: Service<Input = S1::Input, Output = S1::Output, Error = S1::Error>,
+{
+    type Input = S1::Input;
+    type Output = S1::Output;
+    type Error = VerifyForkError<S1::Output, S1::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let (first, second) = self
+            .fork
+            .process(input)
+            .map_err(VerifyForkError::ServiceError)?;
+        VerifyForkError::verify(first, second)
+    }
+}
+impl<S1, S2> MutService for VerifyForkService<S1, S2>
+where
+    S1: MutService,
+    S1::Input: Clone,
+    S1::Output: Eq,
+    S2: MutService<Input = S1::Input, Output = S1::Output, Error = S1::Error>,
+{
+    type Input = S1::Input;
+    type Output = S1::Output;
+    type Error = VerifyForkError<S1::Output, S1::Error>;
+    fn process(&mut self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let (first, second) = self
+            .fork
+            .process(input)
+            .map_err(VerifyForkError::ServiceError)?;
+        VerifyForkError::verify(first, second)
+    }
+}
+#[async_trait]
+impl<S1, S2> AsyncService for VerifyForkService<S1, S2>
+where
+    S1: AsyncService,
+    S1::Input: Clone + Sync,
+    S1::Output: Eq,
+    S2: AsyncService<Input = S1::Input, Output = S1::Output, Error = S1::Error>,
+{
+    type Input = S1::Input;
+    type Output = S1::Output;
+    type Error = VerifyForkError<S1::Output, S1::Error>;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let (first, second) = self
+            .fork
+            .process(input)
+            .await
+            .map_err(VerifyForkError::ServiceError)?;
+        VerifyForkError::verify(first, second)
+    }
+}
+
+/// Returned by [`VerifyForkService`], either when the underlying services disagree or when either underlying service fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifyForkError<O, E> {
+    ForkMismatch { first: O, second: O },
+    ServiceError(E),
+}
+impl<O: Eq, E> VerifyForkError<O, E> {
+    fn verify(first: O, second: O) -> Result<O, Self> {
+        match first == second {
+            true => Ok(first),
+            false => Err(Self::ForkMismatch { first, second }),
+        }
+    }
+}
+impl<O: Debug, E: Display> Display for VerifyForkError<O, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ForkMismatch { first, second } => {
+                write!(
+                    f,
+                    "fork mismatch: first produced {first:?}, second produced {second:?}"
+                )
+            }
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<O: Debug, E: Error + 'static> Error for VerifyForkError<O, E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::ForkMismatch { .. } => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
 /// A [`Service`], [`MutService`], or [`AsyncService`] that encapsulates two service and accepts a input as a reference, which is passed to both underlying services, returning their outputs as a tuple.
 pub struct RefForkService<I, S1, S2> {
     first: S1,
@@ -571,18 +1066,197 @@ impl<
     }
 }
 
-/// A [`Service`], [`MutService`], or [`AsyncService`], which encapsulates a `Service<(), Output = Option<T>>`, `MutService<(), Output = Option<T>>`, or `AsyncService<(), Output = Option<T>>`, blocking with the given idle function until a value is returned or the idle function returns an error.
-///
-/// When the underlying `Service<()>` returns None, the given idle function will be called.
-/// The idle function will be called repeatedly, given the attempt number as input, until Some(T) is returned by the underlying service.
-/// The idle function may return `Err(RetryError::Interrupted)` to return an error and avoid blocking forever.
+/// A [`Service`], [`MutService`], or [`AsyncService`] that encapsulates two service and accepts a [`Clone`]able input, which is always passed to both underlying services, returning their outputs as a tuple only when both succeed.
 ///
-/// See the [`idle`] module for some provided idle functions.
-pub struct PollService<E, S, F>
-where
-    F: Fn(usize) -> Result<(), RetryError<E>>,
-{
-    service: S,
+/// Unlike [`CloningForkService`], the second service is still called when the first service fails, so that both errors may be captured.
+/// When either service fails, a [`ForkError`] is returned, containing the error from each service that failed.
+/// When one service succeeds and the other fails, the successful output is dropped.
+pub struct ForkCollectErrors<S1, S2> {
+    first: S1,
+    second: S2,
+}
+impl<S1, S2> ForkCollectErrors<S1, S2> {
+    pub fn new(first: S1, second: S2) -> Self {
+        Self { first, second }
+    }
+}
+impl<S1: Service, S2: Service<Input = S1::Input>> Service for ForkCollectErrors<S1, S2>
+where
+    S1::Input: Clone,
+{
+    type Input = S1::Input;
+    type Output = (S1::Output, S2::Output);
+    type Error = ForkError<S1::Error, S2::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        ForkError::collect(
+            self.first.process(input.clone()),
+            self.second.process(input),
+        )
+    }
+}
+impl<S1: MutService, S2: MutService<Input = S1::Input>> MutService for ForkCollectErrors<S1, S2>
+where
+    S1::Input: Clone,
+{
+    type Input = S1::Input;
+    type Output = (S1::Output, S2::Output);
+    type Error = ForkError<S1::Error, S2::Error>;
+    fn process(&mut self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        ForkError::collect(
+            self.first.process(input.clone()),
+            self.second.process(input),
+        )
+    }
+}
+#[async_trait]
+impl<S1: AsyncService, S2: AsyncService<Input = S1::Input>> AsyncService
+    for ForkCollectErrors<S1, S2>
+where
+    S1::Input: Clone + Sync,
+{
+    type Input = S1::Input;
+    type Output = (S1::Output, S2::Output);
+    type Error = ForkError<S1::Error, S2::Error>;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        ForkError::collect(
+            self.first.process(input.clone()).await,
+            self.second.process(input).await,
+        )
+    }
+}
+
+/// Returned by [`ForkCollectErrors`] when either underlying service fails, containing the error from each service that failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForkError<E1, E2> {
+    pub first: Option<E1>,
+    pub second: Option<E2>,
+}
+impl<E1, E2> ForkError<E1, E2> {
+    fn collect<O1, O2>(
+        first: Result<O1, E1>,
+        second: Result<O2, E2>,
+    ) -> Result<(O1, O2), ForkError<E1, E2>> {
+        match (first, second) {
+            (Ok(first), Ok(second)) => Ok((first, second)),
+            (first, second) => Err(ForkError {
+                first: first.err(),
+                second: second.err(),
+            }),
+        }
+    }
+}
+impl<E1: Debug, E2: Debug> Display for ForkError<E1, E2> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ForkError(first: {:?}, second: {:?})",
+            self.first, self.second
+        )
+    }
+}
+impl<E1: Debug, E2: Debug> Error for ForkError<E1, E2> {}
+
+/// The policy of a [`BroadcastService`] when a sink fails
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BroadcastPolicy {
+    /// Deliver to every sink, regardless of failures
+    CollectAll,
+    /// Stop delivering at the first sink that fails
+    FailFast,
+}
+
+/// A [`Service`], [`MutService`], or [`AsyncService`], which delivers a clone of each input to every underlying sink in order, isolating each sink's failure.
+///
+/// Unlike a fork, one sink's failure does not fail the call. Instead, the result of each sink is collected and produced as a `Vec<Result<Output, Error>>`.
+/// With [`BroadcastPolicy::CollectAll`], the input is delivered to every sink, and a result is produced for every sink.
+/// With [`BroadcastPolicy::FailFast`], delivery stops at the first sink that fails, so the last result is that sink's `Err`, and later sinks do not receive the input.
+pub struct BroadcastService<S> {
+    sinks: Vec<S>,
+    policy: BroadcastPolicy,
+}
+impl<S> BroadcastService<S> {
+    /// Create a new [`BroadcastService`], which delivers to every sink using [`BroadcastPolicy::CollectAll`]
+    pub fn new(sinks: Vec<S>) -> Self {
+        Self::with_policy(sinks, BroadcastPolicy::CollectAll)
+    }
+
+    /// Create a new [`BroadcastService`] with the given [`BroadcastPolicy`]
+    pub fn with_policy(sinks: Vec<S>, policy: BroadcastPolicy) -> Self {
+        Self { sinks, policy }
+    }
+
+    fn is_done<O, E>(&self, results: &[Result<O, E>]) -> bool {
+        self.policy == BroadcastPolicy::FailFast && results.last().is_some_and(Result::is_err)
+    }
+}
+impl<S: Service> Service for BroadcastService<S>
+where
+    S::Input: Clone,
+{
+    type Input = S::Input;
+    type Output = Vec<Result<S::Output, S::Error>>;
+    type Error = Infallible;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let mut results = Vec::with_capacity(self.sinks.len());
+        for sink in self.sinks.iter() {
+            if self.is_done(&results) {
+                break;
+            }
+            results.push(sink.process(input.clone()));
+        }
+        Ok(results)
+    }
+}
+impl<S: MutService> MutService for BroadcastService<S>
+where
+    S::Input: Clone,
+{
+    type Input = S::Input;
+    type Output = Vec<Result<S::Output, S::Error>>;
+    type Error = Infallible;
+    fn process(&mut self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let mut results = Vec::with_capacity(self.sinks.len());
+        for i in 0..self.sinks.len() {
+            if self.is_done(&results) {
+                break;
+            }
+            results.push(self.sinks[i].process(input.clone()));
+        }
+        Ok(results)
+    }
+}
+#[async_trait]
+impl<S: AsyncService> AsyncService for BroadcastService<S>
+where
+    S::Input: Clone + Sync,
+{
+    type Input = S::Input;
+    type Output = Vec<Result<S::Output, S::Error>>;
+    type Error = Infallible;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let mut results = Vec::with_capacity(self.sinks.len());
+        for sink in self.sinks.iter() {
+            if self.is_done(&results) {
+                break;
+            }
+            results.push(sink.process(input.clone()).await);
+        }
+        Ok(results)
+    }
+}
+
+/// A [`Service`], [`MutService`], or [`AsyncService`], which encapsulates a `Service<(), Output = Option<T>>`, `MutService<(), Output = Option<T>>`, or `AsyncService<(), Output = Option<T>>`, blocking with the given idle function until a value is returned or the idle function returns an error.
+///
+/// When the underlying `Service<()>` returns None, the given idle function will be called.
+/// The idle function will be called repeatedly, given the attempt number as input, until Some(T) is returned by the underlying service.
+/// The idle function may return `Err(RetryError::Interrupted)` to return an error and avoid blocking forever.
+///
+/// See the [`idle`] module for some provided idle functions.
+pub struct PollService<E, S, F>
+where
+    F: Fn(usize) -> Result<(), RetryError<E>>,
+{
+    service: S,
     idle: F,
     _phantom: PhantomData<fn(E)>,
 }
@@ -611,11 +1285,7 @@ where
         loop {
             match self.service.process(()) {
                 Ok(Some(v)) => return Ok(v),
-                Ok(None) => {
-                    if let Err(err) = (self.idle)(attempt) {
-                        return Err(err);
-                    }
-                }
+                Ok(None) => (self.idle)(attempt)?,
                 Err(err) => return Err(RetryError::ServiceError(err)),
             }
             attempt += 1;
@@ -636,11 +1306,7 @@ where
         loop {
             match self.service.process(()) {
                 Ok(Some(v)) => return Ok(v),
-                Ok(None) => {
-                    if let Err(err) = (self.idle)(attempt) {
-                        return Err(err);
-                    }
-                }
+                Ok(None) => (self.idle)(attempt)?,
                 Err(err) => return Err(RetryError::ServiceError(err)),
             }
             attempt += 1;
@@ -663,11 +1329,7 @@ where
         loop {
             match self.service.process(()).await {
                 Ok(Some(v)) => return Ok(v),
-                Ok(None) => {
-                    if let Err(err) = (self.idle)(attempt) {
-                        return Err(err);
-                    }
-                }
+                Ok(None) => (self.idle)(attempt)?,
                 Err(err) => return Err(RetryError::ServiceError(err)),
             }
             attempt += 1;
@@ -687,6 +1349,9 @@ pub trait Retryable<I, E> {
 /// When the underlying service's `Service::process` function returns an Err, it is passed to the given `Retryable`, which must return an `Ok(Input)` to retry or an `Err` to return immediately.
 /// Between retries, the given `idle` function is called, given the attempt number as input, until `Ok(Output)` is returned by the underlying `Service` or `Err` is returned by the `Retryable` or `idle` function.
 ///
+/// A service created by [`RetryService::new`] retries without bound.
+/// A service created by [`RetryService::with_max_attempts`] returns `Err(RetryError::Exhausted)` once the given number of attempts have failed with retryable errors.
+///
 /// See the [`idle`] module for some provided idle functions.
 pub struct RetryService<E, S, F>
 where
@@ -694,13 +1359,36 @@ where
 {
     service: S,
     idle: F,
+    max_attempts: Option<usize>,
 }
 impl<E, S, F> RetryService<E, S, F>
 where
     F: Fn(usize) -> Result<(), RetryError<E>>,
 {
     pub fn new(service: S, idle: F) -> Self {
-        Self { service, idle }
+        Self {
+            service,
+            idle,
+            max_attempts: None,
+        }
+    }
+
+    /// Create a new [`RetryService`] which makes at most `max_attempts` attempts, including the first
+    ///
+    /// # Panics
+    /// Panics if `max_attempts` is zero.
+    pub fn with_max_attempts(service: S, idle: F, max_attempts: usize) -> Self {
+        assert!(max_attempts >= 1, "max_attempts must be at least 1");
+        Self {
+            service,
+            idle,
+            max_attempts: Some(max_attempts),
+        }
+    }
+
+    /// Returns `true` when the attempt with the given zero-based number was the last permitted attempt
+    fn exhausted(&self, attempt: usize) -> bool {
+        self.max_attempts.is_some_and(|max| attempt + 1 >= max)
     }
 }
 impl<S, F> Service for RetryService<S::Error, S, F>
@@ -717,6 +1405,12 @@ where
         loop {
             match self.service.process(input) {
                 Ok(v) => return Ok(v),
+                Err(err) if self.exhausted(attempt) => {
+                    return match self.service.parse_retry(err) {
+                        Ok(_) => Err(RetryError::Exhausted),
+                        Err(err) => Err(err),
+                    }
+                }
                 Err(err) => match (self.idle)(attempt) {
                     Ok(()) => match self.service.parse_retry(err) {
                         Ok(v) => input = v,
@@ -744,6 +1438,12 @@ where
         loop {
             match self.service.process(input).await {
                 Ok(v) => return Ok(v),
+                Err(err) if self.exhausted(attempt) => {
+                    return match self.service.parse_retry(err) {
+                        Ok(_) => Err(RetryError::Exhausted),
+                        Err(err) => Err(err),
+                    }
+                }
                 Err(err) => match (self.idle)(attempt) {
                     Ok(()) => match self.service.parse_retry(err) {
                         Ok(v) => input = v,
@@ -757,6 +1457,244 @@ where
     }
 }
 
+/// A [`Service`] or [`AsyncService`], which retries an underlying service when the given predicate returns true for the returned error, blocking until a value is returned, a non-retryable error is encountered, or the idle function returns an `Err`.
+///
+/// Unlike [`RetryService`], retryability is decided by the given `retry_if` function at the use-site, independent of any [`Retryable`] impl.
+/// Since the input is not handed back by the underlying service, the input must be [`Clone`], and a clone of the original input is passed to each attempt.
+/// Between retries, the given `idle` function is called, given the attempt number as input.
+///
+/// See the [`idle`] module for some provided idle functions.
+pub struct RetryIfService<E, S, P, F>
+where
+    P: Fn(&E) -> bool,
+    F: Fn(usize) -> Result<(), RetryError<E>>,
+{
+    service: S,
+    retry_if: P,
+    idle: F,
+    _phantom: PhantomData<fn(E)>,
+}
+impl<E, S, P, F> RetryIfService<E, S, P, F>
+where
+    P: Fn(&E) -> bool,
+    F: Fn(usize) -> Result<(), RetryError<E>>,
+{
+    pub fn new(service: S, retry_if: P, idle: F) -> Self {
+        Self {
+            service,
+            retry_if,
+            idle,
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<S, P, F> Service for RetryIfService<S::Error, S, P, F>
+where
+    S: Service,
+    S::Input: Clone,
+    P: Fn(&S::Error) -> bool,
+    F: Fn(usize) -> Result<(), RetryError<S::Error>>,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = RetryError<S::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let mut attempt = 0;
+        loop {
+            match self.service.process(input.clone()) {
+                Ok(v) => return Ok(v),
+                Err(err) if (self.retry_if)(&err) => (self.idle)(attempt)?,
+                Err(err) => return Err(RetryError::ServiceError(err)),
+            }
+            attempt += 1;
+        }
+    }
+}
+#[async_trait]
+impl<S, P, F> AsyncService for RetryIfService<S::Error, S, P, F>
+where
+    S: AsyncService,
+    S::Input: Clone + Sync,
+    P: Fn(&S::Error) -> bool + Send + Sync,
+    F: Fn(usize) -> Result<(), RetryError<S::Error>> + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = RetryError<S::Error>;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let mut attempt = 0;
+        loop {
+            match self.service.process(input.clone()).await {
+                Ok(v) => return Ok(v),
+                Err(err) if (self.retry_if)(&err) => (self.idle)(attempt)?,
+                Err(err) => return Err(RetryError::ServiceError(err)),
+            }
+            attempt += 1;
+        }
+    }
+}
+
+/// A [`Service`] or [`AsyncService`], which encapsulates a [`Retryable`] like [`RetryService`], but transforms the input before each retry.
+///
+/// This is useful when each attempt requires a modified input, such as a fresh nonce or an incremented attempt header.
+/// When the underlying service returns an `Err`, the given `idle` function is called, and the error is passed to the underlying [`Retryable`], exactly as in [`RetryService`].
+/// The [`Retryable`] still decides whether the error is retryable and hands back the input, which is then given by reference to the `transform` function,
+/// along with the same attempt number given to the `idle` function, to produce the input for the next attempt.
+///
+/// See the [`idle`] module for some provided idle functions.
+pub struct RetryWithInputService<E, S, T, F>
+where
+    F: Fn(usize) -> Result<(), RetryError<E>>,
+{
+    service: S,
+    transform: T,
+    idle: F,
+}
+impl<E, S, T, F> RetryWithInputService<E, S, T, F>
+where
+    F: Fn(usize) -> Result<(), RetryError<E>>,
+{
+    pub fn new(service: S, transform: T, idle: F) -> Self {
+        Self {
+            service,
+            transform,
+            idle,
+        }
+    }
+}
+impl<S, T, F> Service for RetryWithInputService<S::Error, S, T, F>
+where
+    S: Service + Retryable<S::Input, S::Error>,
+    T: Fn(&S::Input, usize) -> S::Input,
+    F: Fn(usize) -> Result<(), RetryError<S::Error>>,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = RetryError<S::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let mut input = input;
+        let mut attempt = 0;
+        loop {
+            match self.service.process(input) {
+                Ok(v) => return Ok(v),
+                Err(err) => {
+                    (self.idle)(attempt)?;
+                    input = (self.transform)(&self.service.parse_retry(err)?, attempt);
+                }
+            }
+            attempt += 1;
+        }
+    }
+}
+#[async_trait]
+impl<S, T, F> AsyncService for RetryWithInputService<S::Error, S, T, F>
+where
+    S: AsyncService + Retryable<S::Input, S::Error> + Send + Sync,
+    T: Fn(&S::Input, usize) -> S::Input + Send + Sync,
+    F: Fn(usize) -> Result<(), RetryError<S::Error>> + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = RetryError<S::Error>;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let mut input = input;
+        let mut attempt = 0;
+        loop {
+            match self.service.process(input).await {
+                Ok(v) => return Ok(v),
+                Err(err) => {
+                    (self.idle)(attempt)?;
+                    input = (self.transform)(&self.service.parse_retry(err)?, attempt);
+                }
+            }
+            attempt += 1;
+        }
+    }
+}
+
+/// A [`Service`] or [`AsyncService`], which retries an underlying service like [`RetryIfService`], but injects the same idempotency key into every attempt of a logical request.
+///
+/// A key is produced by the given `generate` function once per call to `process`, and the `inject` function builds each attempt's input from the logical input and the key.
+/// A new key is generated for the next call, so distinct logical requests are never mistaken for retries of one another.
+/// This allows a write that timed out after being applied to be retried safely, but only when the downstream service honors the key,
+/// such as by using an [`crate::dedup::IdempotencyService`].
+///
+/// When the underlying service returns an `Err` for which `retry_if` returns true, the given `idle` function is called before the next attempt.
+/// Any other `Err` is returned as [`RetryError::ServiceError`].
+///
+/// See the [`idle`] module for some provided idle functions.
+pub struct SafeRetryService<I, K, S, G, J, P, F> {
+    service: S,
+    generate: G,
+    inject: J,
+    retry_if: P,
+    idle: F,
+    _phantom: PhantomData<fn(I, K)>,
+}
+impl<I, K, S, G, J, P, F> SafeRetryService<I, K, S, G, J, P, F> {
+    pub fn new(service: S, generate: G, inject: J, retry_if: P, idle: F) -> Self {
+        Self {
+            service,
+            generate,
+            inject,
+            retry_if,
+            idle,
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<I, K, S, G, J, P, F> Service for SafeRetryService<I, K, S, G, J, P, F>
+where
+    S: Service,
+    G: Fn() -> K,
+    J: Fn(&I, &K) -> S::Input,
+    P: Fn(&S::Error) -> bool,
+    F: Fn(usize) -> Result<(), RetryError<S::Error>>,
+{
+    type Input = I;
+    type Output = S::Output;
+    type Error = RetryError<S::Error>;
+    fn process(&self, input: I) -> Result<Self::Output, Self::Error> {
+        let key = (self.generate)();
+        let mut attempt = 0;
+        loop {
+            match self.service.process((self.inject)(&input, &key)) {
+                Ok(v) => return Ok(v),
+                Err(err) if (self.retry_if)(&err) => (self.idle)(attempt)?,
+                Err(err) => return Err(RetryError::ServiceError(err)),
+            }
+            attempt += 1;
+        }
+    }
+}
+#[async_trait]
+impl<I, K, S, G, J, P, F> AsyncService for SafeRetryService<I, K, S, G, J, P, F>
+where
+    I: Send + Sync + 'static,
+    K: Send + Sync,
+    S: AsyncService,
+    G: Fn() -> K + Send + Sync,
+    J: Fn(&I, &K) -> S::Input + Send + Sync,
+    P: Fn(&S::Error) -> bool + Send + Sync,
+    F: Fn(usize) -> Result<(), RetryError<S::Error>> + Send + Sync,
+{
+    type Input = I;
+    type Output = S::Output;
+    type Error = RetryError<S::Error>;
+    async fn process(&self, input: I) -> Result<Self::Output, Self::Error> {
+        let key = (self.generate)();
+        let mut attempt = 0;
+        loop {
+            match self.service.process((self.inject)(&input, &key)).await {
+                Ok(v) => return Ok(v),
+                Err(err) if (self.retry_if)(&err) => (self.idle)(attempt)?,
+                Err(err) => return Err(RetryError::ServiceError(err)),
+            }
+            attempt += 1;
+        }
+    }
+}
+
 /// A [`Service`], which encapsulates a [`Retryable`], producing `None` when a retryable event is encounterd.
 ///
 /// This may be used to drive non-blocking duty-cycles in a service chain, continuously passing None through the service chain when no input is available.
@@ -786,22 +1724,132 @@ where
     }
 }
 
+/// The class of an error, as decided by the classify function of a [`ClassifyErrorService`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    /// The call may succeed if it is attempted again
+    Retryable,
+    /// The call will never succeed, and the caller should stop
+    Fatal,
+    /// The error may be safely disregarded
+    Ignorable,
+}
+
+/// A [`Service`], [`MutService`], or [`AsyncService`], which classifies errors from an underlying service, producing a uniform [`Classified`] error.
+///
+/// This centralizes error-handling policy in a single classify function, so a supervisor loop may react to [`Classified`] errors consistently,
+/// independent of the error types of the services it supervises.
+pub struct ClassifyErrorService<S, F> {
+    service: S,
+    classify: F,
+}
+impl<S, F> ClassifyErrorService<S, F> {
+    pub fn new(service: S, classify: F) -> Self {
+        Self { service, classify }
+    }
+}
+impl<S: Service, F: Fn(&S::Error) -> ErrorClass> Service for ClassifyErrorService<S, F> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = Classified<S::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.service
+            .process(input)
+            .map_err(|err| Classified::new((self.classify)(&err), err))
+    }
+}
+impl<S: MutService, F: Fn(&S::Error) -> ErrorClass> MutService for ClassifyErrorService<S, F> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = Classified<S::Error>;
+    fn process(&mut self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.service
+            .process(input)
+            .map_err(|err| Classified::new((self.classify)(&err), err))
+    }
+}
+#[async_trait]
+impl<S, F> AsyncService for ClassifyErrorService<S, F>
+where
+    S: AsyncService,
+    F: Fn(&S::Error) -> ErrorClass + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = Classified<S::Error>;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.service
+            .process(input)
+            .await
+            .map_err(|err| Classified::new((self.classify)(&err), err))
+    }
+}
+
+/// Returned by [`ClassifyErrorService`], encapsulating an error along with its [`ErrorClass`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Classified<E> {
+    Retryable(E),
+    Fatal(E),
+    Ignorable(E),
+}
+impl<E> Classified<E> {
+    pub fn new(class: ErrorClass, err: E) -> Self {
+        match class {
+            ErrorClass::Retryable => Self::Retryable(err),
+            ErrorClass::Fatal => Self::Fatal(err),
+            ErrorClass::Ignorable => Self::Ignorable(err),
+        }
+    }
+
+    /// The [`ErrorClass`] of the encapsulated error
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::Retryable(_) => ErrorClass::Retryable,
+            Self::Fatal(_) => ErrorClass::Fatal,
+            Self::Ignorable(_) => ErrorClass::Ignorable,
+        }
+    }
+
+    /// Unwrap the encapsulated error, discarding its class
+    pub fn into_inner(self) -> E {
+        match self {
+            Self::Retryable(err) | Self::Fatal(err) | Self::Ignorable(err) => err,
+        }
+    }
+}
+impl<E: Display> Display for Classified<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Retryable(err) => write!(f, "retryable: {err}"),
+            Self::Fatal(err) => write!(f, "fatal: {err}"),
+            Self::Ignorable(err) => write!(f, "ignorable: {err}"),
+        }
+    }
+}
+impl<E: Error + 'static> Error for Classified<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Retryable(err) | Self::Fatal(err) | Self::Ignorable(err) => Some(err),
+        }
+    }
+}
+
 /// Used by idle and retry services to interrupt a poll or retry loop
 #[derive(Clone)]
 pub enum RetryError<E> {
     Interrupted,
+    /// The maximum number of attempts failed
+    Exhausted,
     ServiceError(E),
 }
 impl<E: PartialEq> PartialEq for RetryError<E> {
     fn eq(&self, other: &Self) -> bool {
         match self {
-            Self::Interrupted => match other {
-                Self::Interrupted => true,
-                Self::ServiceError(_) => false,
-            },
+            Self::Interrupted => matches!(other, Self::Interrupted),
+            Self::Exhausted => matches!(other, Self::Exhausted),
             Self::ServiceError(err) => match other {
-                Self::Interrupted => false,
                 Self::ServiceError(other_err) => err == other_err,
+                _ => false,
             },
         }
     }
@@ -810,6 +1858,7 @@ impl<E: Debug> Debug for RetryError<E> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Interrupted => f.write_str("Interrupted"),
+            Self::Exhausted => f.write_str("Exhausted"),
             Self::ServiceError(e) => write!(f, "{e:?}"),
         }
     }
@@ -826,6 +1875,11 @@ impl<T: Clone, B: Borrow<T>> CloneService<T, B> {
         }
     }
 }
+impl<T: Clone, B: Borrow<T>> Default for CloneService<T, B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl<T: Clone, B: Borrow<T>> Service for CloneService<T, B> {
     type Input = B;
     type Output = T;
@@ -893,6 +1947,11 @@ impl<F> SpawnService<F> {
         }
     }
 }
+impl<F> Default for SpawnService<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl<T: Send + 'static, F: FnOnce() -> T + Send + 'static> Service for SpawnService<F> {
     type Input = F;
     type Output = JoinHandle<T>;
@@ -937,6 +1996,171 @@ impl Display for Stopped {
 }
 impl Error for Stopped {}
 
+/// When an [`AssertingService`] checks its contracts
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AssertionMode {
+    /// Check contracts only when `debug_assertions` are enabled, such as in debug builds
+    Debug,
+    /// Always check contracts
+    Strict,
+    /// Never check contracts, as in release builds
+    Disabled,
+}
+impl AssertionMode {
+    fn enabled(&self) -> bool {
+        match self {
+            Self::Debug => cfg!(debug_assertions),
+            Self::Strict => true,
+            Self::Disabled => false,
+        }
+    }
+}
+
+/// The contract violated when an [`AssertingService`] returns [`AssertionError::AssertionFailed`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Contract {
+    Input,
+    Output,
+}
+
+/// A [`Service`], [`MutService`], or [`AsyncService`], which checks lightweight contracts on the input and output of an underlying service,
+/// returning [`AssertionError::AssertionFailed`] when either check returns false.
+///
+/// This is intended to catch integration bugs early, not to validate untrusted input:
+/// by default, contracts are only checked when `debug_assertions` are enabled, and checks cost nothing in release builds.
+/// Use [`AssertingService::strict`] to always check contracts.
+///
+/// When the output contract is violated, the output is discarded.
+pub struct AssertingService<S, FIn, FOut> {
+    service: S,
+    check_input: FIn,
+    check_output: FOut,
+    mode: AssertionMode,
+}
+impl<S, FIn, FOut> AssertingService<S, FIn, FOut> {
+    pub fn new(service: S, check_input: FIn, check_output: FOut) -> Self {
+        Self::with_mode(service, check_input, check_output, AssertionMode::Debug)
+    }
+
+    pub fn with_mode(
+        service: S,
+        check_input: FIn,
+        check_output: FOut,
+        mode: AssertionMode,
+    ) -> Self {
+        Self {
+            service,
+            check_input,
+            check_output,
+            mode,
+        }
+    }
+
+    /// Always check contracts, regardless of `debug_assertions`
+    pub fn strict(mut self) -> Self {
+        self.mode = AssertionMode::Strict;
+        self
+    }
+}
+impl<S, FIn, FOut> Service for AssertingService<S, FIn, FOut>
+where
+    S: Service,
+    FIn: Fn(&S::Input) -> bool,
+    FOut: Fn(&S::Output) -> bool,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = AssertionError<S::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let enabled = self.mode.enabled();
+        if enabled && !(self.check_input)(&input) {
+            return Err(AssertionError::AssertionFailed(Contract::Input));
+        }
+        let output = self
+            .service
+            .process(input)
+            .map_err(AssertionError::ServiceError)?;
+        if enabled && !(self.check_output)(&output) {
+            return Err(AssertionError::AssertionFailed(Contract::Output));
+        }
+        Ok(output)
+    }
+}
+impl<S, FIn, FOut> MutService for AssertingService<S, FIn, FOut>
+where
+    S: MutService,
+    FIn: Fn(&S::Input) -> bool,
+    FOut: Fn(&S::Output) -> bool,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = AssertionError<S::Error>;
+    fn process(&mut self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let enabled = self.mode.enabled();
+        if enabled && !(self.check_input)(&input) {
+            return Err(AssertionError::AssertionFailed(Contract::Input));
+        }
+        let output = self
+            .service
+            .process(input)
+            .map_err(AssertionError::ServiceError)?;
+        if enabled && !(self.check_output)(&output) {
+            return Err(AssertionError::AssertionFailed(Contract::Output));
+        }
+        Ok(output)
+    }
+}
+#[async_trait]
+impl<S, FIn, FOut> AsyncService for AssertingService<S, FIn, FOut>
+where
+    S: AsyncService,
+    FIn: Fn(&S::Input) -> bool + Send + Sync,
+    FOut: Fn(&S::Output) -> bool + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = AssertionError<S::Error>;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let enabled = self.mode.enabled();
+        if enabled && !(self.check_input)(&input) {
+            return Err(AssertionError::AssertionFailed(Contract::Input));
+        }
+        let output = self
+            .service
+            .process(input)
+            .await
+            .map_err(AssertionError::ServiceError)?;
+        if enabled && !(self.check_output)(&output) {
+            return Err(AssertionError::AssertionFailed(Contract::Output));
+        }
+        Ok(output)
+    }
+}
+
+/// Returned by [`AssertingService`], either when a contract is violated or when the underlying service fails
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AssertionError<E> {
+    AssertionFailed(Contract),
+    ServiceError(E),
+}
+impl<E: Display> Display for AssertionError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AssertionFailed(Contract::Input) => f.write_str("input assertion failed"),
+            Self::AssertionFailed(Contract::Output) => f.write_str("output assertion failed"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<E: Error + 'static> Error for AssertionError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::AssertionFailed(_) => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
 /// A chain of [`Service`], [`MutService`], or [`AsyncService`] implementations, which is itself a single [`Service`], [`MutService`], or [`AsyncService`] that accepts the first service in the chain's input and produces the the last service in the chain's output.
 /// When any service in the chain returns an `Err`, the chain will break early, encapsulate the error in a `ServiceChainError`, and return `Err(ServiceChainError)` immediately.
 ///
@@ -1024,64 +2248,209 @@ where
 {
     type Input = P::Input;
     type Output = S::Output;
-    type Error = ServiceChainError<Box<dyn Debug>>;
+    type Error = ServiceChainError<Box<dyn Debug>>;
+    fn process(&mut self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let input = match self.prev.process(input) {
+            Ok(o) => o,
+            Err(e) => return Err(ServiceChainError::new(Box::new(e))),
+        };
+        let output = match self.service.process(input) {
+            Ok(o) => o,
+            Err(e) => return Err(ServiceChainError::new(Box::new(e))),
+        };
+        Ok(output)
+    }
+}
+#[async_trait]
+impl<P: AsyncService + Send + Sync, S: AsyncService<Input = P::Output> + Send + Sync> AsyncService
+    for ServiceChain<P, S>
+where
+    P::Error: Debug + Send + 'static,
+    S::Error: Debug + Send + 'static,
+    P::Output: Send,
+    S::Output: Send,
+{
+    type Input = P::Input;
+    type Output = S::Output;
+    type Error = ServiceChainError<Box<dyn Debug + Send>>;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let input = match self.prev.process(input).await {
+            Ok(o) => o,
+            Err(e) => return Err(ServiceChainError::new(Box::new(e))),
+        };
+        let output = match self.service.process(input).await {
+            Ok(o) => o,
+            Err(e) => return Err(ServiceChainError::new(Box::new(e))),
+        };
+        Ok(output)
+    }
+}
+
+/// Returned by [`ServiceChain`] when a service in the chain returns an `Err` [`Result`].
+///
+/// The error of the failing service is boxed as its `cause`, which may be inspected using [`ServiceChainError::cause`].
+/// Since the cause is only known to implement [`Debug`], [`Error::source`] returns `None`.
+/// Use a [`TypedServiceChain`] to preserve the concrete error type of each link instead.
+pub struct ServiceChainError<C: Debug> {
+    cause: C,
+}
+impl<C: Debug> ServiceChainError<C> {
+    fn new(cause: C) -> Self {
+        Self { cause }
+    }
+
+    /// The error returned by the service in the chain which failed
+    pub fn cause(&self) -> &C {
+        &self.cause
+    }
+
+    /// Unwrap the error returned by the service in the chain which failed
+    pub fn into_cause(self) -> C {
+        self.cause
+    }
+}
+impl<C: Debug> Error for ServiceChainError<C> {}
+impl<C: Debug> Debug for ServiceChainError<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServiceChainError")
+            .field("cause", &self.cause)
+            .finish()
+    }
+}
+impl<C: Debug> Display for ServiceChainError<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ServiceChainError")
+    }
+}
+
+/// A [`ServiceChain`] which uses the given function to convert the boxed cause of any [`ServiceChainError`] into a domain error.
+///
+/// Returned by the `end_mapped(self, f)` function of [`ServiceChainBuilder`], [`MutServiceChainBuilder`], and [`AsyncServiceChainBuilder`],
+/// so that callers receive `Result<Output, E>` instead of `Result<Output, ServiceChainError>`.
+pub struct MappedServiceChain<P, S, F> {
+    chain: ServiceChain<P, S>,
+    f: F,
+}
+impl<P, S, F> MappedServiceChain<P, S, F> {
+    fn new(chain: ServiceChain<P, S>, f: F) -> Self {
+        Self { chain, f }
+    }
+}
+impl<E, P: Service, S: Service<Input = P::Output>, F> Service for MappedServiceChain<P, S, F>
+where
+    P::Error: Debug + 'static,
+    S::Error: Debug + 'static,
+    F: Fn(Box<dyn Debug>) -> E,
+{
+    type Input = P::Input;
+    type Output = S::Output;
+    type Error = E;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        Service::process(&self.chain, input).map_err(|err| (self.f)(err.cause))
+    }
+}
+impl<E, P: MutService, S: MutService<Input = P::Output>, F> MutService
+    for MappedServiceChain<P, S, F>
+where
+    P::Error: Debug + 'static,
+    S::Error: Debug + 'static,
+    F: Fn(Box<dyn Debug>) -> E,
+{
+    type Input = P::Input;
+    type Output = S::Output;
+    type Error = E;
+    fn process(&mut self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        MutService::process(&mut self.chain, input).map_err(|err| (self.f)(err.cause))
+    }
+}
+#[async_trait]
+impl<E, P: AsyncService + Send + Sync, S: AsyncService<Input = P::Output> + Send + Sync, F>
+    AsyncService for MappedServiceChain<P, S, F>
+where
+    P::Error: Debug + Send + 'static,
+    S::Error: Debug + Send + 'static,
+    P::Output: Send,
+    S::Output: Send,
+    E: Send + 'static,
+    F: Fn(Box<dyn Debug + Send>) -> E + Send + Sync,
+{
+    type Input = P::Input;
+    type Output = S::Output;
+    type Error = E;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        AsyncService::process(&self.chain, input)
+            .await
+            .map_err(|err| (self.f)(err.cause))
+    }
+}
+
+/// A chain of two [`Service`], [`MutService`], or [`AsyncService`] implementations, like a [`ServiceChain`],
+/// which preserves the concrete error type of each link in a [`ChainError`] rather than boxing it in a [`ServiceChainError`].
+///
+/// This allows callers who control both links to match on the original error by value.
+/// Longer chains may be built by nesting, where `prev` is itself a [`TypedServiceChain`], at the cost of a nested [`ChainError`] type,
+/// so a [`ServiceChain`] remains preferable for deeply nested chains.
+pub struct TypedServiceChain<P, S> {
+    prev: P,
+    service: S,
+}
+impl<P, S> TypedServiceChain<P, S> {
+    /// Create a new [`TypedServiceChain`], passing the output of `prev` to `service`
+    pub fn new(prev: P, service: S) -> Self {
+        Self { prev, service }
+    }
+}
+impl<P: Service, S: Service<Input = P::Output>> Service for TypedServiceChain<P, S> {
+    type Input = P::Input;
+    type Output = S::Output;
+    type Error = ChainError<P::Error, S::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let input = self.prev.process(input).map_err(ChainError::Prev)?;
+        self.service.process(input).map_err(ChainError::This)
+    }
+}
+impl<P: MutService, S: MutService<Input = P::Output>> MutService for TypedServiceChain<P, S> {
+    type Input = P::Input;
+    type Output = S::Output;
+    type Error = ChainError<P::Error, S::Error>;
     fn process(&mut self, input: Self::Input) -> Result<Self::Output, Self::Error> {
-        let input = match self.prev.process(input) {
-            Ok(o) => o,
-            Err(e) => return Err(ServiceChainError::new(Box::new(e))),
-        };
-        let output = match self.service.process(input) {
-            Ok(o) => o,
-            Err(e) => return Err(ServiceChainError::new(Box::new(e))),
-        };
-        Ok(output)
+        let input = self.prev.process(input).map_err(ChainError::Prev)?;
+        self.service.process(input).map_err(ChainError::This)
     }
 }
 #[async_trait]
-impl<P: AsyncService + Send + Sync, S: AsyncService<Input = P::Output> + Send + Sync> AsyncService
-    for ServiceChain<P, S>
-where
-    P::Error: Debug + Send + 'static,
-    S::Error: Debug + Send + 'static,
-    P::Output: Send,
-    S::Output: Send,
-{
+impl<P: AsyncService, S: AsyncService<Input = P::Output>> AsyncService for TypedServiceChain<P, S> {
     type Input = P::Input;
     type Output = S::Output;
-    type Error = ServiceChainError<Box<dyn Debug + Send>>;
+    type Error = ChainError<P::Error, S::Error>;
     async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
-        let input = match self.prev.process(input).await {
-            Ok(o) => o,
-            Err(e) => return Err(ServiceChainError::new(Box::new(e))),
-        };
-        let output = match self.service.process(input).await {
-            Ok(o) => o,
-            Err(e) => return Err(ServiceChainError::new(Box::new(e))),
-        };
-        Ok(output)
+        let input = self.prev.process(input).await.map_err(ChainError::Prev)?;
+        self.service.process(input).await.map_err(ChainError::This)
     }
 }
 
-/// Returned by [`ServiceChain`] when a service in the chain returns an `Err` [`Result`].
-pub struct ServiceChainError<C: Debug> {
-    cause: C,
-}
-impl<C: Debug> ServiceChainError<C> {
-    fn new(cause: C) -> Self {
-        Self { cause }
-    }
+/// Returned by [`TypedServiceChain`], holding the concrete error of the link which failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChainError<P, S> {
+    /// The previous link in the chain failed
+    Prev(P),
+    /// This link in the chain failed
+    This(S),
 }
-impl<C: Debug> Error for ServiceChainError<C> {}
-impl<C: Debug> Debug for ServiceChainError<C> {
+impl<P: Display, S: Display> Display for ChainError<P, S> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("ServiceChainError")
-            .field("cause", &self.cause)
-            .finish()
+        match self {
+            Self::Prev(err) => write!(f, "{err}"),
+            Self::This(err) => write!(f, "{err}"),
+        }
     }
 }
-impl<C: Debug> Display for ServiceChainError<C> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("ServiceChainError")
+impl<P: Error + 'static, S: Error + 'static> Error for ChainError<P, S> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Prev(err) => Some(err),
+            Self::This(err) => Some(err),
+        }
     }
 }
 
@@ -1104,8 +2473,8 @@ impl<'a, S: Service> ServiceChainBuilder<NoOpService<'a, S::Input>, S> {
 }
 impl<P: Service, S: Service<Input = P::Output>> ServiceChainBuilder<P, S>
 where
-    P::Error: Debug,
-    S::Error: Debug,
+    P::Error: Debug + 'static,
+    S::Error: Debug + 'static,
 {
     /// Append another [`Service`] to the end of the service chain.
     pub fn next<NS: Service<Input = S::Output>>(
@@ -1122,8 +2491,8 @@ where
 }
 impl<P: Service, S: Service<Input = P::Output>> ServiceChainBuilder<P, S>
 where
-    P::Error: Debug,
-    S::Error: Debug,
+    P::Error: Debug + 'static,
+    S::Error: Debug + 'static,
     S::Output: Clone,
 {
     /// Fork the service chain to the given two services by cloning the input.
@@ -1143,8 +2512,29 @@ where
             },
         }
     }
+
+    /// Fork the service chain to the given two services by cloning the input, combining their outputs using the given function.
+    pub fn fork_combine<
+        E,
+        O,
+        NS1: Service<Input = S::Output, Error = E>,
+        NS2: Service<Input = S::Output, Error = E>,
+        F: Fn(NS1::Output, NS2::Output) -> O,
+    >(
+        self,
+        first: NS1,
+        second: NS2,
+        combine: F,
+    ) -> ServiceChainBuilder<ServiceChain<P, S>, CombiningForkService<NS1, NS2, F>> {
+        ServiceChainBuilder {
+            chain: ServiceChain {
+                prev: self.chain,
+                service: CombiningForkService::new(first, second, combine),
+            },
+        }
+    }
 }
-impl<'a, P: Service + 'a, S: Service<Input = P::Output> + 'a> ServiceChainBuilder<P, S>
+impl<P: Service, S: Service<Input = P::Output>> ServiceChainBuilder<P, S>
 where
     P::Error: Debug + 'static,
     S::Error: Debug + 'static,
@@ -1153,6 +2543,11 @@ where
     pub fn end(self) -> ServiceChain<P, S> {
         self.chain
     }
+
+    /// End and return the resulting [`ServiceChain`] as a [`MappedServiceChain`], which uses the given function to convert the boxed cause of any [`ServiceChainError`] into a domain error.
+    pub fn end_mapped<E, F: Fn(Box<dyn Debug>) -> E>(self, f: F) -> MappedServiceChain<P, S, F> {
+        MappedServiceChain::new(self.chain, f)
+    }
 }
 
 /// Returned by `ServiceChain::start_mut` to build a mut service chain.
@@ -1167,15 +2562,15 @@ impl<'a, S: MutService> MutServiceChainBuilder<NoOpService<'a, S::Input>, S> {
         MutServiceChainBuilder {
             chain: ServiceChain {
                 prev: NoOpService::new(),
-                service: service,
+                service,
             },
         }
     }
 }
 impl<P: MutService, S: MutService<Input = P::Output>> MutServiceChainBuilder<P, S>
 where
-    P::Error: Debug,
-    S::Error: Debug,
+    P::Error: Debug + 'static,
+    S::Error: Debug + 'static,
 {
     /// Append another [`MutService`] to the end of the service chain
     pub fn next<NS: MutService<Input = S::Output>>(
@@ -1192,8 +2587,8 @@ where
 }
 impl<P: MutService, S: MutService<Input = P::Output>> MutServiceChainBuilder<P, S>
 where
-    P::Error: Debug,
-    S::Error: Debug,
+    P::Error: Debug + 'static,
+    S::Error: Debug + 'static,
     S::Output: Clone,
 {
     /// Fork the service chain to the given two services by cloning the input.
@@ -1213,8 +2608,29 @@ where
             },
         }
     }
+
+    /// Fork the service chain to the given two services by cloning the input, combining their outputs using the given function.
+    pub fn fork_combine<
+        E,
+        O,
+        NS1: MutService<Input = S::Output, Error = E>,
+        NS2: MutService<Input = S::Output, Error = E>,
+        F: Fn(NS1::Output, NS2::Output) -> O,
+    >(
+        self,
+        first: NS1,
+        second: NS2,
+        combine: F,
+    ) -> MutServiceChainBuilder<ServiceChain<P, S>, CombiningForkService<NS1, NS2, F>> {
+        MutServiceChainBuilder {
+            chain: ServiceChain {
+                prev: self.chain,
+                service: CombiningForkService::new(first, second, combine),
+            },
+        }
+    }
 }
-impl<'a, P: MutService + 'a, S: MutService<Input = P::Output> + 'a> MutServiceChainBuilder<P, S>
+impl<P: MutService, S: MutService<Input = P::Output>> MutServiceChainBuilder<P, S>
 where
     P::Error: Debug + 'static,
     S::Error: Debug + 'static,
@@ -1223,6 +2639,11 @@ where
     pub fn end(self) -> ServiceChain<P, S> {
         self.chain
     }
+
+    /// End and return the resulting [`ServiceChain`] as a [`MappedServiceChain`], which uses the given function to convert the boxed cause of any [`ServiceChainError`] into a domain error.
+    pub fn end_mapped<E, F: Fn(Box<dyn Debug>) -> E>(self, f: F) -> MappedServiceChain<P, S, F> {
+        MappedServiceChain::new(self.chain, f)
+    }
 }
 
 /// Returned by `ServiceChain::start_async` to build an async service chain.
@@ -1258,7 +2679,7 @@ where
         AsyncServiceChainBuilder {
             chain: ServiceChain {
                 prev: self.chain,
-                service: service,
+                service,
             },
         }
     }
@@ -1291,26 +2712,53 @@ where
             },
         }
     }
+
+    /// Fork the service chain to the given two services by cloning the input, combining their outputs using the given function.
+    pub fn fork_combine<
+        O: Send + 'static,
+        NS1: AsyncService<Input = S::Output> + Send + Sync,
+        NS2: AsyncService<Input = S::Output, Error = NS1::Error> + Send + Sync,
+        F: Fn(NS1::Output, NS2::Output) -> O + Send + Sync,
+    >(
+        self,
+        first: NS1,
+        second: NS2,
+        combine: F,
+    ) -> AsyncServiceChainBuilder<ServiceChain<P, S>, CombiningForkService<NS1, NS2, F>> {
+        AsyncServiceChainBuilder {
+            chain: ServiceChain {
+                prev: self.chain,
+                service: CombiningForkService::new(first, second, combine),
+            },
+        }
+    }
 }
-impl<
-        'a,
-        P: AsyncService + Send + Sync + 'a,
-        S: AsyncService<Input = P::Output> + Send + Sync + 'a,
-    > AsyncServiceChainBuilder<P, S>
+impl<P: AsyncService + Send + Sync, S: AsyncService<Input = P::Output> + Send + Sync>
+    AsyncServiceChainBuilder<P, S>
 where
     P::Error: Send + Debug + 'static,
     S::Error: Send + Debug + 'static,
-    P::Output: Send + 'a,
-    S::Output: Send + 'a,
+    P::Output: Send,
+    S::Output: Send,
 {
     /// End and return the resulting [`ServiceChain`].
     pub fn end(self) -> ServiceChain<P, S> {
         self.chain
     }
+
+    /// End and return the resulting [`ServiceChain`] as a [`MappedServiceChain`], which uses the given function to convert the boxed cause of any [`ServiceChainError`] into a domain error.
+    pub fn end_mapped<E, F: Fn(Box<dyn Debug + Send>) -> E + Send + Sync>(
+        self,
+        f: F,
+    ) -> MappedServiceChain<P, S, F> {
+        MappedServiceChain::new(self.chain, f)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{cell::Cell, num::ParseIntError};
+
     use futures::executor::block_on;
 
     use super::*;
@@ -1350,6 +2798,252 @@ mod tests {
         }
     }
 
+    #[test]
+    fn service_futures_join() {
+        let double = FnService::new(|n: u32| Ok::<_, ()>(n * 2)).into_async();
+        let greet =
+            FnService::new(|name: &'static str| Ok::<_, ()>(format!("hello {name}"))).into_async();
+        let pending: Vec<ServiceFuture<'_, u32, ()>> = (1..=3).map(|n| double.call(n)).collect();
+        let (doubled, greeting, all) = block_on(async {
+            futures::join!(
+                service_future(&double, 21),
+                greet.call("world"),
+                futures::future::join_all(pending)
+            )
+        });
+        assert_eq!(Ok(42), doubled);
+        assert_eq!(Ok("hello world".to_owned()), greeting);
+        assert_eq!(vec![Ok(2), Ok(4), Ok(6)], all);
+    }
+
+    #[test]
+    fn map_err_before_fork_clone() {
+        let parse = FnService::new(|s: String| s.parse::<u32>());
+        let double = FnService::new(|n: u32| n.checked_mul(2).ok_or("overflow"));
+        let chain = ServiceChain::start(NoOpService::new())
+            .fork_clone(
+                MapErrService::new(parse, |err: ParseIntError| err.to_string()),
+                FnService::new(|s: String| Ok::<_, String>(s.len())),
+            )
+            .end();
+        assert_eq!((42, 2), chain.process("42".to_owned()).unwrap());
+        assert!(chain.process("x".to_owned()).is_err());
+
+        let mut double = MapErrService::new(double.into_mut(), |err: &str| err.len());
+        assert_eq!(Ok(4), MutService::process(&mut double, 2));
+        assert_eq!(Err(8), MutService::process(&mut double, u32::MAX));
+
+        let double = MapErrService::new(
+            FnService::new(|n: u32| n.checked_mul(2).ok_or("overflow")).into_async(),
+            |err: &str| err.to_uppercase(),
+        );
+        assert_eq!(
+            Err("OVERFLOW".to_owned()),
+            block_on(double.process(u32::MAX))
+        );
+    }
+
+    #[test]
+    fn and_then_skips_function_on_error() {
+        let calls = Cell::new(0);
+        let service = AndThenService::new(
+            FnService::new(|s: &str| s.parse::<u32>().map_err(|err| err.to_string())),
+            |n| {
+                calls.set(calls.get() + 1);
+                match n % 2 {
+                    0 => Ok(n / 2),
+                    _ => Err(format!("{n} is odd")),
+                }
+            },
+        );
+        assert_eq!(Ok(21), service.process("42"));
+        assert_eq!(Err("7 is odd".to_owned()), service.process("7"));
+        assert!(service.process("x").is_err());
+        assert_eq!(2, calls.get());
+
+        let service = AndThenService::new(
+            FnService::new(|n: u32| Ok::<_, &str>(n + 1)).into_async(),
+            |n: u32| n.checked_mul(10).ok_or("overflow"),
+        );
+        assert_eq!(Ok(50), block_on(service.process(4)));
+        assert_eq!(Err("overflow"), block_on(service.process(u32::MAX - 1)));
+    }
+
+    #[test]
+    fn or_else_falls_back_on_error() {
+        let fallbacks = Cell::new(0);
+        let service = OrElseService::new(
+            FnService::new(|n: u32| match n {
+                0 => Err("primary"),
+                n => Ok(n * 10),
+            }),
+            FnService::new(|n: u32| {
+                fallbacks.set(fallbacks.get() + 1);
+                match n {
+                    0 => Ok(1),
+                    _ => Err("secondary"),
+                }
+            }),
+        );
+        assert_eq!(Ok(20), service.process(2));
+        assert_eq!(0, fallbacks.get());
+        assert_eq!(Ok(1), service.process(0));
+        assert_eq!(1, fallbacks.get());
+
+        let service = OrElseService::new(
+            FnService::new(|_: u32| Err::<u32, _>("primary")).into_async(),
+            FnService::new(|_: u32| Err::<u32, _>("secondary")).into_async(),
+        );
+        assert_eq!(Err("secondary"), block_on(service.process(3)));
+    }
+
+    #[test]
+    fn filter_rejects_inputs_failing_predicate() {
+        let chain = ServiceChain::start(FilterService::new(|n: &usize| *n < 10, || "too large"))
+            .next(AddService::new(1))
+            .end_mapped(|cause| format!("{cause:?}"));
+        assert_eq!(Ok(5), chain.process(4));
+        assert!(chain.process(10).unwrap_err().contains("too large"));
+
+        let filter = FilterService::new(|s: &String| !s.is_empty(), || Stopped);
+        assert_eq!(
+            Ok("a".to_owned()),
+            block_on(AsyncService::process(&filter, "a".to_owned()))
+        );
+        assert!(block_on(AsyncService::process(&filter, String::new())).is_err());
+    }
+
+    #[test]
+    fn inspect_observes_chain_values() {
+        let seen = Mutex::new(Vec::new());
+        let chain = ServiceChain::start(AddService::new(1))
+            .next(InspectService::new(|n: &usize| {
+                seen.lock().unwrap().push(*n)
+            }))
+            .next(AddService::new(10))
+            .end();
+        assert_eq!(12, chain.process(1).unwrap());
+        assert_eq!(15, chain.process(4).unwrap());
+        assert_eq!(vec![2, 5], *seen.lock().unwrap());
+    }
+
+    #[test]
+    fn typed_chain_preserves_errors() {
+        let chain = TypedServiceChain::new(
+            FnService::new(|s: &str| s.parse::<u8>()),
+            FnService::new(|n: u8| n.checked_add(100).ok_or(Stopped)),
+        );
+        assert_eq!(Ok(142), chain.process("42"));
+        assert!(matches!(chain.process("x"), Err(ChainError::Prev(_))));
+        assert_eq!(Err(ChainError::This(Stopped)), chain.process("200"));
+
+        let err = chain.process("x").unwrap_err();
+        assert!(err.source().unwrap().is::<ParseIntError>());
+
+        let chain =
+            TypedServiceChain::new(chain, FnService::new(|n: u8| Ok::<_, ()>(u32::from(n) + 1)));
+        assert_eq!(Ok(143), chain.process("42"));
+        assert_eq!(
+            Err(ChainError::Prev(ChainError::This(Stopped))),
+            chain.process("200")
+        );
+    }
+
+    #[test]
+    fn service_chain_error_exposes_cause() {
+        let chain = ServiceChain::start(AddService::new(1))
+            .next(FnService::new(|_: usize| Err::<usize, _>("failed")))
+            .end();
+        let err = chain.process(1).unwrap_err();
+        assert_eq!("\"failed\"", format!("{:?}", err.cause()));
+        assert_eq!("\"failed\"", format!("{:?}", err.into_cause()));
+    }
+
+    #[test]
+    fn retry_with_max_attempts() {
+        struct Flaky {
+            failures: Cell<usize>,
+        }
+        impl Service for Flaky {
+            type Input = u32;
+            type Output = u32;
+            type Error = u32;
+            fn process(&self, input: u32) -> Result<u32, u32> {
+                match self.failures.get() {
+                    0 => Ok(input),
+                    n => {
+                        self.failures.set(n - 1);
+                        Err(input)
+                    }
+                }
+            }
+        }
+        impl Retryable<u32, u32> for Flaky {
+            fn parse_retry(&self, err: u32) -> Result<u32, RetryError<u32>> {
+                match err {
+                    0 => Err(RetryError::ServiceError(err)),
+                    input => Ok(input),
+                }
+            }
+        }
+
+        let idles = Cell::new(0);
+        let idle = |_| {
+            idles.set(idles.get() + 1);
+            Ok(())
+        };
+        let service = RetryService::with_max_attempts(
+            Flaky {
+                failures: Cell::new(2),
+            },
+            idle,
+            3,
+        );
+        assert_eq!(Ok(7), service.process(7));
+        assert_eq!(2, idles.get());
+
+        service.service.failures.set(3);
+        assert_eq!(Err(RetryError::Exhausted), service.process(7));
+        assert_eq!(4, idles.get());
+        // a non-retryable error is returned as is
+        service.service.failures.set(3);
+        assert_eq!(Err(RetryError::ServiceError(0)), service.process(0));
+
+        let unbounded = RetryService::new(
+            Flaky {
+                failures: Cell::new(100),
+            },
+            idle,
+        );
+        assert_eq!(Ok(7), unbounded.process(7));
+    }
+
+    #[test]
+    #[should_panic(expected = "max_attempts must be at least 1")]
+    fn retry_with_zero_max_attempts_panics() {
+        RetryService::with_max_attempts(AddService::new(1), |_| Ok::<_, RetryError<()>>(()), 0);
+    }
+
+    #[test]
+    fn verify_fork_agreeing_and_disagreeing() {
+        let square = FnService::new(|n: i64| Ok::<_, ()>(n * n));
+        // agrees with `square` only for non-negative inputs
+        let faulty = FnService::new(|n: i64| Ok::<_, ()>(n * n.abs()));
+        let service = VerifyForkService::new(square, faulty);
+        assert_eq!(Ok(9), service.process(3));
+        assert_eq!(
+            Err(VerifyForkError::ForkMismatch {
+                first: 9,
+                second: -9
+            }),
+            service.process(-3)
+        );
+
+        let failing = FnService::new(|_: i64| Err::<i64, _>(()));
+        let service = VerifyForkService::new(failing, FnService::new(|n: i64| Ok::<_, ()>(n)));
+        assert_eq!(Err(VerifyForkError::ServiceError(())), service.process(3));
+    }
+
     #[test]
     fn service_chain() {
         let chain = ServiceChain::start(AddService::new(1))
@@ -1378,4 +3072,323 @@ mod tests {
         let result = block_on(chain.process(100)).unwrap();
         assert_eq!(107, result);
     }
+
+    #[derive(Debug, PartialEq)]
+    enum ChainFailure {
+        Rejected(String),
+    }
+
+    #[test]
+    fn service_chain_end_mapped() {
+        let chain = ServiceChain::start(AddService::new(1))
+            .next(AddService::new(2))
+            .next(FnService::new(|input: usize| match input {
+                0..=10 => Ok(input),
+                _ => Err("too large"),
+            }))
+            .end_mapped(|cause| ChainFailure::Rejected(format!("{cause:?}")));
+        assert_eq!(Ok(5), chain.process(2));
+        assert_eq!(
+            Err(ChainFailure::Rejected("\"too large\"".to_owned())),
+            chain.process(100)
+        );
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum FlakyError {
+        Transient,
+        Fatal,
+    }
+
+    #[test]
+    fn retry_if_predicate() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = AtomicUsize::new(0);
+        let service = RetryIfService::new(
+            FnService::new(
+                |fatal: bool| match (fatal, calls.fetch_add(1, Ordering::SeqCst)) {
+                    (true, _) => Err(FlakyError::Fatal),
+                    (false, 0..=2) => Err(FlakyError::Transient),
+                    (false, n) => Ok(n),
+                },
+            ),
+            |err: &FlakyError| *err == FlakyError::Transient,
+            idle::spin,
+        );
+
+        assert_eq!(Ok(3), service.process(false));
+        assert_eq!(4, calls.load(Ordering::SeqCst));
+
+        assert_eq!(
+            Err(RetryError::ServiceError(FlakyError::Fatal)),
+            service.process(true)
+        );
+        assert_eq!(5, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn fork_collect_errors() {
+        let service = ForkCollectErrors::new(
+            FnService::new(|input: i32| match input {
+                0.. => Ok(input + 1),
+                _ => Err("first"),
+            }),
+            FnService::new(|input: i32| match input {
+                0..=9 => Ok(input * 2),
+                _ => Err(input),
+            }),
+        );
+        assert_eq!(Ok((2, 2)), service.process(1));
+        assert_eq!(
+            Err(ForkError {
+                first: None,
+                second: Some(10)
+            }),
+            service.process(10)
+        );
+        assert_eq!(
+            Err(ForkError {
+                first: Some("first"),
+                second: Some(-1)
+            }),
+            service.process(-1)
+        );
+    }
+
+    #[test]
+    fn service_chain_fork_combine() {
+        let chain = ServiceChain::start(AddService::new(1))
+            .fork_combine(AddService::new(2), AddService::new(4), |a, b| a + b)
+            .next(AddService::new(1))
+            .end();
+        assert_eq!(19, chain.process(5).unwrap());
+
+        let chain = ServiceChain::start_async(ServiceAsync::new(AddService::new(1)))
+            .fork_combine(
+                ServiceAsync::new(AddService::new(2)),
+                ServiceAsync::new(AddService::new(4)),
+                |a, b| a.max(b),
+            )
+            .end();
+        assert_eq!(10, block_on(chain.process(5)).unwrap());
+
+        // the combined output only needs to be Send
+        let chain = ServiceChain::start_async(ServiceAsync::new(AddService::new(1)))
+            .fork_combine(
+                ServiceAsync::new(AddService::new(2)),
+                ServiceAsync::new(AddService::new(4)),
+                |a, b| Cell::new(a + b),
+            )
+            .end();
+        assert_eq!(18, block_on(chain.process(5)).unwrap().get());
+    }
+
+    struct SinkService {
+        fail: bool,
+        received: Mutex<Vec<u32>>,
+    }
+    impl SinkService {
+        fn new(fail: bool) -> Self {
+            Self {
+                fail,
+                received: Mutex::new(Vec::new()),
+            }
+        }
+    }
+    impl Service for SinkService {
+        type Input = u32;
+        type Output = u32;
+        type Error = &'static str;
+        fn process(&self, input: u32) -> Result<u32, &'static str> {
+            self.received.lock().unwrap().push(input);
+            match self.fail {
+                true => Err("failed"),
+                false => Ok(input),
+            }
+        }
+    }
+
+    #[test]
+    fn broadcast_isolates_sink_failures() {
+        let service = BroadcastService::new(vec![
+            SinkService::new(false),
+            SinkService::new(true),
+            SinkService::new(false),
+        ]);
+        assert_eq!(Ok(vec![Ok(7), Err("failed"), Ok(7)]), service.process(7));
+        for sink in service.sinks.iter() {
+            assert_eq!(vec![7], *sink.received.lock().unwrap());
+        }
+
+        let service = BroadcastService::with_policy(
+            vec![
+                SinkService::new(false),
+                SinkService::new(true),
+                SinkService::new(false),
+            ],
+            BroadcastPolicy::FailFast,
+        );
+        assert_eq!(Ok(vec![Ok(7), Err("failed")]), service.process(7));
+        assert!(service.sinks[2].received.lock().unwrap().is_empty());
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Attempt {
+        nonce: u32,
+    }
+
+    struct NonceService;
+    impl Service for NonceService {
+        type Input = Attempt;
+        type Output = u32;
+        type Error = Attempt;
+        fn process(&self, input: Attempt) -> Result<u32, Attempt> {
+            match input.nonce {
+                3.. => Ok(input.nonce),
+                _ => Err(input),
+            }
+        }
+    }
+    impl Retryable<Attempt, Attempt> for NonceService {
+        fn parse_retry(&self, err: Attempt) -> Result<Attempt, RetryError<Attempt>> {
+            Ok(err)
+        }
+    }
+
+    #[test]
+    fn retry_with_input_transforms_each_attempt() {
+        let attempts = RefCell::new(Vec::new());
+        let service = RetryWithInputService::new(
+            NonceService,
+            |input: &Attempt, attempt| {
+                attempts.borrow_mut().push(attempt);
+                Attempt {
+                    nonce: input.nonce + 1,
+                }
+            },
+            idle::spin,
+        );
+        assert_eq!(Ok(3), service.process(Attempt { nonce: 0 }));
+        assert_eq!(vec![0, 1, 2], *attempts.borrow());
+    }
+
+    #[test]
+    fn safe_retry_reuses_key_across_attempts() {
+        // times out on the first two attempts of each request, after recording the key it was given
+        let keys = RefCell::new(Vec::new());
+        let service = SafeRetryService::new(
+            FnService::new(|(input, key): (u32, u64)| {
+                keys.borrow_mut().push(key);
+                match keys.borrow().iter().filter(|k| **k == key).count() {
+                    1 | 2 => Err("timeout"),
+                    _ => Ok(input * 2),
+                }
+            }),
+            {
+                let next = Cell::new(100);
+                move || next.replace(next.get() + 1)
+            },
+            |input: &u32, key: &u64| (*input, *key),
+            |err: &&str| *err == "timeout",
+            idle::spin,
+        );
+        assert_eq!(Ok(10), service.process(5));
+        assert_eq!(Ok(14), service.process(7));
+        assert_eq!(vec![100, 100, 100, 101, 101, 101], *keys.borrow());
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum AppError {
+        Timeout,
+        Malformed,
+        Corrupted,
+    }
+    struct ScriptedService {
+        script: Mutex<Vec<Result<u32, AppError>>>,
+    }
+    #[async_trait]
+    impl AsyncService for ScriptedService {
+        type Input = ();
+        type Output = u32;
+        type Error = AppError;
+        async fn process(&self, _: ()) -> Result<u32, AppError> {
+            self.script.lock().unwrap().remove(0)
+        }
+    }
+
+    #[test]
+    fn classify_error_drives_supervisor() {
+        let service = ClassifyErrorService::new(
+            ScriptedService {
+                script: Mutex::new(vec![
+                    Ok(1),
+                    Err(AppError::Timeout),
+                    Ok(2),
+                    Err(AppError::Malformed),
+                    Ok(3),
+                    Err(AppError::Corrupted),
+                    Ok(4),
+                ]),
+            },
+            |err: &AppError| match err {
+                AppError::Timeout => ErrorClass::Retryable,
+                AppError::Malformed => ErrorClass::Ignorable,
+                AppError::Corrupted => ErrorClass::Fatal,
+            },
+        );
+        let mut log = Vec::new();
+        block_on(async {
+            loop {
+                match service.process(()).await {
+                    Ok(v) => log.push(format!("ok {v}")),
+                    Err(Classified::Retryable(err)) => log.push(format!("retry {err:?}")),
+                    Err(Classified::Ignorable(err)) => log.push(format!("ignore {err:?}")),
+                    Err(Classified::Fatal(err)) => {
+                        log.push(format!("stop {err:?}"));
+                        break;
+                    }
+                }
+            }
+        });
+        assert_eq!(
+            vec![
+                "ok 1",
+                "retry Timeout",
+                "ok 2",
+                "ignore Malformed",
+                "ok 3",
+                "stop Corrupted"
+            ],
+            log
+        );
+        assert_eq!(1, service.service.script.lock().unwrap().len());
+        assert_eq!(
+            ErrorClass::Fatal,
+            Classified::new(ErrorClass::Fatal, AppError::Corrupted).class()
+        );
+    }
+
+    #[test]
+    fn asserting_output_contract() {
+        let strict = AssertingService::new(
+            AddService::new(1),
+            |_: &usize| true,
+            |out: &usize| *out < 10,
+        )
+        .strict();
+        assert_eq!(Ok(5), strict.process(4));
+        assert_eq!(
+            Err(AssertionError::AssertionFailed(Contract::Output)),
+            strict.process(9)
+        );
+
+        let release = AssertingService::with_mode(
+            AddService::new(1),
+            |_: &usize| true,
+            |out: &usize| *out < 10,
+            AssertionMode::Disabled,
+        );
+        assert_eq!(Ok(10), release.process(9));
+    }
 }
@@ -0,0 +1,889 @@
+//! Services that protect an underlying service from overload by limiting concurrent calls or the rate of calls.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{Debug, Display},
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    async_trait,
+    clock::{Clock, SystemClock},
+    AsyncService, RetryError, Retryable, Service,
+};
+
+/// The factor applied to the limit of an [`AdaptiveConcurrencyService`] when latency exceeds the target
+const BACKOFF_RATIO: f64 = 0.9;
+
+/// An [`AsyncService`] that limits the number of concurrent in-flight calls to an underlying [`AsyncService`], adapting the limit to observed latency.
+///
+/// The limit is adjusted using additive-increase/multiplicative-decrease (AIMD), similar to Netflix's `concurrency-limits`:
+/// each call that completes within the target latency increases the limit by one, up to `max_limit`,
+/// while each call that exceeds the target latency multiplies the limit by `0.9`, down to `min_limit`.
+/// This probes for the highest concurrency the underlying service can sustain, and backs off quickly once it begins to queue.
+///
+/// A call made while the number of in-flight calls is at the limit is rejected immediately with [`ConcurrencyError::LimitExceeded`], without calling the underlying service.
+/// The current limit may be observed using [`AdaptiveConcurrencyService::limit`].
+pub struct AdaptiveConcurrencyService<S, C = SystemClock> {
+    service: S,
+    min_limit: usize,
+    max_limit: usize,
+    target_latency: Duration,
+    state: Mutex<ConcurrencyState>,
+    clock: C,
+}
+struct ConcurrencyState {
+    limit: usize,
+    in_flight: usize,
+}
+impl<S: AsyncService> AdaptiveConcurrencyService<S> {
+    /// Create a new [`AdaptiveConcurrencyService`], starting at `min_limit`
+    ///
+    /// # Arguments
+    /// * `service` - the underlying service
+    /// * `min_limit` - the lowest the limit may be decreased to, which is also the initial limit
+    /// * `max_limit` - the highest the limit may be increased to
+    /// * `target_latency` - calls that complete within this latency increase the limit, while slower calls decrease it
+    ///
+    /// # Panics
+    /// Panics unless `1 <= min_limit <= max_limit`.
+    pub fn new(service: S, min_limit: usize, max_limit: usize, target_latency: Duration) -> Self {
+        Self::with_clock(service, min_limit, max_limit, target_latency, SystemClock)
+    }
+}
+impl<S: AsyncService, C: Clock> AdaptiveConcurrencyService<S, C> {
+    /// Create a new [`AdaptiveConcurrencyService`] which uses the given [`Clock`] to measure latency
+    ///
+    /// # Panics
+    /// Panics unless `1 <= min_limit <= max_limit`.
+    pub fn with_clock(
+        service: S,
+        min_limit: usize,
+        max_limit: usize,
+        target_latency: Duration,
+        clock: C,
+    ) -> Self {
+        assert!(min_limit >= 1, "min_limit must be at least 1");
+        assert!(
+            min_limit <= max_limit,
+            "min_limit {min_limit} must not exceed max_limit {max_limit}"
+        );
+        Self {
+            service,
+            min_limit,
+            max_limit,
+            target_latency,
+            state: Mutex::new(ConcurrencyState {
+                limit: min_limit,
+                in_flight: 0,
+            }),
+            clock,
+        }
+    }
+
+    /// The current limit of concurrent in-flight calls
+    pub fn limit(&self) -> usize {
+        self.state.lock().expect("poisoned mutex").limit
+    }
+
+    fn sample(&self, latency: Duration) {
+        let mut state = self.state.lock().expect("poisoned mutex");
+        state.limit = match latency <= self.target_latency {
+            true => (state.limit + 1).min(self.max_limit),
+            false => ((state.limit as f64 * BACKOFF_RATIO) as usize).max(self.min_limit),
+        };
+    }
+}
+#[async_trait]
+impl<S, C> AsyncService for AdaptiveConcurrencyService<S, C>
+where
+    S: AsyncService,
+    C: Clock + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = ConcurrencyError<S::Error>;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let _permit = {
+            let mut state = self.state.lock().expect("poisoned mutex");
+            if state.in_flight >= state.limit {
+                return Err(ConcurrencyError::LimitExceeded);
+            }
+            state.in_flight += 1;
+            InFlight(&self.state)
+        };
+        let start = self.clock.now();
+        let result = self.service.process(input).await;
+        self.sample(self.clock.now().duration_since(start));
+        result.map_err(ConcurrencyError::ServiceError)
+    }
+}
+
+/// Decrements the in-flight count when dropped, including when a call is cancelled
+struct InFlight<'a>(&'a Mutex<ConcurrencyState>);
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.lock().expect("poisoned mutex").in_flight -= 1;
+    }
+}
+
+/// Returned by concurrency-limiting services, either when a call is rejected or when the underlying service fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConcurrencyError<E> {
+    LimitExceeded,
+    ServiceError(E),
+}
+impl<E: Display> Display for ConcurrencyError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LimitExceeded => f.write_str("concurrency limit exceeded"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<E: Error + 'static> Error for ConcurrencyError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::LimitExceeded => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+/// A pluggable policy which decides when a [`LoadShedService`] sheds an input.
+pub trait ShedPolicy<I> {
+    /// Returns `true` if the input should be shed, given the number of calls currently in flight
+    fn should_shed(&self, input: &I, in_flight: usize) -> bool;
+}
+
+/// A [`ShedPolicy`] that sheds every input once the given number of calls are in flight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaxInFlight(pub usize);
+impl<I> ShedPolicy<I> for MaxInFlight {
+    fn should_shed(&self, _: &I, in_flight: usize) -> bool {
+        in_flight >= self.0
+    }
+}
+
+/// A [`ShedPolicy`] that uses the given function to produce an in-flight threshold for each input, shedding the input once that many calls are in flight.
+///
+/// Giving high-priority inputs a higher threshold than low-priority inputs causes low-priority inputs to be shed first, while high-priority inputs continue to proceed.
+pub struct PriorityShedPolicy<F> {
+    threshold: F,
+}
+impl<F> PriorityShedPolicy<F> {
+    pub fn new(threshold: F) -> Self {
+        Self { threshold }
+    }
+}
+impl<I, F: Fn(&I) -> usize> ShedPolicy<I> for PriorityShedPolicy<F> {
+    fn should_shed(&self, input: &I, in_flight: usize) -> bool {
+        in_flight >= (self.threshold)(input)
+    }
+}
+
+/// A [`Service`] or [`AsyncService`] that tracks concurrent in-flight calls to an underlying service, rejecting calls immediately when the [`ShedPolicy`] decides the service is overloaded.
+///
+/// A rejected call returns [`LoadShedError::ShedLoad`] containing the input, rather than queuing it.
+/// This service implements [`Retryable`] for shed inputs, so it may be encapsulated by a [`crate::RetryService`] to back off using an idle strategy.
+pub struct LoadShedService<S, P> {
+    service: S,
+    policy: P,
+    in_flight: AtomicUsize,
+}
+impl<S, P> LoadShedService<S, P> {
+    pub fn new(service: S, policy: P) -> Self {
+        Self {
+            service,
+            policy,
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number of calls currently in flight
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    fn admit<I, E>(&self, input: I) -> Result<(I, Admitted<'_>), LoadShedError<I, E>>
+    where
+        P: ShedPolicy<I>,
+    {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::AcqRel);
+        let admitted = Admitted(&self.in_flight);
+        match self.policy.should_shed(&input, in_flight) {
+            true => Err(LoadShedError::ShedLoad(input)),
+            false => Ok((input, admitted)),
+        }
+    }
+}
+impl<S: Service, P: ShedPolicy<S::Input>> Service for LoadShedService<S, P> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = LoadShedError<S::Input, S::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let (input, _admitted) = self.admit(input)?;
+        self.service
+            .process(input)
+            .map_err(LoadShedError::ServiceError)
+    }
+}
+#[async_trait]
+impl<S, P> AsyncService for LoadShedService<S, P>
+where
+    S: AsyncService,
+    P: ShedPolicy<S::Input> + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = LoadShedError<S::Input, S::Error>;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let (input, _admitted) = self.admit(input)?;
+        self.service
+            .process(input)
+            .await
+            .map_err(LoadShedError::ServiceError)
+    }
+}
+impl<I, E, S, P> Retryable<I, LoadShedError<I, E>> for LoadShedService<S, P> {
+    fn parse_retry(&self, err: LoadShedError<I, E>) -> Result<I, RetryError<LoadShedError<I, E>>> {
+        match err {
+            LoadShedError::ShedLoad(input) => Ok(input),
+            err => Err(RetryError::ServiceError(err)),
+        }
+    }
+}
+
+/// Decrements the in-flight count when dropped, including when a call is shed or cancelled
+struct Admitted<'a>(&'a AtomicUsize);
+impl Drop for Admitted<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Returned by [`LoadShedService`], either when the input is shed or when the underlying service fails.
+#[derive(Clone, PartialEq, Eq)]
+pub enum LoadShedError<I, E> {
+    ShedLoad(I),
+    ServiceError(E),
+}
+impl<I, E: Debug> Debug for LoadShedError<I, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ShedLoad(_) => f.write_str("ShedLoad"),
+            Self::ServiceError(err) => f.debug_tuple("ServiceError").field(err).finish(),
+        }
+    }
+}
+impl<I, E: Display> Display for LoadShedError<I, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ShedLoad(_) => f.write_str("load shed"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<I, E: Error + 'static> Error for LoadShedError<I, E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::ShedLoad(_) => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+/// A [`Service`] or [`AsyncService`] that caps the number of calls per second to an underlying service, dropping inputs that exceed the cap.
+///
+/// Calls are counted over fixed one-second windows, measured by the given [`Clock`], where each window begins at the first call after the previous window ended.
+/// An accepted input is passed to the underlying service and `Some(Output)` is produced, while a dropped input is discarded and `None` is produced.
+///
+/// The number of accepted and dropped inputs are counted, and may be observed using [`ThrottleWithStatsService::stats`].
+pub struct ThrottleWithStatsService<S, C = SystemClock> {
+    service: S,
+    max_per_second: u64,
+    window: Mutex<ThrottleWindow>,
+    accepted: AtomicU64,
+    dropped: AtomicU64,
+    clock: C,
+}
+struct ThrottleWindow {
+    start: Option<Instant>,
+    count: u64,
+}
+impl<S> ThrottleWithStatsService<S> {
+    /// Create a new [`ThrottleWithStatsService`], accepting up to `max_per_second` calls per second
+    pub fn new(service: S, max_per_second: u64) -> Self {
+        Self::with_clock(service, max_per_second, SystemClock)
+    }
+}
+impl<S, C: Clock> ThrottleWithStatsService<S, C> {
+    /// Create a new [`ThrottleWithStatsService`] which uses the given [`Clock`] to measure each one-second window
+    pub fn with_clock(service: S, max_per_second: u64, clock: C) -> Self {
+        Self {
+            service,
+            max_per_second,
+            window: Mutex::new(ThrottleWindow {
+                start: None,
+                count: 0,
+            }),
+            accepted: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            clock,
+        }
+    }
+
+    /// The number of accepted and dropped inputs so far
+    pub fn stats(&self) -> ThrottleStats {
+        ThrottleStats {
+            accepted: self.accepted.load(Ordering::Acquire),
+            dropped: self.dropped.load(Ordering::Acquire),
+        }
+    }
+
+    fn admit(&self) -> bool {
+        let now = self.clock.now();
+        let mut window = self.window.lock().expect("poisoned mutex");
+        match window.start {
+            Some(start) if now.duration_since(start) < Duration::from_secs(1) => {}
+            _ => {
+                window.start = Some(now);
+                window.count = 0;
+            }
+        }
+        match window.count < self.max_per_second {
+            true => {
+                window.count += 1;
+                self.accepted.fetch_add(1, Ordering::AcqRel);
+                true
+            }
+            false => {
+                self.dropped.fetch_add(1, Ordering::AcqRel);
+                false
+            }
+        }
+    }
+}
+impl<S: Service, C: Clock> Service for ThrottleWithStatsService<S, C> {
+    type Input = S::Input;
+    type Output = Option<S::Output>;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        match self.admit() {
+            true => Ok(Some(self.service.process(input)?)),
+            false => Ok(None),
+        }
+    }
+}
+#[async_trait]
+impl<S, C> AsyncService for ThrottleWithStatsService<S, C>
+where
+    S: AsyncService,
+    C: Clock + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = Option<S::Output>;
+    type Error = S::Error;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        match self.admit() {
+            true => Ok(Some(self.service.process(input).await?)),
+            false => Ok(None),
+        }
+    }
+}
+
+/// The counts of accepted and dropped inputs, produced by [`ThrottleWithStatsService::stats`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ThrottleStats {
+    pub accepted: u64,
+    pub dropped: u64,
+}
+
+/// A [`Service`] or [`AsyncService`] that rate-limits calls per key, using an independent token bucket for each key, so one key's burst does not starve others.
+///
+/// The key is extracted from each input using the given function.
+/// Each key's bucket holds up to `burst` tokens and refills at `per_second` tokens per second, and each call consumes one token.
+/// Buckets are created lazily, starting full.
+/// A bucket that has been idle for at least `idle_ttl` is evicted once it has refilled to `burst` tokens,
+/// so recreating it full never lets a key exceed its rate, however short `idle_ttl` is.
+///
+/// A call for a key whose bucket is empty is rejected immediately with [`RateLimitError::RateLimited`], containing the input, without calling the underlying service.
+/// This service implements [`Retryable`] for rate-limited inputs, so it may be encapsulated by a [`crate::RetryService`] to block until a token is available.
+pub struct KeyedRateLimitService<S, K, F, C = SystemClock> {
+    service: S,
+    key: F,
+    burst: f64,
+    per_second: f64,
+    idle_ttl: Duration,
+    buckets: Mutex<KeyedBuckets<K>>,
+    clock: C,
+}
+struct KeyedBuckets<K> {
+    buckets: HashMap<K, TokenBucket>,
+    last_purge: Option<Instant>,
+}
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+impl<S, K: Eq + Hash, F> KeyedRateLimitService<S, K, F> {
+    /// Create a new [`KeyedRateLimitService`]
+    ///
+    /// # Arguments
+    /// * `service` - the underlying service
+    /// * `key` - extracts the rate-limiting key from each input
+    /// * `burst` - the number of tokens each bucket holds when full, which is the largest burst allowed for a key
+    /// * `per_second` - the number of tokens added to each bucket per second, which is the sustained rate allowed for a key
+    /// * `idle_ttl` - the duration after which an idle key's bucket is evicted, once it has refilled
+    ///
+    /// # Panics
+    /// Panics if `burst` is zero, or if `per_second` is not finite or is not positive.
+    pub fn new(service: S, key: F, burst: u32, per_second: f64, idle_ttl: Duration) -> Self {
+        Self::with_clock(service, key, burst, per_second, idle_ttl, SystemClock)
+    }
+}
+impl<S, K: Eq + Hash, F, C: Clock> KeyedRateLimitService<S, K, F, C> {
+    /// Create a new [`KeyedRateLimitService`] which uses the given [`Clock`] to refill and evict buckets
+    ///
+    /// # Panics
+    /// Panics if `burst` is zero, or if `per_second` is not finite or is not positive.
+    pub fn with_clock(
+        service: S,
+        key: F,
+        burst: u32,
+        per_second: f64,
+        idle_ttl: Duration,
+        clock: C,
+    ) -> Self {
+        assert!(burst > 0, "burst must be non-zero");
+        assert!(
+            per_second.is_finite() && per_second > 0.0,
+            "per_second must be finite and positive, got {per_second}"
+        );
+        Self {
+            service,
+            key,
+            burst: burst as f64,
+            per_second,
+            idle_ttl,
+            buckets: Mutex::new(KeyedBuckets {
+                buckets: HashMap::new(),
+                last_purge: None,
+            }),
+            clock,
+        }
+    }
+
+    /// The number of keys with a bucket that has not been evicted
+    pub fn tracked_keys(&self) -> usize {
+        self.buckets.lock().expect("poisoned mutex").buckets.len()
+    }
+
+    /// Consume a token from the key's bucket, returning `false` if the bucket is empty
+    fn acquire(&self, key: K) -> bool {
+        let now = self.clock.now();
+        let mut state = self.buckets.lock().expect("poisoned mutex");
+        let last_purge = *state.last_purge.get_or_insert(now);
+        if now.duration_since(last_purge) >= self.idle_ttl {
+            state.buckets.retain(|_, bucket| {
+                let idle = now.duration_since(bucket.updated);
+                idle < self.idle_ttl
+                    || bucket.tokens + idle.as_secs_f64() * self.per_second < self.burst
+            });
+            state.last_purge = Some(now);
+        }
+        let bucket = state.buckets.entry(key).or_insert(TokenBucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.updated = now;
+        match bucket.tokens >= 1.0 {
+            true => {
+                bucket.tokens -= 1.0;
+                true
+            }
+            false => false,
+        }
+    }
+}
+impl<S, K, F, C> Service for KeyedRateLimitService<S, K, F, C>
+where
+    S: Service,
+    K: Eq + Hash,
+    F: Fn(&S::Input) -> K,
+    C: Clock,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = RateLimitError<S::Input, S::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        if !self.acquire((self.key)(&input)) {
+            return Err(RateLimitError::RateLimited(input));
+        }
+        self.service
+            .process(input)
+            .map_err(RateLimitError::ServiceError)
+    }
+}
+#[async_trait]
+impl<S, K, F, C> AsyncService for KeyedRateLimitService<S, K, F, C>
+where
+    S: AsyncService,
+    K: Eq + Hash + Send,
+    F: Fn(&S::Input) -> K + Send + Sync,
+    C: Clock + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = RateLimitError<S::Input, S::Error>;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        if !self.acquire((self.key)(&input)) {
+            return Err(RateLimitError::RateLimited(input));
+        }
+        self.service
+            .process(input)
+            .await
+            .map_err(RateLimitError::ServiceError)
+    }
+}
+impl<I, E, S, K, F, C> Retryable<I, RateLimitError<I, E>> for KeyedRateLimitService<S, K, F, C> {
+    fn parse_retry(
+        &self,
+        err: RateLimitError<I, E>,
+    ) -> Result<I, RetryError<RateLimitError<I, E>>> {
+        match err {
+            RateLimitError::RateLimited(input) => Ok(input),
+            err => Err(RetryError::ServiceError(err)),
+        }
+    }
+}
+
+/// Returned by [`KeyedRateLimitService`], either when the input's key is rate-limited or when the underlying service fails.
+#[derive(Clone, PartialEq, Eq)]
+pub enum RateLimitError<I, E> {
+    RateLimited(I),
+    ServiceError(E),
+}
+impl<I, E: Debug> Debug for RateLimitError<I, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RateLimited(_) => f.write_str("RateLimited"),
+            Self::ServiceError(err) => f.debug_tuple("ServiceError").field(err).finish(),
+        }
+    }
+}
+impl<I, E: Display> Display for RateLimitError<I, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RateLimited(_) => f.write_str("rate limited"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<I, E: Error + 'static> Error for RateLimitError<I, E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::RateLimited(_) => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, sync::atomic::AtomicBool, thread};
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::{clock::ManualClock, testing::Delay, FnService};
+
+    /// Advances the clock by the input's milliseconds, simulating latency, or sleeps for real when the input is `0`
+    struct LatencyService {
+        clock: ManualClock,
+    }
+    #[async_trait]
+    impl AsyncService for LatencyService {
+        type Input = u64;
+        type Output = u64;
+        type Error = Infallible;
+        async fn process(&self, millis: u64) -> Result<u64, Infallible> {
+            match millis {
+                0 => Delay::new(Duration::from_millis(50)).await,
+                _ => self.clock.advance(Duration::from_millis(millis)),
+            }
+            Ok(millis)
+        }
+    }
+
+    #[test]
+    fn adaptive_limit_follows_latency() {
+        let clock = ManualClock::new();
+        let service = AdaptiveConcurrencyService::with_clock(
+            LatencyService {
+                clock: clock.clone(),
+            },
+            2,
+            10,
+            Duration::from_millis(50),
+            clock,
+        );
+        assert_eq!(2, service.limit());
+
+        for _ in 0..5 {
+            block_on(service.process(10)).unwrap();
+        }
+        assert_eq!(7, service.limit());
+        for _ in 0..5 {
+            block_on(service.process(10)).unwrap();
+        }
+        assert_eq!(10, service.limit());
+
+        block_on(service.process(100)).unwrap();
+        assert_eq!(9, service.limit());
+        for _ in 0..20 {
+            block_on(service.process(100)).unwrap();
+        }
+        assert_eq!(2, service.limit());
+    }
+
+    #[test]
+    fn adaptive_rejects_above_limit() {
+        let service = AdaptiveConcurrencyService::new(
+            LatencyService {
+                clock: ManualClock::new(),
+            },
+            1,
+            1,
+            Duration::from_secs(1),
+        );
+        block_on(async {
+            // the first call is in flight once it has been polled, so the second call is rejected without racing it
+            let mut first = service.process(0);
+            assert!(futures::poll!(&mut first).is_pending());
+            assert_eq!(
+                Err(ConcurrencyError::LimitExceeded),
+                service.process(0).await
+            );
+            assert_eq!(Ok(0), first.await);
+        });
+        assert_eq!(Ok(0), block_on(service.process(0)));
+    }
+
+    #[test]
+    fn adaptive_rejects_invalid_limits() {
+        let panics = |min_limit, max_limit| {
+            std::panic::catch_unwind(|| {
+                AdaptiveConcurrencyService::new(
+                    LatencyService {
+                        clock: ManualClock::new(),
+                    },
+                    min_limit,
+                    max_limit,
+                    Duration::from_secs(1),
+                )
+            })
+            .is_err()
+        };
+        assert!(!panics(1, 1));
+        assert!(panics(0, 1));
+        assert!(panics(3, 2));
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    struct Request {
+        high_priority: bool,
+        block: bool,
+    }
+    const LOW: Request = Request {
+        high_priority: false,
+        block: false,
+    };
+    const HIGH: Request = Request {
+        high_priority: true,
+        block: false,
+    };
+
+    #[test]
+    fn load_shed_low_priority_first() {
+        let released = AtomicBool::new(false);
+        let service = LoadShedService::new(
+            FnService::new(|request: Request| {
+                while request.block && !released.load(Ordering::Acquire) {
+                    thread::yield_now();
+                }
+                Ok::<_, Infallible>(request.high_priority)
+            }),
+            PriorityShedPolicy::new(|request: &Request| match request.high_priority {
+                true => 4,
+                false => 2,
+            }),
+        );
+
+        thread::scope(|scope| {
+            let blocked: Vec<_> = (0..2)
+                .map(|_| {
+                    scope.spawn(|| {
+                        service.process(Request {
+                            high_priority: false,
+                            block: true,
+                        })
+                    })
+                })
+                .collect();
+            while service.in_flight() < 2 {
+                thread::yield_now();
+            }
+
+            let shed = service.process(LOW).unwrap_err();
+            assert_eq!(LoadShedError::ShedLoad(LOW), shed);
+            assert_eq!(Ok(true), service.process(HIGH));
+
+            released.store(true, Ordering::Release);
+            for handle in blocked {
+                assert_eq!(Ok(false), handle.join().unwrap());
+            }
+            let retry = service.parse_retry(shed).unwrap();
+            assert_eq!(Ok(false), service.process(retry));
+        });
+        assert_eq!(0, service.in_flight());
+    }
+
+    #[test]
+    fn throttle_counts_accepted_and_dropped() {
+        let clock = ManualClock::new();
+        let service = ThrottleWithStatsService::with_clock(
+            FnService::new(|input: u32| Ok::<_, Infallible>(input)),
+            5,
+            clock.clone(),
+        );
+
+        let burst: Vec<_> = (0..8).map(|n| service.process(n).unwrap()).collect();
+        assert_eq!(
+            vec![
+                Some(0),
+                Some(1),
+                Some(2),
+                Some(3),
+                Some(4),
+                None,
+                None,
+                None
+            ],
+            burst
+        );
+        assert_eq!(
+            ThrottleStats {
+                accepted: 5,
+                dropped: 3
+            },
+            service.stats()
+        );
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(Ok(Some(8)), service.process(8));
+        assert_eq!(
+            ThrottleStats {
+                accepted: 6,
+                dropped: 3
+            },
+            service.stats()
+        );
+    }
+
+    #[test]
+    fn keyed_rate_limit_is_independent_per_key() {
+        let clock = ManualClock::new();
+        let service = KeyedRateLimitService::with_clock(
+            FnService::new(|(_, n): (&'static str, u32)| Ok::<_, Infallible>(n)),
+            |(tenant, _): &(&'static str, u32)| *tenant,
+            2,
+            1.0,
+            Duration::from_secs(10),
+            clock.clone(),
+        );
+        let accepted = |tenant| {
+            (0..5)
+                .filter(|n| service.process((tenant, *n)).is_ok())
+                .count()
+        };
+
+        // a burst from one tenant does not starve the other
+        assert_eq!(2, accepted("a"));
+        assert_eq!(2, accepted("b"));
+        assert_eq!(
+            Err(RateLimitError::RateLimited(("a", 9))),
+            service.process(("a", 9))
+        );
+
+        // each bucket refills at its own rate
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(1, accepted("a"));
+        assert_eq!(1, accepted("b"));
+        assert_eq!(2, service.tracked_keys());
+
+        // idle buckets are evicted
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(2, accepted("a"));
+        assert_eq!(1, service.tracked_keys());
+    }
+
+    #[test]
+    fn keyed_rate_limit_only_evicts_refilled_buckets() {
+        let clock = ManualClock::new();
+        let service = KeyedRateLimitService::with_clock(
+            FnService::new(|(_, n): (&'static str, u32)| Ok::<_, Infallible>(n)),
+            |(tenant, _): &(&'static str, u32)| *tenant,
+            10,
+            0.1,
+            Duration::from_secs(10),
+            clock.clone(),
+        );
+        let accepted = |tenant, calls| {
+            (0..calls)
+                .filter(|n| service.process((tenant, *n)).is_ok())
+                .count()
+        };
+        assert_eq!(10, accepted("a", 20));
+
+        // idle past the TTL, but only one token has refilled, so the bucket is kept rather than recreated full
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(1, accepted("b", 1));
+        assert_eq!(2, service.tracked_keys());
+        assert_eq!(1, accepted("a", 5));
+
+        // once fully refilled, the idle bucket is evicted
+        clock.advance(Duration::from_secs(100));
+        assert_eq!(1, accepted("b", 1));
+        assert_eq!(1, service.tracked_keys());
+    }
+
+    #[test]
+    fn keyed_rate_limit_rejects_invalid_rates() {
+        let panics = |burst: u32, per_second: f64| {
+            std::panic::catch_unwind(|| {
+                KeyedRateLimitService::<_, u32, _>::new(
+                    FnService::new(|n: u32| Ok::<_, Infallible>(n)),
+                    |n: &u32| *n,
+                    burst,
+                    per_second,
+                    Duration::from_secs(1),
+                )
+            })
+            .is_err()
+        };
+        assert!(!panics(1, 0.5));
+        assert!(panics(0, 1.0));
+        for per_second in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(panics(1, per_second), "{per_second}");
+        }
+    }
+}
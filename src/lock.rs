@@ -0,0 +1,346 @@
+//! Services that enforce mutual exclusion across processes using a pluggable distributed lock.
+
+use std::{
+    convert::Infallible,
+    error::Error,
+    fmt::{Debug, Display},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    async_trait,
+    clock::{Clock, SystemClock},
+    AsyncService, RetryError, Retryable, Service,
+};
+
+/// A pluggable lock, shared by every instance that requires mutual exclusion, used by [`DistributedLockService`].
+///
+/// Implementations backed by an external store, such as a Redis lock, allow mutual exclusion across processes.
+/// A lock is held until it is released or its ttl elapses, so a crashed holder cannot hold the lock forever.
+pub trait DistributedLock {
+    type Error;
+
+    /// Attempt to acquire the lock for the given ttl, returning a token identifying this hold, or `None` if the lock is held by another.
+    ///
+    /// An `Err` means the lock's state could not be determined, such as when its backing store is unreachable.
+    fn acquire(&self, ttl: Duration) -> Result<Option<u64>, Self::Error>;
+
+    /// Release the lock, only if it is still held by the given token.
+    ///
+    /// A release that fails is left to expire with its ttl.
+    fn release(&self, token: u64);
+}
+impl<L: DistributedLock> DistributedLock for &L {
+    type Error = L::Error;
+    fn acquire(&self, ttl: Duration) -> Result<Option<u64>, Self::Error> {
+        (*self).acquire(ttl)
+    }
+    fn release(&self, token: u64) {
+        (*self).release(token)
+    }
+}
+impl<L: DistributedLock> DistributedLock for Arc<L> {
+    type Error = L::Error;
+    fn acquire(&self, ttl: Duration) -> Result<Option<u64>, Self::Error> {
+        self.as_ref().acquire(ttl)
+    }
+    fn release(&self, token: u64) {
+        self.as_ref().release(token)
+    }
+}
+
+/// An in-memory [`DistributedLock`], which expires holds using the given [`Clock`].
+///
+/// Clones of an [`InMemoryLock`] share the same lock, so clones may be given to multiple services within a process, or used by tests.
+#[derive(Clone, Debug)]
+pub struct InMemoryLock<C = SystemClock> {
+    state: Arc<Mutex<LockState>>,
+    clock: C,
+}
+#[derive(Debug)]
+struct LockState {
+    holder: Option<(u64, Instant)>,
+    next_token: u64,
+}
+impl InMemoryLock {
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+}
+impl Default for InMemoryLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<C> InMemoryLock<C> {
+    pub fn with_clock(clock: C) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(LockState {
+                holder: None,
+                next_token: 0,
+            })),
+            clock,
+        }
+    }
+}
+impl<C: Clock> DistributedLock for InMemoryLock<C> {
+    type Error = Infallible;
+    fn acquire(&self, ttl: Duration) -> Result<Option<u64>, Self::Error> {
+        let now = self.clock.now();
+        let mut state = self.state.lock().expect("poisoned mutex");
+        if let Some((_, expiry)) = state.holder {
+            if expiry > now {
+                return Ok(None);
+            }
+        }
+        let token = state.next_token;
+        state.next_token += 1;
+        state.holder = Some((token, now + ttl));
+        Ok(Some(token))
+    }
+    fn release(&self, token: u64) {
+        let mut state = self.state.lock().expect("poisoned mutex");
+        if matches!(state.holder, Some((holder, _)) if holder == token) {
+            state.holder = None;
+        }
+    }
+}
+
+/// A [`Service`] or [`AsyncService`] that holds a [`DistributedLock`] while delegating to the underlying service, serializing calls across every instance sharing the lock.
+///
+/// The lock is acquired for the given ttl before each call, and released after the call completes,
+/// including when the underlying service returns an `Err`, panics, or an async call is cancelled.
+/// The ttl should exceed the longest expected call, since a call that outlives its ttl no longer excludes other holders.
+///
+/// When the lock is held by another, the underlying service is not called and [`DistributedLockError::Locked`] is returned, containing the input.
+/// This service implements [`Retryable`] for locked inputs, so it may be encapsulated by a [`crate::RetryService`] to wait for the lock using an idle strategy.
+/// When the lock fails to acquire, the underlying service is not called and [`DistributedLockError::LockError`] is returned, which is not retried.
+pub struct DistributedLockService<S, L> {
+    service: S,
+    lock: L,
+    ttl: Duration,
+}
+type Acquired<'a, I, E, L> =
+    Result<(I, Held<'a, L>), DistributedLockError<I, E, <L as DistributedLock>::Error>>;
+impl<S, L: DistributedLock> DistributedLockService<S, L> {
+    pub fn new(service: S, lock: L, ttl: Duration) -> Self {
+        Self { service, lock, ttl }
+    }
+
+    fn acquire<I, E>(&self, input: I) -> Acquired<'_, I, E, L> {
+        match self.lock.acquire(self.ttl) {
+            Ok(Some(token)) => Ok((
+                input,
+                Held {
+                    lock: &self.lock,
+                    token,
+                },
+            )),
+            Ok(None) => Err(DistributedLockError::Locked(input)),
+            Err(err) => Err(DistributedLockError::LockError(err)),
+        }
+    }
+}
+impl<S: Service, L: DistributedLock> Service for DistributedLockService<S, L> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = DistributedLockError<S::Input, S::Error, L::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let (input, _held) = self.acquire(input)?;
+        self.service
+            .process(input)
+            .map_err(DistributedLockError::ServiceError)
+    }
+}
+#[async_trait]
+impl<S, L> AsyncService for DistributedLockService<S, L>
+where
+    S: AsyncService,
+    L: DistributedLock + Send + Sync,
+    L::Error: Send + 'static,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = DistributedLockError<S::Input, S::Error, L::Error>;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let (input, _held) = self.acquire(input)?;
+        self.service
+            .process(input)
+            .await
+            .map_err(DistributedLockError::ServiceError)
+    }
+}
+impl<I, E, LE, S, L> Retryable<I, DistributedLockError<I, E, LE>> for DistributedLockService<S, L> {
+    fn parse_retry(
+        &self,
+        err: DistributedLockError<I, E, LE>,
+    ) -> Result<I, RetryError<DistributedLockError<I, E, LE>>> {
+        match err {
+            DistributedLockError::Locked(input) => Ok(input),
+            err => Err(RetryError::ServiceError(err)),
+        }
+    }
+}
+
+/// Releases the lock when dropped, including when a call fails, panics, or is cancelled
+struct Held<'a, L: DistributedLock> {
+    lock: &'a L,
+    token: u64,
+}
+impl<L: DistributedLock> Drop for Held<'_, L> {
+    fn drop(&mut self) {
+        self.lock.release(self.token);
+    }
+}
+
+/// Returned by [`DistributedLockService`], either when the lock is held by another, when the lock fails to acquire, or when the underlying service fails.
+#[derive(Clone, PartialEq, Eq)]
+pub enum DistributedLockError<I, E, LE> {
+    Locked(I),
+    LockError(LE),
+    ServiceError(E),
+}
+impl<I, E: Debug, LE: Debug> Debug for DistributedLockError<I, E, LE> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Locked(_) => f.write_str("Locked"),
+            Self::LockError(err) => f.debug_tuple("LockError").field(err).finish(),
+            Self::ServiceError(err) => f.debug_tuple("ServiceError").field(err).finish(),
+        }
+    }
+}
+impl<I, E: Display, LE: Display> Display for DistributedLockError<I, E, LE> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Locked(_) => f.write_str("locked"),
+            Self::LockError(err) => write!(f, "lock failed: {err}"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<I, E: Error + 'static, LE: Error + 'static> Error for DistributedLockError<I, E, LE> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Locked(_) => None,
+            Self::LockError(err) => Some(err),
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+
+    use super::*;
+    use crate::{clock::ManualClock, idle::yielding, FnService, RetryService};
+
+    #[test]
+    fn distributed_lock_serializes_instances() {
+        let active = AtomicUsize::new(0);
+        let max_active = AtomicUsize::new(0);
+        let calls = AtomicUsize::new(0);
+        let lock = InMemoryLock::new();
+        let instance = || {
+            RetryService::new(
+                DistributedLockService::new(
+                    FnService::new(|_: ()| {
+                        let now_active = active.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_active.fetch_max(now_active, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(1));
+                        active.fetch_sub(1, Ordering::SeqCst);
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        Ok::<_, ()>(())
+                    }),
+                    lock.clone(),
+                    Duration::from_secs(60),
+                ),
+                yielding,
+            )
+        };
+        let (first, second) = (instance(), instance());
+        thread::scope(|scope| {
+            for instance in [&first, &second] {
+                scope.spawn(move || {
+                    for _ in 0..20 {
+                        instance.process(()).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(40, calls.load(Ordering::SeqCst));
+        assert_eq!(1, max_active.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn distributed_lock_released_on_error_and_expiry() {
+        let clock = ManualClock::new();
+        let lock = InMemoryLock::with_clock(clock.clone());
+        let service = DistributedLockService::new(
+            FnService::new(|fail: bool| match fail {
+                true => Err("failed"),
+                false => Ok(()),
+            }),
+            lock.clone(),
+            Duration::from_secs(10),
+        );
+        assert_eq!(
+            Err(DistributedLockError::ServiceError("failed")),
+            service.process(true)
+        );
+        assert_eq!(Ok(()), service.process(false));
+
+        // another holder blocks the service until its hold expires
+        let token = lock.acquire(Duration::from_secs(10)).unwrap().unwrap();
+        assert_eq!(
+            Err(DistributedLockError::Locked(false)),
+            service.process(false)
+        );
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(Ok(()), service.process(false));
+        // the expired holder's release does not release a newer hold
+        assert!(lock.acquire(Duration::from_secs(10)).unwrap().is_some());
+        lock.release(token);
+        assert_eq!(
+            Err(DistributedLockError::Locked(false)),
+            service.process(false)
+        );
+    }
+
+    /// A lock whose backing store is unreachable
+    struct Unreachable;
+    impl DistributedLock for Unreachable {
+        type Error = &'static str;
+        fn acquire(&self, _: Duration) -> Result<Option<u64>, Self::Error> {
+            Err("unreachable")
+        }
+        fn release(&self, _: u64) {}
+    }
+
+    #[test]
+    fn distributed_lock_surfaces_lock_errors_without_retrying() {
+        let calls = AtomicUsize::new(0);
+        let service = RetryService::new(
+            DistributedLockService::new(
+                FnService::new(|_: ()| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    Ok::<_, ()>(())
+                }),
+                Unreachable,
+                Duration::from_secs(10),
+            ),
+            yielding,
+        );
+        assert_eq!(
+            Err(RetryError::ServiceError(DistributedLockError::LockError(
+                "unreachable"
+            ))),
+            service.process(())
+        );
+        assert_eq!(0, calls.load(Ordering::SeqCst));
+    }
+}
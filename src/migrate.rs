@@ -0,0 +1,179 @@
+//! Services that upgrade versioned inputs to the latest schema, so the underlying service only handles the latest schema.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{Debug, Display},
+};
+
+use crate::Service;
+
+/// A [`Service`] that upgrades each input to the latest schema version by applying registered migrations, before delegating to the underlying service.
+///
+/// The version of each input is read using the given function.
+/// A migration registered for `from_version` upgrades an input from `from_version` to `from_version + 1`, and must set the input's version field accordingly.
+/// Migrations are applied in sequence, so an input at any older version is upgraded one version at a time until it reaches the latest version.
+///
+/// An input newer than the latest version is rejected with [`MigrationError::TooNew`],
+/// and an input whose version has no registered migration is rejected with [`MigrationError::NoMigration`], without calling the underlying service.
+///
+/// Use [`MigratingService::migration`] to register migrations.
+pub struct MigratingService<'a, S: Service, F> {
+    service: S,
+    latest: u32,
+    version: F,
+    migrations: HashMap<u32, Migration<'a, S::Input>>,
+}
+type Migration<'a, I> = Box<dyn Fn(I) -> I + 'a>;
+impl<'a, S: Service, F: Fn(&S::Input) -> u32> MigratingService<'a, S, F> {
+    /// Create a new [`MigratingService`], where `latest` is the schema version expected by the underlying service
+    pub fn new(service: S, latest: u32, version: F) -> Self {
+        Self {
+            service,
+            latest,
+            version,
+            migrations: HashMap::new(),
+        }
+    }
+
+    /// Register the migration which upgrades an input from `from_version` to `from_version + 1`, replacing any existing migration for `from_version`
+    pub fn migration<M: Fn(S::Input) -> S::Input + 'a>(
+        mut self,
+        from_version: u32,
+        migrate: M,
+    ) -> Self {
+        self.migrations.insert(from_version, Box::new(migrate));
+        self
+    }
+}
+impl<'a, S: Service, F: Fn(&S::Input) -> u32> Service for MigratingService<'a, S, F> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = MigrationError<S::Error>;
+    fn process(&self, mut input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let version = (self.version)(&input);
+        if version > self.latest {
+            return Err(MigrationError::TooNew {
+                version,
+                latest: self.latest,
+            });
+        }
+        for from_version in version..self.latest {
+            let migrate = self
+                .migrations
+                .get(&from_version)
+                .ok_or(MigrationError::NoMigration { from_version })?;
+            input = migrate(input);
+        }
+        self.service
+            .process(input)
+            .map_err(MigrationError::ServiceError)
+    }
+}
+
+/// Returned by [`MigratingService`], either when an input cannot be upgraded to the latest version or when the underlying service fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MigrationError<E> {
+    /// The input's version is newer than the latest version
+    TooNew {
+        version: u32,
+        latest: u32,
+    },
+    /// No migration is registered to upgrade from the given version
+    NoMigration {
+        from_version: u32,
+    },
+    ServiceError(E),
+}
+impl<E: Display> Display for MigrationError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooNew { version, latest } => {
+                write!(
+                    f,
+                    "version {version} is newer than the latest version {latest}"
+                )
+            }
+            Self::NoMigration { from_version } => {
+                write!(f, "no migration from version {from_version}")
+            }
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<E: Error + 'static> Error for MigrationError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::TooNew { .. } | Self::NoMigration { .. } => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::FnService;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Message {
+        version: u32,
+        fields: BTreeMap<&'static str, String>,
+    }
+
+    fn message(version: u32, fields: &[(&'static str, &str)]) -> Message {
+        Message {
+            version,
+            fields: fields.iter().map(|(k, v)| (*k, v.to_string())).collect(),
+        }
+    }
+
+    #[test]
+    fn migrating_upgrades_to_latest() {
+        let service = MigratingService::new(
+            FnService::new(|message: Message| Ok::<_, ()>(message)),
+            3,
+            |message: &Message| message.version,
+        )
+        // v2 splits `name` into `first` and `last`
+        .migration(1, |mut message| {
+            let name = message.fields.remove("name").unwrap_or_default();
+            let (first, last) = name.split_once(' ').unwrap_or((&name, ""));
+            message.fields.insert("first", first.to_owned());
+            message.fields.insert("last", last.to_owned());
+            message.version = 2;
+            message
+        })
+        // v3 renames `last` to `surname`
+        .migration(2, |mut message| {
+            let last = message.fields.remove("last").unwrap_or_default();
+            message.fields.insert("surname", last);
+            message.version = 3;
+            message
+        });
+
+        let latest = message(3, &[("first", "Ada"), ("surname", "Lovelace")]);
+        assert_eq!(
+            Ok(latest.clone()),
+            service.process(message(1, &[("name", "Ada Lovelace")]))
+        );
+        assert_eq!(
+            Ok(latest.clone()),
+            service.process(message(2, &[("first", "Ada"), ("last", "Lovelace")]))
+        );
+        assert_eq!(Ok(latest.clone()), service.process(latest));
+        assert_eq!(
+            Err(MigrationError::TooNew {
+                version: 4,
+                latest: 3
+            }),
+            service.process(message(4, &[]))
+        );
+        assert_eq!(
+            Err(MigrationError::NoMigration { from_version: 0 }),
+            service.process(message(0, &[]))
+        );
+    }
+}
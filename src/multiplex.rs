@@ -0,0 +1,195 @@
+//! Services that carry multiple logical streams over a single transport.
+//!
+//! Outgoing messages are tagged with their stream id by a [`TagStreamService`], and incoming messages are routed to per-stream handlers by a [`MultiplexService`].
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    error::Error,
+    fmt::{Debug, Display},
+    hash::Hash,
+    marker::PhantomData,
+    sync::{Arc, RwLock},
+};
+
+use crate::Service;
+
+/// A message tagged with the id of the logical stream it belongs to.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Tagged<K, T> {
+    pub stream: K,
+    pub message: T,
+}
+
+/// A [`Service`] that tags each outgoing message with a fixed stream id, producing a [`Tagged`] message.
+pub struct TagStreamService<K, T> {
+    stream: K,
+    _phantom: PhantomData<fn(T)>,
+}
+impl<K: Clone, T> TagStreamService<K, T> {
+    pub fn new(stream: K) -> Self {
+        Self {
+            stream,
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<K: Clone, T> Service for TagStreamService<K, T> {
+    type Input = T;
+    type Output = Tagged<K, T>;
+    type Error = Infallible;
+    fn process(&self, input: T) -> Result<Self::Output, Self::Error> {
+        Ok(Tagged {
+            stream: self.stream.clone(),
+            message: input,
+        })
+    }
+}
+
+/// A [`Service`] that routes each incoming message to the handler [`Service`] registered for its stream id.
+///
+/// The stream id is decoded from each input using the given function.
+/// Handlers may be registered and deregistered at runtime, including from other threads while messages are being routed, so handlers must be [`Send`] and [`Sync`].
+/// A message for a stream with no registered handler is rejected with [`MultiplexError::UnknownStream`].
+pub struct MultiplexService<'a, K, I, O, E, F> {
+    id: F,
+    handlers: RwLock<HashMap<K, Handler<'a, I, O, E>>>,
+}
+type Handler<'a, I, O, E> = Arc<dyn Service<Input = I, Output = O, Error = E> + Send + Sync + 'a>;
+impl<'a, K: Eq + Hash, I, O, E, F: Fn(&I) -> K> MultiplexService<'a, K, I, O, E, F> {
+    pub fn new(id: F) -> Self {
+        Self {
+            id,
+            handlers: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Register the handler for the given stream id, returning `true` if it replaced an existing handler
+    pub fn register<S>(&self, stream: K, handler: S) -> bool
+    where
+        S: Service<Input = I, Output = O, Error = E> + Send + Sync + 'a,
+    {
+        self.handlers
+            .write()
+            .expect("poisoned lock")
+            .insert(stream, Arc::new(handler))
+            .is_some()
+    }
+
+    /// Deregister the handler for the given stream id, returning `true` if a handler was registered
+    pub fn deregister(&self, stream: &K) -> bool {
+        self.handlers
+            .write()
+            .expect("poisoned lock")
+            .remove(stream)
+            .is_some()
+    }
+}
+impl<'a, K: Eq + Hash, I, O, E, F: Fn(&I) -> K> Service for MultiplexService<'a, K, I, O, E, F> {
+    type Input = I;
+    type Output = O;
+    type Error = MultiplexError<K, E>;
+    fn process(&self, input: I) -> Result<O, Self::Error> {
+        let stream = (self.id)(&input);
+        // release the lock before calling the handler, so handlers may register or deregister streams
+        let handler = self
+            .handlers
+            .read()
+            .expect("poisoned lock")
+            .get(&stream)
+            .cloned();
+        match handler {
+            Some(handler) => handler.process(input).map_err(MultiplexError::ServiceError),
+            None => Err(MultiplexError::UnknownStream(stream)),
+        }
+    }
+}
+
+/// Returned by [`MultiplexService`], either when no handler is registered for a stream or when a handler fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MultiplexError<K, E> {
+    UnknownStream(K),
+    ServiceError(E),
+}
+impl<K: Debug, E: Display> Display for MultiplexError<K, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownStream(stream) => write!(f, "unknown stream {stream:?}"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<K: Debug, E: Error + 'static> Error for MultiplexError<K, E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::UnknownStream(_) => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::FnService;
+
+    #[test]
+    fn multiplex_routes_by_stream() {
+        let mux = MultiplexService::new(|input: &Tagged<u8, String>| input.stream);
+        let handler = |name: &'static str| {
+            FnService::new(move |input: Tagged<u8, String>| {
+                Ok::<_, ()>(format!("{name}:{}", input.message))
+            })
+        };
+        mux.register(1, handler("prices"));
+        mux.register(2, handler("orders"));
+
+        let prices = TagStreamService::new(1);
+        let orders = TagStreamService::new(2);
+        let unknown = TagStreamService::new(3);
+        let route = |tag: &TagStreamService<u8, String>, message: &str| {
+            mux.process(tag.process(message.to_owned()).unwrap())
+        };
+
+        assert_eq!(Ok("prices:a".to_owned()), route(&prices, "a"));
+        assert_eq!(Ok("orders:b".to_owned()), route(&orders, "b"));
+        assert_eq!(Ok("prices:c".to_owned()), route(&prices, "c"));
+        assert_eq!(Err(MultiplexError::UnknownStream(3)), route(&unknown, "d"));
+
+        assert!(mux.deregister(&2));
+        assert_eq!(Err(MultiplexError::UnknownStream(2)), route(&orders, "e"));
+    }
+
+    #[test]
+    fn multiplex_registers_while_routing_on_another_thread() {
+        let mux = MultiplexService::new(|input: &Tagged<u8, u32>| input.stream);
+        mux.register(
+            1,
+            FnService::new(|input: Tagged<u8, u32>| Ok::<_, ()>(input.message)),
+        );
+        let tagged = |stream, message| Tagged { stream, message };
+
+        thread::scope(|scope| {
+            let router = scope.spawn(|| {
+                let mut routed = 0;
+                // route to the existing stream until the new stream is registered by the other thread
+                while mux.process(tagged(2, 0)).is_err() {
+                    assert_eq!(Ok(routed), mux.process(tagged(1, routed)));
+                    routed += 1;
+                    thread::yield_now();
+                }
+                routed
+            });
+            scope.spawn(|| {
+                mux.register(
+                    2,
+                    FnService::new(|input: Tagged<u8, u32>| Ok(input.message * 10)),
+                )
+            });
+            router.join().unwrap();
+        });
+        assert_eq!(Ok(30), mux.process(tagged(2, 3)));
+    }
+}
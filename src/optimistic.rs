@@ -0,0 +1,272 @@
+//! Services that update stored values using optimistic concurrency, retrying on conflicting writes instead of holding a lock.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    error::Error,
+    fmt::{Debug, Display},
+    hash::Hash,
+    marker::PhantomData,
+    sync::Mutex,
+};
+
+use crate::Service;
+
+/// A value read from a [`VersionedStore`], along with the version it was read at
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Versioned<V> {
+    pub value: V,
+    pub version: u64,
+}
+
+/// A pluggable store of versioned values, used by [`OptimisticUpdateService`].
+///
+/// Implementations backed by an external store, such as a database row with a version column, allow lock-free updates across processes.
+pub trait VersionedStore<K, V> {
+    type Error;
+
+    /// Read the current value and version for the key
+    fn read(&self, key: &K) -> Result<Versioned<V>, Self::Error>;
+
+    /// Write the value only if the key's version is still `version`, returning `false` if it has changed since it was read
+    fn compare_and_set(&self, key: &K, version: u64, value: V) -> Result<bool, Self::Error>;
+}
+impl<K, V, St: VersionedStore<K, V>> VersionedStore<K, V> for &St {
+    type Error = St::Error;
+    fn read(&self, key: &K) -> Result<Versioned<V>, Self::Error> {
+        (*self).read(key)
+    }
+    fn compare_and_set(&self, key: &K, version: u64, value: V) -> Result<bool, Self::Error> {
+        (*self).compare_and_set(key, version, value)
+    }
+}
+
+/// An in-memory [`VersionedStore`], where an absent key reads as the default value at version `0`, and each write increments the version.
+pub struct InMemoryVersionedStore<K, V> {
+    values: Mutex<HashMap<K, Versioned<V>>>,
+}
+impl<K, V> InMemoryVersionedStore<K, V> {
+    pub fn new() -> Self {
+        Self {
+            values: Mutex::new(HashMap::new()),
+        }
+    }
+}
+impl<K, V> Default for InMemoryVersionedStore<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K: Clone + Eq + Hash, V: Clone + Default> VersionedStore<K, V>
+    for InMemoryVersionedStore<K, V>
+{
+    type Error = Infallible;
+    fn read(&self, key: &K) -> Result<Versioned<V>, Self::Error> {
+        Ok(self
+            .values
+            .lock()
+            .expect("poisoned mutex")
+            .get(key)
+            .cloned()
+            .unwrap_or_else(|| Versioned {
+                value: V::default(),
+                version: 0,
+            }))
+    }
+    fn compare_and_set(&self, key: &K, version: u64, value: V) -> Result<bool, Self::Error> {
+        let mut values = self.values.lock().expect("poisoned mutex");
+        let current = values.get(key).map(|v| v.version).unwrap_or(0);
+        if current != version {
+            return Ok(false);
+        }
+        values.insert(
+            key.clone(),
+            Versioned {
+                value,
+                version: version + 1,
+            },
+        );
+        Ok(true)
+    }
+}
+
+/// A [`Service`] that performs a read-modify-write of a value in a [`VersionedStore`], writing back only if the value was not modified in the meantime.
+///
+/// The key is extracted from each input using the given function, and the current value is given to the `transform` function along with the input to produce the new value.
+/// When the write conflicts with a concurrent modification, the read-modify-write is retried against the newly read value,
+/// up to `max_attempts` attempts in total, after which [`OptimisticUpdateError::Conflict`] is returned.
+/// The `transform` function may be called once per attempt, so it should not have side effects.
+///
+/// The new value is produced as output once it has been written.
+pub struct OptimisticUpdateService<I, K, V, St, KF, F> {
+    store: St,
+    key: KF,
+    transform: F,
+    max_attempts: usize,
+    _phantom: PhantomData<fn(I, K, V)>,
+}
+impl<I, K, V, St, KF, F> OptimisticUpdateService<I, K, V, St, KF, F>
+where
+    St: VersionedStore<K, V>,
+    KF: Fn(&I) -> K,
+    F: Fn(&I, V) -> V,
+{
+    /// Create a new [`OptimisticUpdateService`], making at most `max_attempts` read-modify-write attempts per call, including the first
+    ///
+    /// # Panics
+    /// Panics if `max_attempts` is zero.
+    pub fn new(store: St, key: KF, transform: F, max_attempts: usize) -> Self {
+        assert!(max_attempts > 0, "max_attempts must be at least 1");
+        Self {
+            store,
+            key,
+            transform,
+            max_attempts,
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<I, K, V, St, KF, F> Service for OptimisticUpdateService<I, K, V, St, KF, F>
+where
+    V: Clone,
+    St: VersionedStore<K, V>,
+    KF: Fn(&I) -> K,
+    F: Fn(&I, V) -> V,
+{
+    type Input = I;
+    type Output = V;
+    type Error = OptimisticUpdateError<St::Error>;
+    fn process(&self, input: I) -> Result<V, Self::Error> {
+        let key = (self.key)(&input);
+        for _ in 0..self.max_attempts {
+            let current = self
+                .store
+                .read(&key)
+                .map_err(OptimisticUpdateError::StoreError)?;
+            let value = (self.transform)(&input, current.value);
+            if self
+                .store
+                .compare_and_set(&key, current.version, value.clone())
+                .map_err(OptimisticUpdateError::StoreError)?
+            {
+                return Ok(value);
+            }
+        }
+        Err(OptimisticUpdateError::Conflict {
+            attempts: self.max_attempts,
+        })
+    }
+}
+
+/// Returned by [`OptimisticUpdateService`], either when every attempt conflicted or when the store fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OptimisticUpdateError<E> {
+    Conflict { attempts: usize },
+    StoreError(E),
+}
+impl<E: Display> Display for OptimisticUpdateError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Conflict { attempts } => {
+                write!(f, "update conflicted on all {attempts} attempts")
+            }
+            Self::StoreError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<E: Error + 'static> Error for OptimisticUpdateError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Conflict { .. } => None,
+            Self::StoreError(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// Simulates another writer, which modifies the key between the read and the write for the first `conflicts` writes
+    struct ContendedStore {
+        store: InMemoryVersionedStore<&'static str, u64>,
+        conflicts: AtomicUsize,
+        writes: AtomicUsize,
+    }
+    impl VersionedStore<&'static str, u64> for ContendedStore {
+        type Error = Infallible;
+        fn read(&self, key: &&'static str) -> Result<Versioned<u64>, Infallible> {
+            self.store.read(key)
+        }
+        fn compare_and_set(
+            &self,
+            key: &&'static str,
+            version: u64,
+            value: u64,
+        ) -> Result<bool, Infallible> {
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            if self.conflicts.load(Ordering::SeqCst) > 0 {
+                self.conflicts.fetch_sub(1, Ordering::SeqCst);
+                let concurrent = self.store.read(key)?;
+                self.store
+                    .compare_and_set(key, concurrent.version, concurrent.value + 100)?;
+            }
+            self.store.compare_and_set(key, version, value)
+        }
+    }
+
+    fn contended(conflicts: usize) -> ContendedStore {
+        ContendedStore {
+            store: InMemoryVersionedStore::new(),
+            conflicts: AtomicUsize::new(conflicts),
+            writes: AtomicUsize::new(0),
+        }
+    }
+
+    #[test]
+    fn optimistic_update_retries_on_conflict() {
+        let store = contended(1);
+        let service = OptimisticUpdateService::new(
+            &store,
+            |_: &u64| "counter",
+            |amount: &u64, value: u64| value + amount,
+            3,
+        );
+        // the concurrent writer's increment is preserved, and this increment is applied on top of it
+        assert_eq!(Ok(105), service.process(5));
+        assert_eq!(2, store.writes.load(Ordering::SeqCst));
+        assert_eq!(
+            Versioned {
+                value: 105,
+                version: 2
+            },
+            store.read(&"counter").unwrap()
+        );
+
+        let store = contended(3);
+        let service = OptimisticUpdateService::new(
+            &store,
+            |_: &u64| "counter",
+            |amount: &u64, value: u64| value + amount,
+            3,
+        );
+        assert_eq!(
+            Err(OptimisticUpdateError::Conflict { attempts: 3 }),
+            service.process(5)
+        );
+        assert_eq!(300, store.read(&"counter").unwrap().value);
+    }
+
+    #[test]
+    #[should_panic(expected = "max_attempts must be at least 1")]
+    fn optimistic_update_rejects_zero_max_attempts() {
+        OptimisticUpdateService::new(
+            contended(0),
+            |_: &u64| "counter",
+            |amount: &u64, value: u64| value + amount,
+            0,
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! Services that reuse a bounded pool of connections across calls, rather than connecting for every call.
+
+use std::{
+    io::{self, ErrorKind},
+    marker::PhantomData,
+    net::{SocketAddr, TcpStream},
+    sync::{Condvar, Mutex},
+};
+
+use crate::Service;
+
+/// A [`Service`] that calls the given function with a [`TcpStream`] from a pool of connections to a target address, returning the connection to the pool afterwards.
+///
+/// An idle connection is reused when one is available, otherwise a new connection is opened, up to `max_size` open connections.
+/// When every connection is in use, a call blocks until another call returns its connection to the pool, applying backpressure to callers.
+///
+/// Before an idle connection is reused, it is checked without blocking, and it is discarded when the peer has closed it,
+/// when it has failed, or when it has unread bytes that would otherwise be mistaken for the response to the next call.
+/// A connection is also discarded when the function returns an error or panics, freeing its slot for a new connection.
+pub struct PooledTcpClientService<I, O, F> {
+    addr: SocketAddr,
+    max_size: usize,
+    function: F,
+    pool: Mutex<Pool>,
+    available: Condvar,
+    _phantom: PhantomData<fn(I, O)>,
+}
+struct Pool {
+    idle: Vec<TcpStream>,
+    open: usize,
+}
+impl<I, O, F> PooledTcpClientService<I, O, F>
+where
+    F: Fn(&mut TcpStream, I) -> io::Result<O>,
+{
+    /// Create a new [`PooledTcpClientService`], holding up to `max_size` open connections to `addr`
+    ///
+    /// # Panics
+    /// Panics if `max_size` is zero.
+    pub fn new(addr: SocketAddr, max_size: usize, function: F) -> Self {
+        assert!(max_size > 0, "max_size must be non-zero");
+        Self {
+            addr,
+            max_size,
+            function,
+            pool: Mutex::new(Pool {
+                idle: Vec::new(),
+                open: 0,
+            }),
+            available: Condvar::new(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// The number of open connections, both idle and in use
+    pub fn open(&self) -> usize {
+        self.pool.lock().expect("poisoned mutex").open
+    }
+
+    /// The number of idle connections waiting in the pool
+    pub fn idle(&self) -> usize {
+        self.pool.lock().expect("poisoned mutex").idle.len()
+    }
+
+    /// Take a live idle connection from the pool, or reserve a slot to open a new one, blocking while every connection is in use
+    fn checkout(&self) -> io::Result<Checkout<'_, I, O, F>> {
+        let mut pool = self.pool.lock().expect("poisoned mutex");
+        loop {
+            while let Some(stream) = pool.idle.pop() {
+                if is_alive(&stream) {
+                    return Ok(Checkout {
+                        service: self,
+                        stream: Some(stream),
+                        released: false,
+                    });
+                }
+                pool.open -= 1;
+            }
+            if pool.open < self.max_size {
+                pool.open += 1;
+                break;
+            }
+            pool = self.available.wait(pool).expect("poisoned mutex");
+        }
+        drop(pool);
+        // the slot is released by dropping the checkout if connecting fails
+        let mut checkout = Checkout {
+            service: self,
+            stream: None,
+            released: false,
+        };
+        checkout.stream = Some(TcpStream::connect(self.addr)?);
+        Ok(checkout)
+    }
+}
+impl<I, O, F> Service for PooledTcpClientService<I, O, F>
+where
+    F: Fn(&mut TcpStream, I) -> io::Result<O>,
+{
+    type Input = I;
+    type Output = O;
+    type Error = io::Error;
+    fn process(&self, input: I) -> Result<Self::Output, Self::Error> {
+        let mut checkout = self.checkout()?;
+        let stream = checkout.stream.as_mut().expect("checked out stream");
+        let output = (self.function)(stream, input)?;
+        checkout.release();
+        Ok(output)
+    }
+}
+
+/// A connection slot held by a call, which is freed when dropped unless the connection is released back to the pool
+struct Checkout<'a, I, O, F> {
+    service: &'a PooledTcpClientService<I, O, F>,
+    stream: Option<TcpStream>,
+    released: bool,
+}
+impl<'a, I, O, F> Checkout<'a, I, O, F> {
+    fn release(mut self) {
+        if let Some(stream) = self.stream.take() {
+            // the connection keeps its slot while idle in the pool
+            self.service
+                .pool
+                .lock()
+                .expect("poisoned mutex")
+                .idle
+                .push(stream);
+            self.released = true;
+        }
+    }
+}
+impl<'a, I, O, F> Drop for Checkout<'a, I, O, F> {
+    fn drop(&mut self) {
+        if !self.released {
+            self.stream = None;
+            self.service.pool.lock().expect("poisoned mutex").open -= 1;
+        }
+        self.service.available.notify_one();
+    }
+}
+
+/// Returns `true` when an idle connection has not been closed by its peer, has not failed, and has no unread bytes
+fn is_alive(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let alive = match stream.peek(&mut [0]) {
+        Err(err) => err.kind() == ErrorKind::WouldBlock,
+        Ok(_) => false,
+    };
+    alive && stream.set_nonblocking(false).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{Shutdown, TcpListener},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+
+    /// Echoes each line back on every accepted connection, closing a connection after a line of `close`
+    fn echo_server(delay: Duration) -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&accepted);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                        thread::sleep(delay);
+                        stream.write_all(line.as_bytes()).unwrap();
+                        if line == "close\n" {
+                            stream.shutdown(Shutdown::Both).ok();
+                            return;
+                        }
+                        line.clear();
+                    }
+                });
+            }
+        });
+        (addr, accepted)
+    }
+
+    fn echo(stream: &mut TcpStream, line: String) -> io::Result<String> {
+        stream.write_all(format!("{line}\n").as_bytes())?;
+        let mut response = vec![0; line.len() + 1];
+        stream.read_exact(&mut response)?;
+        Ok(String::from_utf8(response).unwrap().trim_end().to_owned())
+    }
+
+    #[test]
+    fn pooled_tcp_client_reuses_connections() {
+        let (addr, accepted) = echo_server(Duration::ZERO);
+        let service = PooledTcpClientService::new(addr, 4, echo);
+
+        for n in 0..5 {
+            assert_eq!(
+                format!("hello {n}"),
+                service.process(format!("hello {n}")).unwrap()
+            );
+        }
+        assert_eq!(1, accepted.load(Ordering::SeqCst));
+        assert_eq!((1, 1), (service.open(), service.idle()));
+
+        // a connection closed by the peer is discarded, and the next call connects again
+        assert_eq!("close", service.process("close".to_owned()).unwrap());
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!("again", service.process("again".to_owned()).unwrap());
+        assert_eq!(2, accepted.load(Ordering::SeqCst));
+        assert_eq!((1, 1), (service.open(), service.idle()));
+
+        // a failed call discards its connection
+        let failing = PooledTcpClientService::new(addr, 1, |_: &mut TcpStream, _: ()| {
+            Err::<(), _>(io::Error::other("failed"))
+        });
+        assert!(failing.process(()).is_err());
+        assert_eq!(0, failing.open());
+    }
+
+    #[test]
+    fn pooled_tcp_client_respects_max_size_under_concurrency() {
+        let (addr, accepted) = echo_server(Duration::from_millis(20));
+        let active = AtomicUsize::new(0);
+        let max_active = AtomicUsize::new(0);
+        let service = PooledTcpClientService::new(addr, 3, |stream: &mut TcpStream, line| {
+            let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+            max_active.fetch_max(now, Ordering::SeqCst);
+            let response = echo(stream, line);
+            active.fetch_sub(1, Ordering::SeqCst);
+            response
+        });
+
+        thread::scope(|scope| {
+            for t in 0..8 {
+                let service = &service;
+                scope.spawn(move || {
+                    for n in 0..5 {
+                        let line = format!("{t}-{n}");
+                        assert_eq!(line.clone(), service.process(line).unwrap());
+                    }
+                });
+            }
+        });
+
+        // callers beyond the max size waited for a connection rather than opening more
+        assert_eq!(3, max_active.load(Ordering::SeqCst));
+        assert_eq!(3, accepted.load(Ordering::SeqCst));
+        assert_eq!((3, 3), (service.open(), service.idle()));
+    }
+}
@@ -0,0 +1,718 @@
+//! Services that queue values between producers and consumers, such as by priority or by weighted fairness, that fairly fan in values from multiple queues,
+//! or that batch or time values sent through a queue.
+
+use std::{
+    collections::{BinaryHeap, VecDeque},
+    convert::Infallible,
+    mem,
+    sync::{
+        mpsc::{Receiver, RecvError, SendError, Sender, TryRecvError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    MutService, Service,
+};
+
+/// A priority queue shared between producers and consumers, which are exposed as services by [`PriorityQueueService::push_service`] and [`PriorityQueueService::pop_service`].
+///
+/// Values are held in a [`BinaryHeap`] under a [`Mutex`], so the greatest value according to its [`Ord`] impl is popped first.
+/// Use [`std::cmp::Reverse`] to pop the least value first.
+/// Values with equal priority are not guaranteed to pop in the order they were pushed.
+///
+/// Clones of a [`PriorityQueueService`] share the same queue.
+pub struct PriorityQueueService<T> {
+    heap: Arc<Mutex<BinaryHeap<T>>>,
+}
+impl<T: Ord> PriorityQueueService<T> {
+    pub fn new() -> Self {
+        Self {
+            heap: Arc::new(Mutex::new(BinaryHeap::new())),
+        }
+    }
+
+    /// Create a [`PriorityPushService`], which pushes to this queue
+    pub fn push_service(&self) -> PriorityPushService<T> {
+        PriorityPushService {
+            heap: Arc::clone(&self.heap),
+        }
+    }
+
+    /// Create a [`PriorityPopService`], which pops from this queue
+    pub fn pop_service(&self) -> PriorityPopService<T> {
+        PriorityPopService {
+            heap: Arc::clone(&self.heap),
+        }
+    }
+
+    /// The number of values currently queued
+    pub fn len(&self) -> usize {
+        self.heap.lock().expect("poisoned mutex").len()
+    }
+
+    /// Returns `true` if no values are currently queued
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+impl<T: Ord> Default for PriorityQueueService<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T> Clone for PriorityQueueService<T> {
+    fn clone(&self) -> Self {
+        Self {
+            heap: Arc::clone(&self.heap),
+        }
+    }
+}
+
+/// A [`Service`] or [`MutService`] that pushes each input to a [`PriorityQueueService`].
+pub struct PriorityPushService<T> {
+    heap: Arc<Mutex<BinaryHeap<T>>>,
+}
+impl<T: Ord> Service for PriorityPushService<T> {
+    type Input = T;
+    type Output = ();
+    type Error = Infallible;
+    fn process(&self, input: T) -> Result<(), Infallible> {
+        self.heap.lock().expect("poisoned mutex").push(input);
+        Ok(())
+    }
+}
+impl<T: Ord> MutService for PriorityPushService<T> {
+    type Input = T;
+    type Output = ();
+    type Error = Infallible;
+    fn process(&mut self, input: T) -> Result<(), Infallible> {
+        Service::process(self, input)
+    }
+}
+
+/// A [`Service`] or [`MutService`] that pops the highest-priority value from a [`PriorityQueueService`], producing `None` when it is empty.
+///
+/// This may be encapsulated by a [`crate::PollService`] to block until a value is available.
+pub struct PriorityPopService<T> {
+    heap: Arc<Mutex<BinaryHeap<T>>>,
+}
+impl<T: Ord> Service for PriorityPopService<T> {
+    type Input = ();
+    type Output = Option<T>;
+    type Error = Infallible;
+    fn process(&self, _: ()) -> Result<Option<T>, Infallible> {
+        Ok(self.heap.lock().expect("poisoned mutex").pop())
+    }
+}
+impl<T: Ord> MutService for PriorityPopService<T> {
+    type Input = ();
+    type Output = Option<T>;
+    type Error = Infallible;
+    fn process(&mut self, input: ()) -> Result<Option<T>, Infallible> {
+        Service::process(self, input)
+    }
+}
+
+/// A queue with multiple classes, shared between producers and consumers, which dequeues from each class in proportion to its weight,
+/// exposed as services by [`WeightedFairQueueService::push_service`] and [`WeightedFairQueueService::pop_service`].
+///
+/// Unlike a [`PriorityQueueService`], low-weight classes still progress while high-weight classes are busy, instead of being starved.
+/// Classes are selected using smooth weighted round-robin, like [`crate::balance::WeightedRoundRobinService`], considering only classes which have queued values,
+/// so an idle class neither accumulates credit nor slows down the other classes.
+/// Values within a class are dequeued in the order they were pushed.
+///
+/// Clones of a [`WeightedFairQueueService`] share the same queue.
+pub struct WeightedFairQueueService<T> {
+    state: Arc<Mutex<FairQueueState<T>>>,
+}
+struct FairQueueState<T> {
+    classes: Vec<(VecDeque<T>, u32)>,
+    current: Vec<i64>,
+}
+impl<T> FairQueueState<T> {
+    fn pop(&mut self) -> Option<T> {
+        let mut selected = None;
+        let mut total = 0;
+        for (i, (queue, weight)) in self.classes.iter().enumerate() {
+            if queue.is_empty() {
+                self.current[i] = 0;
+                continue;
+            }
+            self.current[i] += *weight as i64;
+            total += *weight as i64;
+            if selected.is_none_or(|s: usize| self.current[i] > self.current[s]) {
+                selected = Some(i);
+            }
+        }
+        let selected = selected?;
+        self.current[selected] -= total;
+        self.classes[selected].0.pop_front()
+    }
+}
+impl<T> WeightedFairQueueService<T> {
+    /// Create a new [`WeightedFairQueueService`] with one class per given weight, where a class is identified by the index of its weight.
+    ///
+    /// # Panics
+    /// Panics if no weights are given, or if any weight is zero.
+    pub fn new(weights: Vec<u32>) -> Self {
+        assert!(
+            !weights.is_empty() && weights.iter().all(|w| *w > 0),
+            "WeightedFairQueueService requires at least one class, each with a positive weight"
+        );
+        Self {
+            state: Arc::new(Mutex::new(FairQueueState {
+                current: vec![0; weights.len()],
+                classes: weights.into_iter().map(|w| (VecDeque::new(), w)).collect(),
+            })),
+        }
+    }
+
+    /// Create a [`WeightedFairPushService`], which pushes to the given class of this queue
+    ///
+    /// # Panics
+    /// Panics if the class does not exist.
+    pub fn push_service(&self, class: usize) -> WeightedFairPushService<T> {
+        assert!(
+            class < self.state.lock().expect("poisoned mutex").classes.len(),
+            "WeightedFairQueueService has no class {class}"
+        );
+        WeightedFairPushService {
+            state: Arc::clone(&self.state),
+            class,
+        }
+    }
+
+    /// Create a [`WeightedFairPopService`], which pops from this queue
+    pub fn pop_service(&self) -> WeightedFairPopService<T> {
+        WeightedFairPopService {
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    /// The number of values currently queued across all classes
+    pub fn len(&self) -> usize {
+        let state = self.state.lock().expect("poisoned mutex");
+        state.classes.iter().map(|(queue, _)| queue.len()).sum()
+    }
+
+    /// Returns `true` if no values are currently queued
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+impl<T> Clone for WeightedFairQueueService<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+/// A [`Service`] or [`MutService`] that pushes each input to one class of a [`WeightedFairQueueService`].
+pub struct WeightedFairPushService<T> {
+    state: Arc<Mutex<FairQueueState<T>>>,
+    class: usize,
+}
+impl<T> Service for WeightedFairPushService<T> {
+    type Input = T;
+    type Output = ();
+    type Error = Infallible;
+    fn process(&self, input: T) -> Result<(), Infallible> {
+        self.state.lock().expect("poisoned mutex").classes[self.class]
+            .0
+            .push_back(input);
+        Ok(())
+    }
+}
+impl<T> MutService for WeightedFairPushService<T> {
+    type Input = T;
+    type Output = ();
+    type Error = Infallible;
+    fn process(&mut self, input: T) -> Result<(), Infallible> {
+        Service::process(self, input)
+    }
+}
+
+/// A [`Service`] or [`MutService`] that pops the next value from a [`WeightedFairQueueService`], producing `None` when every class is empty.
+///
+/// This may be encapsulated by a [`crate::PollService`] to block until a value is available.
+pub struct WeightedFairPopService<T> {
+    state: Arc<Mutex<FairQueueState<T>>>,
+}
+impl<T> Service for WeightedFairPopService<T> {
+    type Input = ();
+    type Output = Option<T>;
+    type Error = Infallible;
+    fn process(&self, _: ()) -> Result<Option<T>, Infallible> {
+        Ok(self.state.lock().expect("poisoned mutex").pop())
+    }
+}
+impl<T> MutService for WeightedFairPopService<T> {
+    type Input = ();
+    type Output = Option<T>;
+    type Error = Infallible;
+    fn process(&mut self, input: ()) -> Result<Option<T>, Infallible> {
+        Service::process(self, input)
+    }
+}
+
+/// A [`MutService`] that fans in values from multiple [`Receiver`]s, producing `Some((source, value))` where `source` is the index of the receiver that produced it, or `None` when every receiver is empty.
+///
+/// Receivers are polled without blocking, so this may be encapsulated by a [`crate::PollService`] to block until a value is available.
+/// A rotating cursor stays on the current receiver while it has values, up to `max_consecutive` values in a row,
+/// then moves on to the next receiver with a value, so no receiver is starved by a busier one.
+/// A value waiting in a receiver is produced after at most `(receivers - 1) * max_consecutive` values from other receivers.
+/// Disconnected receivers are skipped, and a [`RecvError`] is returned once every receiver is empty and disconnected.
+pub struct FairFanInMpsc<T> {
+    receivers: Vec<Receiver<T>>,
+    max_consecutive: usize,
+    cursor: usize,
+    consecutive: usize,
+}
+impl<T> FairFanInMpsc<T> {
+    /// Create a new [`FairFanInMpsc`], producing up to `max_consecutive` values in a row from one receiver
+    ///
+    /// # Panics
+    /// Panics if `max_consecutive` is zero.
+    pub fn new(receivers: Vec<Receiver<T>>, max_consecutive: usize) -> Self {
+        assert!(max_consecutive > 0, "max_consecutive must be non-zero");
+        Self {
+            receivers,
+            max_consecutive,
+            cursor: 0,
+            consecutive: 0,
+        }
+    }
+}
+impl<T> MutService for FairFanInMpsc<T> {
+    type Input = ();
+    type Output = Option<(usize, T)>;
+    type Error = RecvError;
+    fn process(&mut self, _: ()) -> Result<Self::Output, Self::Error> {
+        let len = self.receivers.len();
+        if len == 0 {
+            return Err(RecvError);
+        }
+        if self.consecutive < self.max_consecutive {
+            if let Ok(value) = self.receivers[self.cursor].try_recv() {
+                self.consecutive += 1;
+                return Ok(Some((self.cursor, value)));
+            }
+        }
+        // visit every other receiver before returning to the current one
+        let mut disconnected = 0;
+        for offset in 1..=len {
+            let source = (self.cursor + offset) % len;
+            match self.receivers[source].try_recv() {
+                Ok(value) => {
+                    self.cursor = source;
+                    self.consecutive = 1;
+                    return Ok(Some((source, value)));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => disconnected += 1,
+            }
+        }
+        match disconnected == len {
+            true => Err(RecvError),
+            false => Ok(None),
+        }
+    }
+}
+
+/// A [`MutService`] that buffers individual values, sending them as a single `Vec<T>` to a [`Sender`] once `batch_size` values are buffered
+/// or once `interval` has elapsed since the first value in the buffer, whichever comes first.
+///
+/// The interval is only checked when a value is processed or when [`BatchingMpscSender::flush_if_due`] is called,
+/// so a caller that stops producing values should call [`BatchingMpscSender::flush`] or [`BatchingMpscSender::flush_if_due`].
+/// The output is the number of values sent by the call, which is `0` when the value was only buffered.
+/// Any values still buffered when the [`BatchingMpscSender`] is dropped are sent as a final batch.
+pub struct BatchingMpscSender<T, C: Clock = SystemClock> {
+    sender: Sender<Vec<T>>,
+    batch_size: usize,
+    interval: Duration,
+    buffer: Vec<T>,
+    first_buffered: Option<Instant>,
+    clock: C,
+}
+impl<T> BatchingMpscSender<T> {
+    /// Create a new [`BatchingMpscSender`], sending batches of up to `batch_size` values, or fewer once `interval` has elapsed
+    ///
+    /// # Panics
+    /// Panics if `batch_size` is zero.
+    pub fn new(sender: Sender<Vec<T>>, batch_size: usize, interval: Duration) -> Self {
+        Self::with_clock(sender, batch_size, interval, SystemClock)
+    }
+}
+impl<T, C: Clock> BatchingMpscSender<T, C> {
+    /// Create a new [`BatchingMpscSender`] which uses the given [`Clock`] to measure the interval
+    ///
+    /// # Panics
+    /// Panics if `batch_size` is zero.
+    pub fn with_clock(
+        sender: Sender<Vec<T>>,
+        batch_size: usize,
+        interval: Duration,
+        clock: C,
+    ) -> Self {
+        assert!(batch_size > 0, "batch_size must be non-zero");
+        Self {
+            sender,
+            batch_size,
+            interval,
+            buffer: Vec::with_capacity(batch_size),
+            first_buffered: None,
+            clock,
+        }
+    }
+
+    /// The number of values currently buffered
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Send all buffered values as a batch, returning the number of values sent
+    pub fn flush(&mut self) -> Result<usize, SendError<Vec<T>>> {
+        self.first_buffered = None;
+        if self.buffer.is_empty() {
+            return Ok(0);
+        }
+        let batch = mem::replace(&mut self.buffer, Vec::with_capacity(self.batch_size));
+        let len = batch.len();
+        self.sender.send(batch)?;
+        Ok(len)
+    }
+
+    /// Send all buffered values as a batch if `interval` has elapsed since the first value in the buffer, returning the number of values sent
+    pub fn flush_if_due(&mut self) -> Result<usize, SendError<Vec<T>>> {
+        match self.first_buffered {
+            Some(first) if self.clock.now().duration_since(first) >= self.interval => self.flush(),
+            _ => Ok(0),
+        }
+    }
+}
+impl<T, C: Clock> MutService for BatchingMpscSender<T, C> {
+    type Input = T;
+    type Output = usize;
+    type Error = SendError<Vec<T>>;
+    fn process(&mut self, input: T) -> Result<Self::Output, Self::Error> {
+        if self.first_buffered.is_none() {
+            self.first_buffered = Some(self.clock.now());
+        }
+        self.buffer.push(input);
+        match self.buffer.len() >= self.batch_size {
+            true => self.flush(),
+            false => self.flush_if_due(),
+        }
+    }
+}
+impl<T, C: Clock> Drop for BatchingMpscSender<T, C> {
+    fn drop(&mut self) {
+        if !self.buffer.is_empty() {
+            self.sender.send(mem::take(&mut self.buffer)).ok();
+        }
+    }
+}
+
+/// A value stamped with the [`Instant`] it was enqueued, as sent by a [`TimedMpscSender`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Timed<T> {
+    pub value: T,
+    pub enqueued: Instant,
+}
+
+/// A [`Service`] that stamps each value with the current time of its [`Clock`] before sending it to a [`Sender`] as a [`Timed`] value,
+/// so a [`TimedMpscReceiver`] may report how long each value waited in the queue.
+pub struct TimedMpscSender<T, C: Clock = SystemClock> {
+    sender: Sender<Timed<T>>,
+    clock: C,
+}
+impl<T> TimedMpscSender<T> {
+    /// Create a new [`TimedMpscSender`]
+    pub fn new(sender: Sender<Timed<T>>) -> Self {
+        Self::with_clock(sender, SystemClock)
+    }
+}
+impl<T, C: Clock> TimedMpscSender<T, C> {
+    /// Create a new [`TimedMpscSender`] which uses the given [`Clock`] to stamp values
+    pub fn with_clock(sender: Sender<Timed<T>>, clock: C) -> Self {
+        Self { sender, clock }
+    }
+}
+impl<T, C: Clock> Service for TimedMpscSender<T, C> {
+    type Input = T;
+    type Output = ();
+    type Error = SendError<T>;
+    fn process(&self, input: T) -> Result<Self::Output, Self::Error> {
+        let timed = Timed {
+            value: input,
+            enqueued: self.clock.now(),
+        };
+        self.sender
+            .send(timed)
+            .map_err(|SendError(timed)| SendError(timed.value))
+    }
+}
+
+/// A [`Service`] that receives [`Timed`] values from a [`Receiver`] without blocking, producing `Some((value, waited))`,
+/// where `waited` is how long the value spent in the queue according to its [`Clock`], or `None` when the queue is empty.
+///
+/// The [`Clock`] must agree with the one given to the [`TimedMpscSender`].
+/// This may be encapsulated by a [`crate::PollService`] to block until a value is available.
+/// A [`RecvError`] is returned once the queue is empty and every sender has disconnected.
+pub struct TimedMpscReceiver<T, C: Clock = SystemClock> {
+    receiver: Receiver<Timed<T>>,
+    clock: C,
+}
+impl<T> TimedMpscReceiver<T> {
+    /// Create a new [`TimedMpscReceiver`]
+    pub fn new(receiver: Receiver<Timed<T>>) -> Self {
+        Self::with_clock(receiver, SystemClock)
+    }
+}
+impl<T, C: Clock> TimedMpscReceiver<T, C> {
+    /// Create a new [`TimedMpscReceiver`] which uses the given [`Clock`] to measure how long values waited
+    pub fn with_clock(receiver: Receiver<Timed<T>>, clock: C) -> Self {
+        Self { receiver, clock }
+    }
+}
+impl<T, C: Clock> Service for TimedMpscReceiver<T, C> {
+    type Input = ();
+    type Output = Option<(T, Duration)>;
+    type Error = RecvError;
+    fn process(&self, _: ()) -> Result<Self::Output, Self::Error> {
+        match self.receiver.try_recv() {
+            Ok(timed) => {
+                let waited = self.clock.now().saturating_duration_since(timed.enqueued);
+                Ok(Some((timed.value, waited)))
+            }
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(RecvError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread};
+
+    use super::*;
+    use crate::{clock::ManualClock, idle::yielding, PollService};
+
+    #[test]
+    fn priority_queue_pops_in_priority_order() {
+        let queue = PriorityQueueService::new();
+        let push = queue.push_service();
+        let pop = queue.pop_service();
+
+        for (priority, name) in [(1, "low"), (5, "high"), (3, "medium"), (4, "urgent-ish")] {
+            push.process((priority, name)).unwrap();
+        }
+        assert_eq!(4, queue.len());
+
+        let mut popped = Vec::new();
+        while let Some((_, name)) = pop.process(()).unwrap() {
+            popped.push(name);
+        }
+        assert_eq!(vec!["high", "urgent-ish", "medium", "low"], popped);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn priority_queue_polls_across_threads() {
+        let queue = PriorityQueueService::new();
+        let poll = PollService::new(queue.pop_service(), yielding);
+        let mut push = queue.push_service();
+        thread::spawn(move || MutService::process(&mut push, 7).unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(Ok(7), poll.process(()));
+    }
+
+    #[test]
+    fn weighted_fair_queue_dequeues_by_weight() {
+        let queue = WeightedFairQueueService::new(vec![3, 1]);
+        let high = queue.push_service(0);
+        let low = queue.push_service(1);
+        let pop = queue.pop_service();
+        for n in 0..400 {
+            high.process(("high", n)).unwrap();
+            low.process(("low", n)).unwrap();
+        }
+        assert_eq!(800, queue.len());
+
+        let mut counts = [0; 2];
+        let mut last = [-1; 2];
+        for _ in 0..400 {
+            let (class, n) = pop.process(()).unwrap().unwrap();
+            let class = (class == "low") as usize;
+            // neither class starves, and each class is dequeued in order
+            assert_eq!(last[class] + 1, n);
+            last[class] = n;
+            counts[class] += 1;
+        }
+        assert_eq!([300, 100], counts);
+
+        // the remaining 100 high values are still dequeued by weight, then the low class is dequeued alone
+        let mut remaining = Vec::new();
+        while let Some((class, _)) = pop.process(()).unwrap() {
+            remaining.push(class);
+        }
+        assert_eq!(400, remaining.len());
+        assert_eq!(
+            Some(132),
+            remaining.iter().rposition(|class| *class == "high")
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn fair_fan_in_does_not_starve_cold_sources() {
+        let (hot_tx, hot_rx) = mpsc::channel();
+        let (cold1_tx, cold1_rx) = mpsc::channel();
+        let (cold2_tx, cold2_rx) = mpsc::channel();
+        let producers = vec![
+            thread::spawn(move || (0..10_000).for_each(|n| hot_tx.send(n).unwrap())),
+            thread::spawn(move || (0..3).for_each(|n| cold1_tx.send(n).unwrap())),
+            thread::spawn(move || (0..3).for_each(|n| cold2_tx.send(n).unwrap())),
+        ];
+        producers.into_iter().for_each(|p| p.join().unwrap());
+
+        let max_consecutive = 4;
+        let mut fan_in = FairFanInMpsc::new(vec![hot_rx, cold1_rx, cold2_rx], max_consecutive);
+        let mut produced = Vec::new();
+        while let Ok(Some((source, value))) = fan_in.process(()) {
+            produced.push((source, value));
+        }
+        assert_eq!(10_006, produced.len());
+        assert_eq!(Err(RecvError), fan_in.process(()));
+
+        // every cold value is produced within a bounded number of cycles, despite the hot backlog
+        let last_cold = produced
+            .iter()
+            .rposition(|(source, _)| *source != 0)
+            .unwrap();
+        assert!(last_cold < 3 * max_consecutive + 6, "{last_cold}");
+        for source in 0..3 {
+            let values: Vec<_> = produced
+                .iter()
+                .filter(|(s, _)| *s == source)
+                .map(|(_, v)| *v)
+                .collect();
+            assert!(values.windows(2).all(|w| w[0] < w[1]));
+        }
+    }
+
+    #[test]
+    fn fair_fan_in_polls_across_threads() {
+        let (tx, rx) = mpsc::channel();
+        let (_idle_tx, idle_rx) = mpsc::channel();
+        let mut poll = PollService::new(FairFanInMpsc::new(vec![idle_rx, rx], 1), yielding);
+        thread::spawn(move || tx.send("hello").unwrap());
+        assert_eq!(Ok((1, "hello")), MutService::process(&mut poll, ()));
+    }
+
+    #[test]
+    fn fair_fan_in_reports_disconnect_once_every_receiver_is_drained() {
+        let (tx1, rx1) = mpsc::channel();
+        let (tx2, rx2) = mpsc::channel();
+        let mut fan_in = FairFanInMpsc::new(vec![rx1, rx2], 1);
+        tx1.send(1).unwrap();
+        drop(tx1);
+
+        // a disconnected receiver is still drained, and is skipped while another is connected
+        assert_eq!(Ok(Some((0, 1))), fan_in.process(()));
+        assert_eq!(Ok(None), fan_in.process(()));
+        tx2.send(2).unwrap();
+        drop(tx2);
+        assert_eq!(Ok(Some((1, 2))), fan_in.process(()));
+        assert_eq!(Err(RecvError), fan_in.process(()));
+        assert_eq!(Err(RecvError), fan_in.process(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "max_consecutive must be non-zero")]
+    fn fair_fan_in_rejects_zero_max_consecutive() {
+        FairFanInMpsc::<()>::new(Vec::new(), 0);
+    }
+
+    #[test]
+    fn batching_sender_sends_on_size_interval_and_flush() {
+        let (tx, rx) = mpsc::channel();
+        let clock = ManualClock::new();
+        let mut sender =
+            BatchingMpscSender::with_clock(tx, 3, Duration::from_secs(1), clock.clone());
+
+        // size threshold
+        assert_eq!(Ok(0), sender.process(1));
+        assert_eq!(Ok(0), sender.process(2));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(Ok(3), sender.process(3));
+        assert_eq!(Ok(vec![1, 2, 3]), rx.try_recv());
+
+        // interval threshold, measured from the first buffered value
+        assert_eq!(Ok(0), sender.process(4));
+        clock.advance(Duration::from_millis(600));
+        assert_eq!(Ok(0), sender.flush_if_due());
+        clock.advance(Duration::from_millis(400));
+        assert_eq!(Ok(2), sender.process(5));
+        assert_eq!(Ok(vec![4, 5]), rx.try_recv());
+        assert_eq!(Ok(0), sender.process(6));
+        assert_eq!(Ok(0), sender.flush_if_due());
+        assert_eq!(Ok(1), sender.flush());
+        assert_eq!(Ok(vec![6]), rx.try_recv());
+
+        // interval elapsed without a new value
+        assert_eq!(Ok(0), sender.process(7));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(Ok(1), sender.flush_if_due());
+        assert_eq!(Ok(vec![7]), rx.try_recv());
+
+        // explicit flush, then the remainder on drop
+        assert_eq!(Ok(0), sender.process(8));
+        assert_eq!(Ok(1), sender.flush());
+        assert_eq!(Ok(0), sender.flush());
+        assert_eq!(Ok(vec![8]), rx.try_recv());
+        assert_eq!(Ok(0), sender.process(9));
+        assert_eq!(1, sender.buffered());
+        drop(sender);
+        assert_eq!(Ok(vec![9]), rx.try_recv());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "batch_size must be non-zero")]
+    fn batching_sender_rejects_zero_batch_size() {
+        let (tx, _rx) = mpsc::channel::<Vec<()>>();
+        BatchingMpscSender::new(tx, 0, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn timed_mpsc_reports_queue_latency() {
+        let (tx, rx) = mpsc::channel();
+        let clock = ManualClock::new();
+        let sender = TimedMpscSender::with_clock(tx, clock.clone());
+        let receiver = TimedMpscReceiver::with_clock(rx, clock.clone());
+
+        assert_eq!(Ok(None), receiver.process(()));
+        sender.process("first").unwrap();
+        clock.advance(Duration::from_millis(250));
+        sender.process("second").unwrap();
+        clock.advance(Duration::from_millis(100));
+
+        assert_eq!(
+            Ok(Some(("first", Duration::from_millis(350)))),
+            receiver.process(())
+        );
+        assert_eq!(
+            Ok(Some(("second", Duration::from_millis(100)))),
+            receiver.process(())
+        );
+        assert_eq!(Ok(None), receiver.process(()));
+        drop(sender);
+        assert_eq!(Err(RecvError), receiver.process(()));
+    }
+}
@@ -0,0 +1,352 @@
+//! Replay recorded inputs through a [`Service`] for deterministic load testing,
+//! or record the results of an [`AsyncService`] and replay them for deterministic tests.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt::{Debug, Display},
+    fs::File,
+    hash::Hash,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::{
+    async_trait,
+    clock::{Clock, SystemClock},
+    AsyncService, Service,
+};
+
+/// Replays a recorded sequence of inputs through a [`Service`], pacing each call by its recorded inter-arrival gap.
+///
+/// Each recorded `(Duration, Input)` pair represents the gap since the previous input, or since the start of the replay for the first input.
+/// Deliveries are scheduled relative to the start of the replay, so time spent in [`Service::process`] is absorbed by the next gap instead of accumulating as drift.
+/// When a call runs past the next scheduled delivery, the next input is delivered immediately and the lateness is reported in [`ReplayStats::max_lag`].
+///
+/// The given [`Clock`] is used for all pacing and measurement. Use a [`crate::clock::ManualClock`] to replay in virtual time.
+pub struct ReplayDriver<S: Service, C = SystemClock> {
+    service: S,
+    recording: Vec<(Duration, S::Input)>,
+    clock: C,
+}
+impl<S: Service> ReplayDriver<S> {
+    pub fn new(service: S, recording: Vec<(Duration, S::Input)>) -> Self {
+        Self::with_clock(service, recording, SystemClock)
+    }
+}
+impl<S: Service, C: Clock> ReplayDriver<S, C> {
+    pub fn with_clock(service: S, recording: Vec<(Duration, S::Input)>, clock: C) -> Self {
+        Self {
+            service,
+            recording,
+            clock,
+        }
+    }
+
+    /// Replay all recorded inputs in order, returning the aggregate [`ReplayStats`].
+    ///
+    /// The replay stops and returns immediately when the underlying service returns an `Err`.
+    pub fn run(self) -> Result<ReplayStats, S::Error> {
+        let start = self.clock.now();
+        let mut scheduled = start;
+        let mut stats = ReplayStats::default();
+        for (gap, input) in self.recording {
+            scheduled += gap;
+            let now = self.clock.now();
+            if scheduled > now {
+                self.clock.sleep(scheduled - now);
+            } else {
+                stats.max_lag = stats.max_lag.max(now - scheduled);
+            }
+            let before = self.clock.now();
+            self.service.process(input)?;
+            let processing = self.clock.now() - before;
+            stats.delivered += 1;
+            stats.processing += processing;
+            stats.max_processing = stats.max_processing.max(processing);
+        }
+        stats.elapsed = self.clock.now() - start;
+        Ok(stats)
+    }
+}
+
+/// Aggregate timing statistics produced by [`ReplayDriver::run`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    /// The number of inputs delivered to the service
+    pub delivered: usize,
+    /// The total duration of the replay
+    pub elapsed: Duration,
+    /// The total duration spent in [`Service::process`]
+    pub processing: Duration,
+    /// The longest duration of a single [`Service::process`] call
+    pub max_processing: Duration,
+    /// The latest any input was delivered relative to its recorded schedule
+    pub max_lag: Duration,
+}
+impl ReplayStats {
+    /// The mean duration of a single [`Service::process`] call
+    pub fn mean_processing(&self) -> Duration {
+        match self.delivered {
+            0 => Duration::ZERO,
+            n => self.processing / n as u32,
+        }
+    }
+}
+
+/// Whether a [`RecordReplayService`] records calls to its underlying service, or replays previously recorded calls
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RecordMode {
+    /// Call the underlying service, and append each input and result to the recording file
+    Record,
+    /// Serve results from the recording file, without calling the underlying service
+    Replay,
+}
+
+/// Encodes and decodes each `(input, result)` pair recorded by a [`RecordReplayService`] as a single line of its recording file.
+pub trait RecordCodec<I, O, E> {
+    /// Encode the pair as a single line, which must not contain a newline
+    fn encode(&self, input: &I, result: &Result<O, E>) -> String;
+
+    /// Decode a line produced by [`RecordCodec::encode`], returning `None` if it is malformed
+    fn decode(&self, line: &str) -> Option<(I, Result<O, E>)>;
+}
+
+/// An [`AsyncService`] that records the results of an underlying service to a file, and later replays them without calling the underlying service,
+/// allowing pipelines that call external systems to be tested quickly and deterministically.
+///
+/// In [`RecordMode::Record`], the recording file is truncated, and each input and the result of the underlying service are appended to it as a line encoded by the [`RecordCodec`].
+/// In [`RecordMode::Replay`], the recording file is read once at construction, and each input is answered with the result recorded for an equal input.
+/// When an input was recorded more than once, its recorded results are served in the order they were recorded.
+/// An input without a remaining recorded result is rejected with [`RecordReplayError::NotRecorded`].
+///
+/// Recorded errors are replayed as [`RecordReplayError::ServiceError`], exactly as they were returned while recording.
+/// A sync [`Service`] may be recorded using [`Service::into_async`].
+pub struct RecordReplayService<S: AsyncService, D> {
+    service: S,
+    codec: D,
+    file: Option<Mutex<File>>,
+    recorded: Mutex<Recorded<S::Input, S::Output, S::Error>>,
+}
+type Recorded<I, O, E> = HashMap<I, VecDeque<Result<O, E>>>;
+impl<S, D> RecordReplayService<S, D>
+where
+    S: AsyncService,
+    S::Input: Eq + Hash,
+    D: RecordCodec<S::Input, S::Output, S::Error>,
+{
+    /// Create a new [`RecordReplayService`], which creates or truncates the recording file at the given path in [`RecordMode::Record`],
+    /// or reads it in [`RecordMode::Replay`], returning an `Err` if the file cannot be opened or contains a malformed line.
+    pub fn new<P: AsRef<Path>>(
+        service: S,
+        codec: D,
+        path: P,
+        mode: RecordMode,
+    ) -> io::Result<Self> {
+        let mut recorded: Recorded<_, _, _> = HashMap::new();
+        let file = match mode {
+            RecordMode::Record => Some(Mutex::new(File::create(path)?)),
+            RecordMode::Replay => {
+                for line in BufReader::new(File::open(path)?).lines() {
+                    let (input, result) = codec.decode(&line?).ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "malformed recording")
+                    })?;
+                    recorded.entry(input).or_default().push_back(result);
+                }
+                None
+            }
+        };
+        Ok(Self {
+            service,
+            codec,
+            file,
+            recorded: Mutex::new(recorded),
+        })
+    }
+}
+#[async_trait]
+impl<S, D> AsyncService for RecordReplayService<S, D>
+where
+    S: AsyncService,
+    S::Input: Clone + Eq + Hash,
+    D: RecordCodec<S::Input, S::Output, S::Error> + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = RecordReplayError<S::Input, S::Error>;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => {
+                let next = self
+                    .recorded
+                    .lock()
+                    .expect("poisoned mutex")
+                    .get_mut(&input)
+                    .and_then(VecDeque::pop_front);
+                return match next {
+                    Some(result) => result.map_err(RecordReplayError::ServiceError),
+                    None => Err(RecordReplayError::NotRecorded(input)),
+                };
+            }
+        };
+        let result = self.service.process(input.clone()).await;
+        let line = self.codec.encode(&input, &result);
+        writeln!(file.lock().expect("poisoned mutex"), "{line}").map_err(RecordReplayError::Io)?;
+        result.map_err(RecordReplayError::ServiceError)
+    }
+}
+
+/// Returned by [`RecordReplayService`], either when an input was not recorded, when the recording file cannot be written, or when the underlying service fails.
+#[derive(Debug)]
+pub enum RecordReplayError<I, E> {
+    NotRecorded(I),
+    Io(io::Error),
+    ServiceError(E),
+}
+impl<I: Debug, E: Display> Display for RecordReplayError<I, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotRecorded(input) => write!(f, "no recorded result for {input:?}"),
+            Self::Io(err) => write!(f, "{err}"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<I: Debug, E: Error + 'static> Error for RecordReplayError<I, E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::NotRecorded(_) => None,
+            Self::Io(err) => Some(err),
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::{clock::ManualClock, FnService};
+
+    #[test]
+    fn replay_paces_inputs_in_virtual_time() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let service = {
+            let clock = clock.clone();
+            let delivered = Arc::clone(&delivered);
+            FnService::new(move |input: &'static str| {
+                delivered.lock().unwrap().push((clock.now() - start, input));
+                clock.advance(Duration::from_millis(1));
+                Ok::<_, Infallible>(())
+            })
+        };
+        let recording = vec![
+            (Duration::from_millis(10), "a"),
+            (Duration::from_millis(5), "b"),
+            (Duration::from_millis(20), "c"),
+        ];
+
+        let stats = ReplayDriver::with_clock(service, recording, clock)
+            .run()
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                (Duration::from_millis(10), "a"),
+                (Duration::from_millis(15), "b"),
+                (Duration::from_millis(35), "c"),
+            ],
+            *delivered.lock().unwrap()
+        );
+        assert_eq!(
+            ReplayStats {
+                delivered: 3,
+                elapsed: Duration::from_millis(36),
+                processing: Duration::from_millis(3),
+                max_processing: Duration::from_millis(1),
+                max_lag: Duration::ZERO,
+            },
+            stats
+        );
+        assert_eq!(Duration::from_millis(1), stats.mean_processing());
+    }
+
+    /// Records `input=ok:output` or `input=err:error`
+    struct LineCodec;
+    impl RecordCodec<String, usize, String> for LineCodec {
+        fn encode(&self, input: &String, result: &Result<usize, String>) -> String {
+            match result {
+                Ok(len) => format!("{input}=ok:{len}"),
+                Err(err) => format!("{input}=err:{err}"),
+            }
+        }
+        fn decode(&self, line: &str) -> Option<(String, Result<usize, String>)> {
+            let (input, result) = line.split_once('=')?;
+            let result = match result.split_once(':')? {
+                ("ok", len) => Ok(len.parse().ok()?),
+                ("err", err) => Err(err.to_owned()),
+                _ => return None,
+            };
+            Some((input.to_owned(), result))
+        }
+    }
+
+    #[test]
+    fn record_then_replay_without_service() {
+        let path = std::env::temp_dir().join(format!("sod-record-{}.log", std::process::id()));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let stub = {
+            let calls = Arc::clone(&calls);
+            FnService::new(move |input: String| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                match input.is_empty() {
+                    true => Err("empty".to_owned()),
+                    false => Ok(input.len()),
+                }
+            })
+            .into_async()
+        };
+        let inputs = ["hello", "", "sod", "hello"].map(str::to_owned);
+
+        let recorder =
+            RecordReplayService::new(stub, LineCodec, &path, RecordMode::Record).unwrap();
+        let recorded: Vec<_> = inputs
+            .iter()
+            .map(|input| block_on(recorder.process(input.clone())).map_err(|err| err.to_string()))
+            .collect();
+        assert_eq!(4, calls.load(Ordering::SeqCst));
+        drop(recorder);
+
+        let unreachable = FnService::new(|_: String| -> Result<usize, String> {
+            panic!("the real service must not be called while replaying")
+        })
+        .into_async();
+        let replayer =
+            RecordReplayService::new(unreachable, LineCodec, &path, RecordMode::Replay).unwrap();
+        let replayed: Vec<_> = inputs
+            .iter()
+            .map(|input| block_on(replayer.process(input.clone())).map_err(|err| err.to_string()))
+            .collect();
+        assert_eq!(recorded, replayed);
+        assert_eq!(vec![Ok(5), Err("empty".to_owned()), Ok(3), Ok(5)], replayed);
+        assert!(matches!(
+            block_on(replayer.process("unknown".to_owned())),
+            Err(RecordReplayError::NotRecorded(input)) if input == "unknown"
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+}
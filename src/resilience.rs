@@ -0,0 +1,292 @@
+//! Services that bundle a timeout, retries, and a circuit breaker behind a single [`ResiliencePolicy`].
+
+use std::{
+    error::Error,
+    fmt::{Debug, Display},
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::{
+    breaker::{Breaker, BreakerState},
+    clock::{Clock, SystemClock},
+    thread::{TimeoutError, TimeoutService},
+    RetryError, Retryable, Service,
+};
+
+/// The configuration of a [`ResilienceService`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResiliencePolicy {
+    /// The longest a single attempt may take before it is considered failed
+    pub timeout: Duration,
+    /// The maximum number of attempts per call, including the first
+    pub max_attempts: usize,
+    /// The delay before the first retry, which is doubled before each subsequent retry
+    pub backoff: Duration,
+    /// The number of consecutive failed calls that opens the breaker
+    pub failure_threshold: usize,
+    /// How long the breaker stays open before allowing a trial call
+    pub open_duration: Duration,
+}
+impl Default for ResiliencePolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(1),
+            max_attempts: 3,
+            backoff: Duration::from_millis(10),
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A [`Service`] that applies a circuit breaker, retries, and a per-attempt timeout to an underlying [`Service`], configured by a single [`ResiliencePolicy`].
+///
+/// The policies are nested as breaker, then retry, then timeout, then the underlying service:
+/// * While the breaker is open, calls are rejected with [`ResilienceError::Open`] without calling the underlying service.
+/// * Otherwise, the input is cloned into up to `max_attempts` attempts, sleeping with an exponential backoff between attempts.
+/// * An attempt that returns `Err` or exceeds the `timeout` fails, and the error of the last attempt is returned once all attempts fail.
+/// * The breaker records the outcome of each call after its retries, so it opens after `failure_threshold` consecutive calls have exhausted their attempts.
+///
+/// Each attempt is bounded by a [`TimeoutService`], so it runs on a spawned thread, and an attempt which has not completed within the timeout fails with [`ResilienceError::TimedOut`].
+/// As with a [`TimeoutService`], a timed out attempt keeps running on its thread, and its result is discarded.
+///
+/// The state of the breaker may be observed using [`ResilienceService::state`].
+/// This service implements [`Retryable`] for inputs rejected by an open breaker, so it may be encapsulated by a [`crate::RetryService`] to wait for the breaker to close.
+pub struct ResilienceService<S, C = SystemClock> {
+    service: TimeoutService<S>,
+    policy: ResiliencePolicy,
+    breaker: Mutex<Breaker>,
+    clock: C,
+}
+impl<S: Service> ResilienceService<S> {
+    /// Create a new [`ResilienceService`]
+    ///
+    /// # Panics
+    /// Panics if the policy's `max_attempts` or `failure_threshold` is zero.
+    pub fn new(service: S, policy: ResiliencePolicy) -> Self {
+        Self::with_clock(service, policy, SystemClock)
+    }
+}
+impl<S: Service, C: Clock> ResilienceService<S, C> {
+    /// Create a new [`ResilienceService`] which uses the given [`Clock`] to time backoff and the breaker
+    ///
+    /// # Panics
+    /// Panics if the policy's `max_attempts` or `failure_threshold` is zero.
+    pub fn with_clock(service: S, policy: ResiliencePolicy, clock: C) -> Self {
+        assert!(policy.max_attempts > 0, "max_attempts must be at least 1");
+        assert!(
+            policy.failure_threshold > 0,
+            "failure_threshold must be at least 1"
+        );
+        Self {
+            service: TimeoutService::new(service, policy.timeout),
+            policy,
+            breaker: Mutex::new(Breaker::default()),
+            clock,
+        }
+    }
+
+    /// The current state of the breaker
+    pub fn state(&self) -> BreakerState {
+        self.breaker
+            .lock()
+            .expect("poisoned mutex")
+            .state(self.clock.now(), self.policy.open_duration)
+    }
+
+    /// The policy applied by this service
+    pub fn policy(&self) -> &ResiliencePolicy {
+        &self.policy
+    }
+}
+impl<S, C> Service for ResilienceService<S, C>
+where
+    S: Service + Send + Sync + 'static,
+    S::Input: Clone + Send + 'static,
+    S::Output: Send + 'static,
+    S::Error: Send + 'static,
+    C: Clock,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = ResilienceError<S::Input, S::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let admitted = self
+            .breaker
+            .lock()
+            .expect("poisoned mutex")
+            .admit(self.clock.now(), self.policy.open_duration);
+        if !admitted {
+            return Err(ResilienceError::Open(input));
+        }
+        let attempt = |input| {
+            self.service.process(input).map_err(|err| match err {
+                TimeoutError::Elapsed => ResilienceError::TimedOut,
+                TimeoutError::ServiceError(err) => ResilienceError::ServiceError(err),
+            })
+        };
+        let mut backoff = self.policy.backoff;
+        let mut result = attempt(input.clone());
+        for _ in 1..self.policy.max_attempts {
+            if result.is_ok() {
+                break;
+            }
+            self.clock.sleep(backoff);
+            backoff = backoff.saturating_mul(2);
+            result = attempt(input.clone());
+        }
+        self.breaker.lock().expect("poisoned mutex").record(
+            self.clock.now(),
+            result.is_ok(),
+            self.policy.failure_threshold,
+        );
+        result
+    }
+}
+impl<I, E, S, C> Retryable<I, ResilienceError<I, E>> for ResilienceService<S, C> {
+    fn parse_retry(
+        &self,
+        err: ResilienceError<I, E>,
+    ) -> Result<I, RetryError<ResilienceError<I, E>>> {
+        match err {
+            ResilienceError::Open(input) => Ok(input),
+            err => Err(RetryError::ServiceError(err)),
+        }
+    }
+}
+
+/// Returned by [`ResilienceService`], when the breaker is open, or when the last attempt timed out or failed.
+#[derive(Clone, PartialEq, Eq)]
+pub enum ResilienceError<I, E> {
+    Open(I),
+    TimedOut,
+    ServiceError(E),
+}
+impl<I, E: Debug> Debug for ResilienceError<I, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Open(_) => f.write_str("Open"),
+            Self::TimedOut => f.write_str("TimedOut"),
+            Self::ServiceError(err) => f.debug_tuple("ServiceError").field(err).finish(),
+        }
+    }
+}
+impl<I, E: Display> Display for ResilienceError<I, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Open(_) => f.write_str("circuit breaker open"),
+            Self::TimedOut => f.write_str("timed out"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<I, E: Error + 'static> Error for ResilienceError<I, E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Open(_) | Self::TimedOut => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Instant,
+    };
+
+    use super::*;
+    use crate::{clock::ManualClock, FnService};
+
+    #[test]
+    fn resilience_times_out_retries_and_opens() {
+        let clock = ManualClock::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        // the number of leading attempts which hang well past the timeout
+        let hangs = Arc::new(AtomicUsize::new(0));
+        let service = ResilienceService::with_clock(
+            FnService::new({
+                let calls = Arc::clone(&calls);
+                let hangs = Arc::clone(&hangs);
+                move |input: u32| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    let hang = hangs
+                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                        .is_ok();
+                    if hang {
+                        thread::sleep(Duration::from_secs(10));
+                    }
+                    match input {
+                        0 => Err("zero"),
+                        n => Ok(n),
+                    }
+                }
+            }),
+            ResiliencePolicy {
+                timeout: Duration::from_millis(50),
+                max_attempts: 3,
+                backoff: Duration::from_millis(10),
+                failure_threshold: 2,
+                open_duration: Duration::from_secs(5),
+            },
+            clock.clone(),
+        );
+
+        // a hanging attempt times out, and is retried after the backoff
+        hangs.store(1, Ordering::SeqCst);
+        let start = Instant::now();
+        let clock_start = clock.now();
+        assert_eq!(Ok(7), service.process(7));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(2, calls.swap(0, Ordering::SeqCst));
+        assert_eq!(Duration::from_millis(10), clock.now() - clock_start);
+
+        // every attempt hangs, so the timeout of the last attempt is returned
+        hangs.store(3, Ordering::SeqCst);
+        let start = Instant::now();
+        assert_eq!(Err(ResilienceError::TimedOut), service.process(7));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(3, calls.swap(0, Ordering::SeqCst));
+        assert_eq!(BreakerState::Closed, service.state());
+
+        // a second consecutive exhausted call opens the breaker
+        assert_eq!(
+            Err(ResilienceError::ServiceError("zero")),
+            service.process(0)
+        );
+        assert_eq!(3, calls.swap(0, Ordering::SeqCst));
+        assert_eq!(BreakerState::Open, service.state());
+        assert_eq!(Err(ResilienceError::Open(7)), service.process(7));
+        assert_eq!(0, calls.load(Ordering::SeqCst));
+
+        // a successful trial closes the breaker
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(Ok(7), service.process(7));
+        assert_eq!(BreakerState::Closed, service.state());
+    }
+
+    #[test]
+    fn resilience_rejects_invalid_policy() {
+        let panics = |policy: ResiliencePolicy| {
+            std::panic::catch_unwind(|| {
+                ResilienceService::new(FnService::new(|n: u32| Ok::<_, ()>(n)), policy)
+            })
+            .is_err()
+        };
+        assert!(!panics(ResiliencePolicy::default()));
+        assert!(panics(ResiliencePolicy {
+            max_attempts: 0,
+            ..Default::default()
+        }));
+        assert!(panics(ResiliencePolicy {
+            failure_threshold: 0,
+            ..Default::default()
+        }));
+    }
+}
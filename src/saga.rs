@@ -0,0 +1,301 @@
+//! Services that provide all-or-nothing semantics over non-transactional services using compensating actions.
+
+use std::{
+    error::Error,
+    fmt::{Debug, Display},
+};
+
+use crate::{DynService, MutService, Service};
+
+/// A [`Service`] that runs a sequence of `(action, compensation)` service pairs, implementing the saga pattern.
+///
+/// Each action is given a clone of the input, and the output of each action is collected and produced as a [`Vec`].
+/// When an action fails, the compensation of every already-completed step is given the output of that step's action, in reverse order, and a [`SagaError`] is returned.
+/// Compensations are best-effort: a failed compensation does not prevent the remaining compensations from running, and its error is captured in the [`SagaError`].
+///
+/// Use [`SagaService::step`] to append steps to the saga.
+pub struct SagaService<'a, I, O, E> {
+    steps: Vec<SagaStep<'a, I, O, E>>,
+}
+struct SagaStep<'a, I, O, E> {
+    action: DynService<'a, I, O, E>,
+    compensation: DynService<'a, O, (), E>,
+}
+impl<'a, I, O, E> SagaService<'a, I, O, E> {
+    pub fn new() -> Self {
+        Self { steps: Vec::new() }
+    }
+
+    /// Append an action to the saga, along with the compensation which undoes it given the action's output.
+    pub fn step<A, C>(mut self, action: A, compensation: C) -> Self
+    where
+        A: Service<Input = I, Output = O, Error = E> + 'a,
+        C: Service<Input = O, Output = (), Error = E> + 'a,
+    {
+        self.steps.push(SagaStep {
+            action: DynService::new(action),
+            compensation: DynService::new(compensation),
+        });
+        self
+    }
+}
+impl<'a, I, O, E> Default for SagaService<'a, I, O, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<'a, I: Clone, O, E> Service for SagaService<'a, I, O, E> {
+    type Input = I;
+    type Output = Vec<O>;
+    type Error = SagaError<E>;
+    fn process(&self, input: I) -> Result<Self::Output, Self::Error> {
+        let mut outputs = Vec::with_capacity(self.steps.len());
+        for (step, SagaStep { action, .. }) in self.steps.iter().enumerate() {
+            match action.process(input.clone()) {
+                Ok(output) => outputs.push(output),
+                Err(error) => {
+                    let compensation_errors = self.steps[..step]
+                        .iter()
+                        .zip(outputs)
+                        .rev()
+                        .filter_map(|(completed, output)| {
+                            completed.compensation.process(output).err()
+                        })
+                        .collect();
+                    return Err(SagaError {
+                        step,
+                        error,
+                        compensation_errors,
+                    });
+                }
+            }
+        }
+        Ok(outputs)
+    }
+}
+
+/// Returned by [`SagaService`] when an action fails, after all compensations have run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SagaError<E> {
+    /// The index of the step whose action failed
+    pub step: usize,
+    /// The error returned by the failed action
+    pub error: E,
+    /// The errors returned by any failed compensations, in the order the compensations ran
+    pub compensation_errors: Vec<E>,
+}
+impl<E: Debug> Display for SagaError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "saga step {} failed: {:?}, with {} failed compensations",
+            self.step,
+            self.error,
+            self.compensation_errors.len()
+        )
+    }
+}
+impl<E: Debug> Error for SagaError<E> {}
+
+/// A [`Service`] or [`MutService`] that writes a clone of each input to two sinks, undoing the write to the first sink when the write to the second sink fails.
+///
+/// This provides all-or-nothing semantics across two sinks, such as those joined by a `fork_clone`.
+/// The first sink is called first. When it fails, the second sink is not called and [`TransactionalForkError::First`] is returned.
+/// When the second sink fails, the given `undo` function is passed the first sink's output to compensate for its write.
+/// When the undo succeeds, the error is returned as [`TransactionalForkError::Second`], and neither write remains in effect.
+/// When the undo itself fails, both errors are returned as [`TransactionalForkError::UndoFailed`], indicating that the first write remains in effect and the sinks are inconsistent.
+pub struct TransactionalForkService<S1, S2, U> {
+    first: S1,
+    second: S2,
+    undo: U,
+}
+impl<S1, S2, U> TransactionalForkService<S1, S2, U> {
+    pub fn new(first: S1, second: S2, undo: U) -> Self {
+        Self {
+            first,
+            second,
+            undo,
+        }
+    }
+}
+impl<S1, S2, U> Service for TransactionalForkService<S1, S2, U>
+where
+    S1: Service,
+    S1::Input: Clone,
+    S2: Service<Input = S1::Input>,
+    U: Fn(S1::Output) -> Result<(), S1::Error>,
+{
+    type Input = S1::Input;
+    type Output = (S1::Output, S2::Output);
+    type Error = TransactionalForkError<S1::Error, S2::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let first = self
+            .first
+            .process(input.clone())
+            .map_err(TransactionalForkError::First)?;
+        match self.second.process(input) {
+            Ok(second) => Ok((first, second)),
+            Err(error) => Err(TransactionalForkError::undone(error, (self.undo)(first))),
+        }
+    }
+}
+impl<S1, S2, U> MutService for TransactionalForkService<S1, S2, U>
+where
+    S1: MutService,
+    S1::Input: Clone,
+    S2: MutService<Input = S1::Input>,
+    U: FnMut(S1::Output) -> Result<(), S1::Error>,
+{
+    type Input = S1::Input;
+    type Output = (S1::Output, S2::Output);
+    type Error = TransactionalForkError<S1::Error, S2::Error>;
+    fn process(&mut self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let first = self
+            .first
+            .process(input.clone())
+            .map_err(TransactionalForkError::First)?;
+        match self.second.process(input) {
+            Ok(second) => Ok((first, second)),
+            Err(error) => Err(TransactionalForkError::undone(error, (self.undo)(first))),
+        }
+    }
+}
+
+/// Returned by [`TransactionalForkService`] when either sink fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransactionalForkError<E1, E2> {
+    /// The first sink failed, and the second sink was not called
+    First(E1),
+    /// The second sink failed, and the first sink's write was undone
+    Second(E2),
+    /// The second sink failed, and undoing the first sink's write also failed, leaving the first write in effect
+    UndoFailed { error: E2, undo_error: E1 },
+}
+impl<E1, E2> TransactionalForkError<E1, E2> {
+    fn undone(error: E2, undo: Result<(), E1>) -> Self {
+        match undo {
+            Ok(()) => Self::Second(error),
+            Err(undo_error) => Self::UndoFailed { error, undo_error },
+        }
+    }
+}
+impl<E1: Debug, E2: Debug> Display for TransactionalForkError<E1, E2> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::First(err) => write!(f, "first sink failed: {err:?}"),
+            Self::Second(err) => write!(f, "second sink failed and first sink was undone: {err:?}"),
+            Self::UndoFailed { error, undo_error } => write!(
+                f,
+                "second sink failed: {error:?}, and undoing first sink failed: {undo_error:?}"
+            ),
+        }
+    }
+}
+impl<E1: Debug, E2: Debug> Error for TransactionalForkError<E1, E2> {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::FnService;
+
+    #[test]
+    fn saga_compensates_in_reverse_order() {
+        let log = Mutex::new(Vec::new());
+        let action = |name: &'static str, fail: bool| {
+            let log = &log;
+            FnService::new(move |input: u32| {
+                log.lock().unwrap().push(format!("do {name}"));
+                match fail {
+                    true => Err(name),
+                    false => Ok(input + name.len() as u32),
+                }
+            })
+        };
+        let compensation = |name: &'static str| {
+            let log = &log;
+            FnService::new(move |output: u32| {
+                log.lock().unwrap().push(format!("undo {name} {output}"));
+                Ok(())
+            })
+        };
+        let saga = SagaService::new()
+            .step(action("a", false), compensation("a"))
+            .step(action("bb", false), compensation("bb"))
+            .step(action("c", true), compensation("c"));
+
+        assert_eq!(
+            Err(SagaError {
+                step: 2,
+                error: "c",
+                compensation_errors: vec![],
+            }),
+            saga.process(7)
+        );
+        assert_eq!(
+            vec!["do a", "do bb", "do c", "undo bb 9", "undo a 8"],
+            *log.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn saga_collects_outputs() {
+        let saga = SagaService::new()
+            .step(
+                FnService::new(|input: u32| Ok::<_, ()>(input + 1)),
+                FnService::new(|_| Ok(())),
+            )
+            .step(
+                FnService::new(|input: u32| Ok(input * 2)),
+                FnService::new(|_| Ok(())),
+            );
+        assert_eq!(Ok(vec![4, 6]), saga.process(3));
+    }
+
+    #[test]
+    fn transactional_fork_undoes_first_sink() {
+        let first_sink = Mutex::new(Vec::new());
+        let second_sink = Mutex::new(Vec::new());
+        let undo_fails = Mutex::new(false);
+        let fork = TransactionalForkService::new(
+            FnService::new(|input: u32| {
+                let mut sink = first_sink.lock().unwrap();
+                sink.push(input);
+                Ok::<_, &str>(sink.len() - 1)
+            }),
+            FnService::new(|input: u32| match input {
+                0 => Err("rejected"),
+                _ => {
+                    second_sink.lock().unwrap().push(input);
+                    Ok(input)
+                }
+            }),
+            |index: usize| match *undo_fails.lock().unwrap() {
+                true => Err("undo failed"),
+                false => {
+                    first_sink.lock().unwrap().remove(index);
+                    Ok(())
+                }
+            },
+        );
+
+        assert_eq!(Ok((0, 1)), fork.process(1));
+        assert_eq!(
+            Err(TransactionalForkError::Second("rejected")),
+            fork.process(0)
+        );
+        assert_eq!(vec![1], *first_sink.lock().unwrap());
+        assert_eq!(vec![1], *second_sink.lock().unwrap());
+
+        *undo_fails.lock().unwrap() = true;
+        assert_eq!(
+            Err(TransactionalForkError::UndoFailed {
+                error: "rejected",
+                undo_error: "undo failed"
+            }),
+            fork.process(0)
+        );
+        assert_eq!(vec![1, 0], *first_sink.lock().unwrap());
+    }
+}
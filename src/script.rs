@@ -0,0 +1,323 @@
+//! Services that apply transformations declared by a script, allowing simple text pipelines to be reconfigured without recompiling.
+
+use std::{
+    convert::Infallible, error::Error, fmt::Display, iter::Peekable, str::CharIndices, str::FromStr,
+};
+
+use crate::Service;
+
+/// A [`Service`] that applies a pipeline of built-in transforms to each `String` input, declared by a script such as `uppercase | trim | prefix("x:")`.
+///
+/// The script is parsed once by [`ScriptedTransformService::parse`], so an invalid script fails at construction rather than while processing.
+/// Transforms are separated by `|` and applied from left to right.
+/// Arguments are given in parentheses as double-quoted strings, where `\"` and `\\` escape a quote and a backslash.
+///
+/// The built-in transforms are:
+/// * `uppercase` - convert to uppercase
+/// * `lowercase` - convert to lowercase
+/// * `trim` - remove leading and trailing whitespace
+/// * `prefix("s")` - prepend `s`
+/// * `suffix("s")` - append `s`
+/// * `replace("from", "to")` - replace every occurrence of `from` with `to`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptedTransformService {
+    transforms: Vec<Transform>,
+}
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Transform {
+    Uppercase,
+    Lowercase,
+    Trim,
+    Prefix(String),
+    Suffix(String),
+    Replace(String, String),
+}
+impl Transform {
+    /// Look up the built-in transform with the given name, checking the number of arguments
+    fn resolve(name: &str, mut args: Vec<String>) -> Result<Self, ScriptError> {
+        let expected = match name {
+            "uppercase" | "lowercase" | "trim" => 0,
+            "prefix" | "suffix" => 1,
+            "replace" => 2,
+            _ => {
+                return Err(ScriptError::UnknownOperator {
+                    name: name.to_owned(),
+                })
+            }
+        };
+        if args.len() != expected {
+            return Err(ScriptError::InvalidArguments {
+                operator: name.to_owned(),
+                expected,
+                got: args.len(),
+            });
+        }
+        let mut arg = || args.remove(0);
+        Ok(match name {
+            "uppercase" => Self::Uppercase,
+            "lowercase" => Self::Lowercase,
+            "trim" => Self::Trim,
+            "prefix" => Self::Prefix(arg()),
+            "suffix" => Self::Suffix(arg()),
+            _ => Self::Replace(arg(), arg()),
+        })
+    }
+
+    fn apply(&self, input: String) -> String {
+        match self {
+            Self::Uppercase => input.to_uppercase(),
+            Self::Lowercase => input.to_lowercase(),
+            Self::Trim => input.trim().to_owned(),
+            Self::Prefix(prefix) => format!("{prefix}{input}"),
+            Self::Suffix(suffix) => input + suffix,
+            Self::Replace(from, to) => input.replace(from.as_str(), to),
+        }
+    }
+}
+impl ScriptedTransformService {
+    /// Parse the given script, returning a [`ScriptError`] if it is malformed or uses an unknown operator
+    pub fn parse(script: &str) -> Result<Self, ScriptError> {
+        let mut parser = Parser {
+            chars: script.char_indices().peekable(),
+            len: script.len(),
+        };
+        let mut transforms = Vec::new();
+        loop {
+            parser.skip_whitespace();
+            let name = parser.identifier()?;
+            parser.skip_whitespace();
+            let args = match parser.peek() {
+                Some('(') => parser.arguments()?,
+                _ => Vec::new(),
+            };
+            transforms.push(Transform::resolve(&name, args)?);
+            parser.skip_whitespace();
+            match parser.next() {
+                None => return Ok(Self { transforms }),
+                Some((_, '|')) => {}
+                Some((position, _)) => {
+                    return Err(ScriptError::Syntax {
+                        position,
+                        expected: "`|` or end of script",
+                    })
+                }
+            }
+        }
+    }
+}
+impl FromStr for ScriptedTransformService {
+    type Err = ScriptError;
+    fn from_str(script: &str) -> Result<Self, Self::Err> {
+        Self::parse(script)
+    }
+}
+impl Service for ScriptedTransformService {
+    type Input = String;
+    type Output = String;
+    type Error = Infallible;
+    fn process(&self, input: String) -> Result<String, Infallible> {
+        Ok(self
+            .transforms
+            .iter()
+            .fold(input, |value, transform| transform.apply(value)))
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    len: usize,
+}
+impl Parser<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    fn next(&mut self) -> Option<(usize, char)> {
+        self.chars.next()
+    }
+
+    fn position(&mut self) -> usize {
+        self.chars.peek().map(|(i, _)| *i).unwrap_or(self.len)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char, description: &'static str) -> Result<(), ScriptError> {
+        match self.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((position, _)) => Err(ScriptError::Syntax {
+                position,
+                expected: description,
+            }),
+            None => Err(ScriptError::Syntax {
+                position: self.len,
+                expected: description,
+            }),
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, ScriptError> {
+        let mut name = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_alphanumeric() || *c == '_') {
+            name.push(c);
+            self.next();
+        }
+        match name.is_empty() {
+            true => Err(ScriptError::Syntax {
+                position: self.position(),
+                expected: "operator name",
+            }),
+            false => Ok(name),
+        }
+    }
+
+    fn arguments(&mut self) -> Result<Vec<String>, ScriptError> {
+        self.expect('(', "`(`")?;
+        let mut args = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(')') {
+            self.next();
+            return Ok(args);
+        }
+        loop {
+            self.skip_whitespace();
+            args.push(self.string()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some((_, ')')) => return Ok(args),
+                Some((_, ',')) => {}
+                Some((position, _)) => {
+                    return Err(ScriptError::Syntax {
+                        position,
+                        expected: "`,` or `)`",
+                    })
+                }
+                None => {
+                    return Err(ScriptError::Syntax {
+                        position: self.len,
+                        expected: "`,` or `)`",
+                    })
+                }
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ScriptError> {
+        self.expect('"', "quoted string")?;
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some((_, '"')) => return Ok(value),
+                Some((_, '\\')) => match self.next() {
+                    Some((_, c @ ('"' | '\\'))) => value.push(c),
+                    Some((position, _)) => {
+                        return Err(ScriptError::Syntax {
+                            position,
+                            expected: "`\"` or `\\` after `\\`",
+                        })
+                    }
+                    None => break,
+                },
+                Some((_, c)) => value.push(c),
+                None => break,
+            }
+        }
+        Err(ScriptError::Syntax {
+            position: self.len,
+            expected: "closing `\"`",
+        })
+    }
+}
+
+/// Returned by [`ScriptedTransformService::parse`] when a script is malformed or uses an unknown operator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScriptError {
+    /// The script could not be parsed at the given byte position
+    Syntax {
+        position: usize,
+        expected: &'static str,
+    },
+    /// The script names an operator which is not a built-in transform
+    UnknownOperator { name: String },
+    /// The script gives the wrong number of arguments to an operator
+    InvalidArguments {
+        operator: String,
+        expected: usize,
+        got: usize,
+    },
+}
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Syntax { position, expected } => {
+                write!(f, "expected {expected} at position {position}")
+            }
+            Self::UnknownOperator { name } => write!(f, "unknown operator `{name}`"),
+            Self::InvalidArguments {
+                operator,
+                expected,
+                got,
+            } => write!(
+                f,
+                "operator `{operator}` expects {expected} arguments, but got {got}"
+            ),
+        }
+    }
+}
+impl Error for ScriptError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripted_pipeline_applies_in_order() {
+        let service = ScriptedTransformService::parse(
+            r#"trim | uppercase | replace("O", "0") | prefix("x:")"#,
+        )
+        .unwrap();
+        assert_eq!(
+            Ok("x:HELL0 W0RLD".to_owned()),
+            service.process("  hello world \n".to_owned())
+        );
+
+        let service: ScriptedTransformService = r#"suffix("\"\\")"#.parse().unwrap();
+        assert_eq!(Ok("a\"\\".to_owned()), service.process("a".to_owned()));
+    }
+
+    #[test]
+    fn scripted_pipeline_rejects_invalid_scripts() {
+        assert_eq!(
+            Err(ScriptError::UnknownOperator {
+                name: "reverse".to_owned()
+            }),
+            ScriptedTransformService::parse("trim | reverse")
+        );
+        assert_eq!(
+            Err(ScriptError::InvalidArguments {
+                operator: "prefix".to_owned(),
+                expected: 1,
+                got: 0
+            }),
+            ScriptedTransformService::parse("prefix")
+        );
+        assert_eq!(
+            Err(ScriptError::Syntax {
+                position: 6,
+                expected: "operator name"
+            }),
+            ScriptedTransformService::parse("trim |")
+        );
+        assert_eq!(
+            Err(ScriptError::Syntax {
+                position: 10,
+                expected: "closing `\"`"
+            }),
+            ScriptedTransformService::parse(r#"prefix("x:"#)
+        );
+    }
+}
@@ -0,0 +1,472 @@
+//! Services that enforce or restore the ordering of inputs by sequence number.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::Display,
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    MutService,
+};
+
+/// A [`MutService`] that enforces that inputs arrive in strictly increasing, gap-free sequence number order.
+///
+/// The sequence number is extracted from each input using the given function.
+/// An input with the expected sequence number is passed to the underlying [`MutService`].
+/// An input with a sequence number lower than expected is a duplicate, and is rejected with a [`SequenceError`].
+/// An input with a sequence number higher than expected is rejected with a [`SequenceError`], unless buffering is enabled by [`SequenceGuardService::with_buffer`],
+/// in which case it is held until the gap is filled and then passed to the underlying service in order.
+///
+/// Each call produces the outputs of every input passed to the underlying service by that call, which is empty when the input was buffered.
+///
+/// A sequence number is only consumed when the underlying service returns `Ok`, so an input that fails may be redelivered.
+/// When a released buffered input fails, it is dropped from the buffer and the error is returned, discarding the outputs of inputs released earlier in the same call.
+pub struct SequenceGuardService<S: MutService, F> {
+    service: S,
+    sequence: F,
+    expected: u64,
+    capacity: usize,
+    buffer: BTreeMap<u64, S::Input>,
+}
+impl<S: MutService, F: Fn(&S::Input) -> u64> SequenceGuardService<S, F> {
+    /// Create a new [`SequenceGuardService`] that rejects early arrivals
+    ///
+    /// # Arguments
+    /// * `service` - the underlying service
+    /// * `sequence` - the function to extract the sequence number from each input
+    /// * `first` - the sequence number of the first expected input
+    pub fn new(service: S, sequence: F, first: u64) -> Self {
+        Self::with_buffer(service, sequence, first, 0)
+    }
+
+    /// Create a new [`SequenceGuardService`] that buffers up to `capacity` early arrivals until the gap is filled
+    pub fn with_buffer(service: S, sequence: F, first: u64, capacity: usize) -> Self {
+        Self {
+            service,
+            sequence,
+            expected: first,
+            capacity,
+            buffer: BTreeMap::new(),
+        }
+    }
+
+    /// The sequence number of the next expected input
+    pub fn expected(&self) -> u64 {
+        self.expected
+    }
+}
+impl<S: MutService, F: Fn(&S::Input) -> u64> MutService for SequenceGuardService<S, F> {
+    type Input = S::Input;
+    type Output = Vec<S::Output>;
+    type Error = SequenceGuardError<S::Error>;
+    fn process(&mut self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let got = (self.sequence)(&input);
+        let out_of_sequence = SequenceGuardError::Sequence(SequenceError {
+            expected: self.expected,
+            got,
+        });
+        if got < self.expected || self.buffer.contains_key(&got) {
+            return Err(out_of_sequence);
+        }
+        if got > self.expected {
+            if self.buffer.len() >= self.capacity {
+                return Err(out_of_sequence);
+            }
+            self.buffer.insert(got, input);
+            return Ok(Vec::new());
+        }
+        let mut outputs = Vec::new();
+        let mut next = Some(input);
+        while let Some(input) = next {
+            outputs.push(
+                self.service
+                    .process(input)
+                    .map_err(SequenceGuardError::ServiceError)?,
+            );
+            self.expected += 1;
+            next = self.buffer.remove(&self.expected);
+        }
+        Ok(outputs)
+    }
+}
+
+/// Returned by [`SequenceGuardService`] when an input is out of sequence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SequenceError {
+    /// The expected sequence number
+    pub expected: u64,
+    /// The sequence number of the rejected input
+    pub got: u64,
+}
+impl Display for SequenceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "expected sequence {} but got {}",
+            self.expected, self.got
+        )
+    }
+}
+impl Error for SequenceError {}
+
+/// Returned by [`SequenceGuardService`], either when an input is out of sequence or when the underlying service fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SequenceGuardError<E> {
+    Sequence(SequenceError),
+    ServiceError(E),
+}
+impl<E: Display> Display for SequenceGuardError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sequence(err) => write!(f, "{err}"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<E: Error + 'static> Error for SequenceGuardError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Sequence(err) => Some(err),
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+/// A [`MutService`] that restores the sequence order of values produced by an unordered polling service, `MutService<Input = (), Output = Option<T>>`,
+/// such as a receiver of datagrams which may arrive out of order or more than once.
+///
+/// The sequence number is extracted from each value using the given function.
+/// Each call produces the next value in sequence order, or `None` when the next value has not arrived and the underlying service is empty,
+/// so this may be encapsulated by a [`crate::PollService`] to block until the next value is available.
+/// Values that arrive early are buffered, up to `capacity` values, and values that arrive once the buffer is full are dropped.
+/// Values with a sequence number that has already been produced or skipped, and duplicates of buffered values, are dropped.
+///
+/// When values are buffered behind a missing value for at least `gap_timeout`, the given gap function is called with the range of missing sequence numbers.
+/// It may return [`GapAction::Skip`] to give up on the missing values and continue from the first buffered value,
+/// or it may request a retransmit and return [`GapAction::Wait`] to wait for another `gap_timeout`.
+pub struct ReorderService<T, S, F, G, C: Clock = SystemClock> {
+    service: S,
+    sequence: F,
+    on_gap: G,
+    expected: u64,
+    capacity: usize,
+    gap_timeout: Duration,
+    buffer: BTreeMap<u64, T>,
+    gap_since: Option<Instant>,
+    clock: C,
+}
+impl<T, S, F, G> ReorderService<T, S, F, G>
+where
+    S: MutService<Input = (), Output = Option<T>>,
+    F: Fn(&T) -> u64,
+    G: FnMut(Range<u64>) -> GapAction,
+{
+    /// Create a new [`ReorderService`]
+    ///
+    /// # Arguments
+    /// * `service` - the underlying polling service
+    /// * `sequence` - the function to extract the sequence number from each value
+    /// * `first` - the sequence number of the first expected value
+    /// * `capacity` - the maximum number of early values to buffer
+    /// * `gap_timeout` - how long values may be buffered behind a missing value before calling `on_gap`
+    /// * `on_gap` - the function to decide whether to skip a gap of missing sequence numbers or to keep waiting
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    pub fn new(
+        service: S,
+        sequence: F,
+        first: u64,
+        capacity: usize,
+        gap_timeout: Duration,
+        on_gap: G,
+    ) -> Self {
+        Self::with_clock(
+            service,
+            sequence,
+            first,
+            capacity,
+            gap_timeout,
+            on_gap,
+            SystemClock,
+        )
+    }
+}
+impl<T, S, F, G, C> ReorderService<T, S, F, G, C>
+where
+    S: MutService<Input = (), Output = Option<T>>,
+    F: Fn(&T) -> u64,
+    G: FnMut(Range<u64>) -> GapAction,
+    C: Clock,
+{
+    /// Create a new [`ReorderService`] which uses the given [`Clock`] to measure the gap timeout
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    pub fn with_clock(
+        service: S,
+        sequence: F,
+        first: u64,
+        capacity: usize,
+        gap_timeout: Duration,
+        on_gap: G,
+        clock: C,
+    ) -> Self {
+        assert!(capacity > 0, "capacity must be non-zero");
+        Self {
+            service,
+            sequence,
+            on_gap,
+            expected: first,
+            capacity,
+            gap_timeout,
+            buffer: BTreeMap::new(),
+            gap_since: None,
+            clock,
+        }
+    }
+
+    /// The sequence number of the next expected value
+    pub fn expected(&self) -> u64 {
+        self.expected
+    }
+
+    /// The number of early values currently buffered
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Produce the expected value, restarting the gap timer when values remain buffered behind the next missing value
+    fn advance(&mut self, value: T) -> Option<T> {
+        self.expected = (self.sequence)(&value) + 1;
+        self.gap_since = match self.buffer.is_empty() {
+            true => None,
+            false => Some(self.clock.now()),
+        };
+        Some(value)
+    }
+}
+impl<T, S, F, G, C> MutService for ReorderService<T, S, F, G, C>
+where
+    S: MutService<Input = (), Output = Option<T>>,
+    F: Fn(&T) -> u64,
+    G: FnMut(Range<u64>) -> GapAction,
+    C: Clock,
+{
+    type Input = ();
+    type Output = Option<T>;
+    type Error = S::Error;
+    fn process(&mut self, _: ()) -> Result<Self::Output, Self::Error> {
+        if let Some(value) = self.buffer.remove(&self.expected) {
+            return Ok(self.advance(value));
+        }
+        while let Some(value) = self.service.process(())? {
+            let got = (self.sequence)(&value);
+            if got == self.expected {
+                return Ok(self.advance(value));
+            }
+            if got < self.expected
+                || self.buffer.contains_key(&got)
+                || self.buffer.len() >= self.capacity
+            {
+                continue;
+            }
+            if self.buffer.is_empty() {
+                self.gap_since = Some(self.clock.now());
+            }
+            self.buffer.insert(got, value);
+        }
+        let first = match (self.gap_since, self.buffer.keys().next()) {
+            (Some(since), Some(&first))
+                if self.clock.now().duration_since(since) >= self.gap_timeout =>
+            {
+                first
+            }
+            _ => return Ok(None),
+        };
+        match (self.on_gap)(self.expected..first) {
+            GapAction::Skip => {
+                let value = self.buffer.remove(&first).expect("first buffered value");
+                Ok(self.advance(value))
+            }
+            GapAction::Wait => {
+                self.gap_since = Some(self.clock.now());
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Returned by the gap function of a [`ReorderService`] to decide how to handle missing sequence numbers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GapAction {
+    /// Give up on the missing values, continuing from the first buffered value
+    Skip,
+    /// Keep waiting for the missing values, such as after requesting a retransmit, calling the gap function again after another gap timeout
+    Wait,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        convert::Infallible,
+        rc::Rc,
+        sync::mpsc::{self, Receiver},
+    };
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    struct Collect(Vec<u64>);
+    impl MutService for Collect {
+        type Input = u64;
+        type Output = u64;
+        type Error = Infallible;
+        fn process(&mut self, input: u64) -> Result<u64, Infallible> {
+            self.0.push(input);
+            Ok(input)
+        }
+    }
+
+    fn out_of_sequence(expected: u64, got: u64) -> SequenceGuardError<Infallible> {
+        SequenceGuardError::Sequence(SequenceError { expected, got })
+    }
+
+    #[test]
+    fn sequence_guard_accepts_in_order() {
+        let mut service = SequenceGuardService::new(Collect(Vec::new()), |seq: &u64| *seq, 1);
+        assert_eq!(Ok(vec![1]), service.process(1));
+        assert_eq!(Ok(vec![2]), service.process(2));
+        assert_eq!(Err(out_of_sequence(3, 5)), service.process(5));
+        assert_eq!(Ok(vec![3]), service.process(3));
+        assert_eq!(4, service.expected());
+    }
+
+    #[test]
+    fn sequence_guard_buffers_gap() {
+        let mut service =
+            SequenceGuardService::with_buffer(Collect(Vec::new()), |seq: &u64| *seq, 1, 2);
+        assert_eq!(Ok(vec![1]), service.process(1));
+        assert_eq!(Ok(vec![]), service.process(4));
+        assert_eq!(Ok(vec![]), service.process(3));
+        assert_eq!(Err(out_of_sequence(2, 5)), service.process(5));
+        assert_eq!(Ok(vec![2, 3, 4]), service.process(2));
+        assert_eq!(vec![1, 2, 3, 4], service.service.0);
+    }
+
+    #[test]
+    fn sequence_guard_rejects_duplicate() {
+        let mut service =
+            SequenceGuardService::with_buffer(Collect(Vec::new()), |seq: &u64| *seq, 1, 2);
+        assert_eq!(Ok(vec![1]), service.process(1));
+        assert_eq!(Err(out_of_sequence(2, 1)), service.process(1));
+        assert_eq!(Ok(vec![]), service.process(3));
+        assert_eq!(Err(out_of_sequence(2, 3)), service.process(3));
+        assert_eq!(vec![1], service.service.0);
+    }
+
+    /// Produces datagrams from a channel without blocking, where the first byte of each datagram is its sequence number
+    struct Datagrams(Receiver<Vec<u8>>);
+    impl MutService for Datagrams {
+        type Input = ();
+        type Output = Option<Vec<u8>>;
+        type Error = Infallible;
+        fn process(&mut self, _: ()) -> Result<Option<Vec<u8>>, Infallible> {
+            Ok(self.0.try_recv().ok())
+        }
+    }
+
+    fn drain<S: MutService<Input = (), Output = Option<Vec<u8>>, Error = Infallible>>(
+        service: &mut S,
+    ) -> Vec<u8> {
+        let mut seqs = Vec::new();
+        while let Some(datagram) = service.process(()).unwrap() {
+            seqs.push(datagram[0]);
+        }
+        seqs
+    }
+
+    #[test]
+    fn reorder_restores_order_and_handles_gaps() {
+        let (tx, rx) = mpsc::channel();
+        let send = |seqs: &[u8]| {
+            seqs.iter()
+                .for_each(|seq| tx.send(vec![*seq, 0xff]).unwrap())
+        };
+        let clock = ManualClock::new();
+        // request a retransmit of each gap once, then skip it
+        let gaps = Rc::new(RefCell::new(Vec::new()));
+        let on_gap = {
+            let gaps = Rc::clone(&gaps);
+            move |gap: Range<u64>| {
+                let mut gaps = gaps.borrow_mut();
+                match gaps.contains(&gap) {
+                    true => GapAction::Skip,
+                    false => {
+                        gaps.push(gap);
+                        GapAction::Wait
+                    }
+                }
+            }
+        };
+        let mut service = ReorderService::with_clock(
+            Datagrams(rx),
+            |datagram: &Vec<u8>| datagram[0] as u64,
+            0,
+            4,
+            Duration::from_secs(1),
+            on_gap,
+            clock.clone(),
+        );
+
+        // shuffled, with duplicates
+        send(&[2, 0, 1, 1, 4, 3, 0, 2]);
+        assert_eq!(vec![0, 1, 2, 3, 4], drain(&mut service));
+
+        // a gap waits for the timeout, then a retransmit is requested and filled
+        send(&[6, 7]);
+        assert_eq!(Vec::<u8>::new(), drain(&mut service));
+        clock.advance(Duration::from_millis(999));
+        assert_eq!(Vec::<u8>::new(), drain(&mut service));
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(Vec::<u8>::new(), drain(&mut service));
+        assert_eq!(vec![5..6], *gaps.borrow());
+        send(&[5]);
+        assert_eq!(vec![5, 6, 7], drain(&mut service));
+
+        // a gap which is not filled is skipped after the retransmit times out, and values beyond the buffer capacity are dropped
+        send(&[10, 9, 12, 13, 14, 11]);
+        assert_eq!(Vec::<u8>::new(), drain(&mut service));
+        assert_eq!(4, service.buffered());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(Vec::<u8>::new(), drain(&mut service));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(vec![9, 10], drain(&mut service));
+        assert_eq!(vec![5..6, 8..9], *gaps.borrow());
+
+        // values which were skipped are dropped when they arrive late
+        send(&[8, 11]);
+        assert_eq!(vec![11, 12, 13], drain(&mut service));
+        assert_eq!(14, service.expected());
+        assert_eq!(0, service.buffered());
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be non-zero")]
+    fn reorder_rejects_zero_capacity() {
+        let (_tx, rx) = mpsc::channel();
+        ReorderService::new(
+            Datagrams(rx),
+            |datagram: &Vec<u8>| datagram[0] as u64,
+            0,
+            0,
+            Duration::from_secs(1),
+            |_: Range<u64>| GapAction::Skip,
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Services whose state may be captured as bytes and later restored, such as to persist an accumulator across restarts.
+
+use std::sync::Mutex;
+
+use crate::{MutService, Service};
+
+/// A stateful service whose state may be encoded as bytes, and replaced by a previously encoded state, used by [`SnapshottableService`].
+///
+/// The encoding is chosen by the implementation, such as a serde format or a hand-written binary layout,
+/// but a snapshot produced by [`Snapshot::snapshot`] must be accepted by [`Snapshot::restore`].
+pub trait Snapshot {
+    type Error;
+
+    /// Encode the current state
+    fn snapshot(&self) -> Vec<u8>;
+
+    /// Replace the current state with the given encoded state, leaving the current state unchanged if it cannot be decoded
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// A [`Service`] or [`MutService`] that encapsulates a stateful [`MutService`] which implements [`Snapshot`],
+/// allowing its state to be captured by [`SnapshottableService::snapshot`] and restored by [`SnapshottableService::restore`].
+///
+/// The underlying service is held in a [`Mutex`], so a snapshot is never taken while a call to `process` is modifying the state,
+/// and each snapshot reflects the state after some number of complete calls.
+pub struct SnapshottableService<S> {
+    service: Mutex<S>,
+}
+impl<S: Snapshot> SnapshottableService<S> {
+    pub fn new(service: S) -> Self {
+        Self {
+            service: Mutex::new(service),
+        }
+    }
+
+    /// Encode the current state of the underlying service
+    pub fn snapshot(&self) -> Vec<u8> {
+        self.service.lock().expect("poisoned mutex").snapshot()
+    }
+
+    /// Replace the state of the underlying service with the given snapshot
+    pub fn restore(&self, snapshot: &[u8]) -> Result<(), S::Error> {
+        self.service
+            .lock()
+            .expect("poisoned mutex")
+            .restore(snapshot)
+    }
+
+    /// Unwrap the underlying service
+    pub fn into_inner(self) -> S {
+        self.service.into_inner().expect("poisoned mutex")
+    }
+}
+impl<S: MutService> Service for SnapshottableService<S> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.service.lock().expect("poisoned mutex").process(input)
+    }
+}
+impl<S: MutService> MutService for SnapshottableService<S> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    fn process(&mut self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.service
+            .get_mut()
+            .expect("poisoned mutex")
+            .process(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+
+    /// Accumulates the sum of its inputs, producing the running total
+    struct AccumulatorService {
+        total: u64,
+    }
+    impl MutService for AccumulatorService {
+        type Input = u64;
+        type Output = u64;
+        type Error = Infallible;
+        fn process(&mut self, input: u64) -> Result<u64, Infallible> {
+            self.total += input;
+            Ok(self.total)
+        }
+    }
+    impl Snapshot for AccumulatorService {
+        type Error = usize;
+        fn snapshot(&self) -> Vec<u8> {
+            self.total.to_le_bytes().to_vec()
+        }
+        fn restore(&mut self, snapshot: &[u8]) -> Result<(), usize> {
+            let bytes = snapshot.try_into().map_err(|_| snapshot.len())?;
+            self.total = u64::from_le_bytes(bytes);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let service = SnapshottableService::new(AccumulatorService { total: 0 });
+        service.process(5).unwrap();
+        service.process(7).unwrap();
+        let snapshot = service.snapshot();
+
+        assert_eq!(Ok(112), service.process(100));
+        service.restore(&snapshot).unwrap();
+        assert_eq!(Ok(13), service.process(1));
+
+        // a snapshot may seed a new instance, such as after a restart
+        let mut restarted = SnapshottableService::new(AccumulatorService { total: 0 });
+        restarted.restore(&snapshot).unwrap();
+        assert_eq!(Ok(15), MutService::process(&mut restarted, 3));
+
+        assert_eq!(Err(3), restarted.restore(&[1, 2, 3]));
+        assert_eq!(15, restarted.into_inner().total);
+    }
+}
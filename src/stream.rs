@@ -0,0 +1,594 @@
+//! Services that combine or transform streams of values produced by polling services, or that produce such streams from other sources.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    error::Error,
+    fmt::{Debug, Display},
+    future::{poll_fn, Future},
+    mem,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use futures_core::Stream;
+
+use crate::{async_trait, AsyncService, MutService};
+
+/// A [`MutService`] that merges two sorted polling services, `MutService<Input = (), Output = Option<T>>`, into a single sorted stream.
+///
+/// Each call produces the smaller of the two sources' next values, retaining the larger value to be compared on the next call.
+/// When only one source produces a value, that value is produced, draining whichever source is non-empty.
+/// When both sources produce `None`, `None` is produced.
+///
+/// The output is globally sorted as long as each source is sorted and a source only produces `None` once it is exhausted.
+/// When a source produces `None` and later produces more values, values from the other source may have already been produced out of order.
+pub struct MergeSortedService<T, S1, S2> {
+    first: S1,
+    second: S2,
+    first_head: Option<T>,
+    second_head: Option<T>,
+}
+impl<T, S1, S2> MergeSortedService<T, S1, S2> {
+    pub fn new(first: S1, second: S2) -> Self {
+        Self {
+            first,
+            second,
+            first_head: None,
+            second_head: None,
+        }
+    }
+}
+impl<T, S1, S2> MutService for MergeSortedService<T, S1, S2>
+where
+    T: Ord,
+    S1: MutService<Input = (), Output = Option<T>>,
+    S2: MutService<Input = (), Output = Option<T>, Error = S1::Error>,
+{
+    type Input = ();
+    type Output = Option<T>;
+    type Error = S1::Error;
+    fn process(&mut self, _: ()) -> Result<Self::Output, Self::Error> {
+        if self.first_head.is_none() {
+            self.first_head = self.first.process(())?;
+        }
+        if self.second_head.is_none() {
+            self.second_head = self.second.process(())?;
+        }
+        Ok(match (&self.first_head, &self.second_head) {
+            (Some(first), Some(second)) if second < first => self.second_head.take(),
+            (Some(_), _) => self.first_head.take(),
+            (None, _) => self.second_head.take(),
+        })
+    }
+}
+
+/// Adapt a polling [`AsyncService`], `AsyncService<Input = (), Output = Option<T>>`, into a [`Stream`] of `Result<T, Error>`.
+///
+/// The service is called repeatedly, yielding each `Some(T)` as `Ok(T)` and each error as `Err(Error)`.
+/// The stream continues after an error, and terminates when the service produces `None`.
+pub fn service_stream<T, S>(service: S) -> ServiceStream<T, S>
+where
+    S: AsyncService<Input = (), Output = Option<T>> + 'static,
+{
+    ServiceStream {
+        service: Arc::new(service),
+        pending: None,
+        done: false,
+    }
+}
+
+/// The [`Stream`] produced by [`service_stream`].
+pub struct ServiceStream<T, S: AsyncService> {
+    service: Arc<S>,
+    pending: Option<PendingCall<T, S::Error>>,
+    done: bool,
+}
+type PendingCall<T, E> = Pin<Box<dyn Future<Output = Result<Option<T>, E>> + Send>>;
+impl<T, S: AsyncService> Unpin for ServiceStream<T, S> {}
+impl<T, S> Stream for ServiceStream<T, S>
+where
+    S: AsyncService<Input = (), Output = Option<T>> + 'static,
+{
+    type Item = Result<T, S::Error>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        let service = Arc::clone(&self.service);
+        let pending = self
+            .pending
+            .get_or_insert_with(|| Box::pin(async move { service.process(()).await }));
+        let result = match pending.as_mut().poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.pending = None;
+        Poll::Ready(match result {
+            Ok(Some(item)) => Some(Ok(item)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => Some(Err(err)),
+        })
+    }
+}
+
+/// An [`AsyncService`] that flattens a cursor-paginated upstream [`AsyncService`] into a polling service, `AsyncService<Input = (), Output = Option<T>>`.
+///
+/// Starting from the given initial request, each page is fetched from the upstream service and split into its items and the request for the next page using the given function,
+/// typically by building the next request from a `next_token` in the response.
+/// Each call produces the next buffered item, fetching the next page when the buffer is empty, and produces `None` once the last page, with no next request, has been drained.
+///
+/// When fetching a page fails, the error is returned and the same page is fetched again by the next call.
+/// Only one page is fetched at a time: a call made while another call is fetching a page waits for that fetch to finish before producing the next item,
+/// so `None` is only produced once the upstream is exhausted.
+/// When the fetching call is dropped before the fetch finishes, the same page is fetched again by a waiting or subsequent call.
+pub struct PaginatingService<T, R, S, F> {
+    service: S,
+    split: F,
+    state: Mutex<PaginationState<T, R>>,
+}
+struct PaginationState<T, R> {
+    items: VecDeque<T>,
+    next: Option<R>,
+    fetching: bool,
+    waiters: Vec<Waker>,
+}
+enum NextPage<T, R> {
+    Item(Option<T>),
+    Fetch(R),
+}
+/// Marks a page as being fetched, restoring its request and waking waiting calls when dropped before [`PageFetch::complete`]
+struct PageFetch<'a, T, R> {
+    state: &'a Mutex<PaginationState<T, R>>,
+    request: Option<R>,
+}
+impl<'a, T, R> PageFetch<'a, T, R> {
+    fn complete(mut self, items: Vec<T>, next: Option<R>) {
+        let mut state = self.state.lock().expect("poisoned mutex");
+        state.items.extend(items);
+        state.next = next;
+        self.request = None;
+    }
+}
+impl<'a, T, R> Drop for PageFetch<'a, T, R> {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().expect("poisoned mutex");
+        if let Some(request) = self.request.take() {
+            state.next = Some(request);
+        }
+        state.fetching = false;
+        let waiters = mem::take(&mut state.waiters);
+        drop(state);
+        waiters.into_iter().for_each(Waker::wake);
+    }
+}
+impl<T, R, S, F> PaginatingService<T, R, S, F>
+where
+    S: AsyncService<Input = R>,
+    F: Fn(S::Output) -> (Vec<T>, Option<R>),
+{
+    pub fn new(service: S, initial: R, split: F) -> Self {
+        Self {
+            service,
+            split,
+            state: Mutex::new(PaginationState {
+                items: VecDeque::new(),
+                next: Some(initial),
+                fetching: false,
+                waiters: Vec::new(),
+            }),
+        }
+    }
+
+    /// Produce the next buffered item, or the request for the next page to fetch, or wait for the page currently being fetched
+    fn poll_next(&self, cx: &mut Context<'_>) -> Poll<NextPage<T, R>> {
+        let mut state = self.state.lock().expect("poisoned mutex");
+        if let Some(item) = state.items.pop_front() {
+            return Poll::Ready(NextPage::Item(Some(item)));
+        }
+        if state.fetching {
+            if !state.waiters.iter().any(|w| w.will_wake(cx.waker())) {
+                state.waiters.push(cx.waker().clone());
+            }
+            return Poll::Pending;
+        }
+        Poll::Ready(match state.next.take() {
+            Some(request) => {
+                state.fetching = true;
+                NextPage::Fetch(request)
+            }
+            None => NextPage::Item(None),
+        })
+    }
+}
+#[async_trait]
+impl<T, R, S, F> AsyncService for PaginatingService<T, R, S, F>
+where
+    T: Send + 'static,
+    R: Clone + Send + 'static,
+    S: AsyncService<Input = R>,
+    F: Fn(S::Output) -> (Vec<T>, Option<R>) + Send + Sync,
+{
+    type Input = ();
+    type Output = Option<T>;
+    type Error = S::Error;
+    async fn process(&self, _: ()) -> Result<Option<T>, S::Error> {
+        loop {
+            let request = match poll_fn(|cx| self.poll_next(cx)).await {
+                NextPage::Item(item) => return Ok(item),
+                NextPage::Fetch(request) => request,
+            };
+            let fetch = PageFetch {
+                state: &self.state,
+                request: Some(request.clone()),
+            };
+            // on failure, dropping the fetch restores the request so the same page is fetched again
+            let page = self.service.process(request).await?;
+            let (items, next) = (self.split)(page);
+            fetch.complete(items, next);
+        }
+    }
+}
+
+/// A [`MutService`] that aggregates inputs into tumbling windows by their event time, producing the aggregate of each key within each window once the window is complete.
+///
+/// Event time is extracted from each input as a [`Duration`] since an arbitrary epoch, such as [`std::time::UNIX_EPOCH`],
+/// and the input is assigned to the window `[start, start + size)` containing it, where `start` is a multiple of `size`.
+/// Each input is folded into the aggregate for its window and key, starting from `A::default()`.
+///
+/// The watermark is the greatest event time seen so far.
+/// A window is complete once the watermark reaches the end of the window plus the allowed lateness,
+/// so out-of-order inputs within the allowed lateness are still aggregated into their window.
+/// Each call produces the aggregates of every window completed by that call, ordered by window and then by key.
+///
+/// An input for a window which has already been completed is rejected with a [`LateEventError`], which returns the input so it may be handled separately.
+/// Use [`TimeWindowAggregateService::flush`] to produce the aggregates of all incomplete windows, such as at the end of a stream.
+pub struct TimeWindowAggregateService<T, K, A, FT, FK, FA> {
+    size: Duration,
+    allowed_lateness: Duration,
+    event_time: FT,
+    key: FK,
+    fold: FA,
+    watermark: Option<Duration>,
+    windows: BTreeMap<(Duration, K), A>,
+    _phantom: std::marker::PhantomData<fn(T)>,
+}
+impl<T, K, A, FT, FK, FA> TimeWindowAggregateService<T, K, A, FT, FK, FA>
+where
+    K: Ord,
+    A: Default,
+    FT: Fn(&T) -> Duration,
+    FK: Fn(&T) -> K,
+    FA: Fn(&mut A, T),
+{
+    /// Create a new [`TimeWindowAggregateService`]
+    ///
+    /// * `size` - the duration of each tumbling window, which must be non-zero
+    /// * `allowed_lateness` - how far the watermark may pass the end of a window before the window is completed
+    /// * `event_time` - extracts the event time of an input
+    /// * `key` - extracts the key of an input
+    /// * `fold` - folds an input into the aggregate for its window and key
+    pub fn new(
+        size: Duration,
+        allowed_lateness: Duration,
+        event_time: FT,
+        key: FK,
+        fold: FA,
+    ) -> Self {
+        assert!(!size.is_zero(), "window size must be non-zero");
+        Self {
+            size,
+            allowed_lateness,
+            event_time,
+            key,
+            fold,
+            watermark: None,
+            windows: BTreeMap::new(),
+            _phantom: std::marker::PhantomData,
+        }
+    }
+
+    /// The greatest event time seen so far, or `None` before the first input
+    pub fn watermark(&self) -> Option<Duration> {
+        self.watermark
+    }
+
+    /// Produce the aggregates of all incomplete windows, ordered by window and then by key
+    pub fn flush(&mut self) -> Vec<WindowAggregate<K, A>> {
+        let size = self.size;
+        std::mem::take(&mut self.windows)
+            .into_iter()
+            .map(|((start, key), aggregate)| WindowAggregate {
+                start,
+                end: start + size,
+                key,
+                aggregate,
+            })
+            .collect()
+    }
+
+    fn window_start(&self, event_time: Duration) -> Duration {
+        let offset = event_time.as_nanos() % self.size.as_nanos();
+        event_time - Duration::from_nanos(offset as u64)
+    }
+
+    fn is_complete(&self, start: Duration) -> bool {
+        self.watermark
+            .is_some_and(|watermark| watermark >= start + self.size + self.allowed_lateness)
+    }
+}
+impl<T, K, A, FT, FK, FA> MutService for TimeWindowAggregateService<T, K, A, FT, FK, FA>
+where
+    K: Ord,
+    A: Default,
+    FT: Fn(&T) -> Duration,
+    FK: Fn(&T) -> K,
+    FA: Fn(&mut A, T),
+{
+    type Input = T;
+    type Output = Vec<WindowAggregate<K, A>>;
+    type Error = LateEventError<T>;
+    fn process(&mut self, input: T) -> Result<Self::Output, Self::Error> {
+        let event_time = (self.event_time)(&input);
+        let start = self.window_start(event_time);
+        if self.is_complete(start) {
+            return Err(LateEventError {
+                event: input,
+                window_end: start + self.size,
+            });
+        }
+        let key = (self.key)(&input);
+        (self.fold)(self.windows.entry((start, key)).or_default(), input);
+        self.watermark = self.watermark.max(Some(event_time));
+
+        let mut completed = Vec::new();
+        while let Some(((start, _), _)) = self.windows.first_key_value() {
+            if !self.is_complete(*start) {
+                break;
+            }
+            let ((start, key), aggregate) = self.windows.pop_first().expect("first window");
+            completed.push(WindowAggregate {
+                start,
+                end: start + self.size,
+                key,
+                aggregate,
+            });
+        }
+        Ok(completed)
+    }
+}
+
+/// The aggregate of all inputs with the same key within a window, produced by [`TimeWindowAggregateService`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WindowAggregate<K, A> {
+    /// The event time at the start of the window, inclusive
+    pub start: Duration,
+    /// The event time at the end of the window, exclusive
+    pub end: Duration,
+    pub key: K,
+    pub aggregate: A,
+}
+
+/// Returned by [`TimeWindowAggregateService`] when an input arrives after its window has been completed, returning the input.
+#[derive(Clone, PartialEq, Eq)]
+pub struct LateEventError<T> {
+    pub event: T,
+    /// The end of the window the event belongs to
+    pub window_end: Duration,
+}
+impl<T> Debug for LateEventError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LateEventError")
+            .field("window_end", &self.window_end)
+            .finish_non_exhaustive()
+    }
+}
+impl<T> Display for LateEventError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "late event for window ending at {:?}", self.window_end)
+    }
+}
+impl<T> Error for LateEventError<T> {}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    };
+
+    use futures::{executor::block_on, future::join, StreamExt};
+
+    use super::*;
+    use crate::{async_trait, testing::Delay, AsyncService};
+
+    struct QueueService {
+        queue: VecDeque<u32>,
+    }
+    impl QueueService {
+        fn new(values: &[u32]) -> Self {
+            Self {
+                queue: values.iter().copied().collect(),
+            }
+        }
+    }
+    impl MutService for QueueService {
+        type Input = ();
+        type Output = Option<u32>;
+        type Error = Infallible;
+        fn process(&mut self, _: ()) -> Result<Option<u32>, Infallible> {
+            Ok(self.queue.pop_front())
+        }
+    }
+
+    #[test]
+    fn merge_sorted_sources() {
+        let mut service = MergeSortedService::new(
+            QueueService::new(&[1, 4, 5, 9, 12, 15]),
+            QueueService::new(&[2, 3, 5, 10]),
+        );
+        let mut output = Vec::new();
+        while let Some(v) = service.process(()).unwrap() {
+            output.push(v);
+        }
+        assert_eq!(vec![1, 2, 3, 4, 5, 5, 9, 10, 12, 15], output);
+        assert_eq!(None, service.process(()).unwrap());
+    }
+
+    struct CountdownService {
+        remaining: AtomicU32,
+    }
+    #[async_trait]
+    impl AsyncService for CountdownService {
+        type Input = ();
+        type Output = Option<u32>;
+        type Error = &'static str;
+        async fn process(&self, _: ()) -> Result<Option<u32>, &'static str> {
+            match self.remaining.fetch_sub(1, Ordering::Relaxed) {
+                0 => Ok(None),
+                2 => Err("two"),
+                n => Ok(Some(n)),
+            }
+        }
+    }
+
+    #[test]
+    fn service_stream_terminates_on_none() {
+        let stream = service_stream(CountdownService {
+            remaining: AtomicU32::new(4),
+        });
+        let items: Vec<_> = block_on(stream.collect());
+        assert_eq!(vec![Ok(4), Ok(3), Err("two"), Ok(1)], items);
+    }
+
+    /// Serves three pages of items, where each response's `next_token` is the token of the next page
+    struct PagedServer {
+        fail_next: Arc<AtomicBool>,
+    }
+    #[async_trait]
+    impl AsyncService for PagedServer {
+        type Input = Option<String>;
+        type Output = (Vec<u32>, Option<String>);
+        type Error = &'static str;
+        async fn process(&self, token: Option<String>) -> Result<Self::Output, &'static str> {
+            if self.fail_next.swap(false, Ordering::Relaxed) {
+                return Err("unavailable");
+            }
+            Ok(match token.as_deref() {
+                None => (vec![1, 2], Some("b".to_owned())),
+                Some("b") => (vec![], Some("c".to_owned())),
+                Some("c") => (vec![3, 4, 5], Some("d".to_owned())),
+                _ => (vec![6], None),
+            })
+        }
+    }
+
+    #[test]
+    fn paginating_yields_all_items_in_order() {
+        let fail_next = Arc::new(AtomicBool::new(false));
+        let server = PagedServer {
+            fail_next: Arc::clone(&fail_next),
+        };
+        let service = PaginatingService::new(server, None, |(items, next_token)| {
+            (items, next_token.map(Some))
+        });
+        block_on(async {
+            let mut items = Vec::new();
+            for _ in 0..5 {
+                items.push(service.process(()).await.unwrap().unwrap());
+            }
+            // a failed fetch is retried by the next call
+            fail_next.store(true, Ordering::Relaxed);
+            assert_eq!(Err("unavailable"), service.process(()).await);
+            while let Some(item) = service.process(()).await.unwrap() {
+                items.push(item);
+            }
+            assert_eq!(vec![1, 2, 3, 4, 5, 6], items);
+            assert_eq!(Ok(None), service.process(()).await);
+        });
+    }
+
+    /// Serves pages `0..3` of one item each after a delay, counting fetches
+    struct SlowPagedServer {
+        fetches: AtomicU32,
+    }
+    #[async_trait]
+    impl AsyncService for SlowPagedServer {
+        type Input = u32;
+        type Output = (Vec<u32>, Option<u32>);
+        type Error = Infallible;
+        async fn process(&self, page: u32) -> Result<Self::Output, Infallible> {
+            self.fetches.fetch_add(1, Ordering::Relaxed);
+            Delay::new(Duration::from_millis(20)).await;
+            Ok((vec![page], Some(page + 1).filter(|next| *next < 3)))
+        }
+    }
+
+    #[test]
+    fn paginating_concurrent_calls_wait_for_fetch() {
+        let service = PaginatingService::new(
+            SlowPagedServer {
+                fetches: AtomicU32::new(0),
+            },
+            0,
+            |page| page,
+        );
+        block_on(async {
+            // the second call waits for the first call's fetch instead of producing `None`
+            let (first, second) = join(service.process(()), service.process(())).await;
+            assert_eq!((Ok(Some(0)), Ok(Some(1))), (first, second));
+            assert_eq!(Ok(Some(2)), service.process(()).await);
+            assert_eq!(Ok(None), service.process(()).await);
+        });
+        assert_eq!(3, service.service.fetches.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn time_window_aggregates_by_event_time() {
+        let secs = Duration::from_secs;
+        // (event time in seconds, key, value), summed per window and key
+        let mut service = TimeWindowAggregateService::new(
+            secs(10),
+            secs(5),
+            |&(time, _, _): &(u64, &str, u32)| secs(time),
+            |&(_, key, _)| key,
+            |sum: &mut u32, (_, _, value)| *sum += value,
+        );
+        let window = |start: u64, key: &'static str, aggregate: u32| WindowAggregate {
+            start: secs(start),
+            end: secs(start + 10),
+            key,
+            aggregate,
+        };
+
+        assert_eq!(Ok(vec![]), service.process((1, "a", 1)));
+        assert_eq!(Ok(vec![]), service.process((4, "b", 2)));
+        assert_eq!(Ok(vec![]), service.process((9, "a", 3)));
+        assert_eq!(Ok(vec![]), service.process((12, "a", 4)));
+        // out of order, but within the allowed lateness of the first window
+        assert_eq!(Ok(vec![]), service.process((7, "b", 5)));
+        // the watermark reaches the end of the first window plus the allowed lateness
+        assert_eq!(
+            Ok(vec![window(0, "a", 4), window(0, "b", 7)]),
+            service.process((15, "b", 6))
+        );
+        assert_eq!(Some(secs(15)), service.watermark());
+
+        // the first window has been completed, so the event is returned
+        let late = service.process((8, "a", 7)).unwrap_err();
+        assert_eq!((8, "a", 7), late.event);
+        assert_eq!(secs(10), late.window_end);
+
+        // skipping ahead completes every earlier window
+        assert_eq!(
+            Ok(vec![window(10, "a", 4), window(10, "b", 6)]),
+            service.process((40, "a", 8))
+        );
+        assert_eq!(vec![window(40, "a", 8)], service.flush());
+        assert!(service.flush().is_empty());
+    }
+}
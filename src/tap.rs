@@ -0,0 +1,363 @@
+//! Services that observe the values passing through a chain without changing them, such as by retaining, reporting, or logging them.
+
+use std::{
+    backtrace::Backtrace,
+    collections::VecDeque,
+    convert::Infallible,
+    fmt::{Debug, Display},
+    marker::PhantomData,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use crate::{async_trait, AsyncService, Service};
+
+/// A [`Service`] or [`AsyncService`] that retains a clone of the most recent outputs of an underlying service in a fixed-capacity ring, for post-mortem debugging.
+///
+/// Outputs are passed through unchanged, and `Err` results are not recorded.
+/// Once the ring is full, each new output evicts the oldest, and the retained outputs may be inspected using [`RingBufferTapService::recent`].
+/// The ring is guarded by a mutex, so the service may be shared between threads behind an [`std::sync::Arc`], while another thread inspects it.
+pub struct RingBufferTapService<S, T> {
+    service: S,
+    capacity: usize,
+    ring: Mutex<VecDeque<T>>,
+}
+impl<S, T> RingBufferTapService<S, T> {
+    /// Create a new [`RingBufferTapService`], retaining up to `capacity` of the most recent outputs
+    pub fn new(service: S, capacity: usize) -> Self {
+        Self {
+            service,
+            capacity,
+            ring: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    fn record(&self, output: &T)
+    where
+        T: Clone,
+    {
+        push_bounded(&self.ring, self.capacity, output.clone());
+    }
+}
+
+/// Push the value to the back of the ring, evicting the oldest value once the ring holds `capacity` values
+fn push_bounded<T>(ring: &Mutex<VecDeque<T>>, capacity: usize, value: T) {
+    if capacity == 0 {
+        return;
+    }
+    let mut ring = ring.lock().expect("poisoned mutex");
+    if ring.len() == capacity {
+        ring.pop_front();
+    }
+    ring.push_back(value);
+}
+impl<S, T: Clone> RingBufferTapService<S, T> {
+    /// The retained outputs, from oldest to newest
+    pub fn recent(&self) -> Vec<T> {
+        self.ring
+            .lock()
+            .expect("poisoned mutex")
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+impl<S: Service> Service for RingBufferTapService<S, S::Output>
+where
+    S::Output: Clone,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let output = self.service.process(input)?;
+        self.record(&output);
+        Ok(output)
+    }
+}
+#[async_trait]
+impl<S: AsyncService> AsyncService for RingBufferTapService<S, S::Output>
+where
+    S::Output: Clone,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let output = self.service.process(input).await?;
+        self.record(&output);
+        Ok(output)
+    }
+}
+
+/// A [`Service`] or [`AsyncService`] that retains a clone of the most recent inputs to an underlying service in a fixed-capacity ring,
+/// and sends a [`DiagnosticReport`] to a sink [`Service`] whenever the underlying service fails.
+///
+/// The report holds the retained inputs, ending with the input of the failed call, along with the time of the failure and the error's message.
+/// A [`Backtrace`] of the call is also captured when enabled by [`DiagnosticService::with_backtrace`], regardless of the `RUST_BACKTRACE` environment variable.
+///
+/// The original error is always returned unchanged, and the result of the sink is ignored, so a failing sink never affects the caller.
+pub struct DiagnosticService<S, I, K> {
+    service: S,
+    sink: K,
+    capacity: usize,
+    backtrace: bool,
+    ring: Mutex<VecDeque<I>>,
+}
+impl<S, I, K> DiagnosticService<S, I, K> {
+    /// Create a new [`DiagnosticService`], retaining up to `capacity` of the most recent inputs for each report
+    pub fn new(service: S, sink: K, capacity: usize) -> Self {
+        Self {
+            service,
+            sink,
+            capacity,
+            backtrace: false,
+            ring: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Capture a [`Backtrace`] in each report
+    pub fn with_backtrace(mut self) -> Self {
+        self.backtrace = true;
+        self
+    }
+}
+impl<S, I: Clone, K: Service<Input = DiagnosticReport<I>>> DiagnosticService<S, I, K> {
+    fn report<E: Display>(&self, err: &E) {
+        let report = DiagnosticReport {
+            inputs: self
+                .ring
+                .lock()
+                .expect("poisoned mutex")
+                .iter()
+                .cloned()
+                .collect(),
+            timestamp: SystemTime::now(),
+            error: err.to_string(),
+            backtrace: self.backtrace.then(Backtrace::force_capture),
+        };
+        let _ = self.sink.process(report);
+    }
+}
+impl<S, K> Service for DiagnosticService<S, S::Input, K>
+where
+    S: Service,
+    S::Input: Clone,
+    S::Error: Display,
+    K: Service<Input = DiagnosticReport<S::Input>>,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        push_bounded(&self.ring, self.capacity, input.clone());
+        self.service
+            .process(input)
+            .inspect_err(|err| self.report(err))
+    }
+}
+#[async_trait]
+impl<S, K> AsyncService for DiagnosticService<S, S::Input, K>
+where
+    S: AsyncService,
+    S::Input: Clone,
+    S::Error: Display,
+    K: Service<Input = DiagnosticReport<S::Input>> + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        push_bounded(&self.ring, self.capacity, input.clone());
+        self.service
+            .process(input)
+            .await
+            .inspect_err(|err| self.report(err))
+    }
+}
+
+/// The context of a failed call, sent to the sink of a [`DiagnosticService`]
+#[derive(Debug)]
+pub struct DiagnosticReport<I> {
+    /// The most recent inputs, from oldest to newest, where the newest is the input of the failed call
+    pub inputs: Vec<I>,
+    /// The time at which the failure was observed
+    pub timestamp: SystemTime,
+    /// The message of the error returned by the underlying service
+    pub error: String,
+    /// The backtrace of the failed call, when enabled by [`DiagnosticService::with_backtrace`]
+    pub backtrace: Option<Backtrace>,
+}
+
+/// A [`Service`] that sends a structured [`LogRecord`] of each input to a sink [`Service`], such as an mpsc queue or a network forwarder,
+/// producing the input unchanged.
+///
+/// Each record holds the configured [`LogLevel`] and prefix, the input formatted by its [`Debug`] or [`Display`] impl, and the time it was processed.
+/// As with [`DiagnosticService`], the result of the sink is ignored, so a failing sink never affects the caller.
+pub struct StructuredLogService<T, K> {
+    level: LogLevel,
+    prefix: String,
+    sink: K,
+    format: fn(&T) -> String,
+    _phantom: PhantomData<fn(T)>,
+}
+impl<T: Debug, K> StructuredLogService<T, K> {
+    /// Create a new [`StructuredLogService`] which formats each input using its [`Debug`] impl
+    pub fn debug(level: LogLevel, prefix: impl Into<String>, sink: K) -> Self {
+        Self::with_format(level, prefix, sink, |input| format!("{input:?}"))
+    }
+}
+impl<T: Display, K> StructuredLogService<T, K> {
+    /// Create a new [`StructuredLogService`] which formats each input using its [`Display`] impl
+    pub fn display(level: LogLevel, prefix: impl Into<String>, sink: K) -> Self {
+        Self::with_format(level, prefix, sink, |input| input.to_string())
+    }
+}
+impl<T, K> StructuredLogService<T, K> {
+    fn with_format(
+        level: LogLevel,
+        prefix: impl Into<String>,
+        sink: K,
+        format: fn(&T) -> String,
+    ) -> Self {
+        Self {
+            level,
+            prefix: prefix.into(),
+            sink,
+            format,
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<T, K: Service<Input = LogRecord>> Service for StructuredLogService<T, K> {
+    type Input = T;
+    type Output = T;
+    type Error = Infallible;
+    fn process(&self, input: T) -> Result<Self::Output, Self::Error> {
+        let record = LogRecord {
+            level: self.level,
+            prefix: self.prefix.clone(),
+            payload: (self.format)(&input),
+            timestamp: SystemTime::now(),
+        };
+        let _ = self.sink.process(record);
+        Ok(input)
+    }
+}
+
+/// The severity of a [`LogRecord`], from most to least severe
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// A structured record of an input, sent to the sink of a [`StructuredLogService`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogRecord {
+    /// The level configured for the [`StructuredLogService`]
+    pub level: LogLevel,
+    /// The prefix configured for the [`StructuredLogService`]
+    pub prefix: String,
+    /// The input, formatted by its [`Debug`] or [`Display`] impl
+    pub payload: String,
+    /// The time at which the input was processed
+    pub timestamp: SystemTime,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+    use crate::FnService;
+
+    #[test]
+    fn ring_buffer_retains_most_recent() {
+        let tap = Arc::new(RingBufferTapService::new(
+            FnService::new(|input: u32| match input {
+                3 => Err(()),
+                n => Ok(n * 10),
+            }),
+            4,
+        ));
+        let handle = {
+            let tap = Arc::clone(&tap);
+            thread::spawn(move || (0..3).map(|n| tap.process(n)).collect::<Vec<_>>())
+        };
+        assert_eq!(vec![Ok(0), Ok(10), Ok(20)], handle.join().unwrap());
+        assert_eq!(Err(()), tap.process(3));
+        for n in 4..7 {
+            assert_eq!(Ok(n * 10), tap.process(n));
+        }
+        assert_eq!(vec![20, 40, 50, 60], tap.recent());
+    }
+
+    #[test]
+    fn diagnostic_reports_recent_inputs() {
+        let reports = Mutex::new(Vec::new());
+        let service = DiagnosticService::new(
+            FnService::new(|input: u32| match input {
+                4 => Err("four"),
+                n => Ok(n),
+            }),
+            FnService::new(|report| {
+                reports.lock().unwrap().push(report);
+                Ok::<_, ()>(())
+            }),
+            3,
+        )
+        .with_backtrace();
+
+        for n in 0..4 {
+            assert_eq!(Ok(n), service.process(n));
+        }
+        assert!(reports.lock().unwrap().is_empty());
+
+        let before = SystemTime::now();
+        assert_eq!(Err("four"), service.process(4));
+        let reports = reports.into_inner().unwrap();
+        assert_eq!(1, reports.len());
+        assert_eq!(vec![2, 3, 4], reports[0].inputs);
+        assert!(reports[0].timestamp >= before && reports[0].timestamp <= SystemTime::now());
+        assert_eq!("four", reports[0].error);
+        assert!(reports[0].backtrace.is_some());
+    }
+
+    #[test]
+    fn structured_log_sends_record_per_input() {
+        let records = Mutex::new(Vec::new());
+        let sink = || {
+            FnService::new(|record: LogRecord| {
+                records.lock().unwrap().push(record);
+                Ok::<_, ()>(())
+            })
+        };
+        let debug = StructuredLogService::debug(LogLevel::Info, "orders", sink());
+        let display = StructuredLogService::display(LogLevel::Warn, "names", sink());
+
+        let before = SystemTime::now();
+        assert_eq!(Ok(Some(7)), debug.process(Some(7)));
+        assert_eq!(Ok("ada"), display.process("ada"));
+        let after = SystemTime::now();
+
+        let records = records.into_inner().unwrap();
+        let fields: Vec<_> = records
+            .iter()
+            .map(|r| (r.level, r.prefix.as_str(), r.payload.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (LogLevel::Info, "orders", "Some(7)"),
+                (LogLevel::Warn, "names", "ada"),
+            ],
+            fields
+        );
+        assert!(records
+            .iter()
+            .all(|r| r.timestamp >= before && r.timestamp <= after));
+    }
+}
@@ -0,0 +1,56 @@
+//! Helpers shared by unit tests.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+    time::Duration,
+};
+
+/// A runtime-agnostic future that completes after the given duration, woken by a helper thread
+pub(crate) struct Delay {
+    duration: Duration,
+    state: Option<Arc<Mutex<DelayState>>>,
+}
+#[derive(Default)]
+struct DelayState {
+    done: bool,
+    waker: Option<Waker>,
+}
+impl Delay {
+    pub(crate) fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            state: None,
+        }
+    }
+}
+impl Future for Delay {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let duration = self.duration;
+        let state = self.state.get_or_insert_with(|| {
+            let state = Arc::new(Mutex::new(DelayState::default()));
+            let thread_state = Arc::clone(&state);
+            thread::spawn(move || {
+                thread::sleep(duration);
+                let mut state = thread_state.lock().unwrap();
+                state.done = true;
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            });
+            state
+        });
+        let mut state = state.lock().unwrap();
+        match state.done {
+            true => Poll::Ready(()),
+            false => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -1,6 +1,16 @@
-use std::thread::{spawn, JoinHandle};
+use std::{
+    any::Any,
+    collections::VecDeque,
+    error::Error,
+    fmt::{Debug, Display},
+    marker::PhantomData,
+    panic::{catch_unwind, resume_unwind, AssertUnwindSafe},
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread::{self, spawn, JoinHandle},
+    time::Duration,
+};
 
-use crate::{MutService, Service};
+use crate::{MutService, RetryError, Retryable, Service};
 
 /// Spawn a [`Service<()>`] in a new thread, calling [`Service::process`] repeatedly, until the given `error_handler` function returns `Err(_)`.
 ///
@@ -14,7 +24,7 @@ where
 {
     spawn(move || loop {
         if let Err(err) = service.process(()) {
-            if let Err(_) = error_handler(err) {
+            if error_handler(err).is_err() {
                 return;
             }
         }
@@ -33,9 +43,452 @@ where
 {
     spawn(move || loop {
         if let Err(err) = service.process(()) {
-            if let Err(_) = error_handler(err) {
+            if error_handler(err).is_err() {
                 return;
             }
         }
     })
 }
+
+/// A [`Service`] that accepts a `FnOnce()` as input, which is dispatched to a fixed-size pool of worker threads, and produces a [`PoolHandle`] as output.
+///
+/// Unlike [`crate::SpawnService`], which spawns a new thread for every input, the number of threads is capped at the configured number of workers.
+/// Jobs are queued in a bounded queue. When the queue is full, a [`PoolFull`] error is returned containing the rejected job,
+/// which implements [`Retryable`] so that a [`crate::RetryService`] may be used to block with an idle strategy until the queue has capacity.
+///
+/// A panicking job does not terminate its worker, and the panic is returned by [`PoolHandle::join`].
+/// When the service is dropped, all queued jobs are completed before the worker threads are joined.
+pub struct ThreadPoolService<F> {
+    queue: Arc<PoolQueue>,
+    capacity: usize,
+    workers: Vec<JoinHandle<()>>,
+    _phantom: PhantomData<fn(F)>,
+}
+type Job = Box<dyn FnOnce() + Send>;
+struct PoolQueue {
+    state: Mutex<PoolState>,
+    available: Condvar,
+}
+struct PoolState {
+    jobs: VecDeque<Job>,
+    shutdown: bool,
+}
+impl<F> ThreadPoolService<F> {
+    /// Create a new [`ThreadPoolService`]
+    ///
+    /// # Arguments
+    /// * `workers` - the number of worker threads to spawn
+    /// * `capacity` - the maximum number of jobs waiting in the queue for a worker
+    pub fn new(workers: usize, capacity: usize) -> Self {
+        let queue = Arc::new(PoolQueue {
+            state: Mutex::new(PoolState {
+                jobs: VecDeque::with_capacity(capacity),
+                shutdown: false,
+            }),
+            available: Condvar::new(),
+        });
+        let workers = (0..workers)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                spawn(move || run_worker(&queue))
+            })
+            .collect();
+        Self {
+            queue,
+            capacity,
+            workers,
+            _phantom: PhantomData,
+        }
+    }
+}
+fn run_worker(queue: &PoolQueue) {
+    loop {
+        let job = {
+            let mut state = queue.state.lock().expect("poisoned mutex");
+            loop {
+                if let Some(job) = state.jobs.pop_front() {
+                    break job;
+                }
+                if state.shutdown {
+                    return;
+                }
+                state = queue.available.wait(state).expect("poisoned mutex");
+            }
+        };
+        job();
+    }
+}
+impl<T: Send + 'static, F: FnOnce() -> T + Send + 'static> Service for ThreadPoolService<F> {
+    type Input = F;
+    type Output = PoolHandle<T>;
+    type Error = PoolFull<F>;
+    fn process(&self, input: F) -> Result<Self::Output, Self::Error> {
+        let mut state = self.queue.state.lock().expect("poisoned mutex");
+        if state.jobs.len() >= self.capacity {
+            return Err(PoolFull(input));
+        }
+        let (sender, receiver) = mpsc::sync_channel(1);
+        state.jobs.push_back(Box::new(move || {
+            sender.send(catch_unwind(AssertUnwindSafe(input))).ok();
+        }));
+        self.queue.available.notify_one();
+        Ok(PoolHandle { receiver })
+    }
+}
+impl<F> Retryable<F, PoolFull<F>> for ThreadPoolService<F> {
+    fn parse_retry(&self, err: PoolFull<F>) -> Result<F, RetryError<PoolFull<F>>> {
+        Ok(err.0)
+    }
+}
+impl<F> Drop for ThreadPoolService<F> {
+    fn drop(&mut self) {
+        self.queue.state.lock().expect("poisoned mutex").shutdown = true;
+        self.queue.available.notify_all();
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
+
+/// Produced by [`ThreadPoolService`] to obtain the result of a job.
+pub struct PoolHandle<T> {
+    receiver: mpsc::Receiver<thread::Result<T>>,
+}
+impl<T> Debug for PoolHandle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PoolHandle")
+    }
+}
+impl<T> PoolHandle<T> {
+    /// Block until the job completes, returning its output, or `Err` with the panic payload if the job panicked.
+    pub fn join(self) -> thread::Result<T> {
+        match self.receiver.recv() {
+            Ok(result) => result,
+            Err(_) => Err(Box::new("job was dropped") as Box<dyn Any + Send>),
+        }
+    }
+}
+
+/// Returned by [`ThreadPoolService`] when the job queue is full, containing the rejected job.
+pub struct PoolFull<F>(pub F);
+impl<F> Debug for PoolFull<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PoolFull")
+    }
+}
+impl<F> Display for PoolFull<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PoolFull")
+    }
+}
+impl<F> Error for PoolFull<F> {}
+
+/// A [`Service`] handle to a [`MutService`] that is owned by a dedicated thread, serializing access to it like an actor's mailbox.
+///
+/// Each call sends the input, along with a reply channel, to the owning thread, and blocks until the reply is received.
+/// The handle may be cloned and shared across threads, allowing a non-`Sync` [`MutService`] to be used concurrently.
+/// The owning thread exits once every handle has been dropped.
+///
+/// When the owning thread has exited, such as when the underlying service panicked, [`ActorError::Disconnected`] is returned.
+pub struct ActorService<S: MutService> {
+    sender: mpsc::Sender<Envelope<S>>,
+}
+type Envelope<S> = (
+    <S as MutService>::Input,
+    mpsc::SyncSender<Result<<S as MutService>::Output, <S as MutService>::Error>>,
+);
+impl<S: MutService + 'static> ActorService<S>
+where
+    S::Input: Send,
+    S::Output: Send,
+    S::Error: Send,
+{
+    /// Spawn a thread that owns the given service, returning a handle to it
+    pub fn new(service: S) -> Self
+    where
+        S: Send,
+    {
+        Self::with_factory(move || service)
+    }
+
+    /// Spawn a thread that owns the service created by the given factory, returning a handle to it.
+    ///
+    /// The factory is called on the spawned thread, so the service itself does not need to be `Send`.
+    pub fn with_factory<F: FnOnce() -> S + Send + 'static>(factory: F) -> Self {
+        let (sender, receiver) = mpsc::channel::<Envelope<S>>();
+        spawn(move || {
+            let mut service = factory();
+            for (input, reply) in receiver {
+                reply.send(service.process(input)).ok();
+            }
+        });
+        Self { sender }
+    }
+}
+impl<S: MutService> Clone for ActorService<S> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+        }
+    }
+}
+impl<S: MutService> Service for ActorService<S> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = ActorError<S::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let (reply, response) = mpsc::sync_channel(1);
+        self.sender
+            .send((input, reply))
+            .map_err(|_| ActorError::Disconnected)?;
+        match response.recv() {
+            Ok(result) => result.map_err(ActorError::ServiceError),
+            Err(_) => Err(ActorError::Disconnected),
+        }
+    }
+}
+
+/// Returned by [`ActorService`], either when the owning thread has exited or when the underlying service fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ActorError<E> {
+    Disconnected,
+    ServiceError(E),
+}
+impl<E: Display> Display for ActorError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Disconnected => f.write_str("actor disconnected"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<E: Error + 'static> Error for ActorError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Disconnected => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+/// A [`Service`] that bounds how long a call to an underlying [`Service`] may block, by calling it on a spawned thread and waiting up to a timeout for its result.
+///
+/// When the timeout elapses first, [`TimeoutError::Elapsed`] is returned.
+/// A thread cannot be cancelled, so the underlying call keeps running on its thread after a timeout, and its result is discarded once it completes.
+/// A service which may block forever will leak one thread for each call that times out.
+///
+/// The underlying service is shared with each spawned thread using an [`Arc`].
+/// When the underlying call panics before the timeout elapses, the panic is resumed on the calling thread, as though the service were called directly.
+pub struct TimeoutService<S> {
+    service: Arc<S>,
+    timeout: Duration,
+}
+impl<S> TimeoutService<S> {
+    pub fn new(service: S, timeout: Duration) -> Self {
+        Self {
+            service: Arc::new(service),
+            timeout,
+        }
+    }
+}
+impl<S> Service for TimeoutService<S>
+where
+    S: Service + Send + Sync + 'static,
+    S::Input: Send + 'static,
+    S::Output: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = TimeoutError<S::Error>;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let service = Arc::clone(&self.service);
+        spawn(move || {
+            sender
+                .send(catch_unwind(AssertUnwindSafe(|| service.process(input))))
+                .ok();
+        });
+        match receiver.recv_timeout(self.timeout) {
+            Ok(Ok(result)) => result.map_err(TimeoutError::ServiceError),
+            Ok(Err(panic)) => resume_unwind(panic),
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(TimeoutError::Elapsed),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                unreachable!("the spawned call always sends its result")
+            }
+        }
+    }
+}
+
+/// Returned by [`TimeoutService`], either when the timeout elapses or when the underlying service fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TimeoutError<E> {
+    Elapsed,
+    ServiceError(E),
+}
+impl<E: Display> Display for TimeoutError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Elapsed => f.write_str("timeout elapsed"),
+            Self::ServiceError(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl<E: Error + 'static> Error for TimeoutError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Elapsed => None,
+            Self::ServiceError(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Instant,
+    };
+
+    use super::*;
+    use crate::{idle, FnService, RetryService};
+
+    #[test]
+    fn thread_pool_bounds_threads() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+        let pool = RetryService::new(ThreadPoolService::new(2, 4), idle::backoff);
+        let handles: Vec<_> = (0..20)
+            .map(|i| {
+                let active = Arc::clone(&active);
+                let max_active = Arc::clone(&max_active);
+                pool.process(move || {
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_active.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(5));
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    (i * 2, thread::current().id())
+                })
+                .unwrap()
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        let outputs: Vec<_> = results.iter().map(|(output, _)| *output).collect();
+        assert_eq!((0..20).map(|i| i * 2).collect::<Vec<_>>(), outputs);
+        let threads: HashSet<_> = results.iter().map(|(_, id)| *id).collect();
+        assert!(threads.len() <= 2);
+        assert!(max_active.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn thread_pool_rejects_when_full() {
+        let (release, blocked) = mpsc::channel::<()>();
+        let pool = ThreadPoolService::new(1, 1);
+        let first = pool
+            .process(Box::new(move || blocked.recv().is_ok()) as Box<dyn FnOnce() -> bool + Send>)
+            .unwrap();
+        // wait for the worker to take the first job, leaving the queue empty
+        while !pool.queue.state.lock().unwrap().jobs.is_empty() {
+            thread::yield_now();
+        }
+        let second = pool.process(Box::new(|| true)).unwrap();
+        let rejected = pool.process(Box::new(|| false)).unwrap_err();
+        assert!(!(rejected.0)());
+
+        release.send(()).unwrap();
+        assert!(first.join().unwrap());
+        assert!(second.join().unwrap());
+    }
+
+    #[test]
+    fn thread_pool_returns_panics() {
+        type Job = Box<dyn FnOnce() -> u32 + Send>;
+        let pool = RetryService::new(ThreadPoolService::<Job>::new(1, 1), idle::backoff);
+        let handle = pool.process(Box::new(|| panic!("boom"))).unwrap();
+        assert!(handle.join().is_err());
+        assert_eq!(7, pool.process(Box::new(|| 7)).unwrap().join().unwrap());
+    }
+
+    struct Counter {
+        // a non-Sync resource
+        count: std::cell::Cell<u32>,
+    }
+    impl MutService for Counter {
+        type Input = u32;
+        type Output = u32;
+        type Error = u32;
+        fn process(&mut self, input: u32) -> Result<u32, u32> {
+            match input {
+                0 => Err(self.count.get()),
+                u32::MAX => panic!("actor failed"),
+                _ => {
+                    self.count.set(self.count.get() + input);
+                    Ok(self.count.get())
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn actor_serializes_mutation() {
+        let actor = ActorService::new(Counter {
+            count: std::cell::Cell::new(0),
+        });
+        let mut outputs: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    let actor = actor.clone();
+                    scope.spawn(move || {
+                        (0..25)
+                            .map(|_| actor.process(1).unwrap())
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+        outputs.sort();
+        assert_eq!((1..=100).collect::<Vec<_>>(), outputs);
+        assert_eq!(Err(ActorError::ServiceError(100)), actor.process(0));
+
+        assert_eq!(Err(ActorError::Disconnected), actor.process(u32::MAX));
+        assert_eq!(Err(ActorError::Disconnected), actor.process(1));
+    }
+
+    #[test]
+    fn timeout_bounds_blocking_call() {
+        let service = TimeoutService::new(
+            FnService::new(|millis: u64| {
+                thread::sleep(Duration::from_millis(millis));
+                match millis {
+                    0 => Err("zero"),
+                    n => Ok(n),
+                }
+            }),
+            Duration::from_millis(200),
+        );
+        assert_eq!(Ok(1), service.process(1));
+        assert_eq!(Err(TimeoutError::ServiceError("zero")), service.process(0));
+
+        let start = Instant::now();
+        assert_eq!(Err(TimeoutError::Elapsed), service.process(5_000));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn timeout_resumes_panic_without_waiting() {
+        let service = TimeoutService::new(
+            FnService::new(|_: ()| -> Result<(), ()> { panic!("inner panic") }),
+            Duration::from_secs(5),
+        );
+        let start = Instant::now();
+        let panic = catch_unwind(AssertUnwindSafe(|| service.process(()))).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(Some(&"inner panic"), panic.downcast_ref::<&str>());
+    }
+}
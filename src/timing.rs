@@ -0,0 +1,313 @@
+//! Services that attribute the latency of a chain to its individual stages, without an external profiler,
+//! or that aggregate the latency of calls into a histogram.
+//!
+//! Each stage to be measured is wrapped in a [`TimedStageService`], and the chain is wrapped in a [`TracedChainService`].
+//! The per-call trace is carried alongside the value as a [`Traced`] input, so concurrent calls never share a trace.
+
+use std::{
+    borrow::Cow,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    async_trait,
+    clock::{Clock, SystemClock},
+    AsyncService, Service,
+};
+
+/// A value carried through a traced chain, along with the `(stage name, duration)` of each [`TimedStageService`] it has passed through, in order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Traced<T> {
+    pub value: T,
+    pub trace: Vec<(Cow<'static, str>, Duration)>,
+}
+
+/// A [`Service`] or [`AsyncService`] that measures the duration of each call to an underlying stage, appending it to the [`Traced`] input's trace under the given name.
+///
+/// When the underlying stage returns an `Err`, the error is passed through and the trace is discarded.
+pub struct TimedStageService<S, C = SystemClock> {
+    name: Cow<'static, str>,
+    service: S,
+    clock: C,
+}
+impl<S> TimedStageService<S> {
+    pub fn new<N: Into<Cow<'static, str>>>(name: N, service: S) -> Self {
+        Self::with_clock(name, service, SystemClock)
+    }
+}
+impl<S, C: Clock> TimedStageService<S, C> {
+    /// Create a new [`TimedStageService`] which uses the given [`Clock`] to measure each call
+    pub fn with_clock<N: Into<Cow<'static, str>>>(name: N, service: S, clock: C) -> Self {
+        Self {
+            name: name.into(),
+            service,
+            clock,
+        }
+    }
+}
+impl<S: Service, C: Clock> Service for TimedStageService<S, C> {
+    type Input = Traced<S::Input>;
+    type Output = Traced<S::Output>;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let Traced { value, mut trace } = input;
+        let start = self.clock.now();
+        let value = self.service.process(value)?;
+        trace.push((self.name.clone(), self.clock.now().duration_since(start)));
+        Ok(Traced { value, trace })
+    }
+}
+#[async_trait]
+impl<S, C> AsyncService for TimedStageService<S, C>
+where
+    S: AsyncService,
+    C: Clock + Send + Sync,
+{
+    type Input = Traced<S::Input>;
+    type Output = Traced<S::Output>;
+    type Error = S::Error;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let Traced { value, mut trace } = input;
+        let start = self.clock.now();
+        let value = self.service.process(value).await?;
+        trace.push((self.name.clone(), self.clock.now().duration_since(start)));
+        Ok(Traced { value, trace })
+    }
+}
+
+/// A [`Service`] or [`AsyncService`] that starts a new, empty trace for each input to an underlying chain of [`TimedStageService`]s,
+/// producing the chain's output along with the trace of every timed stage as a [`Traced`] output.
+pub struct TracedChainService<S> {
+    chain: S,
+}
+impl<S> TracedChainService<S> {
+    pub fn new(chain: S) -> Self {
+        Self { chain }
+    }
+}
+impl<I, O, S> Service for TracedChainService<S>
+where
+    S: Service<Input = Traced<I>, Output = Traced<O>>,
+{
+    type Input = I;
+    type Output = Traced<O>;
+    type Error = S::Error;
+    fn process(&self, input: I) -> Result<Self::Output, Self::Error> {
+        self.chain.process(Traced {
+            value: input,
+            trace: Vec::new(),
+        })
+    }
+}
+#[async_trait]
+impl<I, O, S> AsyncService for TracedChainService<S>
+where
+    I: Send + 'static,
+    O: Send + 'static,
+    S: AsyncService<Input = Traced<I>, Output = Traced<O>>,
+{
+    type Input = I;
+    type Output = Traced<O>;
+    type Error = S::Error;
+    async fn process(&self, input: I) -> Result<Self::Output, Self::Error> {
+        let traced = Traced {
+            value: input,
+            trace: Vec::new(),
+        };
+        self.chain.process(traced).await
+    }
+}
+
+/// A [`Service`] or [`AsyncService`] that records the duration of each call to an underlying service in an exponential-bucket histogram,
+/// so latency percentiles may be queried using [`LatencyHistogramService::percentile`].
+///
+/// Durations are bucketed by their power of two, and each power of two is divided into linear sub-buckets,
+/// similar to an [HDR histogram](http://hdrhistogram.org/).
+/// This bounds the relative error of a reported percentile to about 3%, from nanoseconds up to centuries,
+/// so long-tail latencies are reported as accurately as short ones, unlike with fixed buckets.
+///
+/// Calls are recorded whether they succeed or fail.
+/// Buckets are updated atomically, so this service is `Send` + `Sync` when the underlying service is, and may be shared using an [`crate::ArcService`].
+pub struct LatencyHistogramService<S, C = SystemClock> {
+    service: S,
+    pub(crate) clock: C,
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+}
+impl<S> LatencyHistogramService<S> {
+    pub fn new(service: S) -> Self {
+        Self::with_clock(service, SystemClock)
+    }
+}
+impl<S, C: Clock> LatencyHistogramService<S, C> {
+    /// Create a new [`LatencyHistogramService`] which uses the given [`Clock`] to measure each call
+    pub fn with_clock(service: S, clock: C) -> Self {
+        Self {
+            service,
+            clock,
+            buckets: (0..HISTOGRAM_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    /// The number of calls recorded
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// The duration that `p` percent of recorded calls completed within, or `None` if no calls have been recorded.
+    ///
+    /// `p` is clamped to `0.0..=100.0`, so `percentile(99.0)` gives the p99 latency.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((p.clamp(0.0, 100.0) / 100.0 * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= rank {
+                return Some(Duration::from_nanos(bucket_midpoint(index)));
+            }
+        }
+        // a concurrent call may have incremented the count before its bucket, so fall back to the greatest recorded bucket
+        self.buckets
+            .iter()
+            .rposition(|bucket| bucket.load(Ordering::Relaxed) > 0)
+            .map(|index| Duration::from_nanos(bucket_midpoint(index)))
+    }
+
+    /// Record a call which started at the given [`Instant`] and has just completed or been abandoned
+    pub(crate) fn record(&self, start: Instant) {
+        let nanos = self.clock.now().duration_since(start).as_nanos();
+        let index = bucket_index(u64::try_from(nanos).unwrap_or(u64::MAX));
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+impl<S: Service, C: Clock> Service for LatencyHistogramService<S, C> {
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let start = self.clock.now();
+        let result = self.service.process(input);
+        self.record(start);
+        result
+    }
+}
+#[async_trait]
+impl<S, C> AsyncService for LatencyHistogramService<S, C>
+where
+    S: AsyncService,
+    C: Clock + Send + Sync,
+{
+    type Input = S::Input;
+    type Output = S::Output;
+    type Error = S::Error;
+    async fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let start = self.clock.now();
+        let result = self.service.process(input).await;
+        self.record(start);
+        result
+    }
+}
+
+/// The number of linear sub-buckets per power of two, as a power of two
+const SUB_BUCKET_BITS: u32 = 4;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+const HISTOGRAM_BUCKETS: usize = ((64 - SUB_BUCKET_BITS + 1) as u64 * SUB_BUCKETS) as usize;
+
+/// Values below [`SUB_BUCKETS`] are recorded exactly, and greater values are bucketed by their most significant bits
+fn bucket_index(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS {
+        return nanos as usize;
+    }
+    let shift = 63 - nanos.leading_zeros() - SUB_BUCKET_BITS;
+    let sub_bucket = (nanos >> shift) - SUB_BUCKETS;
+    ((shift as u64 + 1) * SUB_BUCKETS + sub_bucket) as usize
+}
+
+fn bucket_midpoint(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    let lower = (SUB_BUCKETS + index % SUB_BUCKETS) << shift;
+    lower + ((1 << shift) >> 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, FnService, ServiceChain};
+
+    #[test]
+    fn traced_chain_records_each_stage() {
+        let clock = ManualClock::new();
+        let stage = |name: &'static str, millis: u64| {
+            let stage_clock = clock.clone();
+            TimedStageService::with_clock(
+                name,
+                FnService::new(move |input: u32| {
+                    stage_clock.sleep(Duration::from_millis(millis));
+                    Ok::<_, ()>(input + 1)
+                }),
+                clock.clone(),
+            )
+        };
+        let chain = TracedChainService::new(
+            ServiceChain::start(stage("parse", 5))
+                .next(stage("validate", 1))
+                .next(stage("store", 20))
+                .end(),
+        );
+
+        let traced = chain.process(0).unwrap();
+        assert_eq!(3, traced.value);
+        assert_eq!(
+            vec![
+                (Cow::from("parse"), Duration::from_millis(5)),
+                (Cow::from("validate"), Duration::from_millis(1)),
+                (Cow::from("store"), Duration::from_millis(20)),
+            ],
+            traced.trace
+        );
+    }
+
+    #[test]
+    fn latency_histogram_percentiles() {
+        let clock = ManualClock::new();
+        let service_clock = clock.clone();
+        let service = LatencyHistogramService::with_clock(
+            FnService::new(move |millis: u64| {
+                service_clock.sleep(Duration::from_millis(millis));
+                Ok::<_, ()>(())
+            }),
+            clock,
+        );
+        assert_eq!(None, service.percentile(50.0));
+
+        // 1ms to 1000ms, uniformly distributed
+        for millis in 1..=1000 {
+            service.process(millis).unwrap();
+        }
+        assert_eq!(1000, service.count());
+        for (p, expected) in [
+            (0.0, 1.0),
+            (50.0, 500.0),
+            (95.0, 950.0),
+            (99.0, 990.0),
+            (100.0, 1000.0),
+        ] {
+            let actual = service.percentile(p).unwrap().as_secs_f64() * 1000.0;
+            assert!(
+                (actual - expected).abs() / expected < 0.04,
+                "p{p}: {actual}ms"
+            );
+        }
+    }
+}